@@ -0,0 +1,72 @@
+//! Compares the encoded size, and the time to encode and decode, of
+//! a 1MB buffer sent as a `Vec<u8>` and as `essrpc::Bytes`, as
+//! parameters marked `#[essrpc(bytes)]` are. Run with
+//! `cargo bench --bench bytes --features json_transport,bincode_transport`.
+use std::time::{Duration, Instant};
+
+use essrpc::{ByteBuf, Bytes};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+const SIZE: usize = 1 << 20;
+const ITERATIONS: u32 = 10;
+
+// Average time to encode `value` and decode it as `D`, and the size
+// of the encoding.
+fn measure<S: Serialize, D: DeserializeOwned>(
+    value: &S,
+    encode: impl Fn(&S) -> Vec<u8>,
+    decode: impl Fn(&[u8]) -> D,
+) -> (usize, Duration, Duration) {
+    let mut encoded = Vec::new();
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        encoded = encode(value);
+    }
+    let encode_time = start.elapsed() / ITERATIONS;
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        decode(&encoded);
+    }
+    (encoded.len(), encode_time, start.elapsed() / ITERATIONS)
+}
+
+fn main() {
+    let data: Vec<u8> = (0..SIZE).map(|i| (i * 7919 % 251) as u8).collect();
+    println!("{:<24}{:>12}{:>14}{:>14}", "", "bytes", "encode", "decode");
+    let report = |name: &str, (size, encode, decode): (usize, Duration, Duration)| {
+        println!("{:<24}{:>12}{:>14?}{:>14?}", name, size, encode, decode);
+    };
+    report(
+        "json Vec<u8>",
+        measure(
+            &data,
+            |v| serde_json::to_vec(v).unwrap(),
+            |b| serde_json::from_slice::<Vec<u8>>(b).unwrap(),
+        ),
+    );
+    report(
+        "json Bytes",
+        measure(
+            &Bytes::new(&data),
+            |v| serde_json::to_vec(v).unwrap(),
+            |b| serde_json::from_slice::<ByteBuf>(b).unwrap(),
+        ),
+    );
+    report(
+        "bincode Vec<u8>",
+        measure(
+            &data,
+            |v| bincode::serialize(v).unwrap(),
+            |b| bincode::deserialize::<Vec<u8>>(b).unwrap(),
+        ),
+    );
+    report(
+        "bincode Bytes",
+        measure(
+            &Bytes::new(&data),
+            |v| bincode::serialize(v).unwrap(),
+            |b| bincode::deserialize::<ByteBuf>(b).unwrap(),
+        ),
+    );
+}
@@ -0,0 +1,107 @@
+//! Counts the allocations made, and the time taken, by a JSON server
+//! decoding a request and reading a large `Vec` parameter from it:
+//! copying the parameter out of the request before deserializing it,
+//! as servers once did, moving it out instead, and with lazy params,
+//! deserializing it straight from the text of the request without
+//! an intermediate `Value`. Run with
+//! `cargo bench --bench params --features json_transport`.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use essrpc::transports::json_codec::JSONCodec;
+use essrpc::MethodId;
+use serde::{Deserialize, Serialize};
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+#[derive(Deserialize, Serialize)]
+struct Point {
+    x: f64,
+    y: f64,
+    label: String,
+}
+
+const POINTS: usize = 100_000;
+const ITERATIONS: u32 = 10;
+
+// Average allocations and time of decoding the request with `codec`
+// then calling `read` on it
+fn measure(
+    codec: &JSONCodec,
+    request: &[u8],
+    read: impl Fn(&mut essrpc::transports::JRXState),
+) -> (usize, Duration) {
+    let mut allocations = 0;
+    let mut elapsed = Duration::default();
+    for _ in 0..ITERATIONS {
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        let start = Instant::now();
+        let (_, mut state) = codec.decode_call(request).unwrap();
+        read(&mut state);
+        elapsed += start.elapsed();
+        allocations += ALLOCATIONS.load(Ordering::Relaxed) - before;
+    }
+    (allocations / ITERATIONS as usize, elapsed / ITERATIONS)
+}
+
+fn main() {
+    let points: Vec<Point> = (0..POINTS)
+        .map(|i| Point {
+            x: i as f64,
+            y: -(i as f64),
+            label: format!("point {}", i),
+        })
+        .collect();
+    let codec = JSONCodec::new();
+    let mut state = codec.begin_call(MethodId {
+        name: "plot",
+        num: 0,
+    });
+    codec.add_param("points", &points, &mut state).unwrap();
+    let (request, _) = codec.encode_call(&state).unwrap();
+
+    println!("{:<24}{:>14}{:>14}", "", "allocations", "time");
+    let report = |name: &str, (allocations, time): (usize, Duration)| {
+        println!("{:<24}{:>14}{:>14?}", name, allocations, time);
+    };
+    report(
+        "copy then deserialize",
+        measure(&codec, &request, |state| {
+            let value = state.params().unwrap()["points"].clone();
+            let points: Vec<Point> = serde_json::from_value(value).unwrap();
+            assert_eq!(points.len(), POINTS);
+        }),
+    );
+    report(
+        "read_param",
+        measure(&codec, &request, |state| {
+            let points: Vec<Point> = codec.read_param("points", state).unwrap();
+            assert_eq!(points.len(), POINTS);
+        }),
+    );
+    let lazy = JSONCodec::new().with_lazy_params(true);
+    report(
+        "lazy read_param",
+        measure(&lazy, &request, |state| {
+            let points: Vec<Point> = lazy.read_param("points", state).unwrap();
+            assert_eq!(points.len(), POINTS);
+        }),
+    );
+}
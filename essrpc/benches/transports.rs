@@ -0,0 +1,124 @@
+//! Compares the time a whole call takes with `JSONTransport` and
+//! with `BincodeTransport`, from the client beginning it to the
+//! client reading the response, over an in-memory
+//! `DuplexChannel`. Throughput counts the bytes of the request and
+//! response, which are also printed once for each transport. The
+//! call takes and returns a struct mixing integers, strings and a
+//! vector. Run with
+//! `cargo bench --bench transports --features json_transport,bincode_transport`.
+use criterion::measurement::WallTime;
+use criterion::{criterion_group, criterion_main, BenchmarkGroup, Criterion, Throughput};
+use essrpc::transports::{BincodeTransport, DuplexChannel, JSONTransport};
+use essrpc::{ClientTransport, MethodId, ServerTransport};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+struct Order {
+    id: u64,
+    quantity: i32,
+    price: f64,
+    customer: String,
+    note: String,
+    items: Vec<u32>,
+}
+
+const METHOD: MethodId = MethodId {
+    name: "place",
+    num: 0,
+};
+
+// Channels of the client and server ends of a transport
+trait Ends {
+    fn duplex(&self) -> &DuplexChannel;
+}
+
+impl Ends for JSONTransport<DuplexChannel> {
+    fn duplex(&self) -> &DuplexChannel {
+        self.channel()
+    }
+}
+
+impl Ends for BincodeTransport<DuplexChannel> {
+    fn duplex(&self) -> &DuplexChannel {
+        self.channel()
+    }
+}
+
+// One call to `place(order, priority)`, answered with the order
+// itself. Returns the sizes of the request and the response.
+fn call<C, S>(client: &mut C, server: &mut S, order: &Order) -> (usize, usize)
+where
+    C: ClientTransport + Ends,
+    S: ServerTransport + Ends,
+{
+    let mut state = client.tx_begin_call(METHOD).unwrap();
+    client.tx_add_param("order", order, &mut state).unwrap();
+    client.tx_add_param("priority", 3u8, &mut state).unwrap();
+    let state = client.tx_finalize(state).unwrap();
+    let request = server.duplex().pending();
+
+    let (_, mut rxstate) = server.rx_begin_call().unwrap();
+    let received: Order = server.rx_read_param("order", &mut rxstate).unwrap();
+    let _: u8 = server.rx_read_param("priority", &mut rxstate).unwrap();
+    server
+        .tx_response(Ok::<_, String>(received), rxstate)
+        .unwrap();
+    let response = client.duplex().pending();
+
+    let result: Result<Order, String> = client.rx_response(state).unwrap();
+    assert_eq!(result.as_ref(), Ok(order));
+    (request, response)
+}
+
+// Benchmark calls through one transport, after printing the sizes
+// of the request and response
+fn bench_calls<C, S>(
+    group: &mut BenchmarkGroup<WallTime>,
+    name: &str,
+    mut client: C,
+    mut server: S,
+    order: &Order,
+) where
+    C: ClientTransport + Ends,
+    S: ServerTransport + Ends,
+{
+    let (request, response) = call(&mut client, &mut server, order);
+    println!(
+        "{}: {} byte request, {} byte response",
+        name, request, response
+    );
+    group.throughput(Throughput::Bytes((request + response) as u64));
+    group.bench_function(name, |b| b.iter(|| call(&mut client, &mut server, order)));
+}
+
+fn transports(c: &mut Criterion) {
+    let order = Order {
+        id: 1_234_567_890,
+        quantity: -42,
+        price: 19.99,
+        customer: "Ada Lovelace".to_string(),
+        note: "leave at the door, ring twice".to_string(),
+        items: (0..64).map(|i| i * 7919).collect(),
+    };
+    let mut group = c.benchmark_group("call");
+    let (c, s) = DuplexChannel::pair();
+    bench_calls(
+        &mut group,
+        "json",
+        JSONTransport::new(c),
+        JSONTransport::new(s),
+        &order,
+    );
+    let (c, s) = DuplexChannel::pair();
+    bench_calls(
+        &mut group,
+        "bincode",
+        BincodeTransport::new(c),
+        BincodeTransport::new(s),
+        &order,
+    );
+    group.finish();
+}
+
+criterion_group!(benches, transports);
+criterion_main!(benches);
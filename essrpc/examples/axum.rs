@@ -0,0 +1,92 @@
+//! A JSON-RPC service mounted at /rpc in an axum app on
+//! 127.0.0.1:5001, alongside an ordinary route, called over HTTP by
+//! an essrpc client. Run with
+//!
+//! ```text
+//! cargo run --example axum --features http_server,http_transport
+//! ```
+use std::fmt;
+use std::sync::Arc;
+
+use axum::body::{Body, Bytes};
+use axum::routing::{get, post};
+use axum::Router;
+use serde::{Deserialize, Serialize};
+
+use essrpc::essrpc;
+use essrpc::transports::{HTTPService, HTTPTransport};
+use essrpc::{RPCClient, RPCError};
+
+const ADDR: &str = "127.0.0.1:5001";
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct FooError {
+    msg: String,
+}
+
+impl fmt::Display for FooError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "error: {}", self.msg)
+    }
+}
+
+impl std::error::Error for FooError {}
+
+impl From<RPCError> for FooError {
+    fn from(error: RPCError) -> Self {
+        FooError {
+            msg: format!("{}", error),
+        }
+    }
+}
+
+#[essrpc]
+pub trait Foo {
+    fn bar(&self, a: String, b: i32) -> Result<String, FooError>;
+}
+
+struct FooImpl;
+
+impl Foo for FooImpl {
+    fn bar(&self, a: String, b: i32) -> Result<String, FooError> {
+        Ok(format!("{} is {}", a, b))
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), RPCError> {
+    let service = Arc::new(HTTPService::new(|transport| {
+        FooRPCServer::new(FooImpl, transport)
+    }));
+    let app = Router::new()
+        .route("/health", get(|| async { "ok" }))
+        .route(
+            "/rpc",
+            post(move |body: Bytes| async move {
+                // Calls are served synchronously, so off the runtime's
+                // own threads
+                let response = tokio::task::spawn_blocking(move || service.respond(body))
+                    .await
+                    .expect("server panicked");
+                response.map(Body::from)
+            }),
+        );
+    let listener = tokio::net::TcpListener::bind(ADDR)
+        .await
+        .expect("bind failed");
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    tokio::task::spawn_blocking(move || {
+        let url = format!("http://{}/rpc", ADDR);
+        let client = FooRPCClient::new(HTTPTransport::connect(&url)?);
+        for b in 40..43 {
+            match client.bar("the answer".to_string(), b) {
+                Ok(result) => println!("{}", result),
+                Err(e) => println!("{}", e),
+            }
+        }
+        Ok(())
+    })
+    .await
+    .expect("client panicked")
+}
@@ -0,0 +1,86 @@
+//! A JSON-RPC server on 127.0.0.1:5000 which stops gracefully once
+//! its client is done, finishing the call in progress on each
+//! connection. Run with
+//!
+//! ```text
+//! cargo run --example shutdown --features async_tcp
+//! ```
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+
+use essrpc::essrpc;
+use essrpc::transports::{JSONAsyncServerTransport, JSONTransport};
+use essrpc::{RPCClient, RPCError};
+
+const ADDR: &str = "127.0.0.1:5000";
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct FooError {
+    msg: String,
+}
+
+impl fmt::Display for FooError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "error: {}", self.msg)
+    }
+}
+
+impl std::error::Error for FooError {}
+
+impl From<RPCError> for FooError {
+    fn from(error: RPCError) -> Self {
+        FooError {
+            msg: format!("{}", error),
+        }
+    }
+}
+
+#[essrpc(sync, async_server)]
+pub trait Foo {
+    fn bar(&self, a: String, b: i32) -> Result<String, FooError>;
+}
+
+struct FooImpl;
+
+impl Foo for FooImpl {
+    fn bar(&self, a: String, b: i32) -> Result<String, FooError> {
+        Ok(format!("{} is {}", a, b))
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), RPCError> {
+    let listener = tokio::net::TcpListener::bind(ADDR)
+        .await
+        .expect("bind failed");
+    let (stop, stopped) = oneshot::channel::<()>();
+
+    // Async servers are not Send, so run on a LocalSet
+    let local = tokio::task::LocalSet::new();
+    let serving = local.spawn_local(JSONAsyncServerTransport::serve_listener_with_shutdown(
+        listener,
+        |transport| FooAsyncRPCServer::new(FooImpl, transport),
+        async {
+            // A dropped sender stops the server too
+            let _ = stopped.await;
+        },
+    ));
+
+    let client = tokio::task::spawn_blocking(move || {
+        let client = FooRPCClient::new(JSONTransport::connect_tcp(ADDR)?);
+        for b in 40..43 {
+            match client.bar("the answer".to_string(), b) {
+                Ok(result) => println!("{}", result),
+                Err(e) => println!("{}", e),
+            }
+        }
+        let _ = stop.send(());
+        Ok::<_, RPCError>(())
+    });
+    local.run_until(client).await.expect("client panicked")?;
+    local.run_until(serving).await.expect("server panicked")?;
+    println!("server stopped");
+    Ok(())
+}
@@ -0,0 +1,107 @@
+//! A client and server talking JSON-RPC over TLS, using the
+//! self-signed certificate for `localhost` in this directory. Run
+//! with
+//!
+//! ```text
+//! cargo run --example tls --features tls
+//! ```
+use std::fmt;
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::thread;
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+use serde::{Deserialize, Serialize};
+
+use essrpc::essrpc;
+use essrpc::transports::{JSONTlsAcceptor, JSONTransport};
+use essrpc::{RPCClient, RPCError, RPCServer};
+
+const CERT: &[u8] = include_bytes!("cert.pem");
+const KEY: &[u8] = include_bytes!("key.pem");
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct FooError {
+    msg: String,
+}
+
+impl fmt::Display for FooError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "error: {}", self.msg)
+    }
+}
+
+impl std::error::Error for FooError {}
+
+impl From<RPCError> for FooError {
+    fn from(error: RPCError) -> Self {
+        FooError {
+            msg: format!("{}", error),
+        }
+    }
+}
+
+#[essrpc]
+pub trait Foo {
+    fn bar(&self, a: String, b: i32) -> Result<String, FooError>;
+}
+
+struct FooImpl;
+
+impl Foo for FooImpl {
+    fn bar(&self, a: String, b: i32) -> Result<String, FooError> {
+        Ok(format!("{} is {}", a, b))
+    }
+}
+
+fn server_config() -> ServerConfig {
+    let cert = CertificateDer::from_pem_slice(CERT).expect("bad certificate");
+    let key = PrivateKeyDer::from_pem_slice(KEY).expect("bad key");
+    ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert], key)
+        .expect("unusable certificate")
+}
+
+fn client_config() -> ClientConfig {
+    // Trust the server's self-signed certificate directly
+    let mut roots = RootCertStore::empty();
+    roots
+        .add(CertificateDer::from_pem_slice(CERT).expect("bad certificate"))
+        .expect("unusable certificate");
+    ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth()
+}
+
+fn main() -> Result<(), RPCError> {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind failed");
+    let addr = listener.local_addr().expect("no local address");
+    let acceptor = JSONTlsAcceptor::new(Arc::new(server_config()));
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let transport = match acceptor.accept(stream.expect("accept failed")) {
+                Ok(transport) => transport,
+                Err(e) => {
+                    println!("{}", e);
+                    continue;
+                }
+            };
+            thread::spawn(move || FooRPCServer::new(FooImpl, transport).serve_loop());
+        }
+    });
+
+    let transport = JSONTransport::connect_tls(addr, "localhost", Arc::new(client_config()))?;
+    let client = FooRPCClient::new(transport);
+    match client.bar("the answer".to_string(), 42) {
+        Ok(result) => println!("{}", result),
+        Err(e) => println!("{}", e),
+    }
+    Ok(())
+}
@@ -0,0 +1,78 @@
+//! A client and server talking JSON-RPC over the Unix domain socket
+//! `/tmp/foo.sock`. Run with
+//!
+//! ```text
+//! cargo run --example unix_socket --features unix
+//! ```
+use std::fmt;
+use std::fs;
+use std::io;
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+
+use essrpc::essrpc;
+use essrpc::transports::JSONTransport;
+use essrpc::{RPCClient, RPCError, RPCServer};
+
+const SOCKET_PATH: &str = "/tmp/foo.sock";
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct FooError {
+    msg: String,
+}
+
+impl fmt::Display for FooError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "error: {}", self.msg)
+    }
+}
+
+impl std::error::Error for FooError {}
+
+impl From<RPCError> for FooError {
+    fn from(error: RPCError) -> Self {
+        FooError {
+            msg: format!("{}", error),
+        }
+    }
+}
+
+#[essrpc]
+pub trait Foo {
+    fn bar(&self, a: String, b: i32) -> Result<String, FooError>;
+}
+
+struct FooImpl;
+
+impl Foo for FooImpl {
+    fn bar(&self, a: String, b: i32) -> Result<String, FooError> {
+        Ok(format!("{} is {}", a, b))
+    }
+}
+
+fn main() -> Result<(), RPCError> {
+    // Remove the socket left behind by an earlier run
+    match fs::remove_file(SOCKET_PATH) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => panic!("{}", e),
+        _ => (),
+    }
+
+    let listener = JSONTransport::bind_unix(SOCKET_PATH)?;
+    thread::spawn(move || {
+        for transport in listener.incoming() {
+            let transport = transport.expect("accept failed");
+            // One thread per client, serving until it disconnects
+            thread::spawn(move || FooRPCServer::new(FooImpl, transport).serve_loop());
+        }
+    });
+
+    let client = FooRPCClient::new(JSONTransport::connect_unix(SOCKET_PATH)?);
+    for b in 40..43 {
+        match client.bar("the answer".to_string(), b) {
+            Ok(result) => println!("{}", result),
+            Err(e) => println!("{}", e),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,127 @@
+//! Byte buffers which serialize compactly: as base64 strings in
+//! human-readable formats such as JSON, and as raw bytes in binary
+//! formats, rather than as a sequence of numbers. Base64 needs the
+//! "json_transport" feature; without it, bytes are serialized as raw
+//! bytes regardless.
+use std::fmt;
+use std::ops::Deref;
+
+#[cfg(feature = "base64")]
+use base64::engine::general_purpose::STANDARD;
+#[cfg(feature = "base64")]
+use base64::Engine;
+use serde::de::{self, Deserialize, Deserializer, SeqAccess, Visitor};
+use serde::ser::{Serialize, Serializer};
+
+/// Borrowed bytes, serialized compactly. Generated clients send
+/// parameters marked `#[essrpc(bytes)]` as this.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Bytes<'a>(pub &'a [u8]);
+
+impl<'a> Bytes<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Bytes(bytes)
+    }
+}
+
+impl Serialize for Bytes<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[cfg(feature = "base64")]
+        if serializer.is_human_readable() {
+            return serializer.serialize_str(&STANDARD.encode(self.0));
+        }
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+/// Owned bytes, serialized compactly. Generated servers receive
+/// parameters marked `#[essrpc(bytes)]` as this, and it may be used
+/// directly as a parameter or return type. Accepts a sequence of
+/// numbers too, as a `Vec<u8>` is sent without the attribute.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ByteBuf(pub Vec<u8>);
+
+impl ByteBuf {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        ByteBuf(bytes)
+    }
+
+    /// Consume the buffer, returning the bytes.
+    pub fn into_vec(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+impl Deref for ByteBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<Vec<u8>> for ByteBuf {
+    fn from(bytes: Vec<u8>) -> Self {
+        ByteBuf(bytes)
+    }
+}
+
+impl From<ByteBuf> for Vec<u8> {
+    fn from(bytes: ByteBuf) -> Self {
+        bytes.0
+    }
+}
+
+impl Serialize for ByteBuf {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Bytes(&self.0).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ByteBuf {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(ByteBufVisitor)
+        } else {
+            deserializer.deserialize_byte_buf(ByteBufVisitor)
+        }
+    }
+}
+
+struct ByteBufVisitor;
+
+impl<'de> Visitor<'de> for ByteBufVisitor {
+    type Value = ByteBuf;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if cfg!(feature = "base64") {
+            write!(f, "bytes, a base64 string or a sequence of bytes")
+        } else {
+            write!(f, "bytes or a sequence of bytes")
+        }
+    }
+
+    #[cfg(feature = "base64")]
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<ByteBuf, E> {
+        STANDARD
+            .decode(v)
+            .map(ByteBuf)
+            .map_err(|e| E::custom(format!("invalid base64: {}", e)))
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<ByteBuf, E> {
+        Ok(ByteBuf(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<ByteBuf, E> {
+        Ok(ByteBuf(v))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<ByteBuf, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+        while let Some(b) = seq.next_element()? {
+            bytes.push(b);
+        }
+        Ok(ByteBuf(bytes))
+    }
+}
@@ -44,6 +44,38 @@
 //! }
 //! ```
 //!
+//! # Errors
+//!
+//! An error returned by a method implementation travels to the
+//! client as a value of the method's declared error type, serialized
+//! with the rest of the response, so that the client gets back the
+//! very error the server returned, variant and fields included,
+//! rather than a description of it. The JSON transports send it as
+//! the `data` of a JSON-RPC error object with code -32000. Errors
+//! raised by essrpc itself, such as a lost connection or an unknown
+//! method, reach the client as an [RPCError](struct.RPCError.html)
+//! converted with the error type's `From<RPCError>`, so an error type
+//! may keep them apart with a variant of their own:
+//!
+//! ```ignore
+//! #[derive(Debug, Deserialize, Serialize)]
+//! pub enum AccountError {
+//!     Insufficient { balance: u64 },
+//!     Rpc(String),
+//! }
+//!
+//! impl From<RPCError> for AccountError {
+//!     fn from(error: RPCError) -> Self {
+//!         AccountError::Rpc(error.to_string())
+//!     }
+//! }
+//! ```
+//!
+//! `RPCError` converts to and from `std::io::Error`, so that `?`
+//! works in either direction when bridging with code dealing in I/O
+//! errors. The kinds are matched up as closely as they go, and an
+//! `RPCError` converted to an `io::Error` and back comes back whole.
+//!
 //! # Asynchronous Clients
 //!
 //! By default, the `#[essrpc]` attribute generates a synchronous
@@ -67,6 +99,172 @@
 //! `FooAsyncRPCClient` struct implementing both `FooAsync` and
 //! [AsyncRPCClient](trait.AsyncRPCClient.html).
 //!
+//! Dropping the future returned by a method, e.g. in a losing branch
+//! of `select!`, abandons the call, and leaves the client usable for
+//! others as far as its transport allows. The transports of
+//! `JSONAsyncClientTransport::from_buf_reader` and `MultiplexedClient`
+//! do, though the server may still execute an abandoned call; a
+//! `transact` closure given to `JSONAsyncClientTransport::new` must
+//! itself cope with its future being dropped.
+//! [timeout](fn.timeout.html) fails a call which takes too long, and
+//! `JSONAsyncClientTransport::with_timeout` does so for every call
+//! made through the transport, with the same caveats.
+//!
+//! # Asynchronous Servers
+//!
+//! With the `async_server` feature, `#[essrpc(async_server)]`
+//! additionally generates `FooAsyncRPCServer`, which implements
+//! [AsyncRPCServer](trait.AsyncRPCServer.html) on top of an
+//! [AsyncServerTransport](trait.AsyncServerTransport.html) such as
+//! `JSONAsyncServerTransport`. The trait methods themselves are still
+//! called synchronously, only the transport is awaited.
+//!
+//! ```ignore
+//! let transport = JSONAsyncServerTransport::new(stream);
+//! let mut s = FooAsyncRPCServer::new(FooImpl::new(), transport);
+//! s.serve().await
+//! ```
+//!
+//! # Asynchronous Trait Methods
+//!
+//! The methods of the trait may themselves be `async fn`, in which
+//! case the generated `FooRPCClient` requires an
+//! [AsyncClientTransport](trait.AsyncClientTransport.html) and
+//! implements `Foo` by awaiting it, and the generated
+//! `FooAsyncRPCServer` awaits each method of the implementation. No
+//! synchronous client or server is generated, so every method of
+//! such a trait must be async. This requires both the `async_client`
+//! and `async_server` features.
+//!
+//! ```ignore
+//! #[essrpc]
+//! pub trait Store {
+//!    async fn get(&self, key: String) -> Result<String, SomeError>;
+//! }
+//!
+//! let client = StoreRPCClient::new(JSONAsyncClientTransport::new(transact));
+//! let value = client.get("key".to_string()).await?;
+//! ```
+//!
+//! # Streaming Responses
+//!
+//! A method marked `#[essrpc(stream)]` returns an iterator, whose
+//! items the server sends one by one as the implementation produces
+//! them. On the client, the iterator yields each item as it
+//! arrives. Such methods must return `impl Iterator<Item =
+//! Result<T, E>>`, and need a transport implementing
+//! `rx_stream_item`, `tx_stream_item` and `tx_stream_end`, such as
+//! `JSONTransport`. Streaming is not supported by asynchronous
+//! clients or servers.
+//!
+//! Dropping the iterator before the stream ends cancels the rest of
+//! it. The client tells the server, which stops the implementation's
+//! iterator before its next item if its transport can look for the
+//! cancellation without blocking (see
+//! [JSONTransport::with_cancellation](transports/struct.JSONTransport.html#method.with_cancellation)),
+//! and skips whatever items were sent regardless.
+//!
+//! ```ignore
+//! #[essrpc]
+//! pub trait Logs {
+//!    #[essrpc(stream)]
+//!    fn tail(&self, lines: u32) -> impl Iterator<Item = Result<String, SomeError>>;
+//! }
+//!
+//! for line in client.tail(10) {
+//!     println!("{}", line?);
+//! }
+//! ```
+//!
+//! # Uploads
+//!
+//! A method's last parameter may be an [Upload](struct.Upload.html)
+//! marked `#[essrpc(upload)]`, for payloads too large to hold in
+//! memory. The client wraps any reader, and the transport sends what
+//! it reads in chunks after the other parameters. The server's method
+//! reads them from the channel as it consumes the upload, and the
+//! rest is skipped once it returns. This needs a transport
+//! implementing `tx_add_upload` and `rx_read_upload`, such as
+//! `BincodeTransport`; JSON messages are read whole, so
+//! `JSONTransport` refuses uploads. Uploads are not supported by
+//! asynchronous clients or servers, nor by streaming methods.
+//!
+//! ```ignore
+//! #[essrpc]
+//! pub trait Store {
+//!    fn put(&self, key: String, #[essrpc(upload)] data: Upload<'_>) -> Result<u64, SomeError>;
+//! }
+//!
+//! let stored = client.put("backup".to_string(), Upload::new(File::open(path)?))?;
+//! ```
+//!
+//! # Parameter Structs
+//!
+//! Each parameter is serialized on its own, so serde attributes on a
+//! parameter's type apply within that parameter only. With JSON
+//! named parameters, a struct parameter is sent as an object under
+//! the parameter's name, and the members of a `#[serde(flatten)]`
+//! field are lifted into that object, not into `params` alongside
+//! the other parameters.
+//!
+//! ```ignore
+//! #[derive(Deserialize, Serialize)]
+//! pub struct Query {
+//!     text: String,
+//!     #[serde(flatten)]
+//!     paging: Paging,
+//!     #[serde(default, skip_serializing_if = "Option::is_none")]
+//!     language: Option<String>,
+//! }
+//!
+//! #[essrpc]
+//! pub trait Search {
+//!    fn search(&self, query: Query, limit: u32) -> Result<Vec<String>, SomeError>;
+//! }
+//! ```
+//!
+//! Would send `"params": {"query": {"text": "rpc", "offset": 0,
+//! "count": 10}, "limit": 5}` over JSON, omitting `language` when it
+//! is `None`. `#[serde(flatten)]` and `#[serde(skip_serializing_if)]`
+//! need a self-describing format such as JSON. Bincode refuses to
+//! serialize a flattened struct, and reads a struct with a skipped
+//! field wrongly, as it cannot tell that the field is missing.
+//!
+//! # Named Return Values
+//!
+//! Methods may return several values as a tuple, which is sent as a
+//! sequence, e.g. a JSON array. Marking the method
+//! `#[essrpc(returns(...))]` with a name for each element sends it
+//! as a struct instead, e.g. a JSON object, which is easier to read
+//! and to consume from other languages. The client still receives
+//! the tuple.
+//!
+//! ```ignore
+//! #[essrpc]
+//! pub trait Inventory {
+//!    #[essrpc(returns(count, label))]
+//!    fn stock(&self, item: u32) -> Result<(u32, String), SomeError>;
+//! }
+//! ```
+//!
+//! Would send `{"count": 3, "label": "widgets"}` over JSON.
+//!
+//! # Notifications
+//!
+//! A method marked `#[essrpc(notification)]` is one-way: the client
+//! sends the call and returns as soon as it is transmitted, and the
+//! server calls the implementation without sending a response. Any
+//! error the implementation returns is discarded. Notification
+//! methods must return `Result<(), E>`.
+//!
+//! ```ignore
+//! #[essrpc]
+//! pub trait Telemetry {
+//!    #[essrpc(notification)]
+//!    fn record(&self, sample: f64) -> Result<(), SomeError>;
+//! }
+//! ```
+//!
 
 // We do not do doctests on the examples above because with all the
 // macros and generated code, it is simply too much effort to get things working.
@@ -80,14 +278,50 @@ extern crate essrpc_macros;
 // on the macro definition site, but this does not work properly on macros
 pub use essrpc_macros::essrpc;
 
+// Used by code generated for asynchronous servers
+#[cfg(feature = "async_server")]
+#[doc(hidden)]
+pub use async_trait::async_trait;
+
 use std::fmt;
+#[cfg(any(feature = "async_client", feature = "async_server"))]
 use std::future::Future;
+use std::io;
+#[cfg(any(feature = "async_client", feature = "async_server"))]
 use std::pin::Pin;
 
 use serde::{Deserialize, Serialize};
 
 pub mod transports;
 
+mod bytes;
+pub use bytes::{ByteBuf, Bytes};
+
+mod upload;
+pub use upload::Upload;
+
+mod returns;
+#[doc(hidden)]
+pub use returns::{NamedReturn, ReturnNames};
+
+mod trace;
+#[doc(hidden)]
+pub use trace::CallSpan;
+
+// Used by code generated to check each parameter type, so that one
+// which cannot be sent is reported at the parameter rather than deep
+// inside the generated client or server.
+#[doc(hidden)]
+#[diagnostic::on_unimplemented(
+    message = "RPC parameter type `{Self}` must implement serde::Serialize and serde::Deserialize",
+    label = "parameter type cannot be sent over RPC"
+)]
+pub trait RPCParam {}
+impl<T> RPCParam for T where for<'de> T: Serialize + Deserialize<'de> {}
+
+#[doc(hidden)]
+pub fn assert_param<T: RPCParam>() {}
+
 type Result<T> = std::result::Result<T, RPCError>;
 
 /// Identifies a method by both a name and an index. The Indices are
@@ -101,12 +335,63 @@ pub struct MethodId {
 
 /// Identifies a method by either a name or an index.
 /// Used when implementing [ServerTransport](trait.ServerTransport.html).
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum PartialMethodId {
     Name(String),
     Num(u32),
 }
 
+impl PartialMethodId {
+    /// The name of the method, for dispatching or logging calls
+    /// without a generated server. An index is looked up in `names`,
+    /// the method names in the order they are listed on the trait
+    /// (including any namespace), giving `None` if it is out of range.
+    ///
+    /// ```ignore
+    /// const METHODS: &[&str] = &["bar", "expect_error"];
+    /// let (method, state) = transport.rx_begin_call()?;
+    /// match method.name(METHODS) {
+    ///     Some("bar") => ...,
+    ///     _ => ...,
+    /// }
+    /// ```
+    pub fn name<'a>(&'a self, names: &[&'a str]) -> Option<&'a str> {
+        match self {
+            PartialMethodId::Name(name) => Some(name),
+            PartialMethodId::Num(num) => names.get(*num as usize).copied(),
+        }
+    }
+}
+
+/// Shows a name as is and an index as `#` followed by the index.
+impl fmt::Display for PartialMethodId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PartialMethodId::Name(name) => write!(f, "{}", name),
+            PartialMethodId::Num(num) => write!(f, "#{}", num),
+        }
+    }
+}
+
+/// Check run by a generated server on each call before it is
+/// dispatched, set with the server's `with_authorizer`. It is given
+/// the method called and the state returned by `rx_begin_call`, `S`,
+/// which carries whatever the transport knows of the call, such as
+/// [JRXState::meta](transports/struct.JRXState.html#method.meta)
+/// for JSON. Returning `Err` refuses the call with
+/// [reject_call](trait.ServerTransport.html#method.reject_call),
+/// without reading its parameters or calling the implementation.
+///
+/// ```ignore
+/// let server = FooRPCServer::new(FooImpl, JSONTransport::new(stream)).with_authorizer(
+///     |method, state: &JRXState| match state.meta().and_then(|meta| meta.get("token")) {
+///         Some(token) if token == "secret" => Ok(()),
+///         _ => Err(RPCError::new(RPCErrorKind::Application(-32001), format!("{} refused", method))),
+///     },
+/// );
+/// ```
+pub type Authorizer<S> = Box<dyn Fn(&PartialMethodId, &S) -> Result<()> + Send>;
+
 /// Trait for RPC transport (client). ESSRPC attempts to make as few
 /// assumptions about the transport as possible. A transport may work
 /// across a network, via any IPC mechanism, or purely in memory
@@ -131,7 +416,8 @@ pub trait ClientTransport {
     /// after `tx_begin_call` and to be called appropriately for each
     /// parameter of the method passed to `tx_begin_call`.  `state` is
     /// the object returned by `tx_begin_call`. Parameters are always
-    /// added and read in order, so transmitting the name is not a requirement.
+    /// added and read in the order the trait declares them, so
+    /// transmitting the name is not a requirement.
     fn tx_add_param(
         &mut self,
         name: &'static str,
@@ -150,6 +436,99 @@ pub trait ClientTransport {
     fn rx_response<T>(&mut self, state: Self::FinalState) -> Result<T>
     where
         for<'de> T: Deserialize<'de>;
+
+    /// Transmit a notification: a method call to which the server
+    /// sends no response. Used instead of `tx_finalize` for methods
+    /// marked `#[essrpc(notification)]`; `rx_response` is never
+    /// called. The default implementation calls `tx_finalize` and
+    /// discards its state, which suits transports that transmit the
+    /// call there.
+    fn tx_notify(&mut self, state: Self::TXState) -> Result<()> {
+        self.tx_finalize(state).map(|_| ())
+    }
+
+    /// Read the next item of the response to a method marked
+    /// `#[essrpc(stream)]`, in place of `rx_response`. Returns `None`
+    /// once the server has ended the stream. `state` is the object
+    /// returned by `tx_finalize`. The default implementation fails,
+    /// for transports which cannot stream.
+    fn rx_stream_item<T>(&mut self, _state: &mut Self::FinalState) -> Result<Option<T>>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        Err(unsupported_stream())
+    }
+
+    /// Cancel a streaming response which the client no longer wants,
+    /// asking the server to stop producing items. Called when the
+    /// [ClientStream](struct.ClientStream.html) is dropped before
+    /// the stream ends. Must leave the transport ready for the next
+    /// call, reading and discarding any items the server sent before
+    /// it stopped. The default implementation fails, leaving any
+    /// remaining items unread.
+    fn tx_cancel_stream(&mut self, _state: Self::FinalState) -> Result<()> {
+        Err(RPCError::new(
+            RPCErrorKind::Other,
+            "transport does not support cancelling streaming responses",
+        ))
+    }
+
+    /// Add the parameter marked `#[essrpc(upload)]`, sending the
+    /// bytes `upload` reads as they are read rather than serializing
+    /// them. Called in place of `tx_add_param`, after every other
+    /// parameter has been added. The default implementation fails,
+    /// for transports which cannot stream parameters.
+    fn tx_add_upload(
+        &mut self,
+        _name: &'static str,
+        _upload: Upload<'_>,
+        _state: &mut Self::TXState,
+    ) -> Result<()> {
+        Err(unsupported_upload())
+    }
+
+    /// Send `version`, from the trait's `#[essrpc(version = N)]`,
+    /// with the call begun with `tx_begin_call`, for the server to
+    /// check against the versions it serves. Generated clients of a
+    /// versioned trait call this before adding parameters. The
+    /// default implementation sends nothing, leaving the server
+    /// unable to check, for transports with nowhere to put it.
+    fn tx_set_service_version(&mut self, _version: u32, _state: &mut Self::TXState) -> Result<()> {
+        Ok(())
+    }
+
+    /// Fail the response to the call about to be finalized with an
+    /// error of kind `RPCErrorKind::Timeout` if it takes longer than
+    /// `timeout`, or with `None`, go back to the transport's own
+    /// timeout, if any. Generated clients call this around calls to
+    /// methods marked `#[essrpc(timeout_ms = ...)]`. The default
+    /// implementation fails, for transports which cannot time out a
+    /// call. Transports which can should leave themselves usable for
+    /// later calls, or fail them clearly, rather than take the late
+    /// response to a call which timed out for theirs.
+    fn tx_set_timeout(&mut self, _timeout: Option<std::time::Duration>) -> Result<()> {
+        Err(RPCError::new(
+            RPCErrorKind::Other,
+            "transport does not support per-call timeouts",
+        ))
+    }
+
+    /// The size in bytes of the response last read by `rx_response`
+    /// or `rx_stream_item`, for metrics such as those of
+    /// [ObservedTransport](transports/struct.ObservedTransport.html).
+    /// The default implementation returns `None`, for transports
+    /// which do not keep track of it.
+    fn rx_response_bytes(&self) -> Option<usize> {
+        None
+    }
+
+    /// The size in bytes of the request or notification last sent by
+    /// `tx_finalize` or `tx_notify`, counterpart to
+    /// `rx_response_bytes`. The default implementation returns
+    /// `None`, for transports which do not keep track of it.
+    fn tx_request_bytes(&self) -> Option<usize> {
+        None
+    }
 }
 
 #[cfg(feature = "async_client")]
@@ -171,7 +550,8 @@ pub trait AsyncClientTransport {
     /// after `tx_begin_call` and to be called appropriately for each
     /// parameter of the method passed to `tx_begin_call`.  `state` is
     /// the object returned by `tx_begin_call`. Parameters are always
-    /// added and read in order, so transmitting the name is not a requirement.
+    /// added and read in the order the trait declares them, so
+    /// transmitting the name is not a requirement.
     fn tx_add_param(
         &mut self,
         name: &'static str,
@@ -191,6 +571,21 @@ pub trait AsyncClientTransport {
     where
         for<'de> T: Deserialize<'de>,
         T: 'static;
+
+    /// Transmit a notification. See
+    /// [ClientTransport::tx_notify](trait.ClientTransport.html#method.tx_notify).
+    /// The default implementation calls `tx_finalize` and discards
+    /// its state, so transports which transmit only once the state
+    /// is awaited must override this.
+    fn tx_notify(&mut self, state: Self::TXState) -> BoxFuture<(), RPCError> {
+        Box::pin(std::future::ready(self.tx_finalize(state).map(|_| ())))
+    }
+
+    /// Send the trait's version with the call. See
+    /// [ClientTransport::tx_set_service_version](trait.ClientTransport.html#method.tx_set_service_version).
+    fn tx_set_service_version(&mut self, _version: u32, _state: &mut Self::TXState) -> Result<()> {
+        Ok(())
+    }
 }
 
 /// Trait for RPC transport (server). ESSRPC attempts to make as few
@@ -206,14 +601,323 @@ pub trait ServerTransport {
     /// name or identifier and internal state.
     fn rx_begin_call(&mut self) -> Result<(PartialMethodId, Self::RXState)>;
     /// Read a method parameter after a an `rx_begin_call`. Parameters
-    /// are always read in order, so some transports may choose to
-    /// ignore the name.
+    /// are always read in the order the trait declares them, the
+    /// same order in which the client added them, so some transports
+    /// may choose to ignore the name. Transports which do use it,
+    /// such as JSON with named parameters, need not preserve the
+    /// order on the wire.
     fn rx_read_param<T>(&mut self, name: &'static str, state: &mut Self::RXState) -> Result<T>
     where
         for<'de> T: serde::Deserialize<'de>;
 
-    /// Transmit a response (from the server side) to a method call.
-    fn tx_response(&mut self, value: impl Serialize) -> Result<()>;
+    /// Read a method parameter which the client may not have sent,
+    /// returning `None` if it is absent, e.g. because the client was
+    /// built against an older version of the trait. Used for
+    /// parameters marked `#[essrpc(default)]`. Transports which
+    /// cannot tell that a parameter is absent, such as bincode, keep
+    /// this default, which reads it as `rx_read_param` does.
+    fn rx_read_param_opt<T>(
+        &mut self,
+        name: &'static str,
+        state: &mut Self::RXState,
+    ) -> Result<Option<T>>
+    where
+        for<'de> T: serde::Deserialize<'de>,
+    {
+        self.rx_read_param(name, state).map(Some)
+    }
+
+    /// Read the parameter marked `#[essrpc(upload)]`, after every
+    /// other parameter, as an [Upload](struct.Upload.html) reading
+    /// its bytes from the channel as the method consumes them. The
+    /// upload borrows the transport until the method returns, and
+    /// when dropped must discard whatever the method left unread, so
+    /// that the next call can be read. The default implementation
+    /// fails, for transports which cannot stream parameters.
+    fn rx_read_upload(
+        &mut self,
+        _name: &'static str,
+        _state: &mut Self::RXState,
+    ) -> Result<Upload<'_>> {
+        Err(unsupported_upload())
+    }
+
+    /// Transmit a response (from the server side) to a method
+    /// call. `value` is the result returned by the method
+    /// implementation. Transports may encode success and failure
+    /// differently, but the client must be able to deserialize the
+    /// whole `Result` from the response. `state` is the object
+    /// returned by `rx_begin_call`.
+    fn tx_response(
+        &mut self,
+        value: std::result::Result<impl Serialize, impl Serialize>,
+        state: Self::RXState,
+    ) -> Result<()>;
+
+    /// Transmit an error which prevented the call from reaching the
+    /// method implementation, such as an unknown method. Transports
+    /// which can report such errors to the client should do so and
+    /// return `Ok`, allowing the server to continue serving. The
+    /// default implementation returns `error`, ending the call
+    /// without a response.
+    fn tx_error(&mut self, error: RPCError, _state: Self::RXState) -> Result<()> {
+        Err(error)
+    }
+
+    /// Refuse a call begun with `rx_begin_call` without reading its
+    /// parameters, e.g. because the caller may not make it, answering
+    /// it with `error` and discarding the rest of the request so that
+    /// the server can go on to the next call. Lets a dispatcher such
+    /// as a [Router](transports/struct.Router.html) handler gate calls
+    /// on the method, or on request metadata, before touching the
+    /// payload. The default implementation calls `tx_error`, which
+    /// suits transports reading the whole request in `rx_begin_call`,
+    /// such as JSON. Transports reading parameters from the channel
+    /// as they go, such as bincode, cannot skip the rest, so fail the
+    /// call as their `tx_error` does.
+    fn reject_call(&mut self, state: Self::RXState, error: RPCError) -> Result<()> {
+        self.tx_error(error, state)
+    }
+
+    /// Skip whatever is left of a request once reading one of its
+    /// parameters has failed, leaving the channel at the start of the
+    /// next request rather than partway through this one. Generated
+    /// servers call this before returning the error, so that a server
+    /// which carries on after a failed call is not left reading the
+    /// rest of the bad one as the next. The default implementation
+    /// does nothing, which suits transports reading the whole request
+    /// in `rx_begin_call`. A transport reading parameters from the
+    /// channel as it goes skips to the next frame boundary, or fails
+    /// if it has none, as bincode does.
+    fn discard_remaining(&mut self, _state: &mut Self::RXState) -> Result<()> {
+        Ok(())
+    }
+
+    /// The trait version the client sent with the call, as set by
+    /// [ClientTransport::tx_set_service_version](trait.ClientTransport.html#method.tx_set_service_version),
+    /// or `None` if it sent none. Generated servers of a versioned
+    /// trait refuse calls from other versions before dispatching
+    /// them. The default implementation returns `None`.
+    fn rx_service_version(&self, _state: &Self::RXState) -> Option<u32> {
+        None
+    }
+
+    /// Finish a call to which no response is sent, i.e. a method
+    /// marked `#[essrpc(notification)]`, in place of
+    /// `tx_response`. The default implementation does nothing.
+    fn tx_no_response(&mut self, _state: Self::RXState) -> Result<()> {
+        Ok(())
+    }
+
+    /// Write nothing to the channel from now on, serving every call
+    /// as a notification, for a server which answers no calls, such
+    /// as one reading from a write-only sink's other end. Besides
+    /// responses, this covers anything the transport answers by
+    /// itself, such as pings and malformed requests. Called by
+    /// generated servers made `notifications_only`. The default
+    /// implementation does nothing, which suits transports writing
+    /// only when the server asks them to.
+    fn set_notifications_only(&mut self) {}
+
+    /// Transmit one item of the response to a method marked
+    /// `#[essrpc(stream)]`. Called for each item the method produces,
+    /// followed by `tx_stream_end` in place of `tx_response`. The
+    /// default implementation fails, for transports which cannot
+    /// stream.
+    fn tx_stream_item(
+        &mut self,
+        _value: std::result::Result<impl Serialize, impl Serialize>,
+        _state: &mut Self::RXState,
+    ) -> Result<()> {
+        Err(unsupported_stream())
+    }
+
+    /// End the response to a method marked `#[essrpc(stream)]`,
+    /// after which the client sees no more items. The default
+    /// implementation fails, for transports which cannot stream.
+    fn tx_stream_end(&mut self, _state: Self::RXState) -> Result<()> {
+        Err(unsupported_stream())
+    }
+
+    /// Check, without waiting, whether the client has cancelled the
+    /// streaming response being sent (see
+    /// [ClientTransport::tx_cancel_stream](trait.ClientTransport.html#method.tx_cancel_stream)).
+    /// Called before each item, after which the stream is ended
+    /// early with `tx_stream_end` if it returns true. An error, such
+    /// as the client having disconnected, ends the call. The default
+    /// implementation returns false, for transports which cannot
+    /// tell.
+    fn rx_stream_cancelled(&mut self, _state: &mut Self::RXState) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+fn unsupported_stream() -> RPCError {
+    RPCError::new(
+        RPCErrorKind::Other,
+        "transport does not support streaming responses",
+    )
+}
+
+fn unsupported_upload() -> RPCError {
+    RPCError::new(
+        RPCErrorKind::Other,
+        "transport does not support upload parameters",
+    )
+}
+
+/// Iterator over the items of a streaming response, returned by the
+/// methods of generated clients marked `#[essrpc(stream)]`. Holds the
+/// client's transport until the stream ends, so the client cannot
+/// make other calls in the meantime. A transport error is yielded as
+/// the last item. Dropping the stream before it ends cancels the
+/// rest of it (see
+/// [ClientTransport::tx_cancel_stream](trait.ClientTransport.html#method.tx_cancel_stream)).
+pub struct ClientStream<'a, TR: ClientTransport, T, E> {
+    tr: std::cell::RefMut<'a, TR>,
+    state: std::result::Result<Option<TR::FinalState>, Option<RPCError>>,
+    item: std::marker::PhantomData<(T, E)>,
+}
+
+impl<'a, TR: ClientTransport, T, E> ClientStream<'a, TR, T, E> {
+    /// Stream the response to the call whose transmission ended with
+    /// `state`, the result of `tx_finalize`. Used by generated code.
+    pub fn new(tr: std::cell::RefMut<'a, TR>, state: Result<TR::FinalState>) -> Self {
+        ClientStream {
+            tr,
+            state: state.map(Some).map_err(Some),
+            item: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<'a, TR, T, E> Iterator for ClientStream<'a, TR, T, E>
+where
+    TR: ClientTransport,
+    for<'de> T: Deserialize<'de>,
+    for<'de> E: Deserialize<'de> + From<RPCError>,
+{
+    type Item = std::result::Result<T, E>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let state = match &mut self.state {
+            Ok(Some(state)) => state,
+            Ok(None) => return None,
+            Err(e) => return e.take().map(|e| Err(e.into())),
+        };
+        match self.tr.rx_stream_item(state) {
+            Ok(Some(item)) => Some(item),
+            Ok(None) => {
+                self.state = Ok(None);
+                None
+            }
+            Err(e) => {
+                self.state = Ok(None);
+                Some(Err(e.into()))
+            }
+        }
+    }
+}
+
+impl<'a, TR: ClientTransport, T, E> Drop for ClientStream<'a, TR, T, E> {
+    fn drop(&mut self) {
+        if let Ok(state) = &mut self.state {
+            if let Some(state) = state.take() {
+                // Nobody is left to tell if this fails, and the next
+                // call will fail in its turn if the transport is
+                // left unusable
+                let _ = self.tr.tx_cancel_stream(state);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "async_server")]
+/// Trait for RPC transport (server) to be used with asynchronous
+/// servers. Like [ServerTransport](trait.ServerTransport.html), except
+/// that reading and writing may await the underlying channel.
+#[async_trait::async_trait(?Send)]
+pub trait AsyncServerTransport {
+    /// Type of transport-internal state used when receiving a call on
+    /// the server. May be unit if the transport does not need to
+    /// track state or does so through member variables.
+    type RXState;
+
+    /// Begin reading a method cal on the server. Returns the method
+    /// name or identifier and internal state.
+    async fn rx_begin_call(&mut self) -> Result<(PartialMethodId, Self::RXState)>;
+    /// Read a method parameter after a an `rx_begin_call`. Parameters
+    /// are always read in the order the trait declares them, the
+    /// same order in which the client added them, so some transports
+    /// may choose to ignore the name. Transports which do use it,
+    /// such as JSON with named parameters, need not preserve the
+    /// order on the wire.
+    async fn rx_read_param<T>(
+        &mut self,
+        name: &'static str,
+        state: &mut Self::RXState,
+    ) -> Result<T>
+    where
+        for<'de> T: serde::Deserialize<'de>;
+
+    /// Read a method parameter which the client may not have sent,
+    /// as with
+    /// [ServerTransport::rx_read_param_opt](trait.ServerTransport.html#method.rx_read_param_opt).
+    async fn rx_read_param_opt<T>(
+        &mut self,
+        name: &'static str,
+        state: &mut Self::RXState,
+    ) -> Result<Option<T>>
+    where
+        for<'de> T: serde::Deserialize<'de>,
+    {
+        self.rx_read_param(name, state).await.map(Some)
+    }
+
+    /// Transmit a response (from the server side) to a method
+    /// call. See
+    /// [ServerTransport::tx_response](trait.ServerTransport.html#tymethod.tx_response).
+    async fn tx_response<T: Serialize, E: Serialize>(
+        &mut self,
+        value: std::result::Result<T, E>,
+        state: Self::RXState,
+    ) -> Result<()>;
+
+    /// Transmit an error which prevented the call from reaching the
+    /// method implementation. See
+    /// [ServerTransport::tx_error](trait.ServerTransport.html#method.tx_error).
+    async fn tx_error(&mut self, error: RPCError, _state: Self::RXState) -> Result<()> {
+        Err(error)
+    }
+
+    /// Refuse a call without reading its parameters. See
+    /// [ServerTransport::reject_call](trait.ServerTransport.html#method.reject_call).
+    async fn reject_call(&mut self, state: Self::RXState, error: RPCError) -> Result<()> {
+        self.tx_error(error, state).await
+    }
+
+    /// Skip whatever is left of a request once reading one of its
+    /// parameters has failed. See
+    /// [ServerTransport::discard_remaining](trait.ServerTransport.html#method.discard_remaining).
+    async fn discard_remaining(&mut self, _state: &mut Self::RXState) -> Result<()> {
+        Ok(())
+    }
+
+    /// The trait version the client sent with the call. See
+    /// [ServerTransport::rx_service_version](trait.ServerTransport.html#method.rx_service_version).
+    fn rx_service_version(&self, _state: &Self::RXState) -> Option<u32> {
+        None
+    }
+
+    /// Finish a call to which no response is sent. See
+    /// [ServerTransport::tx_no_response](trait.ServerTransport.html#method.tx_no_response).
+    async fn tx_no_response(&mut self, _state: Self::RXState) -> Result<()> {
+        Ok(())
+    }
+
+    /// Write nothing to the channel from now on. See
+    /// [ServerTransport::set_notifications_only](trait.ServerTransport.html#method.set_notifications_only).
+    fn set_notifications_only(&mut self) {}
 }
 
 /// Trait implemented by all RPC clients generated by the `essrpc`
@@ -273,6 +977,118 @@ pub trait RPCServer {
             self.serve_single_call()?;
         }
     }
+
+    /// Serve RPC calls until the client disconnects, which the
+    /// transport reports as RPCErrorKind::TransportEOF, and then
+    /// return `Ok`. Any other error is returned as is.
+    fn serve_loop(&mut self) -> Result<()> {
+        loop {
+            match self.serve_single_call() {
+                Ok(()) => (),
+                Err(e) if e.is_eof() => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "async_server")]
+/// Trait implemented by all asynchronous RPC servers generated by the
+/// `essrpc` macro when the `async_server` parameter is used. For a
+/// trait named `Foo`, the macro will generate `FooAsyncRPCServer`
+/// which implements `AsyncRPCServer` and may be constructed with any
+/// [AsyncServerTransport](trait.AsyncServerTransport.html).
+#[async_trait::async_trait(?Send)]
+pub trait AsyncRPCServer {
+    /// Serve a single RPC call.
+    async fn serve_single_call(&mut self) -> Result<()>;
+
+    /// Serve RPC calls indefinitely. The result will always be an
+    /// error, as it attempts to serve forever. It is recommended that
+    /// transport implementations return an error with
+    /// RPCErrorKind::TransportEOF when the client disconnects.
+    async fn serve(&mut self) -> Result<()> {
+        loop {
+            self.serve_single_call().await?;
+        }
+    }
+
+    /// Serve RPC calls until the client disconnects, which the
+    /// transport reports as RPCErrorKind::TransportEOF, and then
+    /// return `Ok`. Any other error is returned as is.
+    async fn serve_loop(&mut self) -> Result<()> {
+        loop {
+            match self.serve_single_call().await {
+                Ok(()) => (),
+                Err(e) if e.is_eof() => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Serve a single RPC call, unless `shutdown` completes while
+    /// waiting for one, in which case return `Ok(false)` without
+    /// waiting any longer. A call which has arrived is always served
+    /// to the end, response and all. Servers generated by the
+    /// `essrpc` macro wait for the call and `shutdown` together,
+    /// relying on the transport's `rx_begin_call` being cancel-safe,
+    /// as that of
+    /// [JSONAsyncServerTransport](transports/struct.JSONAsyncServerTransport.html)
+    /// is. By default, `shutdown` is only checked before waiting.
+    async fn serve_single_call_until(
+        &mut self,
+        mut shutdown: Pin<&mut dyn Future<Output = ()>>,
+    ) -> Result<bool> {
+        use futures::FutureExt;
+        if shutdown.as_mut().now_or_never().is_some() {
+            return Ok(false);
+        }
+        self.serve_single_call().await.map(|()| true)
+    }
+
+    /// Serve RPC calls until `shutdown` completes, finishing any call
+    /// in progress, or the client disconnects, and then return `Ok`.
+    /// Any other error is returned as is. Useful for stopping a
+    /// service gracefully:
+    ///
+    /// ```ignore
+    /// let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    /// let mut server = FooAsyncRPCServer::new(FooImpl::new(), transport);
+    /// let serving = server.serve_with_shutdown(async {
+    ///     let _ = stopped.await;
+    /// });
+    /// // Elsewhere, once it is time to stop
+    /// stop.send(()).unwrap();
+    /// ```
+    async fn serve_with_shutdown<F>(&mut self, shutdown: F) -> Result<()>
+    where
+        F: Future<Output = ()>,
+    {
+        let mut shutdown = Box::pin(shutdown);
+        loop {
+            match self.serve_single_call_until(shutdown.as_mut()).await {
+                Ok(true) => (),
+                Ok(false) => return Ok(()),
+                Err(e) if e.is_eof() => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+// Await `call` unless `shutdown` completes first. Used by generated
+// async servers waiting for their next call.
+#[cfg(feature = "async_server")]
+#[doc(hidden)]
+pub async fn until_shutdown<T>(
+    call: impl Future<Output = T>,
+    shutdown: Pin<&mut dyn Future<Output = ()>>,
+) -> Option<T> {
+    use futures::future::{select, Either};
+    match select(Box::pin(call), shutdown).await {
+        Either::Left((result, _)) => Some(result),
+        Either::Right(_) => None,
+    }
 }
 
 /// Generic serializable error with a description and optional
@@ -284,10 +1100,9 @@ pub struct GenericSerializableError {
 }
 impl GenericSerializableError {
     pub fn new(e: impl std::error::Error) -> Self {
-        let cause = match e.source() {
-            None => None,
-            Some(ec) => Some(Box::new(GenericSerializableError::from_dyn(ec))),
-        };
+        let cause = e
+            .source()
+            .map(|ec| Box::new(GenericSerializableError::from_dyn(ec)));
         GenericSerializableError {
             description: e.to_string(),
             cause,
@@ -299,10 +1114,9 @@ impl GenericSerializableError {
     /// `GenericSerializableError`), but the specific type and
     /// backtrace of the error are lost.
     pub fn from_dyn(e: &dyn std::error::Error) -> Self {
-        let cause = match e.source() {
-            None => None,
-            Some(ec) => Some(Box::new(GenericSerializableError::from_dyn(ec))),
-        };
+        let cause = e
+            .source()
+            .map(|ec| Box::new(GenericSerializableError::from_dyn(ec)));
         GenericSerializableError {
             description: e.to_string(),
             cause,
@@ -318,12 +1132,11 @@ impl std::error::Error for GenericSerializableError {
         }
     }
 }
+// The cause is left to `source`, so that error reporters walking the
+// chain do not show it twice.
 impl fmt::Display for GenericSerializableError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self.cause {
-            Some(ref e) => write!(f, "{} caused by:\n {}", self.description, e),
-            None => write!(f, "{}", self.description),
-        }
+        write!(f, "{}", self.description)
     }
 }
 
@@ -334,6 +1147,10 @@ pub struct RPCError {
     pub kind: RPCErrorKind,
     msg: String,
     cause: Option<Box<GenericSerializableError>>,
+    #[serde(default)]
+    method: Option<String>,
+    #[serde(default)]
+    param: Option<String>,
 }
 
 impl RPCError {
@@ -343,6 +1160,8 @@ impl RPCError {
             kind,
             msg: msg.into(),
             cause: None,
+            method: None,
+            param: None,
         }
     }
 
@@ -356,35 +1175,133 @@ impl RPCError {
             kind,
             msg: msg.into(),
             cause: Some(Box::new(GenericSerializableError::new(cause))),
+            method: None,
+            param: None,
         }
     }
 
     /// Get the cause of the error (if any).
     pub fn cause(&self) -> Option<&GenericSerializableError> {
-        match self.cause {
-            None => None,
-            Some(ref e) => Some(&e),
-        }
+        self.cause.as_deref()
+    }
+
+    /// Note the method being served when the error arose. Generated
+    /// servers do this for any error while serving a call, such as a
+    /// missing parameter.
+    pub fn with_method(mut self, method: impl Into<String>) -> Self {
+        self.method = Some(method.into());
+        self
+    }
+
+    /// Get the method being served when the error arose, if known.
+    pub fn method(&self) -> Option<&str> {
+        self.method.as_deref()
+    }
+
+    /// Note the parameter which was missing or could not be read.
+    pub fn with_param(mut self, param: impl Into<String>) -> Self {
+        self.param = Some(param.into());
+        self
+    }
+
+    /// Get the parameter which was missing or could not be read, if
+    /// known.
+    pub fn param(&self) -> Option<&str> {
+        self.param.as_deref()
+    }
+
+    /// Get the kind of the error.
+    pub fn kind(&self) -> RPCErrorKind {
+        self.kind
+    }
+
+    /// Whether the transport reached the end of its data, which for a
+    /// server usually means the client disconnected.
+    pub fn is_eof(&self) -> bool {
+        self.kind == RPCErrorKind::TransportEOF
+    }
+
+    /// Whether the transport gave up waiting for its peer.
+    pub fn is_timeout(&self) -> bool {
+        self.kind == RPCErrorKind::Timeout
     }
 }
 
+/// Shows the method, if known, the kind and the message. The cause,
+/// if any, is the error's `source`.
 impl fmt::Display for RPCError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(method) = &self.method {
+            write!(f, "in method {}: ", method)?;
+        }
+        write!(f, "{}: {}", self.kind, self.msg)
+    }
+}
+
+impl std::error::Error for RPCError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        #[allow(clippy::match_as_ref)] // clippy's suggestion doesn't compile
         match self.cause {
-            Some(ref e) => write!(f, "{} caused by:\n {}", self.msg, e),
-            None => write!(f, "{}", self.msg),
+            Some(ref e) => Some(e),
+            None => None,
         }
     }
 }
 
-impl std::error::Error for RPCError {}
+/// Classifies the error by its kind as a transport would: an
+/// unexpected EOF as `TransportEOF`, a timeout as `Timeout`, a
+/// refused connection as `ConnectionFailed` and anything else as
+/// `TransportError`, with the `io::Error` as the cause. An `io::Error`
+/// wrapping an `RPCError`, as made by the conversion the other way,
+/// gives back that `RPCError`.
+impl From<io::Error> for RPCError {
+    fn from(e: io::Error) -> Self {
+        if e.get_ref().is_some_and(|inner| inner.is::<RPCError>()) {
+            return *e.into_inner().unwrap().downcast().unwrap();
+        }
+        let kind = match e.kind() {
+            io::ErrorKind::UnexpectedEof => RPCErrorKind::TransportEOF,
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => RPCErrorKind::Timeout,
+            io::ErrorKind::ConnectionRefused => RPCErrorKind::ConnectionFailed,
+            _ => RPCErrorKind::TransportError,
+        };
+        RPCError::with_cause(kind, "I/O error", e)
+    }
+}
+
+/// Best-effort conversion, for passing an `RPCError` through code
+/// which expects I/O errors. The `io::Error` wraps the `RPCError`,
+/// with the nearest kind to its own: `UnexpectedEof` for
+/// `TransportEOF`, `TimedOut` for `Timeout`, `NotConnected` for
+/// `ConnectionFailed`, `InvalidData` for malformed or unserializable
+/// messages, `Unsupported` for an unknown method and `Other` for the
+/// rest.
+impl From<RPCError> for io::Error {
+    fn from(e: RPCError) -> Self {
+        let kind = match e.kind {
+            RPCErrorKind::TransportEOF => io::ErrorKind::UnexpectedEof,
+            RPCErrorKind::Timeout => io::ErrorKind::TimedOut,
+            RPCErrorKind::ConnectionFailed => io::ErrorKind::NotConnected,
+            RPCErrorKind::SerializationError
+            | RPCErrorKind::MessageTooLarge
+            | RPCErrorKind::ParseError
+            | RPCErrorKind::InvalidRequest
+            | RPCErrorKind::InvalidParams => io::ErrorKind::InvalidData,
+            RPCErrorKind::UnknownMethod => io::ErrorKind::Unsupported,
+            _ => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, e)
+    }
+}
 
 /// Types of [RPCError](trait.RPCError.html)
-#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub enum RPCErrorKind {
     /// Error caused by serialization or deserialization failure.
     SerializationError,
-    /// RPC server was asked to handle an unknown method.
+    /// RPC server was asked to handle an unknown method. Clients
+    /// also see this when the transport can report it, as JSON-RPC
+    /// does with code -32601.
     UnknownMethod,
     /// Error in underlying transport. This code
     /// will only be generated by specific transport implementations,
@@ -398,8 +1315,106 @@ pub enum RPCErrorKind {
     IllegalState,
     /// Other error.
     Other,
+    // Kinds are serialized by their position, e.g. by bincode, so
+    // new ones go last to keep the meaning of those sent by peers
+    // built before them.
+    /// A transport gave up waiting for data from its peer. This code
+    /// will only be generated by specific transport implementations,
+    /// never by the ESSRPC core.
+    Timeout,
+    /// A transport could not reach its peer: the address could not
+    /// be resolved, the connection was refused, or it failed while
+    /// being set up. The cause is the underlying OS error. This code
+    /// will only be generated by specific transport implementations,
+    /// never by the ESSRPC core.
+    ConnectionFailed,
+    /// TLS setup or the TLS handshake with the peer failed, for
+    /// instance because its certificate is not trusted. This code
+    /// will only be generated by specific transport implementations,
+    /// never by the ESSRPC core.
+    Tls,
+    /// A transport refused a message from its peer for exceeding the
+    /// configured size limit. This code will only be generated by
+    /// specific transport implementations, never by the ESSRPC core.
+    MessageTooLarge,
+    /// A message from the peer could not be parsed at all, e.g. it
+    /// was not valid JSON. JSON-RPC reports this with code -32700.
+    ParseError,
+    /// A message from the peer was well-formed but not a valid
+    /// request, e.g. a JSON value without a method. JSON-RPC reports
+    /// this with code -32600.
+    InvalidRequest,
+    /// A parameter of a request was missing or had the wrong type
+    /// for the method, as noted by
+    /// [RPCError::param](struct.RPCError.html#method.param). JSON-RPC
+    /// reports this with code -32602.
+    InvalidParams,
+    /// The server reported an error with the given numeric code
+    /// which could not be converted into the method's own error
+    /// type. Generated by transports which carry error codes, such as
+    /// JSON-RPC.
+    Application(i64),
+    /// The server does not serve the version of the trait the client
+    /// was built against, as set by `#[essrpc(version = N)]`. The
+    /// call is refused before it is dispatched. JSON-RPC reports this
+    /// with code -32010.
+    VersionMismatch,
+    /// The peer did not answer the greeting exchanged when a
+    /// connection is set up with
+    /// [JSONTransport::with_handshake](transports/struct.JSONTransport.html#method.with_handshake)
+    /// with its own, so is not essrpc, or speaks another version of
+    /// essrpc's protocol. This code will only be generated by specific
+    /// transport implementations, never by the ESSRPC core.
+    HandshakeFailed,
+}
+
+impl fmt::Display for RPCErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RPCErrorKind::SerializationError => write!(f, "serialization error"),
+            RPCErrorKind::UnknownMethod => write!(f, "unknown method"),
+            RPCErrorKind::TransportError => write!(f, "transport error"),
+            RPCErrorKind::TransportEOF => write!(f, "transport EOF"),
+            RPCErrorKind::IllegalState => write!(f, "illegal state"),
+            RPCErrorKind::Other => write!(f, "error"),
+            RPCErrorKind::Timeout => write!(f, "timeout"),
+            RPCErrorKind::ConnectionFailed => write!(f, "connection failed"),
+            RPCErrorKind::Tls => write!(f, "TLS error"),
+            RPCErrorKind::MessageTooLarge => write!(f, "message too large"),
+            RPCErrorKind::ParseError => write!(f, "parse error"),
+            RPCErrorKind::InvalidRequest => write!(f, "invalid request"),
+            RPCErrorKind::InvalidParams => write!(f, "invalid params"),
+            RPCErrorKind::Application(code) => write!(f, "application error {}", code),
+            RPCErrorKind::VersionMismatch => write!(f, "version mismatch"),
+            RPCErrorKind::HandshakeFailed => write!(f, "handshake failed"),
+        }
+    }
 }
 
 /// Type returned by async transport methods. A pinned dynamic-dispatch future.
 #[cfg(feature = "async_client")]
 pub type BoxFuture<T, E> = Pin<Box<dyn Future<Output = std::result::Result<T, E>>>>;
+
+/// Fail `future` with an error of kind `RPCErrorKind::Timeout` if it
+/// has not completed within `duration`, dropping it. Intended for the
+/// futures returned by asynchronous clients, whose calls are
+/// cancelled by dropping them. Works with any executor.
+#[cfg(feature = "async_client")]
+pub async fn timeout<T, E>(
+    future: impl Future<Output = std::result::Result<T, E>>,
+    duration: std::time::Duration,
+) -> std::result::Result<T, E>
+where
+    E: From<RPCError>,
+{
+    use futures::future::{select, Either};
+    let future = Box::pin(future);
+    match select(future, futures_timer::Delay::new(duration)).await {
+        Either::Left((result, _)) => result,
+        Either::Right(_) => Err(RPCError::new(
+            RPCErrorKind::Timeout,
+            format!("call did not complete within {:?}", duration),
+        )
+        .into()),
+    }
+}
@@ -0,0 +1,95 @@
+//! Support for methods marked `#[essrpc(returns(...))]`, whose tuple
+//! results are sent as structs with named fields.
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::{self, Deserialize, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+/// Names of the fields of a method's result, in tuple order.
+/// Implemented by generated code for methods marked
+/// `#[essrpc(returns(...))]`.
+pub trait ReturnNames {
+    const NAMES: &'static [&'static str];
+}
+
+/// A tuple `T` which serializes as a struct with the fields named by
+/// `N`. Deserializes from either a struct, whose fields may be in any
+/// order, or a sequence, as compact formats send structs.
+pub struct NamedReturn<T, N>(pub T, pub PhantomData<N>);
+
+impl<T, N> NamedReturn<T, N> {
+    pub fn new(value: T) -> Self {
+        NamedReturn(value, PhantomData)
+    }
+}
+
+// Name of the struct, for formats which record it
+const STRUCT_NAME: &str = "Returns";
+
+struct NamedReturnVisitor<T, N>(PhantomData<(T, N)>);
+
+macro_rules! named_return_impl {
+    ($len:expr => $($n:tt $t:ident $v:ident)+) => {
+        impl<$($t: Serialize,)+ N: ReturnNames> Serialize for NamedReturn<($($t,)+), N> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                let mut s = serializer.serialize_struct(STRUCT_NAME, $len)?;
+                $(s.serialize_field(N::NAMES[$n], &(self.0).$n)?;)+
+                s.end()
+            }
+        }
+
+        impl<'de, $($t: Deserialize<'de>,)+ N: ReturnNames> Deserialize<'de>
+            for NamedReturn<($($t,)+), N>
+        {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                deserializer.deserialize_struct(
+                    STRUCT_NAME,
+                    N::NAMES,
+                    NamedReturnVisitor::<($($t,)+), N>(PhantomData),
+                )
+            }
+        }
+
+        impl<'de, $($t: Deserialize<'de>,)+ N: ReturnNames> Visitor<'de>
+            for NamedReturnVisitor<($($t,)+), N>
+        {
+            type Value = NamedReturn<($($t,)+), N>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a struct with fields {:?}", N::NAMES)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                $(let $v = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length($n, &self))?;)+
+                Ok(NamedReturn::new(($($v,)+)))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                $(let mut $v: Option<$t> = None;)+
+                while let Some(key) = map.next_key::<String>()? {
+                    match N::NAMES.iter().position(|name| *name == key) {
+                        $(Some($n) => $v = Some(map.next_value()?),)+
+                        _ => {
+                            map.next_value::<IgnoredAny>()?;
+                        }
+                    }
+                }
+                Ok(NamedReturn::new(($(
+                    $v.ok_or_else(|| de::Error::missing_field(N::NAMES[$n]))?,
+                )+)))
+            }
+        }
+    };
+}
+
+named_return_impl!(1 => 0 T0 v0);
+named_return_impl!(2 => 0 T0 v0 1 T1 v1);
+named_return_impl!(3 => 0 T0 v0 1 T1 v1 2 T2 v2);
+named_return_impl!(4 => 0 T0 v0 1 T1 v1 2 T2 v2 3 T3 v3);
+named_return_impl!(5 => 0 T0 v0 1 T1 v1 2 T2 v2 3 T3 v3 4 T4 v4);
+named_return_impl!(6 => 0 T0 v0 1 T1 v1 2 T2 v2 3 T3 v3 4 T4 v4 5 T5 v5);
+named_return_impl!(7 => 0 T0 v0 1 T1 v1 2 T2 v2 3 T3 v3 4 T4 v4 5 T5 v5 6 T6 v6);
+named_return_impl!(8 => 0 T0 v0 1 T1 v1 2 T2 v2 3 T3 v3 4 T4 v4 5 T5 v5 6 T6 v6 7 T7 v7);
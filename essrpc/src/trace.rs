@@ -0,0 +1,82 @@
+// Spans around calls, with the "tracing" feature. Without it, these
+// do nothing and compile away.
+
+#[cfg(any(feature = "async_client", feature = "async_server"))]
+use std::future::Future;
+
+use crate::{PartialMethodId, Result};
+
+/// Span covering a single call, used by generated clients and
+/// servers. With the "tracing" feature, a client call is an
+/// `rpc.client` span and a served call an `rpc.server` span, both at
+/// the info level with `method` and `id` fields, and an error from the
+/// call is a warning event within its span. The `id` is recorded by
+/// transports which have one, such as JSON-RPC.
+#[doc(hidden)]
+pub struct CallSpan {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl CallSpan {
+    pub fn client(method: &'static str) -> Self {
+        #[cfg(not(feature = "tracing"))]
+        let _ = method;
+        CallSpan {
+            #[cfg(feature = "tracing")]
+            span: tracing::info_span!("rpc.client", method, id = tracing::field::Empty),
+        }
+    }
+
+    pub fn server(method: &PartialMethodId) -> Self {
+        #[cfg(not(feature = "tracing"))]
+        let _ = method;
+        CallSpan {
+            #[cfg(feature = "tracing")]
+            span: tracing::info_span!(
+                "rpc.server",
+                method = %method,
+                id = tracing::field::Empty
+            ),
+        }
+    }
+
+    /// Run `f` within the span.
+    pub fn in_scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        #[cfg(feature = "tracing")]
+        return self.span.in_scope(f);
+        #[cfg(not(feature = "tracing"))]
+        f()
+    }
+
+    /// Await `future` within the span, entering it each time the
+    /// future is polled rather than across awaits.
+    #[cfg(any(feature = "async_client", feature = "async_server"))]
+    pub async fn instrument<T>(&self, future: impl Future<Output = T>) -> T {
+        #[cfg(feature = "tracing")]
+        return tracing::Instrument::instrument(future, self.span.clone()).await;
+        #[cfg(not(feature = "tracing"))]
+        future.await
+    }
+
+    /// Record the outcome of the call, an event if it failed.
+    pub fn record_result<T>(&self, result: &Result<T>) {
+        #[cfg(feature = "tracing")]
+        if let Err(e) = result {
+            self.span
+                .in_scope(|| tracing::warn!(error = %e, "rpc call failed"));
+        }
+        #[cfg(not(feature = "tracing"))]
+        let _ = result;
+    }
+}
+
+// Record the id of the request being made or served in the current
+// span, if it is a call's.
+#[cfg(feature = "json_transport")]
+pub(crate) fn record_id(id: &impl std::fmt::Display) {
+    #[cfg(feature = "tracing")]
+    tracing::Span::current().record("id", tracing::field::display(id));
+    #[cfg(not(feature = "tracing"))]
+    let _ = id;
+}
@@ -0,0 +1,189 @@
+use std::io;
+use std::io::{Read, Write};
+
+use ciborium::value::Value;
+use serde::{Deserialize, Serialize};
+
+use super::flush_channel;
+use crate::{
+    ClientTransport, MethodId, PartialMethodId, RPCError, RPCErrorKind, Result, ServerTransport,
+};
+
+pub struct CTXState {
+    method: &'static str,
+    params: Vec<(Value, Value)>,
+}
+
+pub struct CRXState {
+    value: Value,
+}
+
+/// Transport implementation using CBOR serialization. Structured
+/// like [JSONTransport](struct.JSONTransport.html): parameters are
+/// sent by name and methods are dispatched by name, but the encoding
+/// is a compact binary one. Can be used over any `Read+Write` channel
+/// (local socket, internet socket, pipe, etc). Enable the
+/// "cbor_transport" feature to use this.
+pub struct CBORTransport<C: Read + Write> {
+    channel: C,
+}
+
+impl<C: Read + Write> CBORTransport<C> {
+    pub fn new(channel: C) -> Self {
+        CBORTransport { channel }
+    }
+
+    /// Get the underlying read/write channel
+    pub fn channel(&self) -> &C {
+        &self.channel
+    }
+
+    /// Get a mutable reference to the underlying read/write channel,
+    /// e.g. to adjust socket options.
+    pub fn channel_mut(&mut self) -> &mut C {
+        &mut self.channel
+    }
+
+    /// Consume the transport, returning the underlying read/write
+    /// channel. Useful for reusing a connection for something else
+    /// once RPC is finished with it.
+    pub fn into_channel(self) -> C {
+        self.channel
+    }
+
+    fn serialize(&mut self, value: impl Serialize) -> Result<()> {
+        ciborium::ser::into_writer(&value, Write::by_ref(&mut self.channel)).map_err(convert_error)
+    }
+
+    // Deserialize a value from the channel
+    fn deserialize<T>(&mut self) -> Result<T>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        ciborium::de::from_reader(Read::by_ref(&mut self.channel)).map_err(|e| match e {
+            ciborium::de::Error::Io(ref ioe) if ioe.kind() == io::ErrorKind::UnexpectedEof => {
+                RPCError::new(
+                    RPCErrorKind::TransportEOF,
+                    "EOF during cbor deserialization",
+                )
+            }
+            _ => convert_error(e),
+        })
+    }
+}
+
+fn convert_error(e: impl std::error::Error) -> RPCError {
+    RPCError::with_cause(
+        RPCErrorKind::SerializationError,
+        "cbor serialization or deserialization failed",
+        e,
+    )
+}
+
+fn not_expected_map() -> RPCError {
+    RPCError::new(RPCErrorKind::SerializationError, "cbor is not expected map")
+}
+
+// Look up a text key in a cbor map
+fn map_get<'a>(map: &'a [(Value, Value)], key: &str) -> Option<&'a Value> {
+    map.iter()
+        .find(|(k, _)| k.as_text() == Some(key))
+        .map(|(_, v)| v)
+}
+
+impl<C: Read + Write> ClientTransport for CBORTransport<C> {
+    type TXState = CTXState;
+    type FinalState = ();
+
+    fn tx_begin_call(&mut self, method: MethodId) -> Result<CTXState> {
+        Ok(CTXState {
+            method: method.name,
+            params: Vec::new(),
+        })
+    }
+
+    fn tx_add_param(
+        &mut self,
+        name: &'static str,
+        value: impl Serialize,
+        state: &mut CTXState,
+    ) -> Result<()> {
+        let value = Value::serialized(&value).map_err(convert_error)?;
+        state.params.push((Value::from(name), value));
+        Ok(())
+    }
+
+    fn tx_finalize(&mut self, state: CTXState) -> Result<()> {
+        self.serialize(Value::Map(vec![
+            (Value::from("method"), Value::from(state.method)),
+            (Value::from("params"), Value::Map(state.params)),
+        ]))?;
+        flush_channel(&mut self.channel)
+    }
+
+    fn rx_response<T>(&mut self, _state: ()) -> Result<T>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        self.deserialize()
+    }
+}
+
+impl<C: Read + Write> ServerTransport for CBORTransport<C> {
+    type RXState = CRXState;
+
+    fn rx_begin_call(&mut self) -> Result<(PartialMethodId, CRXState)> {
+        let value: Value = self.deserialize()?;
+        let method = map_get(value.as_map().ok_or_else(not_expected_map)?, "method")
+            .ok_or_else(not_expected_map)?
+            .as_text()
+            .ok_or_else(|| {
+                RPCError::new(
+                    RPCErrorKind::SerializationError,
+                    "cbor method was not string",
+                )
+            })?
+            .to_string();
+        Ok((PartialMethodId::Name(method), CRXState { value }))
+    }
+
+    fn rx_read_param<T>(&mut self, name: &'static str, state: &mut CRXState) -> Result<T>
+    where
+        for<'de> T: serde::Deserialize<'de>,
+    {
+        self.rx_read_param_opt(name, state)?.ok_or_else(|| {
+            RPCError::new(
+                RPCErrorKind::SerializationError,
+                format!("parameters do not contain {}", name),
+            )
+        })
+    }
+
+    fn rx_read_param_opt<T>(
+        &mut self,
+        name: &'static str,
+        state: &mut CRXState,
+    ) -> Result<Option<T>>
+    where
+        for<'de> T: serde::Deserialize<'de>,
+    {
+        let params = state
+            .value
+            .as_map()
+            .and_then(|m| map_get(m, "params"))
+            .and_then(|p| p.as_map())
+            .ok_or_else(not_expected_map)?;
+        map_get(params, name)
+            .map(|v| v.deserialized().map_err(convert_error))
+            .transpose()
+    }
+
+    fn tx_response(
+        &mut self,
+        value: std::result::Result<impl Serialize, impl Serialize>,
+        _state: CRXState,
+    ) -> Result<()> {
+        self.serialize(value)?;
+        flush_channel(&mut self.channel)
+    }
+}
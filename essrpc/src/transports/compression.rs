@@ -0,0 +1,246 @@
+use std::io;
+use std::io::{Read, Write};
+use std::time::Duration;
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+
+use super::ReadTimeout;
+
+// First byte written by a compressing writer. Deflate data follows.
+// Chosen so that it cannot begin a JSON document, allowing a reader
+// to tell a compressed peer from an uncompressed one.
+const MAGIC: u8 = 0xce;
+
+const BUF_SIZE: usize = 8 * 1024;
+
+// How the incoming stream is encoded, known once its first byte has
+// been read.
+enum ReadMode {
+    Unknown,
+    // The peer does not compress. Holds the first byte read, if it
+    // has not been handed out yet.
+    Plain(Option<u8>),
+    Compressed,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum WriteMode {
+    Compressed,
+    // Compress only if the peer does. Decided at the first write.
+    Mirror,
+    Plain,
+}
+
+/// `Read+Write` channel adapter which compresses everything written
+/// to the underlying channel with deflate and decompresses everything
+/// read from it. Wrap the channel before handing it to a transport,
+/// e.g. `JSONTransport::new(CompressedChannel::new(tcp))`. Enable the
+/// "compression" feature to use this.
+///
+/// Compression is done on the channel rather than by wrapping a
+/// transport because a transport never sees the bytes of a message,
+/// only the values being serialized. The channel sees the bytes of
+/// every transport, so any transport may be compressed this
+/// way. Transports flush the channel after each message, which ends a
+/// deflate block so the peer can decode the message without waiting
+/// for more data.
+///
+/// A compressing writer begins its stream with a single header
+/// byte. The reader checks for it and passes the stream through
+/// unchanged if it is absent, so a compressed peer may read from an
+/// uncompressed one. A server created with
+/// [mirror](#method.mirror) goes further and compresses its responses
+/// only if the client compresses its requests, so it may serve both
+/// old and new clients. Detecting an uncompressed peer relies on the
+/// header byte never beginning an uncompressed stream, which holds for
+/// JSON but not for binary encodings such as bincode.
+pub struct CompressedChannel<C: Read + Write> {
+    channel: C,
+    read_mode: ReadMode,
+    write_mode: WriteMode,
+    header_written: bool,
+    decompress: Decompress,
+    compress: Compress,
+    // Compressed bytes read from the channel and the position of the
+    // first not yet decompressed.
+    inbuf: Vec<u8>,
+    inpos: usize,
+}
+
+impl<C: Read + Write> CompressedChannel<C> {
+    /// Compress everything written. Reads accept compressed or
+    /// uncompressed data.
+    pub fn new(channel: C) -> Self {
+        Self::with_write_mode(channel, WriteMode::Compressed)
+    }
+
+    /// Compress what is written only if what is read is compressed,
+    /// which is decided by the first read. For servers, which always
+    /// read a request before writing.
+    pub fn mirror(channel: C) -> Self {
+        Self::with_write_mode(channel, WriteMode::Mirror)
+    }
+
+    fn with_write_mode(channel: C, write_mode: WriteMode) -> Self {
+        CompressedChannel {
+            channel,
+            read_mode: ReadMode::Unknown,
+            write_mode,
+            header_written: false,
+            decompress: Decompress::new(false),
+            compress: Compress::new(Compression::default(), false),
+            inbuf: Vec::new(),
+            inpos: 0,
+        }
+    }
+
+    /// Get the underlying read/write channel
+    pub fn channel(&self) -> &C {
+        &self.channel
+    }
+
+    /// Get a mutable reference to the underlying read/write channel,
+    /// e.g. to adjust socket options.
+    pub fn channel_mut(&mut self) -> &mut C {
+        &mut self.channel
+    }
+
+    // Read the first byte of the stream to learn whether the peer
+    // compresses. Returns false at EOF.
+    fn detect(&mut self) -> io::Result<bool> {
+        let mut first = [0u8];
+        if self.channel.read(&mut first)? == 0 {
+            return Ok(false);
+        }
+        self.read_mode = if first[0] == MAGIC {
+            ReadMode::Compressed
+        } else {
+            ReadMode::Plain(Some(first[0]))
+        };
+        Ok(true)
+    }
+
+    fn read_compressed(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            // The decompressor may hold output from input it has
+            // already consumed, so it is asked even when no input is
+            // buffered.
+            let before_in = self.decompress.total_in();
+            let before_out = self.decompress.total_out();
+            let status = self
+                .decompress
+                .decompress(&self.inbuf[self.inpos..], buf, FlushDecompress::None)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let consumed = (self.decompress.total_in() - before_in) as usize;
+            let produced = (self.decompress.total_out() - before_out) as usize;
+            self.inpos += consumed;
+            if produced > 0 || status == Status::StreamEnd {
+                return Ok(produced);
+            }
+            if consumed > 0 {
+                continue;
+            }
+            // Buffered input is not enough to produce output, so more
+            // is needed from the channel.
+            self.inbuf.drain(..self.inpos);
+            self.inpos = 0;
+            let start = self.inbuf.len();
+            self.inbuf.resize(start + BUF_SIZE, 0);
+            let n = match self.channel.read(&mut self.inbuf[start..]) {
+                Ok(n) => n,
+                Err(e) => {
+                    self.inbuf.truncate(start);
+                    return Err(e);
+                }
+            };
+            self.inbuf.truncate(start + n);
+            if n == 0 {
+                return Ok(0);
+            }
+        }
+    }
+
+    // Compress `input` and write the result to the channel.
+    fn deflate(&mut self, mut input: &[u8], flush: FlushCompress) -> io::Result<()> {
+        let mut out = Vec::with_capacity(BUF_SIZE);
+        loop {
+            out.clear();
+            let before_in = self.compress.total_in();
+            self.compress
+                .compress_vec(input, &mut out, flush)
+                .map_err(io::Error::other)?;
+            input = &input[(self.compress.total_in() - before_in) as usize..];
+            self.channel.write_all(&out)?;
+            // Output which did not fill the buffer means the
+            // compressor has nothing more to give for now.
+            if input.is_empty() && out.len() < out.capacity() {
+                return Ok(());
+            }
+        }
+    }
+
+    // Decide at the first write whether to compress, writing the
+    // header byte if so.
+    fn compressing(&mut self) -> io::Result<bool> {
+        if self.write_mode == WriteMode::Mirror {
+            self.write_mode = match self.read_mode {
+                ReadMode::Compressed => WriteMode::Compressed,
+                _ => WriteMode::Plain,
+            };
+        }
+        if self.write_mode == WriteMode::Compressed && !self.header_written {
+            self.channel.write_all(&[MAGIC])?;
+            self.header_written = true;
+        }
+        Ok(self.write_mode == WriteMode::Compressed)
+    }
+}
+
+impl<C: Read + Write> Read for CompressedChannel<C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if let ReadMode::Unknown = self.read_mode {
+            if !self.detect()? {
+                return Ok(0);
+            }
+        }
+        match &mut self.read_mode {
+            ReadMode::Plain(first) => match first.take() {
+                Some(b) => {
+                    buf[0] = b;
+                    Ok(1)
+                }
+                None => self.channel.read(buf),
+            },
+            ReadMode::Compressed => self.read_compressed(buf),
+            ReadMode::Unknown => unreachable!(),
+        }
+    }
+}
+
+impl<C: Read + Write> Write for CompressedChannel<C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.compressing()? {
+            return self.channel.write(buf);
+        }
+        self.deflate(buf, FlushCompress::None)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // Nothing compressed has been written yet unless the header
+        // has been.
+        if self.header_written {
+            self.deflate(&[], FlushCompress::Sync)?;
+        }
+        self.channel.flush()
+    }
+}
+
+impl<C: Read + Write + ReadTimeout> ReadTimeout for CompressedChannel<C> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.channel.set_read_timeout(timeout)
+    }
+}
@@ -0,0 +1,191 @@
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::io;
+use std::io::{Read, Write};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "json_transport")]
+use super::JSONTransport;
+use super::{ReadReady, ReadTimeout};
+
+// Bytes travelling in one direction between the two ends.
+struct Pipe {
+    buf: VecDeque<u8>,
+    // Set when either end is dropped
+    closed: bool,
+}
+
+struct Shared {
+    pipe: Mutex<Pipe>,
+    ready: Condvar,
+}
+
+impl Shared {
+    fn new() -> Arc<Self> {
+        Arc::new(Shared {
+            pipe: Mutex::new(Pipe {
+                buf: VecDeque::new(),
+                closed: false,
+            }),
+            ready: Condvar::new(),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Pipe> {
+        // The pipe is never left inconsistent, so a panic while it
+        // was locked does no harm.
+        self.pipe.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn close(&self) {
+        self.lock().closed = true;
+        self.ready.notify_all();
+    }
+}
+
+/// One end of an in-memory, bidirectional `Read+Write` channel, for
+/// exercising clients and servers in tests without sockets or
+/// pipes. Created in pairs by [pair](#method.pair): what is written
+/// to one end is read from the other.
+///
+/// Reads block until the other end writes, so the ends are typically
+/// given to a client and a server on separate threads. Reading
+/// reaches EOF once the other end is dropped and everything it wrote
+/// has been read, and writing to an end whose peer was dropped fails
+/// with `BrokenPipe`.
+///
+/// A client and server may also be driven from a single thread by
+/// stepping through a call, since every message is complete in the
+/// channel before the other side reads it:
+///
+/// ```ignore
+/// let (mut client, server) = JSONTransport::pair();
+/// let mut server = FooRPCServer::new(FooImpl::new(), server);
+/// let mut state = client.tx_begin_call(MethodId { name: "bar", num: 0 })?;
+/// client.tx_add_param("a", "the answer", &mut state)?;
+/// client.tx_add_param("b", 42, &mut state)?;
+/// let state = client.tx_finalize(state)?;
+/// server.serve_single_call()?;
+/// let result: Result<String, SomeError> = client.rx_response(state)?;
+/// ```
+pub struct DuplexChannel {
+    incoming: Arc<Shared>,
+    outgoing: Arc<Shared>,
+    read_timeout: Cell<Option<Duration>>,
+}
+
+impl DuplexChannel {
+    /// Create the two connected ends of a channel.
+    pub fn pair() -> (DuplexChannel, DuplexChannel) {
+        let a = Shared::new();
+        let b = Shared::new();
+        (
+            DuplexChannel::new(a.clone(), b.clone()),
+            DuplexChannel::new(b, a),
+        )
+    }
+
+    fn new(incoming: Arc<Shared>, outgoing: Arc<Shared>) -> Self {
+        DuplexChannel {
+            incoming,
+            outgoing,
+            read_timeout: Cell::new(None),
+        }
+    }
+
+    /// Number of bytes written by the other end and not yet read.
+    pub fn pending(&self) -> usize {
+        self.incoming.lock().buf.len()
+    }
+}
+
+impl Read for DuplexChannel {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let deadline = self.read_timeout.get().map(|t| Instant::now() + t);
+        let mut pipe = self.incoming.lock();
+        loop {
+            if !pipe.buf.is_empty() || buf.is_empty() {
+                let n = std::cmp::min(buf.len(), pipe.buf.len());
+                for (dst, src) in buf.iter_mut().zip(pipe.buf.drain(..n)) {
+                    *dst = src;
+                }
+                return Ok(n);
+            }
+            if pipe.closed {
+                return Ok(0);
+            }
+            pipe = match deadline {
+                None => self
+                    .incoming
+                    .ready
+                    .wait(pipe)
+                    .unwrap_or_else(|e| e.into_inner()),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "duplex channel read timed out",
+                        ));
+                    }
+                    self.incoming
+                        .ready
+                        .wait_timeout(pipe, deadline - now)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+            };
+        }
+    }
+}
+
+impl Write for DuplexChannel {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut pipe = self.outgoing.lock();
+        if pipe.closed {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "other end of duplex channel was dropped",
+            ));
+        }
+        pipe.buf.extend(buf);
+        self.outgoing.ready.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for DuplexChannel {
+    fn drop(&mut self) {
+        self.incoming.close();
+        self.outgoing.close();
+    }
+}
+
+impl ReadTimeout for DuplexChannel {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.read_timeout.set(timeout);
+        Ok(())
+    }
+}
+
+impl ReadReady for DuplexChannel {
+    fn read_ready(&self) -> io::Result<bool> {
+        let pipe = self.incoming.lock();
+        Ok(!pipe.buf.is_empty() || pipe.closed)
+    }
+}
+
+#[cfg(feature = "json_transport")]
+impl JSONTransport<DuplexChannel> {
+    /// Create a client and a server transport connected to each
+    /// other in memory by a [DuplexChannel](struct.DuplexChannel.html).
+    pub fn pair() -> (Self, Self) {
+        let (c1, c2) = DuplexChannel::pair();
+        (Self::new(c1), Self::new(c2))
+    }
+}
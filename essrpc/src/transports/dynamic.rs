@@ -0,0 +1,116 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::sync::{Mutex, OnceLock};
+
+use serde_json::Value;
+
+use crate::{ClientTransport, MethodId, RPCClient, RPCError, RPCErrorKind, Result};
+
+/// Code of the error given when the method itself returns an error,
+/// as essrpc's JSON-RPC servers send it.
+const APPLICATION_ERROR: i64 = -32000;
+
+/// Client which calls methods by name, with parameters and results
+/// as `serde_json::Value`, for use where the trait being called is
+/// not known at compile time, such as in a proxy, a REPL or a test
+/// harness. The client-side counterpart of
+/// [Router](struct.Router.html). Works with any
+/// [ClientTransport](../trait.ClientTransport.html) which identifies
+/// methods by name and can read a response without knowing its type,
+/// such as [JSONTransport](struct.JSONTransport.html), but not
+/// [BincodeTransport](struct.BincodeTransport.html) or a JSON
+/// transport made with `new_numeric`.
+///
+/// ```ignore
+/// let client = DynamicClient::new(JSONTransport::new(stream));
+/// let result = client.call("bar", json!({"a": "the answer", "b": 42}))?;
+/// assert_eq!(result, json!("the answer is 42"));
+/// ```
+pub struct DynamicClient<TR: ClientTransport> {
+    tr: RefCell<TR>,
+}
+
+impl<TR: ClientTransport> DynamicClient<TR> {
+    /// Call `method` and wait for its result. `params` is an object
+    /// of named parameters, an array of positional ones, which are
+    /// named by their index for transports which send names, or
+    /// `Null` for none. An error returned by the method itself is
+    /// given as an `RPCError` of kind `RPCErrorKind::Application`
+    /// with code -32000, whose message is the error as JSON.
+    pub fn call(&self, method: &str, params: Value) -> Result<Value> {
+        let mut tr = self.tr.borrow_mut();
+        let state = begin(&mut *tr, method, params)?;
+        let state = tr.tx_finalize(state)?;
+        match tr.rx_response::<std::result::Result<Value, Value>>(state)? {
+            Ok(result) => Ok(result),
+            Err(error) => Err(RPCError::new(
+                RPCErrorKind::Application(APPLICATION_ERROR),
+                error.to_string(),
+            )
+            .with_method(method)),
+        }
+    }
+
+    /// Call `method` as a notification, expecting no response. See
+    /// `call` for `params`.
+    pub fn notify(&self, method: &str, params: Value) -> Result<()> {
+        let mut tr = self.tr.borrow_mut();
+        let state = begin(&mut *tr, method, params)?;
+        tr.tx_notify(state)
+    }
+}
+
+impl<TR: ClientTransport> RPCClient for DynamicClient<TR> {
+    type TR = TR;
+
+    fn new(transport: TR) -> Self {
+        DynamicClient {
+            tr: RefCell::new(transport),
+        }
+    }
+}
+
+// Begin a call to `method`, adding each of `params`. As the method is
+// not known, neither is its number.
+fn begin<TR: ClientTransport>(tr: &mut TR, method: &str, params: Value) -> Result<TR::TXState> {
+    let mut state = tr.tx_begin_call(MethodId {
+        name: intern(method),
+        num: 0,
+    })?;
+    match params {
+        Value::Object(params) => {
+            for (name, value) in params {
+                tr.tx_add_param(intern(&name), value, &mut state)?;
+            }
+        }
+        Value::Array(params) => {
+            for (i, value) in params.into_iter().enumerate() {
+                tr.tx_add_param(intern(&i.to_string()), value, &mut state)?;
+            }
+        }
+        Value::Null => (),
+        _ => {
+            return Err(RPCError::new(
+                RPCErrorKind::InvalidParams,
+                "params must be an object, an array or null",
+            )
+            .with_method(method))
+        }
+    }
+    Ok(state)
+}
+
+// Transports take method and parameter names as &'static str, so
+// each name is leaked the first time it is used, and reused after.
+fn intern(name: &str) -> &'static str {
+    static NAMES: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+    let mut names = NAMES.get_or_init(Default::default).lock().unwrap();
+    match names.get(name) {
+        Some(name) => name,
+        None => {
+            let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+            names.insert(name);
+            name
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+use std::io::{Read, Write};
+use std::mem;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+use serde::de::Error as _;
+use serde::ser::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::ReadTimeout;
+
+// Most descriptors sent along with a single write, as Linux's
+// SCM_MAX_FD
+const MAX_FDS: usize = 253;
+
+thread_local! {
+    // Descriptors serialized on this thread, to go out with the next
+    // write to an FdChannel
+    static OUTGOING: RefCell<Vec<OwnedFd>> = const { RefCell::new(Vec::new()) };
+    // Descriptors received on this thread, in the order they arrived,
+    // awaiting deserialization
+    static INCOMING: RefCell<VecDeque<OwnedFd>> = const { RefCell::new(VecDeque::new()) };
+}
+
+/// File descriptor parameter or return value, passed to the peer
+/// out-of-band with `SCM_RIGHTS` rather than serialized. The peer
+/// receives a descriptor of its own for the same open file, socket or
+/// pipe. Only works over an [FdChannel](struct.FdChannel.html), on
+/// the thread which reads or writes it, and only for the peer process
+/// on the other end of the socket. Enable the "fd_passing" feature to
+/// use this.
+///
+/// On the wire, an `Fd` is a number standing in for the descriptor.
+/// Descriptors are received in the order they were sent, so each sent
+/// `Fd` must be read by the peer, in order, for those after it to be
+/// matched up correctly.
+///
+/// ```ignore
+/// #[essrpc]
+/// pub trait Supervisor {
+///     fn open_log(&self, name: String) -> Result<Fd, SomeError>;
+/// }
+///
+/// let client = SupervisorRPCClient::new(JSONTransport::new(FdChannel::new(stream)));
+/// let log = File::from(client.open_log("app".to_string())?.into_inner());
+/// ```
+#[derive(Debug)]
+pub struct Fd(OwnedFd);
+
+impl Fd {
+    pub fn new(fd: OwnedFd) -> Self {
+        Fd(fd)
+    }
+
+    /// Consume the wrapper, returning the descriptor.
+    pub fn into_inner(self) -> OwnedFd {
+        self.0
+    }
+}
+
+impl From<OwnedFd> for Fd {
+    fn from(fd: OwnedFd) -> Self {
+        Fd(fd)
+    }
+}
+
+impl AsFd for Fd {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl AsRawFd for Fd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+// A duplicate of the descriptor is sent, as the Fd itself may be
+// closed before the message is written.
+impl Serialize for Fd {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let fd = self.0.try_clone().map_err(S::Error::custom)?;
+        let index = OUTGOING.with(|outgoing| {
+            let mut outgoing = outgoing.borrow_mut();
+            outgoing.push(fd);
+            outgoing.len() - 1
+        });
+        serializer.serialize_u64(index as u64)
+    }
+}
+
+impl<'de> Deserialize<'de> for Fd {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        u64::deserialize(deserializer)?;
+        INCOMING
+            .with(|incoming| incoming.borrow_mut().pop_front())
+            .map(Fd)
+            .ok_or_else(|| D::Error::custom("no file descriptor received for Fd"))
+    }
+}
+
+/// Unix domain socket channel which carries the descriptors of any
+/// [Fd](struct.Fd.html) parameters or return values alongside the
+/// bytes of the message, for use with
+/// [JSONTransport](struct.JSONTransport.html) or any other transport
+/// which serializes and writes on the same thread. Enable the
+/// "fd_passing" feature to use this.
+pub struct FdChannel {
+    stream: UnixStream,
+}
+
+impl FdChannel {
+    pub fn new(stream: UnixStream) -> Self {
+        FdChannel { stream }
+    }
+
+    /// Get the underlying stream
+    pub fn get_ref(&self) -> &UnixStream {
+        &self.stream
+    }
+
+    /// Consume the channel, returning the underlying stream.
+    pub fn into_inner(self) -> UnixStream {
+        self.stream
+    }
+
+    fn send_with_fds(&self, buf: &[u8], fds: &[OwnedFd]) -> io::Result<usize> {
+        if fds.len() > MAX_FDS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} file descriptors exceed {} per message",
+                    fds.len(),
+                    MAX_FDS
+                ),
+            ));
+        }
+        let fds_len = mem::size_of_val(fds);
+        let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(fds_len as u32) } as usize];
+        let mut iov = libc::iovec {
+            iov_base: buf.as_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = control.len() as _;
+        // Safety: the control buffer has room for one header and the
+        // descriptors, as computed by CMSG_SPACE
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(fds_len as u32) as _;
+            let data = libc::CMSG_DATA(cmsg) as *mut RawFd;
+            for (i, fd) in fds.iter().enumerate() {
+                data.add(i).write_unaligned(fd.as_raw_fd());
+            }
+        }
+        let n = unsafe { libc::sendmsg(self.stream.as_raw_fd(), &msg, 0) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(n as usize)
+    }
+
+    fn recv_with_fds(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut control = vec![
+            0u8;
+            unsafe { libc::CMSG_SPACE((MAX_FDS * mem::size_of::<RawFd>()) as u32) }
+                as usize
+        ];
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = control.len() as _;
+        let n = unsafe { libc::recvmsg(self.stream.as_raw_fd(), &mut msg, RECV_FLAGS) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut received = Vec::new();
+        // Safety: the kernel filled in the control messages, which are
+        // walked with the CMSG macros
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                    let data = libc::CMSG_DATA(cmsg) as *const RawFd;
+                    let len = (*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+                    for i in 0..len / mem::size_of::<RawFd>() {
+                        let fd = OwnedFd::from_raw_fd(data.add(i).read_unaligned());
+                        set_cloexec(&fd)?;
+                        received.push(fd);
+                    }
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+        }
+        if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+            return Err(io::Error::other("file descriptors received were truncated"));
+        }
+        INCOMING.with(|incoming| incoming.borrow_mut().extend(received));
+        Ok(n as usize)
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const RECV_FLAGS: libc::c_int = libc::MSG_CMSG_CLOEXEC;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const RECV_FLAGS: libc::c_int = 0;
+
+// Received descriptors are not to leak into child processes. Linux
+// sets this on receipt, elsewhere it is done after the fact.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn set_cloexec(_fd: &OwnedFd) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn set_cloexec(fd: &OwnedFd) -> io::Result<()> {
+    if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+impl Read for FdChannel {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.recv_with_fds(buf)
+    }
+}
+
+impl Write for FdChannel {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let fds = OUTGOING.with(|outgoing| mem::take(&mut *outgoing.borrow_mut()));
+        if fds.is_empty() {
+            return self.stream.write(buf);
+        }
+        // The descriptors go with the first bytes written after they
+        // were serialized, so arrive no later than the message
+        // referring to them. Once sent, the duplicates are closed.
+        self.send_with_fds(buf, &fds).inspect_err(|_| {
+            OUTGOING.with(|outgoing| {
+                let mut outgoing = outgoing.borrow_mut();
+                let later = mem::replace(&mut *outgoing, fds);
+                outgoing.extend(later);
+            })
+        })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl ReadTimeout for FdChannel {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+}
@@ -0,0 +1,139 @@
+//! Length-prefixed message framing shared by transports. Each frame
+//! is a 4-byte big-endian length followed by that many bytes. With
+//! the "compression" feature, frames may instead begin with a flags
+//! byte, whose lowest bit marks the contents as deflate-compressed.
+use std::convert::TryFrom;
+use std::io;
+use std::io::{Read, Write};
+
+use crate::{RPCError, RPCErrorKind, Result};
+
+/// How messages are delimited on a channel.
+#[derive(Clone, Copy)]
+pub(crate) enum Framing {
+    /// Not at all, as each JSON message delimits itself.
+    Unframed,
+    /// By a length prefix.
+    Length,
+    /// By a flags byte and a length prefix, compressing messages
+    /// longer than the given number of bytes.
+    #[cfg(feature = "compression")]
+    Compressed(usize),
+}
+
+/// Flag marking a frame's contents as deflate-compressed.
+#[cfg(feature = "compression")]
+const COMPRESSED: u8 = 0x01;
+
+/// Write `data` as a single frame and flush the channel.
+pub(crate) fn write_frame(mut w: impl Write, data: &[u8]) -> Result<()> {
+    let len = frame_len(data)?;
+    w.write_all(&len.to_be_bytes())
+        .and_then(|_| w.write_all(data))
+        .and_then(|_| w.flush())
+        .map_err(frame_write_error)
+}
+
+/// Write `data` as a single frame with a flags byte, compressing it
+/// if it is longer than `threshold` bytes, and flush the channel.
+#[cfg(feature = "compression")]
+pub(crate) fn write_flagged_frame(mut w: impl Write, data: &[u8], threshold: usize) -> Result<()> {
+    let compressed;
+    let (flags, data) = if data.len() > threshold {
+        let mut encoder =
+            flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        compressed = encoder
+            .write_all(data)
+            .and_then(|_| encoder.finish())
+            .map_err(frame_write_error)?;
+        (COMPRESSED, &compressed[..])
+    } else {
+        (0, data)
+    };
+    let len = frame_len(data)?;
+    w.write_all(&[flags])
+        .and_then(|_| w.write_all(&len.to_be_bytes()))
+        .and_then(|_| w.write_all(data))
+        .and_then(|_| w.flush())
+        .map_err(frame_write_error)
+}
+
+fn frame_len(data: &[u8]) -> Result<u32> {
+    u32::try_from(data.len()).map_err(|_| {
+        RPCError::new(
+            RPCErrorKind::SerializationError,
+            format!("message of {} bytes is too large to frame", data.len()),
+        )
+    })
+}
+
+fn frame_write_error(e: io::Error) -> RPCError {
+    RPCError::with_cause(RPCErrorKind::TransportError, "frame write failed", e)
+}
+
+/// Read a single frame, returning its contents. A frame longer than
+/// `max` bytes is refused before its contents are read.
+pub(crate) fn read_frame(mut r: impl Read, max: Option<usize>) -> Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    r.read_exact(&mut len).map_err(read_error)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if let Some(max) = max.filter(|&max| len > max) {
+        return Err(RPCError::new(
+            RPCErrorKind::MessageTooLarge,
+            format!("frame of {} bytes exceeds {} bytes", len, max),
+        ));
+    }
+    let mut data = vec![0u8; len];
+    r.read_exact(&mut data).map_err(read_error)?;
+    Ok(data)
+}
+
+/// Read a single frame written by `write_flagged_frame`, returning
+/// its contents, decompressed if need be. Both the frame and its
+/// decompressed contents are limited to `max` bytes.
+#[cfg(feature = "compression")]
+pub(crate) fn read_flagged_frame(mut r: impl Read, max: Option<usize>) -> Result<Vec<u8>> {
+    let mut flags = [0u8; 1];
+    r.read_exact(&mut flags).map_err(read_error)?;
+    let data = read_frame(r, max)?;
+    match flags[0] {
+        0 => Ok(data),
+        COMPRESSED => {
+            let max = max.unwrap_or(usize::MAX);
+            let mut decoded = Vec::new();
+            flate2::read::DeflateDecoder::new(&data[..])
+                .take((max as u64).saturating_add(1))
+                .read_to_end(&mut decoded)
+                .map_err(|e| {
+                    RPCError::with_cause(
+                        RPCErrorKind::TransportError,
+                        "frame decompression failed",
+                        e,
+                    )
+                })?;
+            if decoded.len() > max {
+                return Err(RPCError::new(
+                    RPCErrorKind::MessageTooLarge,
+                    format!("decompressed frame exceeds {} bytes", max),
+                ));
+            }
+            Ok(decoded)
+        }
+        flags => Err(RPCError::new(
+            RPCErrorKind::TransportError,
+            format!("frame has unknown flags {:#04x}", flags),
+        )),
+    }
+}
+
+fn read_error(e: io::Error) -> RPCError {
+    match e.kind() {
+        io::ErrorKind::UnexpectedEof => {
+            RPCError::new(RPCErrorKind::TransportEOF, "EOF while reading frame")
+        }
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+            RPCError::with_cause(RPCErrorKind::Timeout, "timed out reading frame", e)
+        }
+        _ => RPCError::with_cause(RPCErrorKind::TransportError, "frame read failed", e),
+    }
+}
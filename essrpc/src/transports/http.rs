@@ -0,0 +1,583 @@
+use std::io;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::json_codec::{JRXState, JSONCodec, JTXState};
+use super::{connect_tcp, flush_channel};
+use crate::{
+    ClientTransport, MethodId, PartialMethodId, RPCError, RPCErrorKind, Result, ServerTransport,
+};
+
+// Limit on the request or status line and headers of a message,
+// whatever the limit on its body.
+const MAX_HEAD_BYTES: usize = 64 * 1024;
+
+const READ_CHUNK: usize = 8 * 1024;
+
+/// Transport implementation sending JSON-RPC over HTTP/1.1, one call
+/// per exchange, so that servers may sit behind reverse proxies such
+/// as nginx or envoy. The client POSTs each request to the URL it was
+/// created with and reads the call's response from the body of the
+/// HTTP response. The server reads each HTTP request, dispatches the
+/// call in its body and answers with the JSON-RPC response, or `204
+/// No Content` for a notification. Requests and responses are the
+/// same as those of [JSONTransport](struct.JSONTransport.html), and
+/// the connection is kept open between exchanges. Enable the
+/// "http_transport" feature to use this.
+///
+/// A client seeing an HTTP status other than 2xx, e.g. from a proxy
+/// which could not reach the server, fails the call with an error
+/// whose kind follows the status: `Timeout` for 408 and 504,
+/// `MessageTooLarge` for 413, `ConnectionFailed` for 502 and 503, and
+/// `TransportError` otherwise. Chunked bodies are accepted but never
+/// sent. Streaming methods and batches are not supported.
+///
+/// ```ignore
+/// let client = FooRPCClient::new(HTTPTransport::connect("http://localhost:8080/rpc")?);
+/// ```
+pub struct HTTPTransport<C: Read + Write> {
+    channel: C,
+    codec: JSONCodec,
+    // Host header and path requests are sent with. Unused by servers.
+    host: String,
+    path: String,
+    max_message_bytes: Option<usize>,
+    // Bytes read from the channel but not yet consumed
+    buf: Vec<u8>,
+    // Size of the last body read
+    read_bytes: usize,
+}
+
+impl<C: Read + Write> HTTPTransport<C> {
+    /// Create a client transport POSTing requests to `url`, which
+    /// must be an `http` or `https` URL, over `channel`. The channel
+    /// must already be connected to the URL's host, e.g. with TLS for
+    /// `https`.
+    pub fn new(channel: C, url: &str) -> Result<Self> {
+        let url = Url::parse(url)?;
+        Ok(HTTPTransport {
+            host: url.host_header(),
+            path: url.path,
+            ..Self::server(channel)
+        })
+    }
+
+    /// Create a server transport, reading HTTP requests from
+    /// `channel`. Only `POST` requests are served, at any path;
+    /// others are answered with `405 Method Not Allowed`.
+    pub fn server(channel: C) -> Self {
+        HTTPTransport {
+            channel,
+            codec: JSONCodec::new(),
+            host: String::new(),
+            path: String::new(),
+            max_message_bytes: None,
+            buf: Vec::new(),
+            read_bytes: 0,
+        }
+    }
+
+    /// Refuse bodies longer than `max` bytes with
+    /// `RPCErrorKind::MessageTooLarge`. A server answers such a
+    /// request with `413 Payload Too Large`. Without a limit, a peer
+    /// may make the transport buffer an arbitrarily large body.
+    pub fn with_max_message_bytes(mut self, max: usize) -> Self {
+        self.max_message_bytes = Some(max);
+        self
+    }
+
+    /// Get the underlying read/write channel
+    pub fn channel(&self) -> &C {
+        &self.channel
+    }
+
+    /// Get a mutable reference to the underlying read/write channel,
+    /// e.g. to adjust socket options.
+    pub fn channel_mut(&mut self) -> &mut C {
+        &mut self.channel
+    }
+
+    /// Consume the transport, returning the underlying read/write
+    /// channel.
+    pub fn into_channel(self) -> C {
+        self.channel
+    }
+
+    fn post(&mut self, body: &[u8]) -> Result<()> {
+        let head = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             Accept: application/json\r\nContent-Length: {}\r\n\r\n",
+            self.path,
+            self.host,
+            body.len()
+        );
+        self.write_message(&head, body)
+    }
+
+    fn respond(&mut self, status: &str, body: Option<&[u8]>) -> Result<()> {
+        let head = match body {
+            Some(body) => format!(
+                "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+                status,
+                body.len()
+            ),
+            None => format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status),
+        };
+        self.write_message(&head, body.unwrap_or_default())
+    }
+
+    fn write_message(&mut self, head: &str, body: &[u8]) -> Result<()> {
+        self.channel
+            .write_all(head.as_bytes())
+            .and_then(|_| self.channel.write_all(body))
+            .map_err(|e| {
+                RPCError::with_cause(RPCErrorKind::TransportError, "http write failed", e)
+            })?;
+        flush_channel(&mut self.channel)
+    }
+
+    // The next HTTP response, skipping any interim (1xx) ones. Fails
+    // unless it is a success.
+    fn read_response(&mut self) -> Result<Vec<u8>> {
+        loop {
+            let head = self.read_head()?;
+            let status = head.status()?;
+            if (100..200).contains(&status) {
+                continue;
+            }
+            // Without a length, the body of a response runs to the end
+            // of the connection.
+            let to_eof = status != 204 && status != 304;
+            let body = self.read_body(&head, to_eof)?;
+            if !(200..300).contains(&status) {
+                return Err(status_error(&head.start_line, status));
+            }
+            self.read_bytes = body.len();
+            return Ok(body);
+        }
+    }
+
+    fn read_head(&mut self) -> Result<Head> {
+        let start_line = loop {
+            let line = self.read_line()?;
+            // Blank lines before a message are to be ignored
+            if !line.is_empty() {
+                break line;
+            }
+        };
+        let mut headers = Vec::new();
+        loop {
+            let line = self.read_line()?;
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| malformed(format!("malformed http header {:?}", line)))?;
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+        Ok(Head {
+            start_line,
+            headers,
+        })
+    }
+
+    // A line of the head, without its line ending. The end of the
+    // channel before any of the message is the peer disconnecting.
+    fn read_line(&mut self) -> Result<String> {
+        let mut searched = 0;
+        loop {
+            if let Some(i) = self.buf[searched..].iter().position(|&b| b == b'\n') {
+                let end = searched + i;
+                let mut line: Vec<u8> = self.buf.drain(..=end).collect();
+                line.pop();
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                return String::from_utf8(line).map_err(|e| {
+                    RPCError::with_cause(RPCErrorKind::InvalidRequest, "http head is not utf-8", e)
+                });
+            }
+            searched = self.buf.len();
+            if searched > MAX_HEAD_BYTES {
+                return Err(RPCError::new(
+                    RPCErrorKind::MessageTooLarge,
+                    format!("http head exceeds {} bytes", MAX_HEAD_BYTES),
+                ));
+            }
+            if self.fill()? == 0 {
+                return Err(RPCError::new(
+                    RPCErrorKind::TransportEOF,
+                    "EOF while reading http head",
+                ));
+            }
+        }
+    }
+
+    fn read_body(&mut self, head: &Head, to_eof: bool) -> Result<Vec<u8>> {
+        let chunked = head
+            .header("transfer-encoding")
+            .is_some_and(|te| te.to_ascii_lowercase().ends_with("chunked"));
+        if chunked {
+            return self.read_chunked();
+        }
+        match head.header("content-length") {
+            Some(len) => {
+                let len = len
+                    .parse()
+                    .map_err(|_| malformed(format!("malformed content length {:?}", len)))?;
+                self.check_len(len)?;
+                self.read_exact(len)
+            }
+            None if to_eof => {
+                while self.fill()? > 0 {
+                    self.check_len(self.buf.len())?;
+                }
+                Ok(std::mem::take(&mut self.buf))
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn read_chunked(&mut self) -> Result<Vec<u8>> {
+        let mut body = Vec::new();
+        loop {
+            let line = self.read_line()?;
+            // Chunk extensions follow a semicolon
+            let size = line.split(';').next().unwrap_or_default().trim();
+            let size = usize::from_str_radix(size, 16)
+                .map_err(|_| malformed(format!("malformed chunk size {:?}", line)))?;
+            if size == 0 {
+                // Trailers, up to the blank line ending the message
+                while !self.read_line()?.is_empty() {}
+                return Ok(body);
+            }
+            self.check_len(body.len().saturating_add(size))?;
+            body.extend(self.read_exact(size)?);
+            self.read_line()?;
+        }
+    }
+
+    fn check_len(&self, len: usize) -> Result<()> {
+        match self.max_message_bytes {
+            Some(max) if len > max => Err(RPCError::new(
+                RPCErrorKind::MessageTooLarge,
+                format!("http body of {} bytes exceeds {} bytes", len, max),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn read_exact(&mut self, len: usize) -> Result<Vec<u8>> {
+        while self.buf.len() < len {
+            if self.fill()? == 0 {
+                return Err(RPCError::new(
+                    RPCErrorKind::TransportEOF,
+                    "EOF while reading http body",
+                ));
+            }
+        }
+        Ok(self.buf.drain(..len).collect())
+    }
+
+    // Read more of the channel into buf, returning how much was read
+    fn fill(&mut self) -> Result<usize> {
+        let start = self.buf.len();
+        self.buf.resize(start + READ_CHUNK, 0);
+        let result = loop {
+            match self.channel.read(&mut self.buf[start..]) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                result => break result,
+            }
+        };
+        let n = result.as_ref().map_or(0, |&n| n);
+        self.buf.truncate(start + n);
+        result.map_err(read_error)
+    }
+
+    // Answer a request the server gives up on, with the JSON-RPC
+    // error for it unless it is a notification.
+    fn answer_error<T>(&mut self, result: Result<T>, state: &JRXState) -> Result<T> {
+        result.inspect_err(|e| {
+            let _ = self.tx_error_ref(e, state);
+        })
+    }
+
+    fn tx_error_ref(&mut self, error: &RPCError, state: &JRXState) -> Result<()> {
+        if state.is_notification() {
+            return self.respond("204 No Content", None);
+        }
+        let body = self.codec.encode_error(error, state.id())?;
+        self.respond("200 OK", Some(&body))
+    }
+}
+
+impl HTTPTransport<TcpStream> {
+    /// Connect over TCP to the host of `url`, which must be an `http`
+    /// URL, and create a client transport POSTing requests to it. For
+    /// `https`, connect with TLS and use `new`.
+    pub fn connect(url: &str) -> Result<Self> {
+        let parsed = Url::parse(url)?;
+        if parsed.https {
+            return Err(RPCError::new(
+                RPCErrorKind::ConnectionFailed,
+                "https needs a TLS channel, given to HTTPTransport::new",
+            ));
+        }
+        Self::new(connect_tcp((parsed.host.as_str(), parsed.port))?, url)
+    }
+}
+
+impl<C: Read + Write> ClientTransport for HTTPTransport<C> {
+    type TXState = JTXState;
+    type FinalState = Value;
+
+    fn tx_begin_call(&mut self, method: MethodId) -> Result<JTXState> {
+        Ok(self.codec.begin_call(method))
+    }
+
+    fn tx_add_param(
+        &mut self,
+        name: &'static str,
+        value: impl Serialize,
+        state: &mut JTXState,
+    ) -> Result<()> {
+        self.codec.add_param(name, value, state)
+    }
+
+    fn tx_set_service_version(&mut self, version: u32, state: &mut JTXState) -> Result<()> {
+        self.codec.set_service_version(version, state);
+        Ok(())
+    }
+
+    fn tx_finalize(&mut self, state: JTXState) -> Result<Value> {
+        let (body, id) = self.codec.encode_call(&state)?;
+        self.post(&body)?;
+        Ok(id)
+    }
+
+    fn rx_response<T>(&mut self, id: Value) -> Result<T>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        let body = self.read_response()?;
+        self.codec.decode_response(&body, &id)
+    }
+
+    // The server still answers the HTTP request, with no body
+    fn tx_notify(&mut self, state: JTXState) -> Result<()> {
+        let body = self.codec.encode_notification(&state)?;
+        self.post(&body)?;
+        self.read_response().map(|_| ())
+    }
+
+    fn rx_response_bytes(&self) -> Option<usize> {
+        Some(self.read_bytes)
+    }
+}
+
+impl<C: Read + Write> ServerTransport for HTTPTransport<C> {
+    type RXState = JRXState;
+
+    fn rx_begin_call(&mut self) -> Result<(PartialMethodId, JRXState)> {
+        loop {
+            let head = match self.read_head() {
+                Err(e) if e.kind == RPCErrorKind::MessageTooLarge => {
+                    let _ = self.respond("431 Request Header Fields Too Large", None);
+                    return Err(e);
+                }
+                Err(e) if e.kind == RPCErrorKind::InvalidRequest => {
+                    let _ = self.respond("400 Bad Request", None);
+                    return Err(e);
+                }
+                head => head?,
+            };
+            let body = match self.read_body(&head, false) {
+                Err(e) if e.kind == RPCErrorKind::MessageTooLarge => {
+                    let _ = self.respond("413 Payload Too Large", None);
+                    return Err(e);
+                }
+                Err(e) if e.kind == RPCErrorKind::InvalidRequest => {
+                    let _ = self.respond("400 Bad Request", None);
+                    return Err(e);
+                }
+                body => body?,
+            };
+            if !head.start_line.starts_with("POST ") {
+                self.write_message(
+                    "HTTP/1.1 405 Method Not Allowed\r\nAllow: POST\r\nContent-Length: 0\r\n\r\n",
+                    &[],
+                )?;
+                continue;
+            }
+            return self.codec.decode_call(&body).inspect_err(|e| {
+                if let Ok(body) = self.codec.encode_error(e, &Value::Null) {
+                    let _ = self.respond("200 OK", Some(&body));
+                }
+            });
+        }
+    }
+
+    fn rx_read_param<T>(&mut self, name: &'static str, state: &mut JRXState) -> Result<T>
+    where
+        for<'de> T: serde::Deserialize<'de>,
+    {
+        let param = self.codec.read_param(name, state);
+        self.answer_error(param, state)
+    }
+
+    fn rx_read_param_opt<T>(
+        &mut self,
+        name: &'static str,
+        state: &mut JRXState,
+    ) -> Result<Option<T>>
+    where
+        for<'de> T: serde::Deserialize<'de>,
+    {
+        let param = self.codec.read_param_opt(name, state);
+        self.answer_error(param, state)
+    }
+
+    fn tx_response(
+        &mut self,
+        value: std::result::Result<impl Serialize, impl Serialize>,
+        state: JRXState,
+    ) -> Result<()> {
+        if state.is_notification() {
+            return self.respond("204 No Content", None);
+        }
+        let body = self.codec.encode_response(value, state.id())?;
+        self.respond("200 OK", Some(&body))
+    }
+
+    fn rx_service_version(&self, state: &JRXState) -> Option<u32> {
+        state.service_version()
+    }
+
+    fn tx_error(&mut self, error: RPCError, state: JRXState) -> Result<()> {
+        self.tx_error_ref(&error, &state)
+    }
+
+    fn tx_no_response(&mut self, _state: JRXState) -> Result<()> {
+        self.respond("204 No Content", None)
+    }
+}
+
+// The start line and headers of an HTTP message
+struct Head {
+    start_line: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Head {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    // The status code of a response
+    fn status(&self) -> Result<u16> {
+        let mut parts = self.start_line.split(' ');
+        match (parts.next(), parts.next().map(str::parse)) {
+            (Some(version), Some(Ok(status))) if version.starts_with("HTTP/") => Ok(status),
+            _ => Err(RPCError::new(
+                RPCErrorKind::TransportError,
+                format!("malformed http status line {:?}", self.start_line),
+            )),
+        }
+    }
+}
+
+// The parts of an http or https URL needed to send requests to it
+struct Url {
+    https: bool,
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Url {
+    fn parse(url: &str) -> Result<Self> {
+        let invalid = || {
+            RPCError::new(
+                RPCErrorKind::ConnectionFailed,
+                format!("invalid http url {:?}", url),
+            )
+        };
+        let (https, rest) = if let Some(rest) = url.strip_prefix("http://") {
+            (false, rest)
+        } else if let Some(rest) = url.strip_prefix("https://") {
+            (true, rest)
+        } else {
+            return Err(invalid());
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            // Not the colons of a bare IPv6 address
+            Some((host, port)) if !port.contains(']') => {
+                (host, port.parse().map_err(|_| invalid())?)
+            }
+            _ => (authority, if https { 443 } else { 80 }),
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Url {
+            https,
+            host: host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    // The value of the Host header, which leaves out the default port
+    fn host_header(&self) -> String {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        match (self.https, self.port) {
+            (false, 80) | (true, 443) => host,
+            (_, port) => format!("{}:{}", host, port),
+        }
+    }
+}
+
+fn malformed(msg: String) -> RPCError {
+    RPCError::new(RPCErrorKind::InvalidRequest, msg)
+}
+
+fn read_error(e: io::Error) -> RPCError {
+    match e.kind() {
+        io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted => {
+            RPCError::with_cause(RPCErrorKind::TransportEOF, "http peer disconnected", e)
+        }
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+            RPCError::with_cause(RPCErrorKind::Timeout, "timed out reading http", e)
+        }
+        _ => RPCError::with_cause(RPCErrorKind::TransportError, "http read failed", e),
+    }
+}
+
+// The error for a response whose status is not a success
+fn status_error(status_line: &str, status: u16) -> RPCError {
+    let kind = match status {
+        408 | 504 => RPCErrorKind::Timeout,
+        413 => RPCErrorKind::MessageTooLarge,
+        502 | 503 => RPCErrorKind::ConnectionFailed,
+        _ => RPCErrorKind::TransportError,
+    };
+    RPCError::new(kind, format!("http request failed: {}", status_line))
+}
@@ -0,0 +1,158 @@
+use std::io;
+use std::io::{Cursor, Read, Write};
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+
+use http::{header, Method, Request, Response, StatusCode};
+
+use serde_json::Value;
+
+use super::json_codec::{next_value, JSONCodec};
+use super::JSONTransport;
+use crate::{RPCError, RPCErrorKind, RPCServer};
+
+/// Adapter serving JSON-RPC calls made in the bodies of HTTP
+/// requests, for mounting an essrpc server in an HTTP framework such
+/// as hyper or axum, which looks after the sockets and the HTTP
+/// itself. Enable the "http_server" feature to use this.
+///
+/// For each request, `make_server` is given a
+/// [JSONTransport](struct.JSONTransport.html) carrying just its body,
+/// and the server it returns serves the call, or calls if the body is
+/// a batch. The response is the JSON-RPC response with `200 OK`, or
+/// `204 No Content` if there is none, as for a notification, just as
+/// [HTTPTransport](struct.HTTPTransport.html) servers answer. State
+/// shared by all calls is shared by capturing an `Arc` in
+/// `make_server`, as with
+/// [ThreadedServer](struct.ThreadedServer.html).
+///
+/// Calls are served on the calling thread, so from an async framework
+/// they belong on a blocking thread, e.g. with tokio's
+/// `spawn_blocking`. Limits on the size of request bodies are left to
+/// the framework.
+///
+/// ```ignore
+/// let service = Arc::new(HTTPService::new(|transport| {
+///     FooRPCServer::new(FooImpl::new(), transport)
+/// }));
+/// let app = Router::new().route(
+///     "/rpc",
+///     post(move |body: Bytes| async move {
+///         let response = spawn_blocking(move || service.respond(body)).await.unwrap();
+///         response.map(Body::from)
+///     }),
+/// );
+/// ```
+pub struct HTTPService<F> {
+    make_server: F,
+}
+
+impl<S, F> HTTPService<F>
+where
+    S: RPCServer,
+    F: Fn(JSONTransport<ExchangeChannel>) -> S,
+{
+    pub fn new(make_server: F) -> Self {
+        HTTPService { make_server }
+    }
+
+    /// Answer an HTTP request. `POST` requests are served as with
+    /// [respond](#method.respond), at any path, and others are
+    /// answered with `405 Method Not Allowed`.
+    pub fn handle(&self, request: Request<impl Into<Vec<u8>>>) -> Response<Vec<u8>> {
+        if request.method() != Method::POST {
+            return Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .header(header::ALLOW, "POST")
+                .body(Vec::new())
+                .unwrap();
+        }
+        self.respond(request.into_body())
+    }
+
+    /// Serve the calls in the body of a `POST` request, giving the
+    /// HTTP response to send back. A server failing other than by
+    /// reaching the end of the body is logged with the `log` crate,
+    /// and if it sent nothing, or panicked, the response is `500
+    /// Internal Server Error`.
+    pub fn respond(&self, body: impl Into<Vec<u8>>) -> Response<Vec<u8>> {
+        let body = body.into();
+        // A truncated body would be taken for the end of the calls, as
+        // on a stream, rather than the parse error it is
+        if !body.iter().all(u8::is_ascii_whitespace) && matches!(next_value(&body), Ok(None)) {
+            let e = RPCError::new(RPCErrorKind::ParseError, "json request truncated");
+            return match JSONCodec::new().encode_error(&e, &Value::Null) {
+                Ok(error) => json_response(error),
+                Err(_) => status_only(StatusCode::BAD_REQUEST),
+            };
+        }
+        let response = Arc::new(Mutex::new(Vec::new()));
+        let channel = ExchangeChannel {
+            request: Cursor::new(body),
+            response: response.clone(),
+        };
+        // serve_loop, since the body may be a batch or a ping; it ends
+        // on reaching the end of the body.
+        let served = panic::catch_unwind(AssertUnwindSafe(|| {
+            (self.make_server)(JSONTransport::new(channel)).serve_loop()
+        }));
+        let failed = match served {
+            Ok(Ok(())) => false,
+            Ok(Err(e)) => {
+                log::warn!("error serving call: {}", e);
+                true
+            }
+            Err(_) => {
+                log::warn!("panic serving call");
+                return status_only(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        };
+        // The server and its transport are gone, so nothing else
+        // holds the response.
+        let body = mem::take(&mut *response.lock().unwrap_or_else(|e| e.into_inner()));
+        match (body.is_empty(), failed) {
+            (true, true) => status_only(StatusCode::INTERNAL_SERVER_ERROR),
+            (true, false) => status_only(StatusCode::NO_CONTENT),
+            (false, _) => json_response(body),
+        }
+    }
+}
+
+fn json_response(body: Vec<u8>) -> Response<Vec<u8>> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(body)
+        .unwrap()
+}
+
+fn status_only(status: StatusCode) -> Response<Vec<u8>> {
+    Response::builder().status(status).body(Vec::new()).unwrap()
+}
+
+/// Channel over which an [HTTPService](struct.HTTPService.html)
+/// serves the calls in a single request. Reading gives the request's
+/// body, and what is written makes up the response's.
+pub struct ExchangeChannel {
+    request: Cursor<Vec<u8>>,
+    response: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Read for ExchangeChannel {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.request.read(buf)
+    }
+}
+
+impl Write for ExchangeChannel {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut response = self.response.lock().unwrap_or_else(|e| e.into_inner());
+        response.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -1,54 +1,826 @@
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::io;
 use std::io::{Read, Write};
+use std::mem;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, Instant};
 
+use serde::de::{DeserializeOwned, IgnoredAny};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
-use serde_json::value::Value;
-use uuid::Uuid;
+use serde_json::value::{RawValue, Value};
+use serde_json::Map;
 
+use super::framing::Framing;
+use super::json_codec::{
+    add_param, check_handshake, convert_error, decode_error, handshake_greeting, is_stream_end,
+    read_param, read_param_opt, request_id, response_for_error, response_for_result,
+    result_from_response, set_service_version, stream_end_response, stream_item_from_response,
+    EmptyParams, JRXState, JSONCodec, JSONTransportConfig, JTXState, Message, NonFiniteFloats,
+    Request,
+};
+use super::{
+    connect_tcp, flush_channel, framing, ReadReady, ReadTimeout, ReadWrite, ACCEPT_RETRY_DELAY,
+};
 use crate::{
-    ClientTransport, MethodId, PartialMethodId, RPCError, RPCErrorKind, Result, ServerTransport,
+    ClientTransport, MethodId, PartialMethodId, RPCError, RPCErrorKind, RPCServer, Result,
+    ServerTransport,
 };
 
-pub struct JTXState {
-    method: &'static str,
-    params: Value,
-}
+// Hook set by JSONTransport::on_call
+type CallHook = Box<dyn FnMut(&JRXState) + Send>;
+
+// ReadTimeout::set_read_timeout for the channel, where it has one
+type SetReadTimeout<C> = fn(&C, Option<Duration>) -> io::Result<()>;
 
-pub struct JRXState {
-    json: Value,
+// ReadReady::read_ready for the channel, where it has one
+type IsReadReady<C> = fn(&C) -> io::Result<bool>;
+
+/// Sizes and duration of a call made through a
+/// [JSONTransport](struct.JSONTransport.html), from its `last_stats`.
+/// Sizes exclude any framing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CallStats {
+    /// Size of the request, as sent.
+    pub request_bytes: usize,
+    /// Size of the response, or for a streaming method, of all of its
+    /// responses together. Zero for a notification.
+    pub response_bytes: usize,
+    /// Time from sending the request to reading the last response.
+    /// Zero for a notification.
+    pub duration: Duration,
 }
 
 /// Transport implementation over JSON-RPC. Can be used over any
 /// `Read+Write` channel (local socket, internet socket, pipe,
 /// etc). Enable the "json_transport" feature to use this.
+///
+/// When a method returns `Ok`, the response carries the value in its
+/// `result` member, which is `null` for a method returning
+/// `Result<(), E>`. When it returns `Err`, the response carries a
+/// JSON-RPC `error` object with code -32000 and the serialized error
+/// in `data`. The client converts either back into the method's
+/// `Result`. Errors from other JSON-RPC servers, which do not carry
+/// an essrpc error in `data`, become an `RPCError` of kind
+/// `RPCErrorKind::Application` with the server's code.
+///
+/// Integers are sent as JSON numbers and round-trip exactly across
+/// the full range of `u64` and `i64`. Values of `i128` and `u128`
+/// beyond that range need the "json_arbitrary_precision" feature;
+/// without it they are refused with `RPCErrorKind::SerializationError`
+/// rather than sent with lost precision. Peers in languages which
+/// read every JSON number as a double, such as JavaScript, may still
+/// lose precision above 2^53.
 pub struct JSONTransport<C: Read + Write> {
-    channel: C,
+    // Only taken by into_channel and close, which consume the
+    // transport, so always present otherwise
+    channel: Option<C>,
+    codec: JSONCodec,
+    framing: Framing,
+    max_message_bytes: Option<usize>,
+    // Unless set, messages are held in write_buf until flushed
+    auto_flush: bool,
+    write_buf: Vec<u8>,
+    // Size of the last message read, excluding any framing
+    read_bytes: usize,
+    // Size of the last message written, excluding any framing, and
+    // of the last request or notification the client sent
+    written_bytes: usize,
+    request_bytes: usize,
+    // Stats of the call awaiting its response, with when it was
+    // sent, and of the last call to complete
+    pending_stats: Option<(Instant, CallStats)>,
+    last_stats: Option<CallStats>,
+    // Set by with_timeout or with_call_timeouts, along with the
+    // timeout for calls without one of their own
+    set_read_timeout: Option<SetReadTimeout<C>>,
+    timeout: Option<Duration>,
+    // Called with each request received by the server
+    call_hook: Option<CallHook>,
+    // Set by with_cancellation, to look for cancellations of a
+    // streaming response without blocking. Requests read while
+    // looking are served afterwards, from deferred.
+    read_ready: Option<IsReadReady<C>>,
+    deferred: VecDeque<Value>,
+    // Server-side state for a batch request: the calls not yet
+    // handed out by rx_begin_call and the responses collected so far.
+    batch: Option<(VecDeque<Value>, Vec<Value>)>,
+    // Why the channel can no longer be read or written, once it is
+    // left part way through a message
+    unusable: Option<&'static str>,
+    // Ids of calls which timed out awaiting a response, whose late
+    // responses are skipped, with whether they are streaming calls,
+    // whose responses are skipped up to the end of the stream
+    abandoned: Vec<(Value, bool)>,
 }
 
 impl<C: Read + Write> JSONTransport<C> {
     pub fn new(channel: C) -> Self {
-        JSONTransport { channel }
+        Self::with_config(channel, JSONTransportConfig::default())
+    }
+
+    /// Like `new`, but with the given encoding configuration.
+    pub fn with_config(channel: C, config: JSONTransportConfig) -> Self {
+        JSONTransport {
+            channel: Some(channel),
+            codec: JSONCodec::with_config(config),
+            framing: Framing::Unframed,
+            max_message_bytes: None,
+            auto_flush: true,
+            write_buf: Vec::new(),
+            read_bytes: 0,
+            written_bytes: 0,
+            request_bytes: 0,
+            pending_stats: None,
+            last_stats: None,
+            set_read_timeout: None,
+            timeout: None,
+            call_hook: None,
+            read_ready: None,
+            deferred: VecDeque::new(),
+            batch: None,
+            unusable: None,
+            abandoned: Vec::new(),
+        }
+    }
+
+    /// Like `new`, except the client identifies methods by their
+    /// number rather than their name. This shrinks requests and
+    /// avoids name collisions across versions, but is not
+    /// interoperable with other JSON-RPC implementations. A server
+    /// accepts either form regardless of how it was constructed.
+    pub fn new_numeric(channel: C) -> Self {
+        let mut transport = Self::new(channel);
+        transport.codec.options.numeric = true;
+        transport
+    }
+
+    /// Like `new`, except every message is prefixed with its length
+    /// as a 4-byte big-endian integer, and the receiver reads exactly
+    /// that many bytes before deserializing. This removes any
+    /// ambiguity about where one message ends and the next begins,
+    /// making it the best choice for long-lived connections carrying
+    /// many calls. Both ends must be framed.
+    pub fn framed(channel: C) -> Self {
+        let mut transport = Self::new(channel);
+        transport.framing = Framing::Length;
+        transport
+    }
+
+    /// Like `framed`, except each frame begins with a flags byte, and
+    /// messages longer than `threshold` bytes are compressed with
+    /// deflate and flagged as such. Smaller messages, which gain
+    /// little from compression, are sent as they are. The receiver
+    /// decompresses each frame as its flags say, so the two ends may
+    /// use different thresholds, but both must be created this
+    /// way. Unlike [CompressedChannel](struct.CompressedChannel.html),
+    /// each message is compressed on its own. Enable the
+    /// "compression" feature to use this.
+    #[cfg(feature = "compression")]
+    pub fn framed_compressed(channel: C, threshold: usize) -> Self {
+        let mut transport = Self::new(channel);
+        transport.framing = Framing::Compressed(threshold);
+        transport
+    }
+
+    /// Like `new`, except the client sends `params` as an array in
+    /// the order the method declares them, rather than as an object
+    /// keyed by parameter name. Many existing JSON-RPC services
+    /// expect this. A server accepts either form regardless of how
+    /// it was constructed.
+    pub fn positional(channel: C) -> Self {
+        let mut transport = Self::new(channel);
+        transport.codec.options.positional = true;
+        transport
+    }
+
+    /// Like `new`, except messages go without the JSON-RPC envelope,
+    /// for talking to ad-hoc JSON APIs which are not JSON-RPC at all.
+    /// Requests carry only `method` and `params`, e.g.
+    /// `{"method": "bar", "params": {"a": "the answer", "b": 42}}`,
+    /// and responses only `result` or `error`, with neither a
+    /// `jsonrpc` nor an `id` member. Both ends must be raw.
+    ///
+    /// Without ids, a response is taken to answer the oldest call
+    /// awaiting one, and a request is not a notification unless its
+    /// method is marked `#[essrpc(notification)]`, so the server
+    /// answers every other request. Batches, which need ids to match
+    /// responses to calls, are refused.
+    pub fn raw(channel: C) -> Self {
+        let mut transport = Self::new(channel);
+        transport.codec = JSONCodec::raw();
+        transport
+    }
+
+    /// Begin a JSON-RPC batch. Calls made through the returned
+    /// [JSONBatch](struct.JSONBatch.html) are collected and sent to
+    /// the server as a single array when the first response is
+    /// requested.
+    pub fn begin_batch(&mut self) -> JSONBatch<'_, C> {
+        JSONBatch {
+            transport: self,
+            requests: Vec::new(),
+            responses: None,
+        }
     }
 
     /// Get the underlying read/write channel
     pub fn channel(&self) -> &C {
-        &self.channel
+        open(&self.channel)
+    }
+
+    /// Get a mutable reference to the underlying read/write channel,
+    /// e.g. to adjust socket options.
+    pub fn channel_mut(&mut self) -> &mut C {
+        open_mut(&mut self.channel)
+    }
+
+    /// Consume the transport, returning the underlying read/write
+    /// channel. Useful for reusing a connection for something else
+    /// once RPC is finished with it. Anything held since the last
+    /// flush is written first, ignoring any error, as when the
+    /// transport is dropped; use `flush` beforehand to see errors.
+    pub fn into_channel(mut self) -> C {
+        let _ = self.flush();
+        self.channel.take().unwrap()
+    }
+
+    /// Flush anything held since the last flush, as with `flush`, and
+    /// close the transport, dropping the channel. Dropping the
+    /// transport does the same, but has nowhere to report an error,
+    /// so a message held without auto flush, or left in a buffered
+    /// channel such as a `BufWriter`, may be lost without trace; call
+    /// this instead to find out. Closing the channel, which ends the
+    /// peer's session with `RPCErrorKind::TransportEOF`, is all that
+    /// marks the end, framed or not.
+    pub fn close(mut self) -> Result<()> {
+        self.flush()
+    }
+
+    /// Choose how `params` is sent for methods without
+    /// parameters. See [EmptyParams](enum.EmptyParams.html).
+    pub fn with_empty_params(mut self, empty_params: EmptyParams) -> Self {
+        self.codec = mem::take(&mut self.codec).with_empty_params(empty_params);
+        self
+    }
+
+    /// Choose how floats JSON has no numbers for, NaN and the
+    /// infinities, are sent and read, in parameters and results
+    /// alike. Both ends must agree on it. See
+    /// [NonFiniteFloats](enum.NonFiniteFloats.html).
+    pub fn with_non_finite_floats(mut self, floats: NonFiniteFloats) -> Self {
+        self.codec = mem::take(&mut self.codec).with_non_finite_floats(floats);
+        self
+    }
+
+    /// Give requests incrementing integer ids, starting at 1, rather
+    /// than random UUIDs, which are the default with the "uuid_ids"
+    /// feature. Smaller on the wire, and much easier to match up in
+    /// logs.
+    pub fn use_sequential_ids(mut self) -> Self {
+        self.codec = mem::take(&mut self.codec).use_sequential_ids();
+        self
+    }
+
+    /// Call `generator` for the id of each subsequent request, rather
+    /// than using the default ids. Ids should be unique among the
+    /// calls in flight, and must not be `null`, which the server takes
+    /// for a notification.
+    pub fn set_id_generator(&mut self, generator: Box<dyn FnMut() -> Value + Send>) {
+        self.codec.set_id_generator(generator);
+    }
+
+    /// Send `context` with every subsequent request, as its `meta`
+    /// member, for metadata such as trace ids or credentials which
+    /// concern every call rather than any one method. The server
+    /// sees it through [JRXState::meta](struct.JRXState.html#method.meta),
+    /// e.g. in a hook set by `on_call`. An empty map sends no `meta`.
+    pub fn set_context(&mut self, context: Map<String, Value>) {
+        self.codec.set_context(context);
+    }
+
+    /// Call `hook` with each request the server receives, before the
+    /// method is called. Useful to pick up the request's
+    /// [meta](struct.JRXState.html#method.meta) for tracing or
+    /// logging without involving the methods themselves.
+    pub fn on_call(mut self, hook: impl FnMut(&JRXState) + Send + 'static) -> Self {
+        self.call_hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Refuse any message larger than `max` bytes, failing the read
+    /// with an error of kind `RPCErrorKind::MessageTooLarge` rather
+    /// than buffering it. Guards servers exposed to untrusted peers
+    /// against running out of memory. The rest of an oversized
+    /// message is left unread, so the connection cannot be used
+    /// afterwards.
+    pub fn with_max_message_bytes(mut self, max: usize) -> Self {
+        self.max_message_bytes = Some(max);
+        self
+    }
+
+    /// Refuse any message whose arrays and objects are nested more
+    /// than `depth` deep, counting the request or response object
+    /// itself, with an error of kind
+    /// `RPCErrorKind::SerializationError`. Messages nested more than
+    /// 128 deep are always refused, as serde_json does not go deeper
+    /// for fear of overflowing the stack.
+    pub fn with_max_depth(mut self, depth: usize) -> Self {
+        self.codec = mem::take(&mut self.codec).with_max_depth(depth);
+        self
+    }
+
+    /// Choose whether the server refuses requests whose `jsonrpc`
+    /// member is missing or not `"2.0"`, as the JSON-RPC 2.0
+    /// specification asks. Such requests are answered with an
+    /// invalid request error, code -32600, and the server goes on to
+    /// the next call. Strict by default; pass `false` to serve older
+    /// peers, which can be told apart by
+    /// [JRXState::version](struct.JRXState.html#method.version).
+    pub fn with_strict_version(mut self, strict: bool) -> Self {
+        self.codec = mem::take(&mut self.codec).with_strict_version(strict);
+        self
+    }
+
+    /// Choose whether the server leaves the `params` of each request
+    /// as JSON text, deserializing each parameter straight from it
+    /// as the method reads it, rather than parsing them all into a
+    /// `serde_json::Value` and deserializing each parameter from
+    /// that. Saves building and dropping the `Value` for requests
+    /// with large parameters; see `benches/params.rs`. Off by
+    /// default. Only requests read on their own are served lazily,
+    /// not the calls of a batch, and
+    /// [JRXState::params](struct.JRXState.html#method.params) parses
+    /// the text afresh when called.
+    pub fn with_lazy_params(mut self, lazy: bool) -> Self {
+        self.codec = mem::take(&mut self.codec).with_lazy_params(lazy);
+        self
+    }
+
+    /// Use `method` as the name of the keep-alive ping, rather than
+    /// `"rpc.ping"`, e.g. if a service already has a method of that
+    /// name. Both ends must agree on it. See `ping`.
+    pub fn with_ping_method(mut self, method: impl Into<String>) -> Self {
+        self.codec = mem::take(&mut self.codec).with_ping_method(method);
+        self
+    }
+
+    /// Check that the connection is still alive by sending a ping
+    /// and waiting for the server to answer it. The server transport
+    /// answers the ping itself, without involving the server's
+    /// methods, and goes on to the next call. Calling this
+    /// periodically between calls, with a timeout set by
+    /// `with_timeout`, keeps idle connections from being dropped by
+    /// load balancers and finds those which have been. Must not be
+    /// called while the response to a call is outstanding.
+    pub fn ping(&mut self) -> Result<()> {
+        let request = self.codec.ping_request();
+        self.write_to_channel(&request)?;
+        let response = self.read_response(request.id(), false)?;
+        self.codec.check_response_id(&response, request.id())?;
+        result_from_response::<std::result::Result<Value, Value>>(response, NonFiniteFloats::Null)
+            .map(|_| ())
     }
 
-    // Deserialize a value from the channel
-    fn read_from_channel<T>(&mut self) -> Result<T>
+    /// Fail any read from the channel which takes longer than
+    /// `timeout` with an error of kind `RPCErrorKind::Timeout`. On
+    /// the client this bounds how long a call waits for its
+    /// response. Only available for channels implementing
+    /// [ReadTimeout](trait.ReadTimeout.html), such as `TcpStream` and
+    /// `UnixStream`. Implies `with_call_timeouts`, with `timeout`
+    /// applying to calls to methods without a timeout of their own.
+    ///
+    /// A read which times out once part of a message has arrived
+    /// leaves the rest of it on the channel, so every later read or
+    /// write fails with an error of kind
+    /// `RPCErrorKind::TransportError` and the transport must be
+    /// discarded. One which times out before any of the message has
+    /// arrived leaves the transport usable: should the response to a
+    /// call which timed out arrive later, it is skipped by the next
+    /// call, unless the transport is `raw`, whose responses have
+    /// no ids, in which case the transport is unusable too.
+    pub fn with_timeout(mut self, timeout: Duration) -> Result<Self>
     where
-        for<'de> T: serde::Deserialize<'de>,
+        C: ReadTimeout,
     {
-        read_value_from_json(Read::by_ref(&mut self.channel))
+        open(&self.channel)
+            .set_read_timeout(Some(timeout))
+            .map_err(timeout_error)?;
+        self.timeout = Some(timeout);
+        Ok(self.with_call_timeouts())
+    }
+
+    /// Exchange greetings with the peer, each end sending a magic
+    /// string and the version of essrpc's protocol it speaks,
+    /// `{"essrpc": 1}`, and checking the other's. Pointed at a peer
+    /// which is not essrpc, or speaks another version, the transport
+    /// fails here with an error of kind
+    /// `RPCErrorKind::HandshakeFailed`, rather than hanging or
+    /// failing confusingly on the first call. Both ends must do it,
+    /// and plain JSON-RPC peers know nothing of it, so it is off by
+    /// default. Call it once the transport is otherwise set up, as it
+    /// sends and reads at once. Reading blocks until the peer's
+    /// greeting arrives; against a peer which may send nothing, set a
+    /// timeout first with `with_timeout`, whose expiry also fails the
+    /// handshake.
+    pub fn with_handshake(mut self) -> Result<Self> {
+        let failed = |e| {
+            RPCError::with_cause(
+                RPCErrorKind::HandshakeFailed,
+                "essrpc greetings not exchanged",
+                e,
+            )
+        };
+        self.write_to_channel(&handshake_greeting())
+            .map_err(failed)?;
+        let greeting = self.read_from_channel().map_err(failed)?;
+        check_handshake(&greeting)?;
+        Ok(self)
+    }
+
+    /// Let the client time out calls to methods marked
+    /// `#[essrpc(timeout_ms = ...)]` by setting the channel's read
+    /// timeout for the duration of each call. Without this, or
+    /// `with_timeout`, such calls fail. Only available for channels
+    /// implementing [ReadTimeout](trait.ReadTimeout.html). A call
+    /// which times out part way through its response leaves the
+    /// transport unusable, as described for `with_timeout`.
+    pub fn with_call_timeouts(mut self) -> Self
+    where
+        C: ReadTimeout,
+    {
+        self.set_read_timeout = Some(C::set_read_timeout);
+        self
+    }
+
+    /// Let the server stop a streaming response early when the
+    /// client cancels it, by dropping the stream, or disconnects. The
+    /// server checks for the client's `$cancel` notification, whose
+    /// `params.id` is the id of the request, before sending each item.
+    /// Without this, the server sends every item regardless, and the
+    /// client reads and discards those it no longer wants. Only
+    /// available for channels implementing
+    /// [ReadReady](trait.ReadReady.html), such as `TcpStream`.
+    pub fn with_cancellation(mut self) -> Self
+    where
+        C: ReadReady,
+    {
+        self.read_ready = Some(C::read_ready);
+        self
+    }
+
+    /// Choose whether each message is written and flushed to the
+    /// channel as soon as it is sent, as it is by default. Without
+    /// auto flush, requests, notifications and responses are held by
+    /// the transport until `flush` is called, so that many small
+    /// calls can be written together rather than with a system call
+    /// each. Nothing held is seen by the peer, so no response to it
+    /// comes back, until it is flushed. Anything held is flushed
+    /// before the transport waits to read, so that calls through a
+    /// generated client still complete, but a notification is held
+    /// indefinitely if nothing is read after it, and anything held
+    /// when the transport is dropped or `into_channel` is called is
+    /// flushed then, errors and all being ignored. See `close`.
+    pub fn set_auto_flush(&mut self, auto_flush: bool) {
+        self.auto_flush = auto_flush;
+    }
+
+    /// Write anything held since the last flush to the channel, then
+    /// flush the channel. See `set_auto_flush`.
+    pub fn flush(&mut self) -> Result<()> {
+        self.check_usable()?;
+        if !self.write_buf.is_empty() {
+            let result = open_mut(&mut self.channel).write_all(&self.write_buf);
+            self.write_buf.clear();
+            result.map_err(write_error)?;
+        }
+        flush_channel(open_mut(&mut self.channel))
+    }
+
+    /// Sizes and duration of the last call made through this
+    /// transport as a client to complete, or `None` before any has.
+    /// A call completes when its response is read, or for a streaming
+    /// method, once the stream ends; a notification completes once
+    /// sent. Calls made through a `JSONBatch` are not counted. Also
+    /// see `ObservedTransport`, which reports the same sizes for
+    /// every call to an `Observer`.
+    pub fn last_stats(&self) -> Option<CallStats> {
+        self.last_stats
+    }
+
+    // Count the response just read towards the pending call's stats,
+    // completing them if it is the last
+    fn record_response(&mut self, last: bool) {
+        if let Some((start, stats)) = &mut self.pending_stats {
+            stats.response_bytes += self.read_bytes;
+            if last {
+                stats.duration = start.elapsed();
+                self.last_stats = Some(*stats);
+                self.pending_stats = None;
+            }
+        }
+    }
+
+    // Read a message from the channel, after flushing anything held
+    fn read_from_channel(&mut self) -> Result<Value> {
+        if !self.write_buf.is_empty() {
+            self.flush()?;
+        }
+        let value = self.read_unchecked()?;
+        self.codec.check_depth(&value)?;
+        Ok(value)
+    }
+
+    // Read the response to the call with the given id, skipping any
+    // to calls which timed out. Should this call time out in turn, its
+    // response is skipped once it arrives. Raw responses have no ids
+    // to tell them apart by, so a timeout leaves the transport
+    // unusable instead.
+    fn read_response(&mut self, id: &Value, stream: bool) -> Result<Value> {
+        loop {
+            let response = match self.read_from_channel() {
+                Ok(response) => response,
+                Err(e) if e.kind == RPCErrorKind::Timeout => {
+                    if self.codec.is_raw() {
+                        self.unusable = Some("a call timed out, leaving its response unread");
+                    } else {
+                        self.abandoned.push((id.clone(), stream));
+                    }
+                    return Err(e);
+                }
+                Err(e) => return Err(e),
+            };
+            let abandoned = response
+                .get("id")
+                .and_then(|rid| self.abandoned.iter().position(|(id, _)| id == rid));
+            match abandoned {
+                Some(i) => {
+                    if !self.abandoned[i].1 || is_stream_end(&response) {
+                        self.abandoned.remove(i);
+                    }
+                }
+                None => return Ok(response),
+            }
+        }
+    }
+
+    // Read a request for a server with lazy params
+    fn read_lazily(&mut self) -> Result<(Value, Option<Box<RawValue>>)> {
+        if !self.write_buf.is_empty() {
+            self.flush()?;
+        }
+        let message: Message = self.read_unchecked()?;
+        self.codec.request_from_message(message)
+    }
+
+    fn read_unchecked<T: DeserializeOwned>(&mut self) -> Result<T> {
+        self.check_usable()?;
+        // Only counts what the frame is read through
+        let mut counted = LimitedRead {
+            inner: open_mut(&mut self.channel),
+            remaining: usize::MAX,
+            exceeded: false,
+            started: false,
+        };
+        let frame = read_frame(&mut counted, self.framing, self.max_message_bytes);
+        let started = counted.remaining < usize::MAX;
+        if let Some(frame) = frame.map_err(|e| self.timed_out(e, started))? {
+            self.read_bytes = frame.len();
+            return serde_json::from_slice(&frame).map_err(|e| decode_error(e, &frame));
+        }
+        let max = self.max_message_bytes.unwrap_or(usize::MAX);
+        let mut limited = LimitedRead {
+            inner: open_mut(&mut self.channel),
+            remaining: max,
+            exceeded: false,
+            started: false,
+        };
+        let result = read_value_from_json(&mut limited);
+        self.read_bytes = max - limited.remaining;
+        if limited.exceeded {
+            return Err(RPCError::new(
+                RPCErrorKind::MessageTooLarge,
+                format!("json message exceeds {} bytes", max),
+            ));
+        }
+        let started = limited.started;
+        result.map_err(|e| self.timed_out(e, started))
+    }
+
+    // Pass on an error reading a message, first marking the
+    // transport unusable if the read timed out with the message
+    // started, as the rest of it would be taken for the next
+    fn timed_out(&mut self, e: RPCError, started: bool) -> RPCError {
+        if started && e.kind == RPCErrorKind::Timeout {
+            self.unusable = Some("a read timed out part way through a message");
+        }
+        e
+    }
+
+    fn check_usable(&self) -> Result<()> {
+        match self.unusable {
+            Some(reason) => Err(RPCError::new(
+                RPCErrorKind::TransportError,
+                format!("json transport unusable: {}", reason),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    // Serialize a value to the channel, or hold it until flushed
+    fn write_to_channel(&mut self, value: &impl Serialize) -> Result<()> {
+        self.check_usable()?;
+        let data = self.codec.encode_value(value)?;
+        self.written_bytes = data.len();
+        if self.auto_flush && self.write_buf.is_empty() {
+            return write_message(open_mut(&mut self.channel), &data, self.framing);
+        }
+        write_message(&mut self.write_buf, &data, self.framing)?;
+        if self.auto_flush {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Encode a call as this transport would send it, without
+    /// sending it: the request, with any context and framing, is
+    /// returned as bytes. `params` are given in the order the method
+    /// declares them. Useful for logging, fuzzing and golden tests of
+    /// the wire format, bearing in mind that with the "uuid_ids"
+    /// feature the request id is random unless set with
+    /// `use_sequential_ids` or `set_id_generator`.
+    pub fn encode_call(
+        &self,
+        method: MethodId,
+        params: &[(&'static str, Value)],
+    ) -> Result<Vec<u8>> {
+        let mut state = self.codec.begin_call(method);
+        for (name, value) in params {
+            self.codec.add_param(name, value, &mut state)?;
+        }
+        let (data, _) = self.codec.encode_call(&state)?;
+        let mut bytes = Vec::new();
+        write_message(&mut bytes, &data, self.framing)?;
+        Ok(bytes)
+    }
+
+    /// Decode a single request, such as one produced by
+    /// `encode_call`, as the server side of this transport would
+    /// receive it. Returns the method called and the state from which
+    /// its parameters can be read with
+    /// [JRXState::params](struct.JRXState.html#method.params).
+    pub fn decode_call(&self, bytes: &[u8]) -> Result<(PartialMethodId, JRXState)> {
+        match read_frame(bytes, self.framing, self.max_message_bytes)? {
+            Some(frame) => self.codec.decode_call(&frame),
+            None => self.codec.decode_call(bytes),
+        }
     }
 }
+
+fn timeout_error(e: io::Error) -> RPCError {
+    RPCError::with_cause(
+        RPCErrorKind::TransportError,
+        "cannot set channel read timeout",
+        e,
+    )
+}
+
+// Errors writing to the channel, reported as serde_json reports them
+fn write_error(e: io::Error) -> RPCError {
+    convert_error(serde_json::Error::io(e))
+}
+
+// The transport's channel, present until the transport is consumed
+fn open<C>(channel: &Option<C>) -> &C {
+    channel
+        .as_ref()
+        .expect("json transport used after its channel was taken")
+}
+
+fn open_mut<C>(channel: &mut Option<C>) -> &mut C {
+    channel
+        .as_mut()
+        .expect("json transport used after its channel was taken")
+}
+
+// Write one encoded message to the channel, framed if need be
+fn write_message(mut channel: impl Write, data: &[u8], framing: Framing) -> Result<()> {
+    match framing {
+        Framing::Unframed => {
+            channel.write_all(data).map_err(write_error)?;
+            flush_channel(channel)
+        }
+        Framing::Length => framing::write_frame(channel, data),
+        #[cfg(feature = "compression")]
+        Framing::Compressed(threshold) => framing::write_flagged_frame(channel, data, threshold),
+    }
+}
+
+// Read the contents of one frame from the channel, or None if
+// messages are not framed, leaving the channel untouched.
+fn read_frame(channel: impl Read, framing: Framing, max: Option<usize>) -> Result<Option<Vec<u8>>> {
+    match framing {
+        Framing::Unframed => Ok(None),
+        Framing::Length => framing::read_frame(channel, max).map(Some),
+        #[cfg(feature = "compression")]
+        Framing::Compressed(_) => framing::read_flagged_frame(channel, max).map(Some),
+    }
+}
+
+impl JSONTransport<TcpStream> {
+    /// Connect to `addr` over TCP. Failure to resolve the address or
+    /// to connect is reported with `RPCErrorKind::ConnectionFailed`,
+    /// carrying the OS error as its cause, so that it can be told
+    /// apart from errors in the calls themselves.
+    pub fn connect_tcp(addr: impl ToSocketAddrs) -> Result<Self> {
+        Ok(Self::new(connect_tcp(addr)?))
+    }
+
+    /// Bind a TCP listener to `addr` and serve each client which
+    /// connects on its own thread, as with
+    /// [serve_listener](#method.serve_listener). Only returns if
+    /// binding fails.
+    ///
+    /// ```ignore
+    /// JSONTransport::serve_tcp("0.0.0.0:4000", |transport| {
+    ///     FooRPCServer::new(FooImpl::new(), transport)
+    /// })
+    /// ```
+    pub fn serve_tcp<S, F>(addr: impl ToSocketAddrs, make_server: F) -> Result<Infallible>
+    where
+        S: RPCServer + Send + 'static,
+        F: FnMut(Self) -> S,
+    {
+        let listener = TcpListener::bind(addr).map_err(|e| {
+            RPCError::with_cause(RPCErrorKind::TransportError, "tcp bind failed", e)
+        })?;
+        Self::serve_listener(listener, make_server)
+    }
+
+    /// Accept clients from `listener` indefinitely, creating a server
+    /// for each with `make_server` and running its `serve_loop` on a
+    /// new thread. A client disconnecting ends its thread quietly;
+    /// other errors serving a client, and failures to accept one, are
+    /// logged with the `log` crate, the latter being retried after a
+    /// short pause. Never returns.
+    pub fn serve_listener<S, F>(listener: TcpListener, mut make_server: F) -> Result<Infallible>
+    where
+        S: RPCServer + Send + 'static,
+        F: FnMut(Self) -> S,
+    {
+        loop {
+            let stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    log::warn!("tcp accept failed: {}", e);
+                    thread::sleep(ACCEPT_RETRY_DELAY);
+                    continue;
+                }
+            };
+            let mut server = make_server(Self::new(stream));
+            thread::spawn(move || {
+                if let Err(e) = server.serve_loop() {
+                    log::warn!("error serving tcp client: {}", e);
+                }
+            });
+        }
+    }
+}
+
+/// [JSONTransport](struct.JSONTransport.html) over the separate read
+/// and write halves of a connection, such as a `TcpStream` and its
+/// `try_clone`, combined by [ReadWrite](struct.ReadWrite.html). The
+/// halves stay reachable through `channel_mut`, and the transport
+/// times out reads as the read half does. See
+/// [AsyncSplitTransport](type.AsyncSplitTransport.html) for the
+/// asynchronous equivalent.
+pub type SplitTransport<R, W> = JSONTransport<ReadWrite<R, W>>;
+
+impl<R: Read, W: Write> SplitTransport<R, W> {
+    /// Create a transport reading from `reader` and writing to `writer`.
+    pub fn from_halves(reader: R, writer: W) -> Self {
+        Self::new(ReadWrite::new(reader, writer))
+    }
+}
+
+// Messages held without auto flush, or buffered by the channel, would
+// otherwise be lost silently. There is nowhere to report an error.
+impl<C: Read + Write> Drop for JSONTransport<C> {
+    fn drop(&mut self) {
+        if self.channel.is_some() {
+            let _ = self.flush();
+        }
+    }
+}
+
 impl<C: Read + Write> ClientTransport for JSONTransport<C> {
     type TXState = JTXState;
-    type FinalState = ();
+    /// The id of the request, checked against the response.
+    type FinalState = Value;
 
     fn tx_begin_call(&mut self, method: MethodId) -> Result<JTXState> {
-        Ok(begin_call(method))
+        Ok(self.codec.begin_call(method))
     }
 
     fn tx_add_param(
@@ -60,49 +832,254 @@ impl<C: Read + Write> ClientTransport for JSONTransport<C> {
         add_param(name, value, state)
     }
 
-    fn tx_finalize(&mut self, state: JTXState) -> Result<()> {
-        serde_json::to_writer(Write::by_ref(&mut self.channel), &value_for_state(&state))
-            .map_err(convert_error)
+    fn tx_set_service_version(&mut self, version: u32, state: &mut JTXState) -> Result<()> {
+        set_service_version(version, state);
+        Ok(())
     }
 
-    fn rx_response<T>(&mut self, _state: ()) -> Result<T>
+    fn tx_finalize(&mut self, state: JTXState) -> Result<Value> {
+        let request = self.codec.request_value(&state, false);
+        let start = Instant::now();
+        self.write_to_channel(&request)?;
+        self.request_bytes = self.written_bytes;
+        self.pending_stats = Some((
+            start,
+            CallStats {
+                request_bytes: self.written_bytes,
+                ..CallStats::default()
+            },
+        ));
+        Ok(request.id().clone())
+    }
+
+    fn tx_notify(&mut self, state: JTXState) -> Result<()> {
+        let notification = self.codec.request_value(&state, true);
+        self.write_to_channel(&notification)?;
+        self.request_bytes = self.written_bytes;
+        self.last_stats = Some(CallStats {
+            request_bytes: self.written_bytes,
+            ..CallStats::default()
+        });
+        Ok(())
+    }
+
+    fn rx_response<T>(&mut self, id: Value) -> Result<T>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        let response = self.read_response(&id, false)?;
+        self.codec.check_response_id(&response, &id)?;
+        self.record_response(true);
+        result_from_response(response, self.codec.non_finite_floats())
+    }
+
+    fn rx_stream_item<T>(&mut self, id: &mut Value) -> Result<Option<T>>
     where
         for<'de> T: Deserialize<'de>,
     {
-        self.read_from_channel()
+        let response = self.read_response(id, true)?;
+        self.codec.check_response_id(&response, id)?;
+        let item = stream_item_from_response(response, self.codec.non_finite_floats());
+        self.record_response(!matches!(item, Ok(Some(_))));
+        item
+    }
+
+    // Items the server sent before it saw the cancellation, or all of
+    // them if it does not look for one, are read up to the end of
+    // the stream.
+    fn tx_cancel_stream(&mut self, id: Value) -> Result<()> {
+        let request = self.codec.cancel_request(&id);
+        self.write_to_channel(&request)?;
+        loop {
+            let response = self.read_response(&id, true)?;
+            self.codec.check_response_id(&response, &id)?;
+            let item = stream_item_from_response::<std::result::Result<IgnoredAny, IgnoredAny>>(
+                response,
+                NonFiniteFloats::Null,
+            )?;
+            if item.is_none() {
+                return Ok(());
+            }
+        }
+    }
+
+    fn rx_response_bytes(&self) -> Option<usize> {
+        Some(self.read_bytes)
+    }
+
+    fn tx_request_bytes(&self) -> Option<usize> {
+        Some(self.request_bytes)
+    }
+
+    fn tx_set_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        let set_read_timeout = self.set_read_timeout.ok_or_else(|| {
+            RPCError::new(
+                RPCErrorKind::Other,
+                "json transport needs with_call_timeouts for per-call timeouts",
+            )
+        })?;
+        set_read_timeout(open(&self.channel), timeout.or(self.timeout)).map_err(timeout_error)
     }
 }
 
-fn convert_error(e: impl std::error::Error) -> RPCError {
-    RPCError::with_cause(
-        RPCErrorKind::SerializationError,
-        "json serialization or deserialization failed",
-        e,
-    )
+/// A JSON-RPC batch, created by
+/// [JSONTransport::begin_batch](struct.JSONTransport.html#method.begin_batch).
+/// Implements `ClientTransport` so calls are built exactly as they
+/// would be directly on the `JSONTransport`. `tx_finalize` only
+/// queues a call; the whole batch is written as one array the first
+/// time `rx_response` is called, after which no more calls may be
+/// added. The state returned from `tx_finalize` identifies the
+/// position of the call within the batch. Responses are matched to
+/// calls by their JSON-RPC id, so they may be read in any order and
+/// the server may answer in any order. Notifications sent with
+/// `tx_notify` are queued too and get no response, so a batch must
+/// include at least one ordinary call to be sent.
+pub struct JSONBatch<'a, C: Read + Write> {
+    transport: &'a mut JSONTransport<C>,
+    requests: Vec<Request>,
+    responses: Option<Vec<Option<Value>>>,
 }
 
-fn begin_call(method: MethodId) -> JTXState {
-    JTXState {
-        method: method.name,
-        params: json!({}),
+impl<'a, C: Read + Write> JSONBatch<'a, C> {
+    /// Number of calls in the batch.
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    /// True if no calls have been added to the batch.
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    // Write all queued requests and read back the array of responses.
+    fn transact(&mut self) -> Result<Vec<Option<Value>>> {
+        if self.transport.codec.is_raw() {
+            return Err(RPCError::new(
+                RPCErrorKind::IllegalState,
+                "json batches need the JSON-RPC envelope",
+            ));
+        }
+        self.transport.write_to_channel(&self.requests)?;
+        let responses: Vec<Value> =
+            serde_json::from_value(self.transport.read_from_channel()?).map_err(convert_error)?;
+        // Notifications get no response
+        let calls = self
+            .requests
+            .iter()
+            .filter(|r| !r.is_notification())
+            .count();
+        if responses.len() != calls {
+            return Err(RPCError::new(
+                RPCErrorKind::SerializationError,
+                format!(
+                    "json batch of {} calls received {} responses",
+                    calls,
+                    responses.len()
+                ),
+            ));
+        }
+        Ok(responses.into_iter().map(Some).collect())
     }
 }
 
-fn value_for_state(state: &JTXState) -> serde_json::Value {
-    json!({
-        "jsonrpc": "2.0",
-        "method": state.method,
-        "params": state.params,
-        "id": format!("{}", Uuid::new_v4())
-    })
+impl<'a, C: Read + Write> ClientTransport for JSONBatch<'a, C> {
+    type TXState = JTXState;
+    type FinalState = usize;
+
+    fn tx_begin_call(&mut self, method: MethodId) -> Result<JTXState> {
+        Ok(self.transport.codec.begin_call(method))
+    }
+
+    fn tx_add_param(
+        &mut self,
+        name: &'static str,
+        value: impl Serialize,
+        state: &mut JTXState,
+    ) -> Result<()> {
+        add_param(name, value, state)
+    }
+
+    fn tx_set_service_version(&mut self, version: u32, state: &mut JTXState) -> Result<()> {
+        set_service_version(version, state);
+        Ok(())
+    }
+
+    fn tx_finalize(&mut self, state: JTXState) -> Result<usize> {
+        if self.responses.is_some() {
+            return Err(RPCError::new(
+                RPCErrorKind::IllegalState,
+                "json batch has already been sent",
+            ));
+        }
+        self.requests
+            .push(self.transport.codec.request_value(&state, false));
+        Ok(self.requests.len() - 1)
+    }
+
+    fn tx_notify(&mut self, state: JTXState) -> Result<()> {
+        if self.responses.is_some() {
+            return Err(RPCError::new(
+                RPCErrorKind::IllegalState,
+                "json batch has already been sent",
+            ));
+        }
+        self.requests
+            .push(self.transport.codec.request_value(&state, true));
+        Ok(())
+    }
+
+    fn rx_response<T>(&mut self, index: usize) -> Result<T>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        if self.responses.is_none() {
+            self.responses = Some(self.transact()?);
+        }
+        let id = self.requests.get(index).map(|r| r.id());
+        let response = self
+            .responses
+            .as_mut()
+            .and_then(|r| {
+                r.iter_mut()
+                    .find(|r| r.as_ref().map(|r| Some(&r["id"])) == Some(id))
+            })
+            .and_then(Option::take)
+            .ok_or_else(|| {
+                RPCError::new(
+                    RPCErrorKind::IllegalState,
+                    format!("no response for json batch call {}", index),
+                )
+            })?;
+        result_from_response(response, self.transport.codec.non_finite_floats())
+    }
+}
+
+// Reader failing once more than `remaining` bytes have been read
+// through it, to bound the size of a single message.
+struct LimitedRead<R> {
+    inner: R,
+    remaining: usize,
+    exceeded: bool,
+    // Whether anything but whitespace has been read, i.e. a json
+    // message begun
+    started: bool,
 }
 
-fn add_param(name: &'static str, value: impl Serialize, state: &mut JTXState) -> Result<()> {
-    state.params.as_object_mut().unwrap().insert(
-        name.to_string(),
-        serde_json::to_value(value).map_err(convert_error)?,
-    );
-    Ok(())
+impl<R: Read> Read for LimitedRead<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 && !buf.is_empty() {
+            self.exceeded = true;
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "message size limit exceeded",
+            ));
+        }
+        let len = buf.len().min(self.remaining);
+        let n = self.inner.read(&mut buf[..len])?;
+        self.remaining -= n;
+        self.started |= buf[..n].iter().any(|b| !b" \t\n\r".contains(b));
+        Ok(n)
+    }
 }
 
 fn read_value_from_json<T, R>(reader: R) -> Result<T>
@@ -113,12 +1090,15 @@ where
     let read = serde_json::de::IoRead::new(reader);
     let mut de = serde_json::de::Deserializer::new(read);
     serde::de::Deserialize::deserialize(&mut de).map_err(|e| {
-        println!("classification {:?}", e.classify());
         if e.classify() == serde_json::error::Category::Eof {
             RPCError::new(
                 RPCErrorKind::TransportEOF,
                 "EOF during json deserialization",
             )
+        } else if let Some(io::ErrorKind::WouldBlock) | Some(io::ErrorKind::TimedOut) =
+            e.io_error_kind()
+        {
+            RPCError::with_cause(RPCErrorKind::Timeout, "timed out reading json", e)
         } else {
             convert_error(e)
         }
@@ -129,95 +1109,467 @@ impl<C: Read + Write> ServerTransport for JSONTransport<C> {
     type RXState = JRXState;
 
     fn rx_begin_call(&mut self) -> Result<(PartialMethodId, JRXState)> {
-        let value: Value = self.read_from_channel()?;
-        let method = value
-            .get("method")
-            .ok_or_else(|| {
-                RPCError::new(
-                    RPCErrorKind::SerializationError,
-                    "json is not expected object",
-                )
-            })?
-            .as_str()
-            .ok_or_else(|| {
-                RPCError::new(
-                    RPCErrorKind::SerializationError,
-                    "json method was not string",
-                )
-            })?
-            .to_string();
-        Ok((PartialMethodId::Name(method), JRXState { json: value }))
+        let (method, state) = self.next_call()?;
+        if let Some(hook) = &mut self.call_hook {
+            hook(&state);
+        }
+        Ok((method, state))
     }
 
     fn rx_read_param<T>(&mut self, name: &'static str, state: &mut JRXState) -> Result<T>
     where
         for<'de> T: serde::Deserialize<'de>,
     {
-        let param_val = state
-            .json
-            .get("params")
-            .ok_or_else(|| {
-                RPCError::new(
-                    RPCErrorKind::SerializationError,
-                    "json is not expected object",
-                )
-            })?
-            .get(name)
-            .ok_or_else(|| {
-                RPCError::new(
-                    RPCErrorKind::SerializationError,
-                    format!("parameters do not contain {}", name),
-                )
-            })?;
-        serde_json::from_value(param_val.clone()).map_err(convert_error)
+        let param = read_param(name, state);
+        self.answer_param_error(param, state)
+    }
+
+    fn rx_read_param_opt<T>(
+        &mut self,
+        name: &'static str,
+        state: &mut JRXState,
+    ) -> Result<Option<T>>
+    where
+        for<'de> T: serde::Deserialize<'de>,
+    {
+        let param = read_param_opt(name, state);
+        self.answer_param_error(param, state)
+    }
+
+    fn tx_response(
+        &mut self,
+        value: std::result::Result<impl Serialize, impl Serialize>,
+        state: JRXState,
+    ) -> Result<()> {
+        if state.is_notification() {
+            return self.finish_call(None);
+        }
+        let response = response_for_result(value, state.id, state.floats)?;
+        self.finish_call(Some(response))
+    }
+
+    fn rx_service_version(&self, state: &JRXState) -> Option<u32> {
+        state.service_version()
+    }
+
+    /// Does nothing: `rx_begin_call` reads the whole request, so a
+    /// parameter failing to deserialize leaves nothing of it on the
+    /// channel.
+    fn discard_remaining(&mut self, _state: &mut JRXState) -> Result<()> {
+        Ok(())
+    }
+
+    fn tx_error(&mut self, error: RPCError, state: JRXState) -> Result<()> {
+        if state.is_notification() {
+            return self.finish_call(None);
+        }
+        self.finish_call(Some(response_for_error(&error, state.id)))
+    }
+
+    fn tx_no_response(&mut self, _state: JRXState) -> Result<()> {
+        self.finish_call(None)
+    }
+
+    /// Serve every request as a notification, including those the
+    /// transport would answer itself: pings, requests of the wrong
+    /// JSON-RPC version, and messages which are not requests or not
+    /// JSON at all are passed over or returned as errors without a
+    /// word to the client.
+    fn set_notifications_only(&mut self) {
+        self.codec.set_notifications_only();
+    }
+
+    fn tx_stream_item(
+        &mut self,
+        value: std::result::Result<impl Serialize, impl Serialize>,
+        state: &mut JRXState,
+    ) -> Result<()> {
+        self.check_not_batch()?;
+        if state.is_notification() {
+            return Ok(());
+        }
+        let response = response_for_result(value, state.id.clone(), state.floats)?;
+        self.write_to_channel(&response)
+    }
+
+    fn tx_stream_end(&mut self, state: JRXState) -> Result<()> {
+        self.check_not_batch()?;
+        if state.is_notification() {
+            return Ok(());
+        }
+        self.write_to_channel(&stream_end_response(state.id))
+    }
+
+    fn rx_stream_cancelled(&mut self, state: &mut JRXState) -> Result<bool> {
+        let read_ready = match self.read_ready {
+            Some(read_ready) => read_ready,
+            None => return Ok(false),
+        };
+        while read_ready(open(&self.channel)).map_err(|e| {
+            RPCError::with_cause(RPCErrorKind::TransportError, "json channel poll failed", e)
+        })? {
+            let request = self.read_from_channel()?;
+            match self.codec.cancel_target(&request) {
+                Some(id) if *id == state.id => return Ok(true),
+                // Too late for an earlier stream
+                Some(_) => (),
+                None => self.deferred.push_back(request),
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl<C: Read + Write> JSONTransport<C> {
+    // The next call, answering and skipping any request of the
+    // wrong JSON-RPC version. Messages which are not JSON, or not
+    // requests, are answered with the JSON-RPC error for them before
+    // the error is returned.
+    fn next_call(&mut self) -> Result<(PartialMethodId, JRXState)> {
+        loop {
+            let (request, params) = match self.next_request() {
+                Err(e)
+                    if matches!(
+                        e.kind,
+                        RPCErrorKind::ParseError | RPCErrorKind::InvalidRequest
+                    ) =>
+                {
+                    // The peer may well have gone, so failing to tell
+                    // it is no worse than the error itself
+                    if !self.codec.is_notifications_only() {
+                        let _ = self.write_to_channel(&response_for_error(&e, Value::Null));
+                    }
+                    return Err(e);
+                }
+                request => request?,
+            };
+            if let Some(pong) = self.codec.pong_response(&request) {
+                self.finish_call(pong)?;
+                continue;
+            }
+            // A cancellation which arrived after the stream ended
+            if self.codec.cancel_target(&request).is_some() {
+                continue;
+            }
+            match self.codec.version_response(&request) {
+                Some(response) => self.finish_call(Some(response))?,
+                None => {
+                    let id = request_id(&request);
+                    return self
+                        .codec
+                        .call_with_params(request, params)
+                        .inspect_err(|e| {
+                            let _ = self.finish_call(Some(response_for_error(e, id)));
+                        });
+                }
+            }
+        }
+    }
+
+    // The next request: from the batch being served, if any, or else
+    // one deferred while streaming or from the channel, either of
+    // which may begin a new batch. Along with it comes the text of
+    // its params, if they were left unparsed.
+    fn next_request(&mut self) -> Result<(Value, Option<Box<RawValue>>)> {
+        if let Some((pending, _)) = &mut self.batch {
+            if let Some(value) = pending.pop_front() {
+                return Ok((value, None));
+            }
+        }
+        let (request, params) = match self.deferred.pop_front() {
+            Some(request) => (request, None),
+            None if self.codec.is_lazy() => self.read_lazily()?,
+            None => (self.read_from_channel()?, None),
+        };
+        match request {
+            Value::Array(calls) => {
+                let mut pending = VecDeque::from(calls);
+                let first = pending.pop_front().ok_or_else(|| {
+                    RPCError::new(RPCErrorKind::InvalidRequest, "json batch is empty")
+                })?;
+                let responses = Vec::with_capacity(pending.len() + 1);
+                self.batch = Some((pending, responses));
+                Ok((first, None))
+            }
+            value => Ok((value, params)),
+        }
+    }
+
+    // Responses within a batch are sent together in one array, which
+    // leaves no room for streaming.
+    fn check_not_batch(&self) -> Result<()> {
+        match self.batch {
+            Some(_) => Err(RPCError::new(
+                RPCErrorKind::IllegalState,
+                "json batch calls cannot stream responses",
+            )),
+            None => Ok(()),
+        }
     }
 
-    fn tx_response(&mut self, value: impl Serialize) -> Result<()> {
-        serde_json::to_writer(Write::by_ref(&mut self.channel), &value).map_err(convert_error)
+    // A parameter which is missing or has the wrong type ends the
+    // call, which is answered with the JSON-RPC error for it (unless
+    // it is a notification) before the error is returned.
+    fn answer_param_error<T>(&mut self, param: Result<T>, state: &JRXState) -> Result<T> {
+        param.inspect_err(|e| {
+            let response = if state.is_notification() {
+                None
+            } else {
+                Some(response_for_error(e, state.id.clone()))
+            };
+            let _ = self.finish_call(response);
+        })
+    }
+
+    // Write the response to a call, if any, or hold it until the rest
+    // of the batch is answered. A batch consisting only of
+    // notifications gets no response at all.
+    fn finish_call(&mut self, response: Option<Value>) -> Result<()> {
+        let response = response.filter(|_| !self.codec.is_notifications_only());
+        if let Some((pending, mut responses)) = self.batch.take() {
+            responses.extend(response);
+            if !pending.is_empty() {
+                self.batch = Some((pending, responses));
+                return Ok(());
+            }
+            if responses.is_empty() {
+                return Ok(());
+            }
+            return self.write_to_channel(&responses);
+        }
+        match response {
+            Some(response) => self.write_to_channel(&response),
+            None => Ok(()),
+        }
     }
 }
 
 #[cfg(feature = "async_client")]
 mod async_client {
+    use super::super::json_framer::ValueFramer;
     use super::*;
     use crate::{AsyncClientTransport, BoxFuture};
-    use std::ops::Deref;
-    use futures::{Future, TryFutureExt, FutureExt};
+    use futures::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+    use futures::lock::Mutex;
+    use futures::{Future, FutureExt, TryFutureExt};
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::rc::Rc;
 
-		type FutureBytes = BoxFuture<Vec<u8>, RPCError>;
-		
-		/// Like JSONTransport except for use as AsyncClientTransport.
+    type FutureBytes = BoxFuture<Vec<u8>, RPCError>;
+    type Transact = Box<dyn Fn(Vec<u8>) -> FutureBytes>;
+    type Notify = Box<dyn Fn(Vec<u8>) -> BoxFuture<(), RPCError>>;
+
+    /// Like JSONTransport except for use as AsyncClientTransport.
+    /// Each call is a separate future from `transact`, so dropping
+    /// the future of one call cancels it without affecting others.
     pub struct JSONAsyncClientTransport<F, FT>
     where
         F: Fn(Vec<u8>) -> FT,
-				FT: Future<Output = Result<Vec<u8>>>
+        FT: Future<Output = Result<Vec<u8>>>,
     {
         transact: F,
+        // Sends notifications, if they are not to go through transact
+        notify: Option<Notify>,
+        codec: JSONCodec,
+        timeout: Option<Duration>,
     }
 
     impl<F, FT> JSONAsyncClientTransport<F, FT>
     where
-				F: Fn(Vec<u8>) -> FT,
-				FT: Future<Output = Result<Vec<u8>>>
+        F: Fn(Vec<u8>) -> FT,
+        FT: Future<Output = Result<Vec<u8>>>,
     {
         /// Create an AsyncJSONTransport. `transact` must be a
         /// function which given the raw bytes to transmit to the server,
         /// returns a future representing the raw bytes returned from the server.
         pub fn new(transact: F) -> Self {
-            JSONAsyncClientTransport { transact }
+            JSONAsyncClientTransport {
+                transact,
+                notify: None,
+                codec: JSONCodec::new(),
+                timeout: None,
+            }
+        }
+
+        /// Fail any call whose response has not arrived within
+        /// `timeout` of being awaited with an error of kind
+        /// `RPCErrorKind::Timeout`, dropping the future returned by
+        /// `transact`. See [timeout](../fn.timeout.html). Later calls
+        /// only succeed if `transact` copes with that, as those of
+        /// `from_buf_reader` and `MultiplexedClient` do.
+        pub fn with_timeout(mut self, timeout: Duration) -> Self {
+            self.timeout = Some(timeout);
+            self
+        }
+
+        /// Choose how `params` is sent for methods without
+        /// parameters. See [EmptyParams](enum.EmptyParams.html).
+        pub fn with_empty_params(mut self, empty_params: EmptyParams) -> Self {
+            self.codec = self.codec.with_empty_params(empty_params);
+            self
+        }
+
+        /// Choose how floats JSON has no numbers for are sent and
+        /// read. See [NonFiniteFloats](enum.NonFiniteFloats.html).
+        pub fn with_non_finite_floats(mut self, floats: NonFiniteFloats) -> Self {
+            self.codec = self.codec.with_non_finite_floats(floats);
+            self
+        }
+
+        /// Give requests incrementing integer ids, starting at 1,
+        /// rather than random UUIDs, which are the default with the
+        /// "uuid_ids" feature.
+        pub fn use_sequential_ids(mut self) -> Self {
+            self.codec = self.codec.use_sequential_ids();
+            self
+        }
+    }
+
+    impl JSONAsyncClientTransport<Transact, FutureBytes> {
+        /// Create a transport sending requests to `writer` and reading
+        /// responses from `reader`, e.g. the halves of a split socket.
+        /// Responses are read straight from the reader's buffer, as
+        /// much as is needed and no more, so buffering is entirely up
+        /// to the caller. Calls are made one at a time, each waiting
+        /// for the previous response to be read.
+        ///
+        /// Dropping the future of a call, e.g. when it times out,
+        /// leaves the connection usable: once its turn has come, the
+        /// call's request is still sent in full, by the next call if
+        /// need be, and its response is read and discarded before the
+        /// next call's. A dropped call may therefore still be
+        /// executed by the server.
+        pub fn from_buf_reader<R, W>(reader: R, writer: W) -> Self
+        where
+            R: AsyncBufRead + Unpin + 'static,
+            W: AsyncWrite + Unpin + 'static,
+        {
+            let connection = Rc::new(Mutex::new(Connection {
+                reader,
+                responses: ResponseReader::default(),
+                writer: RequestWriter::new(writer),
+                owed: 0,
+            }));
+            let notify_connection = connection.clone();
+            let mut transport = Self::new(Box::new(move |request: Vec<u8>| -> FutureBytes {
+                let connection = connection.clone();
+                async move { connection.lock().await.transact(&request).await }.boxed_local()
+            }));
+            transport.notify = Some(Box::new(move |request| {
+                let connection = notify_connection.clone();
+                async move { connection.lock().await.writer.send(&request).await }.boxed_local()
+            }));
+            transport
+        }
+    }
+
+    // The connection of a transport from from_buf_reader. What is
+    // needed to pick up where a dropped call left off is kept here
+    // rather than in the call's future.
+    struct Connection<R, W> {
+        reader: R,
+        responses: ResponseReader,
+        writer: RequestWriter<W>,
+        // Responses due before that of the next call, to calls dropped
+        // before reading them, and to the call in progress
+        owed: usize,
+    }
+
+    impl<R: AsyncBufRead + Unpin, W: AsyncWrite + Unpin> Connection<R, W> {
+        async fn transact(&mut self, request: &[u8]) -> Result<Vec<u8>> {
+            self.owed += 1;
+            self.writer.send(request).await?;
+            while self.owed > 1 {
+                self.responses.read(&mut self.reader).await?;
+                self.owed -= 1;
+            }
+            let response = self.responses.read(&mut self.reader).await?;
+            self.owed -= 1;
+            Ok(response)
+        }
+    }
+
+    // Writes requests so that dropping the future of one part way
+    // through leaves no partial request on the connection: the rest
+    // of it is written before the next.
+    struct RequestWriter<W> {
+        writer: W,
+        unsent: Vec<u8>,
+    }
+
+    impl<W: AsyncWrite + Unpin> RequestWriter<W> {
+        fn new(writer: W) -> Self {
+            RequestWriter {
+                writer,
+                unsent: Vec::new(),
+            }
+        }
+
+        async fn send(&mut self, request: &[u8]) -> Result<()> {
+            let write_error =
+                |e| RPCError::with_cause(RPCErrorKind::TransportError, "json write failed", e);
+            self.unsent.extend_from_slice(request);
+            while !self.unsent.is_empty() {
+                let n = self.writer.write(&self.unsent).await.map_err(write_error)?;
+                if n == 0 {
+                    return Err(write_error(io::ErrorKind::WriteZero.into()));
+                }
+                self.unsent.drain(..n);
+            }
+            self.writer.flush().await.map_err(write_error)
+        }
+    }
+
+    // Reads one json value at a time from a reader, consuming none of
+    // what follows it. What has been read of a value is kept if the
+    // future reading it is dropped, for the next read to finish.
+    #[derive(Default)]
+    struct ResponseReader {
+        response: Vec<u8>,
+        framer: ValueFramer,
+    }
+
+    impl ResponseReader {
+        // Whether the value is valid is left to whoever parses it
+        async fn read(&mut self, reader: &mut (impl AsyncBufRead + Unpin)) -> Result<Vec<u8>> {
+            loop {
+                let available = reader.fill_buf().await.map_err(|e| {
+                    RPCError::with_cause(RPCErrorKind::TransportError, "json read failed", e)
+                })?;
+                if available.is_empty() {
+                    return Err(RPCError::new(
+                        RPCErrorKind::TransportEOF,
+                        "EOF during json deserialization",
+                    ));
+                }
+                let n = available.len();
+                self.response.extend_from_slice(available);
+                match self.framer.value_len(&self.response) {
+                    Some(len) => {
+                        reader.consume_unpin(n - (self.response.len() - len));
+                        self.response.truncate(len);
+                        self.framer = ValueFramer::default();
+                        return Ok(mem::take(&mut self.response));
+                    }
+                    None => reader.consume_unpin(n),
+                }
+            }
         }
     }
 
     impl<F, FT> AsyncClientTransport for JSONAsyncClientTransport<F, FT>
     where
-				F: Fn(Vec<u8>) -> FT,
-				FT: Future<Output = Result<Vec<u8>>> + 'static
+        F: Fn(Vec<u8>) -> FT,
+        FT: Future<Output = Result<Vec<u8>>> + 'static,
     {
         type TXState = JTXState;
-        type FinalState = FutureBytes;
+        /// The id of the request, checked against the response, and
+        /// the pending response bytes.
+        type FinalState = (Value, FutureBytes);
 
         fn tx_begin_call(&mut self, method: MethodId) -> Result<JTXState> {
-            Ok(begin_call(method))
+            Ok(self.codec.begin_call(method))
         }
 
         fn tx_add_param(
@@ -226,31 +1578,515 @@ mod async_client {
             value: impl Serialize,
             state: &mut JTXState,
         ) -> Result<()> {
-            add_param(name, value, state)
+            self.codec.add_param(name, value, state)
         }
 
-        fn tx_finalize(&mut self, state: JTXState) -> Result<FutureBytes> {
-            let j = serde_json::to_vec(&value_for_state(&state)).map_err(convert_error)?;
-            Ok((self.transact)(j).boxed_local())
+        fn tx_set_service_version(&mut self, version: u32, state: &mut JTXState) -> Result<()> {
+            set_service_version(version, state);
+            Ok(())
         }
 
-        fn rx_response<T>(
-            &mut self,
-            state: FutureBytes,
-        ) -> BoxFuture<T, RPCError>
+        fn tx_finalize(&mut self, state: JTXState) -> Result<(Value, FutureBytes)> {
+            let (j, id) = self.codec.encode_call(&state)?;
+            let response = (self.transact)(j);
+            let response = match self.timeout {
+                Some(timeout) => crate::timeout(response, timeout).boxed_local(),
+                None => response.boxed_local(),
+            };
+            Ok((id, response))
+        }
+
+        fn tx_notify(&mut self, state: JTXState) -> BoxFuture<(), RPCError> {
+            match (self.codec.encode_notification(&state), &self.notify) {
+                (Ok(j), Some(notify)) => notify(j),
+                // Any bytes returned by the server are ignored
+                (Ok(j), None) => (self.transact)(j).map_ok(|_| ()).boxed_local(),
+                (Err(e), _) => futures::future::err(e).boxed_local(),
+            }
+        }
+
+        fn rx_response<T>(&mut self, state: (Value, FutureBytes)) -> BoxFuture<T, RPCError>
+        where
+            for<'de> T: Deserialize<'de>,
+            T: 'static,
+        {
+            let (id, response) = state;
+            let codec = self.codec.clone();
+            response
+                .and_then(|data| async move { codec.decode_response(&data, &id) })
+                .boxed_local()
+        }
+    }
+
+    /// Client connection over which many calls may be in flight at
+    /// once, their responses matched to them by id in whatever order
+    /// they arrive, rather than one call waiting on the response to
+    /// the previous one. Clones share the connection, so calls may be
+    /// made from many tasks.
+    ///
+    /// `new` returns the client along with the future which reads
+    /// the responses; spawn it on the same executor as the callers.
+    /// Calls are made with `call`, or through a generated client by
+    /// way of `transport`. Requests are sent as unframed JSON, as
+    /// `JSONTransport::new` and `JSONAsyncServerTransport` expect.
+    /// The server must answer concurrent calls with the ids they were
+    /// sent with, as essrpc servers do.
+    ///
+    /// ```ignore
+    /// let (client, reader) = MultiplexedClient::new(read_half, write_half);
+    /// tokio::task::spawn_local(reader);
+    /// let foo = FooRPCClient::new(client.transport());
+    /// let (a, b) = futures::join!(foo.bar("a".to_string(), 1), foo.bar("b".to_string(), 2));
+    /// ```
+    #[derive(Clone)]
+    pub struct MultiplexedClient {
+        shared: Rc<Multiplexed>,
+    }
+
+    type ResponseSender = futures::channel::oneshot::Sender<Result<Vec<u8>>>;
+
+    struct Multiplexed {
+        writer: Mutex<RequestWriter<Box<dyn AsyncWrite + Unpin>>>,
+        // Calls awaiting a response, by the serialized id of their
+        // request
+        pending: RefCell<HashMap<String, ResponseSender>>,
+        // Why the reader stopped, once it has
+        closed: RefCell<Option<(RPCErrorKind, String)>>,
+        codec: JSONCodec,
+    }
+
+    impl MultiplexedClient {
+        /// Create a client sending requests to `writer` and reading
+        /// responses from `reader`, e.g. the halves of a split socket.
+        /// Calls get incrementing integer ids. The returned future
+        /// reads responses until the reader ends or fails, at which
+        /// point every call still waiting fails, as does any later
+        /// call. It resolves to `Ok` at the end of the reader.
+        pub fn new<R, W>(reader: R, writer: W) -> (Self, impl Future<Output = Result<()>>)
+        where
+            R: AsyncBufRead + Unpin + 'static,
+            W: AsyncWrite + Unpin + 'static,
+        {
+            let shared = Rc::new(Multiplexed {
+                writer: Mutex::new(RequestWriter::new(Box::new(writer))),
+                pending: RefCell::new(HashMap::new()),
+                closed: RefCell::new(None),
+                codec: JSONCodec::new().use_sequential_ids(),
+            });
+            let client = MultiplexedClient {
+                shared: shared.clone(),
+            };
+            (client, read_responses(reader, shared))
+        }
+
+        /// Call `method` with `params`, which must serialize to an
+        /// object of named parameters or an array of positional ones.
+        /// `T` is the `Result` type returned by the method, as with
+        /// [JSONCodec::decode_response](json_codec/struct.JSONCodec.html#method.decode_response).
+        /// Dropping the future abandons the call; its response is
+        /// discarded on arrival.
+        pub fn call<T>(&self, method: &str, params: impl Serialize) -> BoxFuture<T, RPCError>
         where
             for<'de> T: Deserialize<'de>,
             T: 'static,
         {
-            println!("rx response");
-            state.and_then(|data| async move {
-                println!("json is {}", std::str::from_utf8(data.deref()).unwrap());
-                println!("result type is {}", std::any::type_name::<T>());
-                read_value_from_json(data.deref())
-            }).boxed_local()
+            let encoded = serde_json::to_value(params)
+                .map_err(convert_error)
+                .and_then(|params| {
+                    let state = self.shared.codec.begin_call_with(method, params);
+                    self.shared.codec.encode_call(&state)
+                });
+            let shared = self.shared.clone();
+            async move {
+                let (request, id) = encoded?;
+                let response = shared.transact(request, &id).await?;
+                shared.codec.decode_response(&response, &id)
+            }
+            .boxed_local()
+        }
+
+        /// A transport making its calls over this connection, for use
+        /// by a generated client. Calls through it take their ids from
+        /// the same count as those made by `call`, so never clash.
+        pub fn transport(&self) -> JSONAsyncClientTransport<Transact, FutureBytes> {
+            let shared = self.shared.clone();
+            let notify_shared = self.shared.clone();
+            let mut transport =
+                JSONAsyncClientTransport::new(Box::new(move |request: Vec<u8>| -> FutureBytes {
+                    let shared = shared.clone();
+                    async move {
+                        let id = request_id(&shared.codec.decode_value(&request)?);
+                        shared.transact(request, &id).await
+                    }
+                    .boxed_local()
+                }) as Transact);
+            transport.codec.options.ids = self.shared.codec.options.ids.clone();
+            transport.notify = Some(Box::new(move |request| {
+                let shared = notify_shared.clone();
+                async move { shared.write(&request).await }.boxed_local()
+            }));
+            transport
+        }
+
+        /// The number of calls awaiting a response.
+        pub fn in_flight(&self) -> usize {
+            self.shared.pending.borrow().len()
+        }
+    }
+
+    impl Multiplexed {
+        async fn transact(&self, request: Vec<u8>, id: &Value) -> Result<Vec<u8>> {
+            let (sender, receiver) = futures::channel::oneshot::channel();
+            self.pending.borrow_mut().insert(id.to_string(), sender);
+            if let Err(e) = self.write(&request).await {
+                self.pending.borrow_mut().remove(&id.to_string());
+                return Err(e);
+            }
+            match receiver.await {
+                Ok(response) => response,
+                // The reader was dropped without failing the call
+                Err(_) => Err(self.closed_error()),
+            }
+        }
+
+        async fn write(&self, request: &[u8]) -> Result<()> {
+            if self.closed.borrow().is_some() {
+                return Err(self.closed_error());
+            }
+            // Held for the whole request, so that requests are not
+            // interleaved
+            self.writer.lock().await.send(request).await
+        }
+
+        fn closed_error(&self) -> RPCError {
+            match &*self.closed.borrow() {
+                Some((kind, msg)) => RPCError::new(*kind, msg.clone()),
+                None => RPCError::new(
+                    RPCErrorKind::TransportEOF,
+                    "multiplexed client's reader is gone",
+                ),
+            }
+        }
+
+        fn close(&self, kind: RPCErrorKind, msg: String) {
+            *self.closed.borrow_mut() = Some((kind, msg));
+            for (_, sender) in self.pending.borrow_mut().drain() {
+                let _ = sender.send(Err(self.closed_error()));
+            }
+        }
+    }
+
+    async fn read_responses(
+        mut reader: impl AsyncBufRead + Unpin,
+        shared: Rc<Multiplexed>,
+    ) -> Result<()> {
+        let mut responses = ResponseReader::default();
+        loop {
+            let response = match responses.read(&mut reader).await {
+                Ok(response) => response,
+                Err(e) if e.kind == RPCErrorKind::TransportEOF => {
+                    shared.close(e.kind, "connection closed".to_string());
+                    return Ok(());
+                }
+                Err(e) => {
+                    shared.close(e.kind, format!("connection failed: {}", e));
+                    return Err(e);
+                }
+            };
+            let id = match shared.codec.decode_value(&response) {
+                Ok(value) => request_id(&value),
+                Err(e) => {
+                    shared.close(e.kind, format!("connection failed: {}", e));
+                    return Err(e);
+                }
+            };
+            let sender = shared.pending.borrow_mut().remove(&id.to_string());
+            match sender {
+                // The call may have been abandoned, in which case the
+                // response goes nowhere
+                Some(sender) => {
+                    let _ = sender.send(Ok(response));
+                }
+                None => log::warn!("discarding json response with unknown id {}", id),
+            }
         }
     }
 }
 
 #[cfg(feature = "async_client")]
-pub use self::async_client::JSONAsyncClientTransport;
+pub use self::async_client::{JSONAsyncClientTransport, MultiplexedClient};
+
+#[cfg(feature = "async_server")]
+mod async_server {
+    use super::super::json_framer::ValueFramer;
+    use super::*;
+    use crate::AsyncServerTransport;
+    use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+    /// Like JSONTransport except for use as AsyncServerTransport. Can
+    /// be used over any `AsyncRead+AsyncWrite` channel. Batch
+    /// requests are not supported.
+    ///
+    /// Waiting for a call with `rx_begin_call` is cancel-safe: if its
+    /// future is dropped, e.g. by `select!` in favour of a shutdown
+    /// signal, anything read so far is kept for the next call.
+    pub struct JSONAsyncServerTransport<C: AsyncRead + AsyncWrite + Unpin> {
+        channel: C,
+        // Bytes read from the channel but not yet parsed, and how far
+        // the value at their start has been scanned
+        buf: Vec<u8>,
+        framer: ValueFramer,
+        max_message_bytes: Option<usize>,
+        codec: JSONCodec,
+    }
+
+    impl<C: AsyncRead + AsyncWrite + Unpin> JSONAsyncServerTransport<C> {
+        pub fn new(channel: C) -> Self {
+            JSONAsyncServerTransport {
+                channel,
+                buf: Vec::new(),
+                framer: ValueFramer::default(),
+                max_message_bytes: None,
+                codec: JSONCodec::new(),
+            }
+        }
+
+        /// Refuse any request larger than `max` bytes, as with
+        /// [JSONTransport::with_max_message_bytes](struct.JSONTransport.html#method.with_max_message_bytes).
+        /// What has been read of an oversized request is discarded,
+        /// and the rest left unread, so the connection cannot be used
+        /// afterwards.
+        pub fn with_max_message_bytes(mut self, max: usize) -> Self {
+            self.max_message_bytes = Some(max);
+            self
+        }
+
+        /// Choose whether to refuse requests not marked JSON-RPC 2.0,
+        /// as with
+        /// [JSONTransport::with_strict_version](struct.JSONTransport.html#method.with_strict_version).
+        pub fn with_strict_version(mut self, strict: bool) -> Self {
+            self.codec = self.codec.with_strict_version(strict);
+            self
+        }
+
+        /// Use `method` as the name of the keep-alive ping, as with
+        /// [JSONTransport::with_ping_method](struct.JSONTransport.html#method.with_ping_method).
+        pub fn with_ping_method(mut self, method: impl Into<String>) -> Self {
+            self.codec = self.codec.with_ping_method(method);
+            self
+        }
+
+        /// Choose how floats JSON has no numbers for are read and
+        /// sent, as with
+        /// [JSONTransport::with_non_finite_floats](struct.JSONTransport.html#method.with_non_finite_floats).
+        pub fn with_non_finite_floats(mut self, floats: NonFiniteFloats) -> Self {
+            self.codec = self.codec.with_non_finite_floats(floats);
+            self
+        }
+
+        /// Get the underlying read/write channel
+        pub fn channel(&self) -> &C {
+            &self.channel
+        }
+
+        /// Get a mutable reference to the underlying read/write channel
+        pub fn channel_mut(&mut self) -> &mut C {
+            &mut self.channel
+        }
+
+        /// Consume the transport, returning the underlying read/write
+        /// channel. Any data already read from the channel but not
+        /// yet consumed by a call is discarded.
+        pub fn into_channel(self) -> C {
+            self.channel
+        }
+
+        // Read a complete json value from the channel. Anything
+        // beyond the end of the value is kept for the next read.
+        async fn read_from_channel(&mut self) -> Result<Value> {
+            let max = self.max_message_bytes.unwrap_or(usize::MAX);
+            loop {
+                let result = match self.framer.next_value(&self.buf) {
+                    Ok(Some((_, consumed))) if consumed > max => Err(too_large(max)),
+                    Ok(None) if self.buf.len() > max => Err(too_large(max)),
+                    result => result,
+                };
+                match result {
+                    Ok(Some((value, consumed))) => {
+                        self.buf.drain(..consumed);
+                        return Ok(value);
+                    }
+                    Err(e) => {
+                        // There is no telling where the next message
+                        // starts, so drop the rest rather than fail on
+                        // it again at every read.
+                        self.buf.clear();
+                        self.framer = ValueFramer::default();
+                        return Err(e);
+                    }
+                    Ok(None) => (),
+                }
+                let mut chunk = [0u8; 1024];
+                let n = self.channel.read(&mut chunk).await.map_err(|e| {
+                    RPCError::with_cause(RPCErrorKind::TransportError, "json read failed", e)
+                })?;
+                if n == 0 {
+                    return Err(RPCError::new(
+                        RPCErrorKind::TransportEOF,
+                        "EOF during json deserialization",
+                    ));
+                }
+                self.buf.extend_from_slice(&chunk[..n]);
+            }
+        }
+    }
+
+    fn too_large(max: usize) -> RPCError {
+        RPCError::new(
+            RPCErrorKind::MessageTooLarge,
+            format!("json message exceeds {} bytes", max),
+        )
+    }
+
+    #[async_trait::async_trait(?Send)]
+    impl<C: AsyncRead + AsyncWrite + Unpin> AsyncServerTransport for JSONAsyncServerTransport<C> {
+        type RXState = JRXState;
+
+        async fn rx_begin_call(&mut self) -> Result<(PartialMethodId, JRXState)> {
+            loop {
+                let request = match self.read_from_channel().await {
+                    Err(e) if e.kind == RPCErrorKind::ParseError => {
+                        let _ = self
+                            .send_response(response_for_error(&e, Value::Null))
+                            .await;
+                        return Err(e);
+                    }
+                    request => request?,
+                };
+                if let Some(pong) = self.codec.pong_response(&request) {
+                    if let Some(pong) = pong {
+                        self.send_response(pong).await?;
+                    }
+                    continue;
+                }
+                match self.codec.version_response(&request) {
+                    Some(response) => self.send_response(response).await?,
+                    None => {
+                        let id = request_id(&request);
+                        return match self.codec.call_with_params(request, None) {
+                            Err(e) => {
+                                let _ = self.send_response(response_for_error(&e, id)).await;
+                                Err(e)
+                            }
+                            call => call,
+                        };
+                    }
+                }
+            }
+        }
+
+        async fn rx_read_param<T>(&mut self, name: &'static str, state: &mut JRXState) -> Result<T>
+        where
+            for<'de> T: serde::Deserialize<'de>,
+        {
+            let param = read_param(name, state);
+            self.answer_param_error(param, state).await
+        }
+
+        async fn rx_read_param_opt<T>(
+            &mut self,
+            name: &'static str,
+            state: &mut JRXState,
+        ) -> Result<Option<T>>
+        where
+            for<'de> T: serde::Deserialize<'de>,
+        {
+            let param = read_param_opt(name, state);
+            self.answer_param_error(param, state).await
+        }
+
+        async fn tx_response<T: Serialize, E: Serialize>(
+            &mut self,
+            value: std::result::Result<T, E>,
+            state: JRXState,
+        ) -> Result<()> {
+            if state.is_notification() {
+                return Ok(());
+            }
+            let response = response_for_result(value, state.id, state.floats)?;
+            self.send_response(response).await
+        }
+
+        fn rx_service_version(&self, state: &JRXState) -> Option<u32> {
+            state.service_version()
+        }
+
+        async fn tx_error(&mut self, error: RPCError, state: JRXState) -> Result<()> {
+            if state.is_notification() {
+                return Ok(());
+            }
+            self.send_response(response_for_error(&error, state.id))
+                .await
+        }
+
+        /// Serve every request as a notification, answering nothing,
+        /// as with
+        /// [JSONTransport](struct.JSONTransport.html#method.set_notifications_only).
+        fn set_notifications_only(&mut self) {
+            self.codec.set_notifications_only();
+        }
+    }
+
+    impl<C> JSONAsyncServerTransport<C>
+    where
+        C: AsyncRead + AsyncWrite + Unpin,
+    {
+        async fn send_response(&mut self, response: Value) -> Result<()> {
+            if self.codec.is_notifications_only() {
+                return Ok(());
+            }
+            let j = self.codec.encode_value(&response)?;
+            let write_error =
+                |e| RPCError::with_cause(RPCErrorKind::TransportError, "json write failed", e);
+            self.channel.write_all(&j).await.map_err(write_error)?;
+            self.channel.flush().await.map_err(write_error)
+        }
+
+        // As for JSONTransport, a parameter which is missing or has
+        // the wrong type is answered with the JSON-RPC error for it.
+        async fn answer_param_error<T>(&mut self, param: Result<T>, state: &JRXState) -> Result<T> {
+            if let Err(e) = &param {
+                if !state.is_notification() {
+                    let _ = self
+                        .send_response(response_for_error(e, state.id.clone()))
+                        .await;
+                }
+            }
+            param
+        }
+    }
+}
+
+#[cfg(feature = "async_server")]
+pub use self::async_server::JSONAsyncServerTransport;
+
+/// [JSONAsyncServerTransport](struct.JSONAsyncServerTransport.html)
+/// over the separate `AsyncRead` and `AsyncWrite` halves of a
+/// connection, such as those from `futures::io::AsyncReadExt::split`,
+/// combined by [ReadWrite](struct.ReadWrite.html). Asynchronous
+/// clients take the halves directly, with
+/// [JSONAsyncClientTransport::from_buf_reader](struct.JSONAsyncClientTransport.html#method.from_buf_reader).
+#[cfg(feature = "async_server")]
+pub type AsyncSplitTransport<R, W> = JSONAsyncServerTransport<ReadWrite<R, W>>;
+
+#[cfg(feature = "async_server")]
+impl<R, W> AsyncSplitTransport<R, W>
+where
+    R: futures::io::AsyncRead + Unpin,
+    W: futures::io::AsyncWrite + Unpin,
+{
+    /// Create a transport reading from `reader` and writing to `writer`.
+    pub fn from_halves(reader: R, writer: W) -> Self {
+        Self::new(ReadWrite::new(reader, writer))
+    }
+}
@@ -0,0 +1,1531 @@
+//! Encoding and decoding of the messages of the JSON transports,
+//! without any I/O. Enable the "json_transport" feature to use this.
+//!
+//! [JSONCodec](struct.JSONCodec.html) turns calls into request bytes
+//! and response bytes back into results, and likewise for the server
+//! side, so that JSON-RPC can be driven by any runtime or event loop,
+//! or over a channel essrpc knows nothing about. The transports in
+//! [transports](../index.html) are built on it.
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use serde::de::value::SeqAccessDeserializer;
+use serde::de::{DeserializeOwned, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{SerializeMap, SerializeSeq, Serializer};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use serde_json::value::{RawValue, Value};
+use serde_json::Map;
+#[cfg(feature = "uuid_ids")]
+use uuid::Uuid;
+
+use super::json_float;
+use super::raw_param::raw_param;
+use crate::trace;
+use crate::{MethodId, PartialMethodId, RPCError, RPCErrorKind, Result};
+
+/// Encodes and decodes JSON-RPC messages as bytes, leaving reading
+/// and writing them to the caller. A client encodes a call with
+/// `begin_call`, `add_param` and `encode_call`, sends the bytes
+/// however it likes, and decodes the bytes which come back with
+/// `decode_response`. A server decodes each request with
+/// `decode_call`, reads its parameters with `read_param`, and
+/// encodes the answer with `encode_response` or `encode_error`,
+/// sending nothing for a notification.
+///
+/// Each call to an `encode_` method produces exactly one message.
+/// When messages share a byte stream, `message_len` tells where the
+/// first of them ends.
+///
+/// The options are those of
+/// [JSONTransport](../struct.JSONTransport.html), whose
+/// constructors and methods of the same names they mirror.
+#[derive(Clone)]
+pub struct JSONCodec {
+    config: JSONTransportConfig,
+    pub(super) options: CallOptions,
+    // Sent as the meta member of each request, unless empty
+    context: Map<String, Value>,
+    max_depth: Option<usize>,
+    // Whether requests not marked JSON-RPC 2.0 are refused
+    strict_version: bool,
+    // Method answered by the server transports themselves
+    ping_method: String,
+    // Whether messages go without the jsonrpc and id members
+    raw: bool,
+    // Whether requests keep their params as text until read
+    lazy_params: bool,
+    non_finite_floats: NonFiniteFloats,
+    // Whether every request is served as a notification
+    notifications_only: bool,
+}
+
+impl Default for JSONCodec {
+    fn default() -> Self {
+        JSONCodec::with_config(JSONTransportConfig::default())
+    }
+}
+
+impl JSONCodec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Like `new`, but with the given encoding configuration.
+    pub fn with_config(config: JSONTransportConfig) -> Self {
+        JSONCodec {
+            config,
+            options: CallOptions::default(),
+            context: Map::new(),
+            max_depth: None,
+            strict_version: true,
+            ping_method: PING_METHOD.to_string(),
+            raw: false,
+            lazy_params: false,
+            non_finite_floats: NonFiniteFloats::default(),
+            notifications_only: false,
+        }
+    }
+
+    /// Like `new`, except calls identify methods by their number
+    /// rather than their name.
+    pub fn new_numeric() -> Self {
+        let mut codec = Self::new();
+        codec.options.numeric = true;
+        codec
+    }
+
+    /// Like `new`, except calls send `params` as an array in the
+    /// order the method declares them.
+    pub fn positional() -> Self {
+        let mut codec = Self::new();
+        codec.options.positional = true;
+        codec
+    }
+
+    /// Like `new`, except messages go without the JSON-RPC envelope:
+    /// no `jsonrpc` or `id` member is encoded, and none is expected
+    /// when decoding. See
+    /// [JSONTransport::raw](../struct.JSONTransport.html#method.raw).
+    pub fn raw() -> Self {
+        let mut codec = Self::new();
+        codec.raw = true;
+        codec
+    }
+
+    /// Choose how `params` is encoded for methods without
+    /// parameters. See [EmptyParams](../enum.EmptyParams.html).
+    pub fn with_empty_params(mut self, empty_params: EmptyParams) -> Self {
+        self.options.empty_params = empty_params;
+        self
+    }
+
+    /// Give requests incrementing integer ids, starting at 1, rather
+    /// than random UUIDs, which are the default with the "uuid_ids"
+    /// feature. Clones of the codec share the count.
+    pub fn use_sequential_ids(mut self) -> Self {
+        self.options.ids = RequestIds::Sequential(Arc::new(AtomicU64::new(1)));
+        self
+    }
+
+    /// Call `generator` for the id of each subsequently encoded
+    /// request, rather than using the default ids. Ids should be
+    /// unique among the calls in flight, and must not be `null`,
+    /// which the server takes for a notification.
+    pub fn set_id_generator(&mut self, generator: Box<dyn FnMut() -> Value + Send>) {
+        self.options.ids = RequestIds::Custom(Arc::new(Mutex::new(generator)));
+    }
+
+    /// Send `context` with every subsequently encoded request, as its
+    /// `meta` member. An empty map sends no `meta`.
+    pub fn set_context(&mut self, context: Map<String, Value>) {
+        self.context = context;
+    }
+
+    /// Refuse to decode any message whose arrays and objects are
+    /// nested more than `depth` deep, with an error of kind
+    /// `RPCErrorKind::SerializationError`.
+    pub fn with_max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Choose whether `decode_call` refuses requests whose `jsonrpc`
+    /// member is missing or not `"2.0"`, with an error of kind
+    /// `RPCErrorKind::InvalidRequest`. Strict by default.
+    pub fn with_strict_version(mut self, strict: bool) -> Self {
+        self.strict_version = strict;
+        self
+    }
+
+    /// Use `method` as the name of the keep-alive ping, rather than
+    /// `"rpc.ping"`. See
+    /// [JSONTransport::ping](../struct.JSONTransport.html#method.ping).
+    pub fn with_ping_method(mut self, method: impl Into<String>) -> Self {
+        self.ping_method = method.into();
+        self
+    }
+
+    /// Choose whether `decode_call` leaves the `params` of a request
+    /// as JSON text, deserializing each parameter straight from it
+    /// when read, rather than first parsing them all into a `Value`
+    /// and deserializing each from that. See
+    /// [JSONTransport::with_lazy_params](../struct.JSONTransport.html#method.with_lazy_params).
+    pub fn with_lazy_params(mut self, lazy: bool) -> Self {
+        self.lazy_params = lazy;
+        self
+    }
+
+    /// Choose how floats JSON has no numbers for, NaN and the
+    /// infinities, are encoded and decoded. See
+    /// [NonFiniteFloats](../enum.NonFiniteFloats.html).
+    pub fn with_non_finite_floats(mut self, floats: NonFiniteFloats) -> Self {
+        self.non_finite_floats = floats;
+        self
+    }
+
+    /// Begin encoding a call to `method`.
+    pub fn begin_call(&self, method: MethodId) -> JTXState {
+        let mut state = begin_call(method, &self.options);
+        state.floats = self.non_finite_floats;
+        state
+    }
+
+    // Begin a call to a method named at run time, with its params
+    // already serialized
+    #[cfg(feature = "async_client")]
+    pub(super) fn begin_call_with(&self, method: &str, params: Value) -> JTXState {
+        JTXState {
+            method: json!(method),
+            params,
+            raw: Vec::new(),
+            version: None,
+            floats: self.non_finite_floats,
+        }
+    }
+
+    /// Add a parameter to a call begun with `begin_call`. Parameters
+    /// are added in the order the method declares them. A
+    /// `serde_json::value::RawValue`, such as a `Box<RawValue>`, is
+    /// sent as its text stands, without being parsed, for passing on
+    /// params without interpreting them.
+    pub fn add_param(
+        &self,
+        name: &'static str,
+        value: impl Serialize,
+        state: &mut JTXState,
+    ) -> Result<()> {
+        add_param(name, value, state)
+    }
+
+    /// Send `version` of the trait with a call begun with
+    /// `begin_call`, as the request's `v` member.
+    pub fn set_service_version(&self, version: u32, state: &mut JTXState) {
+        set_service_version(version, state)
+    }
+
+    /// Encode a call as a request. Returns the request and its id,
+    /// which `decode_response` checks the response against.
+    pub fn encode_call(&self, state: &JTXState) -> Result<(Vec<u8>, Value)> {
+        let request = self.request_value(state, false);
+        Ok((self.encode_value(&request)?, request.id().clone()))
+    }
+
+    /// Encode a call as a notification, to which the server sends no
+    /// response.
+    pub fn encode_notification(&self, state: &JTXState) -> Result<Vec<u8>> {
+        self.encode_value(&self.request_value(state, true))
+    }
+
+    /// Decode the response to the request with the given id. `T` is
+    /// the `Result` type returned by the method.
+    pub fn decode_response<T>(&self, bytes: &[u8], id: &Value) -> Result<T>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        let response = self.decode_value(bytes)?;
+        self.check_response_id(&response, id)?;
+        result_from_response(response, self.non_finite_floats)
+    }
+
+    /// Decode one response of a streaming response to the request
+    /// with the given id, or `None` if it ends the stream.
+    pub fn decode_stream_item<T>(&self, bytes: &[u8], id: &Value) -> Result<Option<T>>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        let response = self.decode_value(bytes)?;
+        self.check_response_id(&response, id)?;
+        stream_item_from_response(response, self.non_finite_floats)
+    }
+
+    /// Decode a single request, returning the method called and the
+    /// state from which its parameters are read. Errors of kind
+    /// `RPCErrorKind::ParseError` and `RPCErrorKind::InvalidRequest`
+    /// should be answered with `encode_error`, using a null id as the
+    /// request's own cannot be relied upon.
+    pub fn decode_call(&self, bytes: &[u8]) -> Result<(PartialMethodId, JRXState)> {
+        let (request, params) = if self.lazy_params {
+            self.request_from_message(self.decode(bytes)?)?
+        } else {
+            (self.decode_value(bytes)?, None)
+        };
+        if self.strict_version && !self.raw {
+            check_version(&request)?;
+        }
+        self.call_with_params(request, params)
+    }
+
+    /// Read the next parameter of a call decoded by `decode_call`. A
+    /// missing parameter, or one of the wrong type, is an error of
+    /// kind `RPCErrorKind::InvalidParams`, which `encode_error` turns
+    /// into the JSON-RPC error for it.
+    pub fn read_param<T>(&self, name: &'static str, state: &mut JRXState) -> Result<T>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        read_param(name, state)
+    }
+
+    /// Like `read_param`, but `None` if the request does not have
+    /// the parameter.
+    pub fn read_param_opt<T>(&self, name: &'static str, state: &mut JRXState) -> Result<Option<T>>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        read_param_opt(name, state)
+    }
+
+    /// Encode the response carrying the result of the call with the
+    /// given id, or, for a streaming method, one item of it.
+    pub fn encode_response(
+        &self,
+        value: std::result::Result<impl Serialize, impl Serialize>,
+        id: &Value,
+    ) -> Result<Vec<u8>> {
+        self.encode_value(&response_for_result(
+            value,
+            id.clone(),
+            self.non_finite_floats,
+        )?)
+    }
+
+    /// Encode the response for an error raised by essrpc itself
+    /// rather than the method, such as an unknown method.
+    pub fn encode_error(&self, error: &RPCError, id: &Value) -> Result<Vec<u8>> {
+        self.encode_value(&response_for_error(error, id.clone()))
+    }
+
+    /// Encode the response which ends a streaming response.
+    pub fn encode_stream_end(&self, id: &Value) -> Result<Vec<u8>> {
+        self.encode_value(&stream_end_response(id.clone()))
+    }
+
+    /// The length of the first message in `buf`, or `None` if `buf`
+    /// does not yet hold all of it. An error means the message is not
+    /// valid JSON, leaving no telling where the next one starts.
+    pub fn message_len(&self, buf: &[u8]) -> Result<Option<usize>> {
+        Ok(next_value(buf)?.map(|(_, len)| len))
+    }
+
+    // Build the request object for a call, with the context if any
+    pub(super) fn request_value(&self, state: &JTXState, notification: bool) -> Request {
+        let mut request = if notification {
+            notification_for_state(state, &self.options)
+        } else {
+            value_for_state(state, &self.options)
+        };
+        if !self.context.is_empty() {
+            request.value["meta"] = Value::Object(self.context.clone());
+        }
+        request
+    }
+
+    pub(super) fn encode_value(&self, value: &impl Serialize) -> Result<Vec<u8>> {
+        if self.raw {
+            let mut value = serde_json::to_value(value).map_err(convert_error)?;
+            strip_envelope(&mut value);
+            return self.encode_json(&value);
+        }
+        self.encode_json(value)
+    }
+
+    fn encode_json(&self, value: &impl Serialize) -> Result<Vec<u8>> {
+        if self.config.pretty {
+            serde_json::to_vec_pretty(value)
+        } else {
+            serde_json::to_vec(value)
+        }
+        .map_err(convert_error)
+    }
+
+    pub(super) fn decode_value(&self, bytes: &[u8]) -> Result<Value> {
+        let value = self.decode(bytes)?;
+        self.check_depth(&value)?;
+        Ok(value)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T> {
+        serde_json::from_slice(bytes).map_err(|e| {
+            if e.classify() == serde_json::error::Category::Eof {
+                RPCError::new(
+                    RPCErrorKind::TransportEOF,
+                    "EOF during json deserialization",
+                )
+            } else {
+                decode_error(e, bytes)
+            }
+        })
+    }
+
+    // The request to serve from a message read with lazy params,
+    // along with the text of its params. Pings and cancellations,
+    // which the transports answer from the request itself, have
+    // their params parsed back into it.
+    pub(super) fn request_from_message(
+        &self,
+        message: Message,
+    ) -> Result<(Value, Option<Box<RawValue>>)> {
+        let (mut members, params) = match message {
+            Message::Request(members, params) => (members, params),
+            Message::Other(value) => {
+                self.check_depth(&value)?;
+                return Ok((value, None));
+            }
+        };
+        let method = members.get("method").and_then(Value::as_str);
+        let params = match params {
+            Some(params) if method == Some(CANCEL_METHOD) || method == Some(&self.ping_method) => {
+                let params = serde_json::from_str(params.get()).map_err(convert_error)?;
+                members.insert("params".to_string(), params);
+                None
+            }
+            params => params,
+        };
+        let request = Value::Object(members);
+        self.check_depth(&request)?;
+        if let (Some(max), Some(params)) = (self.max_depth, &params) {
+            // The params sit a level below the request
+            if text_depth(params.get()) + 1 > max {
+                return Err(RPCError::new(
+                    RPCErrorKind::SerializationError,
+                    format!("json nested more than {} deep", max),
+                ));
+            }
+        }
+        Ok((request, params))
+    }
+
+    pub(super) fn is_lazy(&self) -> bool {
+        self.lazy_params
+    }
+
+    pub(super) fn check_depth(&self, value: &Value) -> Result<()> {
+        let max = match self.max_depth {
+            Some(max) => max,
+            None => return Ok(()),
+        };
+        // Walk the value without recursion, which a deep value could
+        // overflow the stack with.
+        let mut pending = vec![(value, 1)];
+        while let Some((value, depth)) = pending.pop() {
+            match value {
+                Value::Array(items) => pending.extend(items.iter().map(|v| (v, depth + 1))),
+                Value::Object(members) => pending.extend(members.values().map(|v| (v, depth + 1))),
+                _ => continue,
+            }
+            if depth > max {
+                return Err(RPCError::new(
+                    RPCErrorKind::SerializationError,
+                    format!("json nested more than {} deep", max),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Build a ping request, which has no params
+    pub(super) fn ping_request(&self) -> Request {
+        let state = JTXState {
+            method: json!(self.ping_method),
+            params: json!({}),
+            raw: Vec::new(),
+            version: None,
+            floats: NonFiniteFloats::default(),
+        };
+        self.request_value(&state, false)
+    }
+
+    // The notification cancelling the streaming response to the
+    // request with the given id
+    pub(super) fn cancel_request(&self, id: &Value) -> Request {
+        let state = JTXState {
+            method: json!(CANCEL_METHOD),
+            params: json!({ "id": id }),
+            raw: Vec::new(),
+            version: None,
+            floats: NonFiniteFloats::default(),
+        };
+        self.request_value(&state, true)
+    }
+
+    // The id of the request whose streaming response `request`
+    // cancels, or None if it is not a cancellation.
+    pub(super) fn cancel_target<'a>(&self, request: &'a Value) -> Option<&'a Value> {
+        if request.get("method").and_then(Value::as_str) != Some(CANCEL_METHOD) {
+            return None;
+        }
+        Some(request.pointer("/params/id").unwrap_or(&Value::Null))
+    }
+
+    // The response to a ping request: Some(None) for a ping sent as
+    // a notification, which gets none, and None if it is not a ping.
+    pub(super) fn pong_response(&self, request: &Value) -> Option<Option<Value>> {
+        if request.get("method").and_then(Value::as_str) != Some(self.ping_method.as_str()) {
+            return None;
+        }
+        let id = match request.get("id") {
+            Some(id) => id.clone(),
+            None if self.raw => Value::Null,
+            None => return Some(None),
+        };
+        Some(Some(json!({"jsonrpc": "2.0", "result": "pong", "id": id})))
+    }
+
+    // The invalid request response for a request of the wrong
+    // JSON-RPC version, if the version is checked.
+    pub(super) fn version_response(&self, request: &Value) -> Option<Value> {
+        if !self.strict_version || self.raw {
+            return None;
+        }
+        check_version(request)
+            .err()
+            .map(|e| response_for_error(&e, request_id(request)))
+    }
+
+    // Raw messages have no ids, so responses are taken to answer the
+    // calls in the order they were made.
+    pub(super) fn check_response_id(&self, response: &Value, id: &Value) -> Result<()> {
+        if self.raw {
+            return Ok(());
+        }
+        check_response_id(response, id)
+    }
+
+    // Raw requests have no ids, so are never notifications: the server
+    // answers every call but those to methods marked as notifications.
+    // With notifications_only, on the other hand, every request is one.
+    // The params of the request are taken from it, unless left as
+    // text by request_from_message.
+    pub(super) fn call_with_params(
+        &self,
+        value: Value,
+        params: Option<Box<RawValue>>,
+    ) -> Result<(PartialMethodId, JRXState)> {
+        let (method, mut state) = call_from_value(value)?;
+        if self.raw {
+            state.notification = false;
+        }
+        if self.notifications_only {
+            state.notification = true;
+        }
+        state.lazy = params.map(LazyParams::new);
+        state.floats = self.non_finite_floats;
+        Ok((method, state))
+    }
+
+    pub(super) fn is_raw(&self) -> bool {
+        self.raw
+    }
+
+    pub(super) fn non_finite_floats(&self) -> NonFiniteFloats {
+        self.non_finite_floats
+    }
+
+    pub(super) fn set_notifications_only(&mut self) {
+        self.notifications_only = true;
+    }
+
+    pub(super) fn is_notifications_only(&self) -> bool {
+        self.notifications_only
+    }
+}
+
+// Remove the JSON-RPC envelope from a message, or from each message of
+// a batch, leaving the method and params of a request, or the result
+// or error of a response.
+fn strip_envelope(message: &mut Value) {
+    match message {
+        Value::Object(members) => {
+            members.remove("jsonrpc");
+            members.remove("id");
+        }
+        Value::Array(messages) => messages.iter_mut().for_each(strip_envelope),
+        _ => (),
+    }
+}
+
+// The first complete json value in `buf` and the number of bytes it
+// takes up, or None if the value is not yet complete.
+pub(super) fn next_value(buf: &[u8]) -> Result<Option<(Value, usize)>> {
+    let mut values = serde_json::Deserializer::from_slice(buf).into_iter();
+    match values.next() {
+        Some(Ok(value)) => Ok(Some((value, values.byte_offset()))),
+        Some(Err(e)) if !e.is_eof() => Err(decode_error(e, buf)),
+        _ => Ok(None),
+    }
+}
+
+/// State of a JSON-RPC call being built by a client, from
+/// `tx_begin_call` until `tx_finalize`. Cloning it before finalizing
+/// lets a call whose request failed to send be sent again, e.g. on a
+/// fresh connection, without adding its params afresh; the request
+/// id is chosen as it is finalized, so each attempt gets its own.
+#[derive(Clone)]
+pub struct JTXState {
+    method: Value,
+    params: Value,
+    // Params which are RawValues, sent as their text stands in place
+    // of the nulls holding their places in params
+    raw: Vec<(RawSlot, Box<RawValue>)>,
+    // Trait version sent as the request's v member, if any
+    version: Option<u32>,
+    floats: NonFiniteFloats,
+}
+
+// Where in params a raw param goes
+#[derive(Clone)]
+enum RawSlot {
+    Name(String),
+    Index(usize),
+}
+
+// A request as built for a call, which serializes as `value` but with
+// any raw params in place
+pub(super) struct Request {
+    value: Value,
+    raw: Vec<(RawSlot, Box<RawValue>)>,
+}
+
+impl Request {
+    // The request's id, null for a notification
+    pub(super) fn id(&self) -> &Value {
+        &self.value["id"]
+    }
+
+    pub(super) fn is_notification(&self) -> bool {
+        self.value.get("id").is_none()
+    }
+}
+
+impl Serialize for Request {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let request = match &self.value {
+            Value::Object(request) if !self.raw.is_empty() => request,
+            value => return value.serialize(serializer),
+        };
+        let mut map = serializer.serialize_map(Some(request.len()))?;
+        for (key, value) in request {
+            if key == "params" {
+                map.serialize_entry(key, &RawParams(value, &self.raw))?;
+            } else {
+                map.serialize_entry(key, value)?;
+            }
+        }
+        map.end()
+    }
+}
+
+// Params with the raw ones in place
+struct RawParams<'a>(&'a Value, &'a [(RawSlot, Box<RawValue>)]);
+
+impl RawParams<'_> {
+    fn raw(&self, at: impl Fn(&RawSlot) -> bool) -> Option<&RawValue> {
+        self.1
+            .iter()
+            .find(|(slot, _)| at(slot))
+            .map(|(_, raw)| &**raw)
+    }
+}
+
+impl Serialize for RawParams<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        match self.0 {
+            Value::Object(params) => {
+                let mut map = serializer.serialize_map(Some(params.len()))?;
+                for (name, value) in params {
+                    match self.raw(|slot| matches!(slot, RawSlot::Name(n) if n == name)) {
+                        Some(raw) => map.serialize_entry(name, raw)?,
+                        None => map.serialize_entry(name, value)?,
+                    }
+                }
+                map.end()
+            }
+            Value::Array(params) => {
+                let mut seq = serializer.serialize_seq(Some(params.len()))?;
+                for (i, value) in params.iter().enumerate() {
+                    match self.raw(|slot| matches!(slot, RawSlot::Index(n) if *n == i)) {
+                        Some(raw) => seq.serialize_element(raw)?,
+                        None => seq.serialize_element(value)?,
+                    }
+                }
+                seq.end()
+            }
+            params => params.serialize(serializer),
+        }
+    }
+}
+
+/// How a JSON client encodes `params` for a method without
+/// parameters. Some strict JSON-RPC servers reject one form or
+/// another. The server side accepts any of them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EmptyParams {
+    /// Send the empty params as built: `"params": {}`, or
+    /// `"params": []` for a positional transport. This is the
+    /// default.
+    Object,
+    /// Send an empty array, `"params": []`.
+    Array,
+    /// Omit the `params` member entirely.
+    Omit,
+}
+
+/// How the JSON transports encode floats which JSON has no numbers
+/// for: NaN, infinity and negative infinity, e.g. the gaps and
+/// overflows of scientific data. Applies to parameters and results,
+/// including floats nested within them, and to reading as well as
+/// writing, so both ends should agree on it.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum NonFiniteFloats {
+    /// Send them as `null`, as serde_json does, and read `null` as
+    /// NaN where a float is expected. The infinities come back as
+    /// NaN. This is the default.
+    #[default]
+    Null,
+    /// Send them as the strings `"NaN"`, `"Infinity"` and
+    /// `"-Infinity"`, as JavaScript and Python name them, and read
+    /// those strings as the floats where a float is expected.
+    Strings,
+    /// Refuse to send them, with an error of kind
+    /// `RPCErrorKind::SerializationError`, and read only numbers as
+    /// floats.
+    Error,
+}
+
+// Options affecting how a client encodes requests. Shared by all of
+// the JSON client transports.
+#[derive(Clone)]
+pub(super) struct CallOptions {
+    pub(super) numeric: bool,
+    pub(super) positional: bool,
+    pub(super) empty_params: EmptyParams,
+    pub(super) ids: RequestIds,
+}
+
+// How a client generates the ids of its requests
+#[derive(Clone)]
+pub(super) enum RequestIds {
+    #[cfg(feature = "uuid_ids")]
+    Uuid,
+    // The next id, shared with clones so that they never reuse one
+    Sequential(Arc<AtomicU64>),
+    Custom(Arc<Mutex<Box<dyn FnMut() -> Value + Send>>>),
+}
+
+impl RequestIds {
+    fn next(&self) -> Value {
+        match self {
+            #[cfg(feature = "uuid_ids")]
+            RequestIds::Uuid => json!(format!("{}", Uuid::new_v4())),
+            RequestIds::Sequential(next) => json!(next.fetch_add(1, Ordering::Relaxed)),
+            RequestIds::Custom(generator) => {
+                let mut generator = generator.lock().unwrap_or_else(|e| e.into_inner());
+                generator()
+            }
+        }
+    }
+}
+
+// Random UUIDs where the "uuid_ids" feature provides them, and
+// otherwise incrementing integers
+impl Default for RequestIds {
+    #[cfg(feature = "uuid_ids")]
+    fn default() -> Self {
+        RequestIds::Uuid
+    }
+
+    #[cfg(not(feature = "uuid_ids"))]
+    fn default() -> Self {
+        RequestIds::Sequential(Arc::new(AtomicU64::new(1)))
+    }
+}
+
+impl Default for CallOptions {
+    fn default() -> Self {
+        CallOptions {
+            numeric: false,
+            positional: false,
+            empty_params: EmptyParams::Object,
+            ids: RequestIds::default(),
+        }
+    }
+}
+
+/// Configuration of the JSON encoding used by a
+/// [JSONTransport](struct.JSONTransport.html).
+///
+/// Number handling is not configured here because serde_json fixes it
+/// at compile time. Enable the "json_arbitrary_precision" feature to
+/// turn on serde_json's `arbitrary_precision`, which preserves numbers
+/// exactly rather than converting them through `f64`.
+#[derive(Clone, Debug, Default)]
+pub struct JSONTransportConfig {
+    /// Write indented, multi-line JSON. Larger on the wire but much
+    /// easier to read when inspecting traffic. Readers accept either
+    /// form.
+    pub pretty: bool,
+}
+
+/// State of a call received by the JSON server transports, the
+/// `RXState` of their `ServerTransport` implementations. Gives
+/// access to the raw request, e.g. for a
+/// [Router](struct.Router.html) handler forwarding calls elsewhere.
+pub struct JRXState {
+    json: Value,
+    pub(super) id: Value,
+    // True if the request has no id, so gets no response
+    notification: bool,
+    // Index of the next parameter when params are positional
+    next_param: usize,
+    // The params, when left as text by with_lazy_params
+    lazy: Option<LazyParams>,
+    pub(super) floats: NonFiniteFloats,
+}
+
+impl JRXState {
+    /// The `params` member of the request: an object keyed by
+    /// parameter name, an array of positional parameters, or `None`
+    /// if the request has no parameters. Parameters are moved out of
+    /// it as the server reads them, so this is best looked at before
+    /// the call is dispatched, e.g. in a hook set by
+    /// [JSONTransport::on_call](struct.JSONTransport.html#method.on_call).
+    /// With lazy params, they are instead parsed from their text when
+    /// first asked for, and include those already read.
+    pub fn params(&self) -> Option<&Value> {
+        match &self.lazy {
+            Some(lazy) => Some(
+                lazy.parsed
+                    .get_or_init(|| serde_json::from_str(lazy.text.get()).unwrap_or(Value::Null)),
+            ),
+            None => self.json.get("params"),
+        }
+    }
+
+    /// The `meta` member of the request, carrying the context set by
+    /// the client with
+    /// [JSONTransport::set_context](struct.JSONTransport.html#method.set_context),
+    /// or `None` if it has none.
+    pub fn meta(&self) -> Option<&Map<String, Value>> {
+        self.json.get("meta").and_then(Value::as_object)
+    }
+
+    /// The `jsonrpc` member of the request, giving its JSON-RPC
+    /// version, or `None` if it has none. Always `"2.0"` unless the
+    /// server transport accepts any version.
+    pub fn version(&self) -> Option<&str> {
+        self.json.get("jsonrpc").and_then(Value::as_str)
+    }
+
+    /// The `v` member of the request, giving the version of the trait
+    /// the client was built against, or `None` if it has none or it
+    /// is not a `u32`. See
+    /// [ClientTransport::tx_set_service_version](../trait.ClientTransport.html#method.tx_set_service_version).
+    pub fn service_version(&self) -> Option<u32> {
+        self.json
+            .get("v")
+            .and_then(Value::as_u64)
+            .and_then(|v| u32::try_from(v).ok())
+    }
+
+    /// The JSON-RPC id of the request, `Null` for a notification.
+    pub fn id(&self) -> &Value {
+        &self.id
+    }
+
+    /// True if the request is a JSON-RPC notification, i.e. it has no
+    /// `id` member. The server transports send no response to a
+    /// notification, whatever the method returns.
+    pub fn is_notification(&self) -> bool {
+        self.notification
+    }
+}
+
+// A message read by a server with lazy params: a request object,
+// less its params, which are left as text, or any other message,
+// such as a batch, as a Value.
+pub(super) enum Message {
+    Request(Map<String, Value>, Option<Box<RawValue>>),
+    Other(Value),
+}
+
+impl<'de> Deserialize<'de> for Message {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        deserializer.deserialize_any(MessageVisitor)
+    }
+}
+
+struct MessageVisitor;
+
+impl<'de> Visitor<'de> for MessageVisitor {
+    type Value = Message;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a json message")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> std::result::Result<Message, A::Error> {
+        let mut members = Map::new();
+        let mut params = None;
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "params" {
+                params = Some(map.next_value()?);
+            } else {
+                let value = map.next_value()?;
+                members.insert(key, value);
+            }
+        }
+        Ok(Message::Request(members, params))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> std::result::Result<Message, A::Error> {
+        Value::deserialize(SeqAccessDeserializer::new(seq)).map(Message::Other)
+    }
+
+    fn visit_bool<E>(self, v: bool) -> std::result::Result<Message, E> {
+        Ok(Message::Other(Value::Bool(v)))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> std::result::Result<Message, E> {
+        Ok(Message::Other(v.into()))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> std::result::Result<Message, E> {
+        Ok(Message::Other(v.into()))
+    }
+
+    fn visit_f64<E>(self, v: f64) -> std::result::Result<Message, E> {
+        Ok(Message::Other(v.into()))
+    }
+
+    fn visit_str<E>(self, v: &str) -> std::result::Result<Message, E> {
+        Ok(Message::Other(v.into()))
+    }
+
+    fn visit_unit<E>(self) -> std::result::Result<Message, E> {
+        Ok(Message::Other(Value::Null))
+    }
+}
+
+// Params left as text, along with where the text of each parameter
+// lies within it
+struct LazyParams {
+    text: Box<RawValue>,
+    spans: ParamSpans,
+    // The params as a Value, should JRXState::params be called
+    parsed: OnceCell<Value>,
+}
+
+enum ParamSpans {
+    Named(HashMap<String, Range<usize>>),
+    Positional(Vec<Range<usize>>),
+    // Neither an object nor an array, so holding no parameters
+    Neither,
+}
+
+impl LazyParams {
+    fn new(text: Box<RawValue>) -> Self {
+        let spans = {
+            let whole = text.get();
+            let span = |param: &RawValue| {
+                let start = param.get().as_ptr() as usize - whole.as_ptr() as usize;
+                start..start + param.get().len()
+            };
+            // The text is known to be valid, so only its shape is
+            // in question
+            match whole.trim_start().as_bytes().first() {
+                Some(b'{') => serde_json::from_str::<HashMap<String, &RawValue>>(whole)
+                    .map(|params| {
+                        ParamSpans::Named(params.into_iter().map(|(k, v)| (k, span(v))).collect())
+                    })
+                    .unwrap_or(ParamSpans::Neither),
+                Some(b'[') => serde_json::from_str::<Vec<&RawValue>>(whole)
+                    .map(|params| ParamSpans::Positional(params.into_iter().map(span).collect()))
+                    .unwrap_or(ParamSpans::Neither),
+                _ => ParamSpans::Neither,
+            }
+        };
+        LazyParams {
+            text,
+            spans,
+            parsed: OnceCell::new(),
+        }
+    }
+}
+
+// How deeply arrays and objects are nested in valid JSON text,
+// found without parsing it
+fn text_depth(text: &str) -> usize {
+    let mut depth = 0;
+    let mut deepest = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for b in text.bytes() {
+        if in_string {
+            match b {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => (),
+            }
+            continue;
+        }
+        match b {
+            b'"' => in_string = true,
+            b'[' | b'{' => {
+                depth += 1;
+                deepest = deepest.max(depth);
+            }
+            b']' | b'}' => depth -= 1,
+            _ => (),
+        }
+    }
+    deepest
+}
+
+/// JSON-RPC error code used when a method implementation returns an
+/// error. The error itself is carried in the `data` member.
+const APPLICATION_ERROR: i64 = -32000;
+/// JSON-RPC error code for a message which is not valid JSON.
+const PARSE_ERROR: i64 = -32700;
+/// JSON-RPC error code for a request which is not a valid request
+/// object.
+const INVALID_REQUEST: i64 = -32600;
+/// JSON-RPC error code for a method the server does not implement.
+const METHOD_NOT_FOUND: i64 = -32601;
+/// JSON-RPC error code for a request whose params do not suit the
+/// method. The `data` member names the parameter, and whether it was
+/// missing or could not be deserialized.
+const INVALID_PARAMS: i64 = -32602;
+/// Name of the keep-alive ping answered by the server transports,
+/// within the method names JSON-RPC reserves for itself.
+const PING_METHOD: &str = "rpc.ping";
+/// Name of the notification with which a client cancels a streaming
+/// response, whose request id it carries as `params.id`.
+const CANCEL_METHOD: &str = "$cancel";
+/// JSON-RPC error code for a call from a client built against a
+/// version of the trait the server does not serve, within the range
+/// JSON-RPC leaves to servers.
+const VERSION_MISMATCH: i64 = -32010;
+/// JSON-RPC error code for an internal error.
+const INTERNAL_ERROR: i64 = -32603;
+/// Member of the greeting sent by each end of a connection set up
+/// with a handshake, whose value is HANDSHAKE_VERSION.
+const HANDSHAKE_MAGIC: &str = "essrpc";
+/// Version of the protocol spoken after the handshake, to be raised
+/// should the messages change incompatibly.
+const HANDSHAKE_VERSION: u32 = 1;
+
+// Errors from serde_json may wrap a failure of the channel rather
+// than of the json itself, which are reported as transport errors.
+pub(super) fn convert_error(e: serde_json::Error) -> RPCError {
+    match e.io_error_kind() {
+        Some(io::ErrorKind::ConnectionReset)
+        | Some(io::ErrorKind::ConnectionAborted)
+        | Some(io::ErrorKind::BrokenPipe)
+        | Some(io::ErrorKind::UnexpectedEof) => {
+            RPCError::with_cause(RPCErrorKind::TransportEOF, "json peer disconnected", e)
+        }
+        Some(_) => RPCError::with_cause(RPCErrorKind::TransportError, "json io failed", e),
+        None if e.classify() == serde_json::error::Category::Syntax => {
+            RPCError::with_cause(RPCErrorKind::ParseError, "json parse error", e)
+        }
+        None => RPCError::with_cause(
+            RPCErrorKind::SerializationError,
+            "json serialization or deserialization failed",
+            e,
+        ),
+    }
+}
+
+// Most bytes of a message quoted in an error decoding it
+const SNIPPET_BYTES: usize = 64;
+
+// An error decoding `bytes`, quoting their start to help diagnose
+// corrupted messages
+pub(super) fn decode_error(e: serde_json::Error, bytes: &[u8]) -> RPCError {
+    let mut error = convert_error(e);
+    if let RPCErrorKind::ParseError | RPCErrorKind::SerializationError = error.kind {
+        error.msg = format!("{} in {}", error.msg, snippet(bytes));
+    }
+    error
+}
+
+// The start of `bytes`, quoted, with any invalid UTF-8 replaced
+fn snippet(bytes: &[u8]) -> String {
+    let shown = String::from_utf8_lossy(&bytes[..bytes.len().min(SNIPPET_BYTES)]);
+    if bytes.len() > SNIPPET_BYTES {
+        format!("{:?}...", shown)
+    } else {
+        format!("{:?}", shown)
+    }
+}
+
+// The greeting sent by each end of a connection set up with a
+// handshake: {"essrpc": <protocol version>}
+pub(super) fn handshake_greeting() -> Value {
+    json!({ (HANDSHAKE_MAGIC): HANDSHAKE_VERSION })
+}
+
+// Check the greeting received from the peer in a handshake
+pub(super) fn check_handshake(greeting: &Value) -> Result<()> {
+    match greeting.get(HANDSHAKE_MAGIC) {
+        Some(version) if *version == HANDSHAKE_VERSION => Ok(()),
+        Some(version) => Err(RPCError::new(
+            RPCErrorKind::HandshakeFailed,
+            format!(
+                "peer speaks essrpc protocol version {}, not {}",
+                version, HANDSHAKE_VERSION
+            ),
+        )),
+        None => Err(RPCError::new(
+            RPCErrorKind::HandshakeFailed,
+            format!(
+                "peer is not essrpc, greeting with {}",
+                snippet(greeting.to_string().as_bytes())
+            ),
+        )),
+    }
+}
+
+pub(super) fn begin_call(method: MethodId, options: &CallOptions) -> JTXState {
+    JTXState {
+        method: if options.numeric {
+            json!(method.num)
+        } else {
+            json!(method.name)
+        },
+        params: if options.positional {
+            json!([])
+        } else {
+            json!({})
+        },
+        raw: Vec::new(),
+        version: None,
+        floats: NonFiniteFloats::default(),
+    }
+}
+
+// Send the trait's version with a call, as the request's v member
+pub(super) fn set_service_version(version: u32, state: &mut JTXState) {
+    state.version = Some(version);
+}
+
+pub(super) fn value_for_state(state: &JTXState, options: &CallOptions) -> Request {
+    let mut request = request_for_state(state, options);
+    let id = options.ids.next();
+    trace::record_id(&id);
+    request.value["id"] = id;
+    request
+}
+
+// Build a request without its id, which value_for_state gives it and
+// a notification goes without.
+fn request_for_state(state: &JTXState, options: &CallOptions) -> Request {
+    let mut request = json!({
+        "jsonrpc": "2.0",
+        "method": state.method,
+        "params": state.params,
+        "id": null
+    });
+    if let Some(version) = state.version {
+        request["v"] = json!(version);
+    }
+    let empty = match &state.params {
+        Value::Array(params) => params.is_empty(),
+        Value::Object(params) => params.is_empty(),
+        _ => false,
+    };
+    if empty {
+        match options.empty_params {
+            EmptyParams::Object => (),
+            EmptyParams::Array => request["params"] = json!([]),
+            EmptyParams::Omit => {
+                if let Value::Object(request) = &mut request {
+                    request.remove("params");
+                }
+            }
+        }
+    }
+    Request {
+        value: request,
+        raw: state.raw.clone(),
+    }
+}
+
+// Build a JSON-RPC notification: a request object without an id, to
+// which the server sends no response.
+pub(super) fn notification_for_state(state: &JTXState, options: &CallOptions) -> Request {
+    let mut notification = request_for_state(state, options);
+    if let Value::Object(notification) = &mut notification.value {
+        notification.remove("id");
+    }
+    notification
+}
+
+// Build the JSON-RPC response object for the result of a method call.
+pub(super) fn response_for_result(
+    value: std::result::Result<impl Serialize, impl Serialize>,
+    id: Value,
+    floats: NonFiniteFloats,
+) -> Result<Value> {
+    trace::record_id(&id);
+    Ok(match value {
+        Ok(v) => json!({
+            "jsonrpc": "2.0",
+            "result": json_float::to_value(v, floats).map_err(convert_error)?,
+            "id": id
+        }),
+        Err(e) => json!({
+            "jsonrpc": "2.0",
+            "error": {
+                "code": APPLICATION_ERROR,
+                "message": "application error",
+                "data": json_float::to_value(e, floats).map_err(convert_error)?
+            },
+            "id": id
+        }),
+    })
+}
+
+// Build the JSON-RPC response object for an error raised by essrpc
+// itself rather than the method implementation.
+pub(super) fn response_for_error(error: &RPCError, id: Value) -> Value {
+    if !id.is_null() {
+        trace::record_id(&id);
+    }
+    let code = match error.kind {
+        RPCErrorKind::UnknownMethod => METHOD_NOT_FOUND,
+        RPCErrorKind::ParseError => PARSE_ERROR,
+        RPCErrorKind::InvalidRequest => INVALID_REQUEST,
+        RPCErrorKind::InvalidParams => INVALID_PARAMS,
+        RPCErrorKind::VersionMismatch => VERSION_MISMATCH,
+        RPCErrorKind::Application(code) => code,
+        _ => INTERNAL_ERROR,
+    };
+    let mut response = json!({
+        "jsonrpc": "2.0",
+        "error": {
+            "code": code,
+            "message": error.msg
+        },
+        "id": id
+    });
+    if let Some(param) = error.param() {
+        response["error"]["data"] = match error.cause() {
+            Some(cause) => json!({
+                "param": param,
+                "reason": "invalid",
+                "detail": cause.to_string()
+            }),
+            None => json!({ "param": param, "reason": "missing" }),
+        };
+    }
+    response
+}
+
+// Build the response which ends a streaming response. It carries a
+// null result, so that it is still a valid JSON-RPC response, and is
+// told apart from the items by the `stream_end` member.
+pub(super) fn stream_end_response(id: Value) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "result": null,
+        "stream_end": true,
+        "id": id
+    })
+}
+
+// Convert one response of a streaming response back into an item, or
+// `None` if it ends the stream.
+pub(super) fn stream_item_from_response<T>(
+    response: Value,
+    floats: NonFiniteFloats,
+) -> Result<Option<T>>
+where
+    for<'de> T: serde::Deserialize<'de>,
+{
+    if is_stream_end(&response) {
+        return Ok(None);
+    }
+    result_from_response(response, floats).map(Some)
+}
+
+// Whether a response is the one ending a streaming response
+pub(super) fn is_stream_end(response: &Value) -> bool {
+    response.get("stream_end").and_then(Value::as_bool) == Some(true)
+}
+
+// Verify a response answers the request with the given id. Errors
+// which the server could not attribute to a request have a null id.
+pub(super) fn check_response_id(response: &Value, id: &Value) -> Result<()> {
+    match response.get("id") {
+        Some(rid) if rid == id => Ok(()),
+        Some(Value::Null) if response.get("error").is_some() => Ok(()),
+        rid => Err(RPCError::new(
+            RPCErrorKind::SerializationError,
+            format!(
+                "json response id {} does not match request id {}",
+                rid.unwrap_or(&Value::Null),
+                id
+            ),
+        )),
+    }
+}
+
+// Convert a JSON-RPC response object back into the `Result` type
+// returned by the method. `T` is expected to be that `Result` type.
+pub(super) fn result_from_response<T>(mut response: Value, floats: NonFiniteFloats) -> Result<T>
+where
+    for<'de> T: serde::Deserialize<'de>,
+{
+    if let Some(result) = response.get_mut("result") {
+        return json_float::from_value(json!({ "Ok": result.take() }), floats)
+            .map_err(convert_error);
+    }
+    let error = response.get_mut("error").ok_or_else(|| {
+        RPCError::new(
+            RPCErrorKind::SerializationError,
+            "json response has neither result nor error",
+        )
+    })?;
+    let code = error
+        .get("code")
+        .and_then(Value::as_i64)
+        .unwrap_or(APPLICATION_ERROR);
+    if code == APPLICATION_ERROR {
+        if let Some(data) = error.get_mut("data") {
+            if let Ok(v) = json_float::from_value(json!({ "Err": data.take() }), floats) {
+                return Ok(v);
+            }
+        }
+    }
+    let message = error
+        .get("message")
+        .and_then(Value::as_str)
+        .unwrap_or("json-rpc error");
+    let kind = match code {
+        METHOD_NOT_FOUND => RPCErrorKind::UnknownMethod,
+        PARSE_ERROR => RPCErrorKind::ParseError,
+        INVALID_REQUEST => RPCErrorKind::InvalidRequest,
+        INVALID_PARAMS => RPCErrorKind::InvalidParams,
+        VERSION_MISMATCH => RPCErrorKind::VersionMismatch,
+        code => RPCErrorKind::Application(code),
+    };
+    let param = match kind {
+        RPCErrorKind::InvalidParams => error["data"]["param"].as_str(),
+        _ => None,
+    };
+    match param {
+        Some(param) => Err(RPCError::new(kind, message).with_param(param)),
+        None => Err(RPCError::new(kind, message)),
+    }
+}
+
+pub(super) fn add_param(
+    name: &'static str,
+    value: impl Serialize,
+    state: &mut JTXState,
+) -> Result<()> {
+    // A raw param's place is held by a null until it is sent
+    let raw = raw_param(&value);
+    let value = match raw {
+        Some(_) => Value::Null,
+        None => json_float::to_value(value, state.floats).map_err(convert_error)?,
+    };
+    let slot = match &mut state.params {
+        Value::Array(params) => {
+            params.push(value);
+            RawSlot::Index(params.len() - 1)
+        }
+        Value::Object(params) => {
+            // The macro refuses methods whose params share a name, so
+            // this is a hand-made call adding one twice
+            if cfg!(debug_assertions) && params.contains_key(name) {
+                return Err(RPCError::new(
+                    RPCErrorKind::SerializationError,
+                    format!("json param {} added twice", name),
+                ));
+            }
+            params.insert(name.to_string(), value);
+            RawSlot::Name(name.to_string())
+        }
+        _ => {
+            return Err(RPCError::new(
+                RPCErrorKind::SerializationError,
+                "json params are neither an array nor an object",
+            ))
+        }
+    };
+    if let Some(raw) = raw {
+        state.raw.push((slot, raw));
+    }
+    Ok(())
+}
+
+// Refuse a request object whose `jsonrpc` member is missing or not
+// "2.0" as an invalid request. Values which are not objects are left
+// for call_from_value to refuse.
+pub(super) fn check_version(request: &Value) -> Result<()> {
+    let msg = match request.get("jsonrpc") {
+        _ if !request.is_object() => return Ok(()),
+        Some(Value::String(version)) if version == "2.0" => return Ok(()),
+        Some(version) => format!("unsupported jsonrpc version {}", version),
+        None => "request has no jsonrpc version".to_string(),
+    };
+    Err(RPCError::new(RPCErrorKind::InvalidRequest, msg))
+}
+
+// The id to answer a request with, null if it has none
+pub(super) fn request_id(request: &Value) -> Value {
+    request.get("id").cloned().unwrap_or(Value::Null)
+}
+
+pub(super) fn call_from_value(value: Value) -> Result<(PartialMethodId, JRXState)> {
+    let method = match value.get("method") {
+        Some(Value::String(name)) => PartialMethodId::Name(name.to_string()),
+        Some(Value::Number(num)) => PartialMethodId::Num(
+            num.as_u64()
+                .and_then(|n| u32::try_from(n).ok())
+                .ok_or_else(|| {
+                    RPCError::new(
+                        RPCErrorKind::InvalidRequest,
+                        "json method number out of range",
+                    )
+                })?,
+        ),
+        Some(method) => {
+            return Err(RPCError::new(
+                RPCErrorKind::InvalidRequest,
+                format!(
+                    "json method {} was not string or number",
+                    snippet(method.to_string().as_bytes())
+                ),
+            ))
+        }
+        None if !value.is_object() => {
+            return Err(RPCError::new(
+                RPCErrorKind::InvalidRequest,
+                format!(
+                    "json request is not an object: {}",
+                    snippet(value.to_string().as_bytes())
+                ),
+            ))
+        }
+        None => {
+            return Err(RPCError::new(
+                RPCErrorKind::InvalidRequest,
+                "json request has no method",
+            ))
+        }
+    };
+    let id = value.get("id").cloned();
+    Ok((
+        method,
+        JRXState {
+            json: value,
+            notification: id.is_none(),
+            id: id.unwrap_or(Value::Null),
+            next_param: 0,
+            lazy: None,
+            floats: NonFiniteFloats::default(),
+        },
+    ))
+}
+
+pub(super) fn read_param<T>(name: &'static str, state: &mut JRXState) -> Result<T>
+where
+    for<'de> T: serde::Deserialize<'de>,
+{
+    read_param_opt(name, state)?.ok_or_else(|| {
+        RPCError::new(
+            RPCErrorKind::InvalidParams,
+            format!("parameters do not contain {}", name),
+        )
+        .with_param(name)
+    })
+}
+
+// Read a parameter, or None if the request does not have it.
+// Requests without params have none of their parameters.
+pub(super) fn read_param_opt<T>(name: &'static str, state: &mut JRXState) -> Result<Option<T>>
+where
+    for<'de> T: serde::Deserialize<'de>,
+{
+    if !state.json.is_object() {
+        return Err(RPCError::new(
+            RPCErrorKind::SerializationError,
+            "json is not expected object",
+        ));
+    }
+    let floats = state.floats;
+    if let Some(lazy) = &mut state.lazy {
+        let span = match &mut lazy.spans {
+            ParamSpans::Named(spans) => spans.remove(name),
+            ParamSpans::Positional(spans) => {
+                state.next_param += 1;
+                spans.get(state.next_param - 1).cloned()
+            }
+            ParamSpans::Neither => None,
+        };
+        return span
+            .map(|span| {
+                json_float::from_str(&lazy.text.get()[span], floats)
+                    .map_err(|e| invalid_param(name, e))
+            })
+            .transpose();
+    }
+    let params = match state.json.get_mut("params") {
+        Some(params) => params,
+        None => return Ok(None),
+    };
+    // Positional params are read in the order the method declares
+    // them. Each is moved out of the request as it is read, rather
+    // than copied.
+    let param_val = match params {
+        Value::Array(params) => {
+            state.next_param += 1;
+            params.get_mut(state.next_param - 1).map(Value::take)
+        }
+        Value::Object(params) => params.remove(name),
+        _ => None,
+    };
+    param_val
+        .map(|v| json_float::from_value(v, floats).map_err(|e| invalid_param(name, e)))
+        .transpose()
+}
+
+fn invalid_param(name: &'static str, e: serde_json::Error) -> RPCError {
+    RPCError::with_cause(
+        RPCErrorKind::InvalidParams,
+        format!("parameter {} is not valid for the method", name),
+        e,
+    )
+    .with_param(name)
+}
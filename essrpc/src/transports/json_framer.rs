@@ -0,0 +1,159 @@
+// Finding where each json value read from a stream ends, for
+// transports which read whatever bytes have arrived rather than
+// parsing straight from the channel. Rescanning the whole buffer
+// after each read, as parsing it afresh would, makes reading a large
+// value quadratic, so the scan picks up where it stopped. It follows
+// the structure of the value, so that misplaced brackets, commas and
+// the like are caught as soon as they arrive; what is within
+// strings, numbers and literals is left to the parser.
+
+use serde_json::Value;
+
+use super::json_codec::decode_error;
+use crate::Result;
+
+// Deepest nesting serde_json parses
+const RECURSION_LIMIT: usize = 128;
+
+// Finds the json values in bytes read bit by bit from a stream,
+// scanning each byte once however it arrives, and parsing a value
+// only once it is complete.
+#[derive(Default)]
+pub(super) struct ValueFramer {
+    // Bytes of the value in progress scanned so far
+    scanned: usize,
+    // Whether each array or object the scan is within is an object
+    nesting: Vec<bool>,
+    expect: Expect,
+    in_string: bool,
+    escaped: bool,
+    in_scalar: bool,
+}
+
+// What may come next in a value, bar whitespace
+#[derive(Clone, Copy, Default)]
+enum Expect {
+    #[default]
+    Value,
+    // At the start of an array
+    ValueOrEnd,
+    Key,
+    // At the start of an object
+    KeyOrEnd,
+    Colon,
+    CommaOrEnd,
+}
+
+impl ValueFramer {
+    // Like json_codec::next_value, for a buffer which starts where the last value
+    // found ended, and otherwise only grows between calls.
+    pub(super) fn next_value(&mut self, buf: &[u8]) -> Result<Option<(Value, usize)>> {
+        let len = match self.value_len(buf) {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+        *self = ValueFramer::default();
+        let value =
+            serde_json::from_slice(&buf[..len]).map_err(|e| decode_error(e, &buf[..len]))?;
+        Ok(Some((value, len)))
+    }
+
+    // The length of the first value in buf, with any whitespace
+    // before it, or None if it is not yet complete, for a buffer as
+    // for next_value. Invalid json ends the value at the first byte
+    // out of place, for the parser to fail on. Once a length is
+    // found, the framer is spent.
+    pub(super) fn value_len(&mut self, buf: &[u8]) -> Option<usize> {
+        while let Some(&b) = buf.get(self.scanned) {
+            if self.in_scalar {
+                if is_scalar_byte(b) {
+                    self.scanned += 1;
+                    continue;
+                }
+                // The byte after a number or literal is scanned anew
+                self.in_scalar = false;
+                if self.end_value() {
+                    return Some(self.scanned);
+                }
+                continue;
+            }
+            self.scanned += 1;
+            if self.in_string {
+                match b {
+                    _ if self.escaped => self.escaped = false,
+                    b'\\' => self.escaped = true,
+                    b'"' => {
+                        self.in_string = false;
+                        if let Expect::Key | Expect::KeyOrEnd = self.expect {
+                            self.expect = Expect::Colon;
+                        } else if self.end_value() {
+                            return Some(self.scanned);
+                        }
+                    }
+                    _ => (),
+                }
+                continue;
+            }
+            if b.is_ascii_whitespace() {
+                continue;
+            }
+            let in_object = self.nesting.last().copied();
+            match (self.expect, b) {
+                (Expect::Value, _) | (Expect::ValueOrEnd, _) if b != b']' => match b {
+                    b'{' | b'[' if self.nesting.len() == RECURSION_LIMIT => {
+                        return Some(self.scanned)
+                    }
+                    b'{' => {
+                        self.nesting.push(true);
+                        self.expect = Expect::KeyOrEnd;
+                    }
+                    b'[' => {
+                        self.nesting.push(false);
+                        self.expect = Expect::ValueOrEnd;
+                    }
+                    b'"' => self.in_string = true,
+                    _ if is_scalar_byte(b) => self.in_scalar = true,
+                    _ => return Some(self.scanned),
+                },
+                (Expect::Key, b'"') | (Expect::KeyOrEnd, b'"') => self.in_string = true,
+                (Expect::Colon, b':') => self.expect = Expect::Value,
+                (Expect::CommaOrEnd, b',') => {
+                    self.expect = match in_object {
+                        Some(true) => Expect::Key,
+                        _ => Expect::Value,
+                    }
+                }
+                (Expect::ValueOrEnd, b']') | (Expect::CommaOrEnd, b']')
+                    if in_object == Some(false) =>
+                {
+                    self.nesting.pop();
+                    if self.end_value() {
+                        return Some(self.scanned);
+                    }
+                }
+                (Expect::KeyOrEnd, b'}') | (Expect::CommaOrEnd, b'}')
+                    if in_object == Some(true) =>
+                {
+                    self.nesting.pop();
+                    if self.end_value() {
+                        return Some(self.scanned);
+                    }
+                }
+                _ => return Some(self.scanned),
+            }
+        }
+        None
+    }
+
+    // A value has just ended: true if it is the whole value, at the
+    // top level
+    fn end_value(&mut self) -> bool {
+        self.expect = Expect::CommaOrEnd;
+        self.nesting.is_empty()
+    }
+}
+
+// Whether `b` may be part of a number or literal
+fn is_scalar_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'-' || b == b'+' || b == b'.'
+}
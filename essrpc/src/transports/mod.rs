@@ -1,32 +1,188 @@
 //! `Transport` implementations and helpers.
 use std::io;
 use std::io::{Read, Write};
+use std::net::TcpStream;
+#[cfg(feature = "json_transport")]
+use std::net::ToSocketAddrs;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
 
 #[cfg(feature = "bincode_transport")]
 mod bincode;
 #[cfg(all(feature = "bincode_transport", feature = "async_client"))]
 pub use self::bincode::BincodeAsyncClientTransport;
 #[cfg(feature = "bincode_transport")]
-pub use self::bincode::BincodeTransport;
+pub use self::bincode::{BincodeTransport, ByteOrder};
+
+#[cfg(feature = "json_transport")]
+mod dynamic;
+#[cfg(feature = "json_transport")]
+pub use self::dynamic::DynamicClient;
+
+#[cfg(feature = "json_transport")]
+mod framing;
 
 #[cfg(feature = "json_transport")]
 mod json;
+#[cfg(all(feature = "json_transport", feature = "async_server"))]
+pub use self::json::{AsyncSplitTransport, JSONAsyncServerTransport};
+#[cfg(feature = "json_transport")]
+pub use self::json::{CallStats, JSONBatch, JSONTransport, SplitTransport};
 #[cfg(all(feature = "json_transport", feature = "async_client"))]
-pub use self::json::JSONAsyncClientTransport;
+pub use self::json::{JSONAsyncClientTransport, MultiplexedClient};
+#[cfg(feature = "json_transport")]
+pub use self::json_codec::{EmptyParams, JRXState, JSONTransportConfig, NonFiniteFloats};
+
+#[cfg(feature = "json_transport")]
+pub mod json_codec;
+
+#[cfg(feature = "json_transport")]
+mod json_float;
+#[cfg(feature = "json_transport")]
+mod json_framer;
+
+#[cfg(feature = "json_transport")]
+mod raw_param;
+
+#[cfg(all(feature = "json_transport", feature = "bincode_transport"))]
+mod negotiate;
+#[cfg(all(feature = "json_transport", feature = "bincode_transport"))]
+pub use self::negotiate::{NegotiatingTransport, WireFormat};
+
+#[cfg(all(feature = "json_transport", feature = "async_server"))]
+mod pipeline;
+#[cfg(all(feature = "json_transport", feature = "async_server"))]
+pub use self::pipeline::{PipelinedCall, PipelinedServer};
+
+#[cfg(feature = "json_transport")]
+mod threaded;
+#[cfg(feature = "json_transport")]
+pub use self::threaded::{CallChannel, ThreadedServer};
+
+#[cfg(all(unix, feature = "unix"))]
+mod unix;
+#[cfg(all(unix, feature = "unix"))]
+pub use self::unix::JSONUnixListener;
+
+#[cfg(all(unix, feature = "fd_passing"))]
+mod fd;
+#[cfg(all(unix, feature = "fd_passing"))]
+pub use self::fd::{Fd, FdChannel};
+
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(feature = "tls")]
+pub use self::tls::{JSONTlsAcceptor, TlsClientStream, TlsServerStream};
+
+#[cfg(feature = "http_transport")]
+mod http;
+#[cfg(feature = "http_transport")]
+pub use self::http::HTTPTransport;
+
+#[cfg(feature = "http_server")]
+mod http_service;
+#[cfg(feature = "http_server")]
+pub use self::http_service::{ExchangeChannel, HTTPService};
+
+#[cfg(feature = "websocket_transport")]
+mod websocket;
+#[cfg(feature = "async_websocket_transport")]
+pub use self::websocket::WebSocketAsyncServerTransport;
+#[cfg(feature = "websocket_transport")]
+pub use self::websocket::WebSocketTransport;
+
+#[cfg(feature = "async_tcp")]
+mod tokio_io;
+#[cfg(feature = "async_tcp")]
+pub use self::tokio_io::TokioChannel;
+
+#[cfg(feature = "compression")]
+mod compression;
+#[cfg(feature = "compression")]
+pub use self::compression::CompressedChannel;
+
+#[cfg(feature = "cbor_transport")]
+mod cbor;
+#[cfg(feature = "cbor_transport")]
+pub use self::cbor::CBORTransport;
+
+#[cfg(feature = "msgpack_transport")]
+mod msgpack;
+#[cfg(feature = "msgpack_transport")]
+pub use self::msgpack::MessagePackTransport;
+
+mod duplex;
+pub use self::duplex::DuplexChannel;
+
+mod observe;
+pub use self::observe::{ObservedState, ObservedTransport, Observer};
+
+mod pool;
+pub use self::pool::{PooledTransport, TransportPool};
+
+mod record;
+pub use self::record::{RecordingChannel, RecordingTransport};
+
+mod retry;
+pub use self::retry::{RetryTransport, RetryingClient};
+
+mod router;
+pub use self::router::Router;
+
+/// Flush the channel once a message has been written, so that
+/// buffering channels (including
+/// [CompressedChannel](struct.CompressedChannel.html)) pass it on.
+#[cfg(any(
+    feature = "bincode_transport",
+    feature = "cbor_transport",
+    feature = "json_transport",
+    feature = "msgpack_transport"
+))]
+fn flush_channel(mut w: impl Write) -> crate::Result<()> {
+    w.flush().map_err(|e| {
+        crate::RPCError::with_cause(
+            crate::RPCErrorKind::TransportError,
+            "channel flush failed",
+            e,
+        )
+    })
+}
+
+/// How long a server waits after failing to accept a connection
+/// before trying again, so that a lasting failure, such as running
+/// out of file descriptors, does not spin.
 #[cfg(feature = "json_transport")]
-pub use self::json::JSONTransport;
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Connect to `addr`, reporting a failure to resolve or reach it as
+/// `RPCErrorKind::ConnectionFailed`.
+#[cfg(feature = "json_transport")]
+fn connect_tcp(addr: impl ToSocketAddrs) -> crate::Result<TcpStream> {
+    let failed =
+        |msg, e| crate::RPCError::with_cause(crate::RPCErrorKind::ConnectionFailed, msg, e);
+    let addrs: Vec<_> = addr
+        .to_socket_addrs()
+        .map_err(|e| failed("cannot resolve address", e))?
+        .collect();
+    TcpStream::connect(&addrs[..]).map_err(|e| failed("tcp connect failed", e))
+}
 
 /// Type which combines a `Read` and a `Write` to implement both
 /// `Read` and `Write` in a single type. May be useful in satisfying
 /// the construction requirements of transports such as
 /// [BincodeTransport](struct.BincodeTransport.html) or
-/// [JSONTransport](struct.JSONTransport.html).
-pub struct ReadWrite<R: Read, W: Write> {
+/// [JSONTransport](struct.JSONTransport.html), for instance with the
+/// two halves of a split connection (see
+/// [SplitTransport](type.SplitTransport.html)). With the
+/// "async_client" or "async_server" feature, likewise combines an
+/// `AsyncRead` and an `AsyncWrite`.
+pub struct ReadWrite<R, W> {
     r: R,
     w: W,
 }
 
-impl<R: Read, W: Write> ReadWrite<R, W> {
+impl<R, W> ReadWrite<R, W> {
     pub fn new(r: R, w: W) -> Self {
         ReadWrite { r, w }
     }
@@ -38,15 +194,27 @@ impl<R: Read, W: Write> ReadWrite<R, W> {
     pub fn writable(&self) -> &W {
         &self.w
     }
+    /// Get a mutable reference to the underlying read channel
+    pub fn readable_mut(&mut self) -> &mut R {
+        &mut self.r
+    }
+    /// Get a mutable reference to the underlying write channel
+    pub fn writable_mut(&mut self) -> &mut W {
+        &mut self.w
+    }
+    /// Consume the combination, returning the read and write channels.
+    pub fn into_inner(self) -> (R, W) {
+        (self.r, self.w)
+    }
 }
 
-impl<R: Read, W: Write> Read for ReadWrite<R, W> {
+impl<R: Read, W> Read for ReadWrite<R, W> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         self.r.read(buf)
     }
 }
 
-impl<R: Read, W: Write> Write for ReadWrite<R, W> {
+impl<R, W: Write> Write for ReadWrite<R, W> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         self.w.write(buf)
     }
@@ -54,3 +222,102 @@ impl<R: Read, W: Write> Write for ReadWrite<R, W> {
         self.w.flush()
     }
 }
+
+/// Times out reads from the read channel.
+impl<R: ReadTimeout, W> ReadTimeout for ReadWrite<R, W> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.r.set_read_timeout(timeout)
+    }
+}
+
+/// Ready when the read channel is.
+impl<R: ReadReady, W> ReadReady for ReadWrite<R, W> {
+    fn read_ready(&self) -> io::Result<bool> {
+        self.r.read_ready()
+    }
+}
+
+#[cfg(any(feature = "async_client", feature = "async_server"))]
+mod async_read_write {
+    use super::ReadWrite;
+    use futures::io::{AsyncRead, AsyncWrite};
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    impl<R: AsyncRead + Unpin, W: Unpin> AsyncRead for ReadWrite<R, W> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.r).poll_read(cx, buf)
+        }
+    }
+
+    impl<R: Unpin, W: AsyncWrite + Unpin> AsyncWrite for ReadWrite<R, W> {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.w).poll_write(cx, buf)
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.w).poll_flush(cx)
+        }
+
+        fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.w).poll_close(cx)
+        }
+    }
+}
+
+/// Channel whose reads can be given a timeout. Transports use this
+/// to fail a call with `RPCErrorKind::Timeout` rather than block
+/// forever on an unresponsive peer. Implemented for `TcpStream`,
+/// [DuplexChannel](struct.DuplexChannel.html) and (on unix)
+/// `UnixStream`. Other channels may implement it if they
+/// can time out a read, in which case the read must fail with an
+/// `io::Error` of kind `WouldBlock` or `TimedOut`.
+pub trait ReadTimeout {
+    /// Set the read timeout. `None` means reads block indefinitely.
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl ReadTimeout for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl ReadTimeout for UnixStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+/// Channel which can tell, without blocking, whether a read would
+/// return at once, because data or EOF is waiting. Lets a server
+/// notice messages from the client while it is busy sending a
+/// streaming response, such as a request to cancel it. Implemented
+/// for `TcpStream` and [DuplexChannel](struct.DuplexChannel.html).
+pub trait ReadReady {
+    /// True if a read would not block.
+    fn read_ready(&self) -> io::Result<bool>;
+}
+
+impl ReadReady for TcpStream {
+    fn read_ready(&self) -> io::Result<bool> {
+        self.set_nonblocking(true)?;
+        let peeked = self.peek(&mut [0]);
+        self.set_nonblocking(false)?;
+        match peeked {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use std::io;
+use std::io::{Read, Write};
+
+use rmpv::Value;
+use serde::{Deserialize, Serialize};
+
+use super::flush_channel;
+use crate::{
+    ClientTransport, MethodId, PartialMethodId, RPCError, RPCErrorKind, Result, ServerTransport,
+};
+
+pub struct MPTXState {
+    method: &'static str,
+    params: Vec<(Value, Value)>,
+}
+
+pub struct MPRXState {
+    params: Vec<(Value, Value)>,
+}
+
+/// Transport implementation using MessagePack serialization. Like
+/// [JSONTransport](struct.JSONTransport.html), parameters are sent
+/// by name and methods are dispatched by name. Can be used over any
+/// `Read+Write` channel (local socket, internet socket, pipe,
+/// etc). Enable the "msgpack_transport" feature to use this.
+pub struct MessagePackTransport<C: Read + Write> {
+    channel: C,
+}
+
+impl<C: Read + Write> MessagePackTransport<C> {
+    pub fn new(channel: C) -> Self {
+        MessagePackTransport { channel }
+    }
+
+    /// Get the underlying read/write channel
+    pub fn channel(&self) -> &C {
+        &self.channel
+    }
+
+    /// Get a mutable reference to the underlying read/write channel,
+    /// e.g. to adjust socket options.
+    pub fn channel_mut(&mut self) -> &mut C {
+        &mut self.channel
+    }
+
+    /// Consume the transport, returning the underlying read/write
+    /// channel. Useful for reusing a connection for something else
+    /// once RPC is finished with it.
+    pub fn into_channel(self) -> C {
+        self.channel
+    }
+
+    fn serialize(&mut self, value: impl Serialize) -> Result<()> {
+        rmp_serde::encode::write_named(Write::by_ref(&mut self.channel), &value).map_err(|e| {
+            RPCError::with_cause(
+                RPCErrorKind::SerializationError,
+                "msgpack serialization failure",
+                e,
+            )
+        })
+    }
+
+    fn deserialize<T>(&mut self) -> Result<T>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        rmp_serde::from_read(Read::by_ref(&mut self.channel)).map_err(convert_decode_error)
+    }
+}
+
+fn convert_decode_error(e: rmp_serde::decode::Error) -> RPCError {
+    match e {
+        rmp_serde::decode::Error::InvalidMarkerRead(ref ioe)
+        | rmp_serde::decode::Error::InvalidDataRead(ref ioe)
+            if ioe.kind() == io::ErrorKind::UnexpectedEof =>
+        {
+            RPCError::new(
+                RPCErrorKind::TransportEOF,
+                "EOF during msgpack deserialization",
+            )
+        }
+        _ => RPCError::with_cause(
+            RPCErrorKind::SerializationError,
+            "msgpack deserialization failure",
+            e,
+        ),
+    }
+}
+
+impl<C: Read + Write> ClientTransport for MessagePackTransport<C> {
+    type TXState = MPTXState;
+    type FinalState = ();
+
+    fn tx_begin_call(&mut self, method: MethodId) -> Result<MPTXState> {
+        Ok(MPTXState {
+            method: method.name,
+            params: Vec::new(),
+        })
+    }
+
+    fn tx_add_param(
+        &mut self,
+        name: &'static str,
+        value: impl Serialize,
+        state: &mut MPTXState,
+    ) -> Result<()> {
+        let value = rmpv::ext::to_value(value).map_err(|e| {
+            RPCError::with_cause(
+                RPCErrorKind::SerializationError,
+                "msgpack serialization failure",
+                e,
+            )
+        })?;
+        state.params.push((Value::from(name), value));
+        Ok(())
+    }
+
+    fn tx_finalize(&mut self, state: MPTXState) -> Result<()> {
+        self.serialize(state.method)?;
+        self.serialize(Value::Map(state.params))?;
+        flush_channel(&mut self.channel)
+    }
+
+    fn rx_response<T>(&mut self, _state: ()) -> Result<T>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        self.deserialize()
+    }
+}
+
+impl<C: Read + Write> ServerTransport for MessagePackTransport<C> {
+    type RXState = MPRXState;
+
+    fn rx_begin_call(&mut self) -> Result<(PartialMethodId, MPRXState)> {
+        let method: String = self.deserialize()?;
+        let params = match self.deserialize()? {
+            Value::Map(params) => params,
+            _ => {
+                return Err(RPCError::new(
+                    RPCErrorKind::SerializationError,
+                    "msgpack params are not a map",
+                ))
+            }
+        };
+        Ok((PartialMethodId::Name(method), MPRXState { params }))
+    }
+
+    fn rx_read_param<T>(&mut self, name: &'static str, state: &mut MPRXState) -> Result<T>
+    where
+        for<'de> T: serde::Deserialize<'de>,
+    {
+        self.rx_read_param_opt(name, state)?.ok_or_else(|| {
+            RPCError::new(
+                RPCErrorKind::SerializationError,
+                format!("parameters do not contain {}", name),
+            )
+        })
+    }
+
+    fn rx_read_param_opt<T>(
+        &mut self,
+        name: &'static str,
+        state: &mut MPRXState,
+    ) -> Result<Option<T>>
+    where
+        for<'de> T: serde::Deserialize<'de>,
+    {
+        let pos = match state
+            .params
+            .iter()
+            .position(|(k, _)| k.as_str() == Some(name))
+        {
+            Some(pos) => pos,
+            None => return Ok(None),
+        };
+        let (_, value) = state.params.swap_remove(pos);
+        rmpv::ext::from_value(value).map(Some).map_err(|e| {
+            RPCError::with_cause(
+                RPCErrorKind::SerializationError,
+                "msgpack deserialization failure",
+                e,
+            )
+        })
+    }
+
+    fn tx_response(
+        &mut self,
+        value: std::result::Result<impl Serialize, impl Serialize>,
+        _state: MPRXState,
+    ) -> Result<()> {
+        self.serialize(value)?;
+        flush_channel(&mut self.channel)
+    }
+}
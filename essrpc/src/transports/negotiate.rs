@@ -0,0 +1,448 @@
+use std::io;
+use std::io::{Read, Write};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::json_codec::JTXState;
+use super::{BincodeTransport, JRXState, JSONTransport};
+use crate::{
+    ClientTransport, MethodId, PartialMethodId, RPCError, RPCErrorKind, Result, ServerTransport,
+    Upload,
+};
+
+/// Serialization format agreed by a
+/// [NegotiatingTransport](struct.NegotiatingTransport.html).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WireFormat {
+    /// JSON-RPC, as spoken by [JSONTransport](struct.JSONTransport.html)
+    JSON,
+    /// Bincode, as spoken by [BincodeTransport](struct.BincodeTransport.html)
+    Bincode,
+}
+
+impl WireFormat {
+    // The byte identifying the format in the handshake. Neither can
+    // begin a JSON message.
+    fn id(self) -> u8 {
+        match self {
+            WireFormat::JSON => 1,
+            WireFormat::Bincode => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(WireFormat::JSON),
+            2 => Some(WireFormat::Bincode),
+            _ => None,
+        }
+    }
+}
+
+pub enum NTXState {
+    Json(JTXState),
+    Bincode,
+}
+
+pub enum NFinalState {
+    Json(Value),
+    Bincode,
+}
+
+pub enum NRXState {
+    Json(JRXState),
+    Bincode,
+}
+
+enum Inner<C: Read + Write> {
+    Json(Box<JSONTransport<Replay<C>>>),
+    Bincode(BincodeTransport<Replay<C>>),
+}
+
+/// Transport which agrees a serialization format with its peer when
+/// it is created, then delegates to
+/// [JSONTransport](struct.JSONTransport.html) or
+/// [BincodeTransport](struct.BincodeTransport.html). Allows clients
+/// to upgrade to the more compact Bincode where the server supports
+/// it. Can be used over any `Read+Write` channel. Enable both the
+/// "json_transport" and "bincode_transport" features to use this.
+///
+/// The client offers a format by sending its one-byte id, and the
+/// server replies with the id of the format both will use: the one
+/// offered if it knows it, and JSON otherwise. A client which prefers
+/// JSON sends no offer and speaks plain JSON-RPC straight away, so it
+/// can also talk to a server using `JSONTransport`. Likewise, a
+/// server treats a peer whose first message is JSON as a
+/// `JSONTransport` client. Framed JSON is not supported.
+pub struct NegotiatingTransport<C: Read + Write> {
+    inner: Inner<C>,
+}
+
+impl<C: Read + Write> NegotiatingTransport<C> {
+    /// Create a client transport, offering `preferred` to the server
+    /// and waiting for its reply unless `preferred` is JSON.
+    pub fn client(mut channel: C, preferred: WireFormat) -> Result<Self> {
+        let format = match preferred {
+            WireFormat::JSON => WireFormat::JSON,
+            _ => {
+                write_byte(&mut channel, preferred.id())?;
+                let id = read_byte(&mut channel)?;
+                WireFormat::from_id(id).ok_or_else(|| {
+                    RPCError::new(
+                        RPCErrorKind::TransportError,
+                        format!("server chose unknown format {}", id),
+                    )
+                })?
+            }
+        };
+        Ok(Self::with_format(channel, format, None))
+    }
+
+    /// Create a server transport, reading the client's offer (or
+    /// the start of its first JSON request) and replying to it. Blocks
+    /// until the client sends something.
+    pub fn server(mut channel: C) -> Result<Self> {
+        let first = read_byte(&mut channel)?;
+        if let b'{' | b'[' | b' ' | b'\t' | b'\r' | b'\n' = first {
+            return Ok(Self::with_format(channel, WireFormat::JSON, Some(first)));
+        }
+        let format = WireFormat::from_id(first).unwrap_or(WireFormat::JSON);
+        write_byte(&mut channel, format.id())?;
+        Ok(Self::with_format(channel, format, None))
+    }
+
+    fn with_format(channel: C, format: WireFormat, first: Option<u8>) -> Self {
+        let channel = Replay { first, channel };
+        let inner = match format {
+            WireFormat::JSON => Inner::Json(Box::new(JSONTransport::new(channel))),
+            WireFormat::Bincode => Inner::Bincode(BincodeTransport::new(channel)),
+        };
+        NegotiatingTransport { inner }
+    }
+
+    /// The format agreed with the peer.
+    pub fn format(&self) -> WireFormat {
+        match self.inner {
+            Inner::Json(_) => WireFormat::JSON,
+            Inner::Bincode(_) => WireFormat::Bincode,
+        }
+    }
+
+    /// Get the underlying read/write channel
+    pub fn channel(&self) -> &C {
+        match &self.inner {
+            Inner::Json(t) => &t.channel().channel,
+            Inner::Bincode(t) => &t.channel().channel,
+        }
+    }
+
+    /// Get a mutable reference to the underlying read/write channel,
+    /// e.g. to adjust socket options.
+    pub fn channel_mut(&mut self) -> &mut C {
+        match &mut self.inner {
+            Inner::Json(t) => &mut t.channel_mut().channel,
+            Inner::Bincode(t) => &mut t.channel_mut().channel,
+        }
+    }
+
+    /// Consume the transport, returning the underlying read/write
+    /// channel. Useful for reusing a connection for something else
+    /// once RPC is finished with it.
+    pub fn into_channel(self) -> C {
+        match self.inner {
+            Inner::Json(t) => t.into_channel().channel,
+            Inner::Bincode(t) => t.into_channel().channel,
+        }
+    }
+}
+
+fn write_byte(channel: &mut impl Write, byte: u8) -> Result<()> {
+    channel
+        .write_all(&[byte])
+        .and_then(|_| channel.flush())
+        .map_err(|e| {
+            RPCError::with_cause(RPCErrorKind::TransportError, "format negotiation failed", e)
+        })
+}
+
+fn read_byte(channel: &mut impl Read) -> Result<u8> {
+    let mut byte = [0u8];
+    channel.read_exact(&mut byte).map_err(|e| {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            RPCError::new(RPCErrorKind::TransportEOF, "EOF during format negotiation")
+        } else {
+            RPCError::with_cause(RPCErrorKind::TransportError, "format negotiation failed", e)
+        }
+    })?;
+    Ok(byte[0])
+}
+
+// Error for a state belonging to the other format, which the
+// generated code never passes.
+fn wrong_state() -> RPCError {
+    RPCError::new(
+        RPCErrorKind::IllegalState,
+        "state does not match negotiated format",
+    )
+}
+
+impl<C: Read + Write> ClientTransport for NegotiatingTransport<C> {
+    type TXState = NTXState;
+    type FinalState = NFinalState;
+
+    fn tx_begin_call(&mut self, method: MethodId) -> Result<NTXState> {
+        match &mut self.inner {
+            Inner::Json(t) => t.tx_begin_call(method).map(NTXState::Json),
+            Inner::Bincode(t) => t.tx_begin_call(method).map(|_| NTXState::Bincode),
+        }
+    }
+
+    fn tx_add_param(
+        &mut self,
+        name: &'static str,
+        value: impl Serialize,
+        state: &mut NTXState,
+    ) -> Result<()> {
+        match (&mut self.inner, state) {
+            (Inner::Json(t), NTXState::Json(state)) => t.tx_add_param(name, value, state),
+            (Inner::Bincode(t), NTXState::Bincode) => t.tx_add_param(name, value, &mut ()),
+            _ => Err(wrong_state()),
+        }
+    }
+
+    fn tx_add_upload(
+        &mut self,
+        name: &'static str,
+        upload: Upload<'_>,
+        state: &mut NTXState,
+    ) -> Result<()> {
+        match (&mut self.inner, state) {
+            (Inner::Json(t), NTXState::Json(state)) => t.tx_add_upload(name, upload, state),
+            (Inner::Bincode(t), NTXState::Bincode) => t.tx_add_upload(name, upload, &mut ()),
+            _ => Err(wrong_state()),
+        }
+    }
+
+    fn tx_set_service_version(&mut self, version: u32, state: &mut NTXState) -> Result<()> {
+        match (&mut self.inner, state) {
+            (Inner::Json(t), NTXState::Json(state)) => t.tx_set_service_version(version, state),
+            (Inner::Bincode(t), NTXState::Bincode) => t.tx_set_service_version(version, &mut ()),
+            _ => Err(wrong_state()),
+        }
+    }
+
+    fn tx_finalize(&mut self, state: NTXState) -> Result<NFinalState> {
+        match (&mut self.inner, state) {
+            (Inner::Json(t), NTXState::Json(state)) => t.tx_finalize(state).map(NFinalState::Json),
+            (Inner::Bincode(t), NTXState::Bincode) => {
+                t.tx_finalize(()).map(|_| NFinalState::Bincode)
+            }
+            _ => Err(wrong_state()),
+        }
+    }
+
+    fn rx_response<T>(&mut self, state: NFinalState) -> Result<T>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        match (&mut self.inner, state) {
+            (Inner::Json(t), NFinalState::Json(state)) => t.rx_response(state),
+            (Inner::Bincode(t), NFinalState::Bincode) => t.rx_response(()),
+            _ => Err(wrong_state()),
+        }
+    }
+
+    fn tx_notify(&mut self, state: NTXState) -> Result<()> {
+        match (&mut self.inner, state) {
+            (Inner::Json(t), NTXState::Json(state)) => t.tx_notify(state),
+            (Inner::Bincode(t), NTXState::Bincode) => t.tx_notify(()),
+            _ => Err(wrong_state()),
+        }
+    }
+
+    fn rx_stream_item<T>(&mut self, state: &mut NFinalState) -> Result<Option<T>>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        match (&mut self.inner, state) {
+            (Inner::Json(t), NFinalState::Json(state)) => t.rx_stream_item(state),
+            (Inner::Bincode(t), NFinalState::Bincode) => t.rx_stream_item(&mut ()),
+            _ => Err(wrong_state()),
+        }
+    }
+
+    fn tx_cancel_stream(&mut self, state: NFinalState) -> Result<()> {
+        match (&mut self.inner, state) {
+            (Inner::Json(t), NFinalState::Json(state)) => t.tx_cancel_stream(state),
+            (Inner::Bincode(t), NFinalState::Bincode) => t.tx_cancel_stream(()),
+            _ => Err(wrong_state()),
+        }
+    }
+}
+
+impl<C: Read + Write> ServerTransport for NegotiatingTransport<C> {
+    type RXState = NRXState;
+
+    fn rx_begin_call(&mut self) -> Result<(PartialMethodId, NRXState)> {
+        match &mut self.inner {
+            Inner::Json(t) => t
+                .rx_begin_call()
+                .map(|(method, state)| (method, NRXState::Json(state))),
+            Inner::Bincode(t) => t
+                .rx_begin_call()
+                .map(|(method, _)| (method, NRXState::Bincode)),
+        }
+    }
+
+    fn rx_read_param<T>(&mut self, name: &'static str, state: &mut NRXState) -> Result<T>
+    where
+        for<'de> T: serde::Deserialize<'de>,
+    {
+        match (&mut self.inner, state) {
+            (Inner::Json(t), NRXState::Json(state)) => t.rx_read_param(name, state),
+            (Inner::Bincode(t), NRXState::Bincode) => t.rx_read_param(name, &mut ()),
+            _ => Err(wrong_state()),
+        }
+    }
+
+    fn rx_read_param_opt<T>(
+        &mut self,
+        name: &'static str,
+        state: &mut NRXState,
+    ) -> Result<Option<T>>
+    where
+        for<'de> T: serde::Deserialize<'de>,
+    {
+        match (&mut self.inner, state) {
+            (Inner::Json(t), NRXState::Json(state)) => t.rx_read_param_opt(name, state),
+            (Inner::Bincode(t), NRXState::Bincode) => t.rx_read_param_opt(name, &mut ()),
+            _ => Err(wrong_state()),
+        }
+    }
+
+    fn rx_read_upload(&mut self, name: &'static str, state: &mut NRXState) -> Result<Upload<'_>> {
+        match (&mut self.inner, state) {
+            (Inner::Json(t), NRXState::Json(state)) => t.rx_read_upload(name, state),
+            (Inner::Bincode(t), NRXState::Bincode) => t.rx_read_upload(name, &mut ()),
+            _ => Err(wrong_state()),
+        }
+    }
+
+    fn rx_service_version(&self, state: &NRXState) -> Option<u32> {
+        match (&self.inner, state) {
+            (Inner::Json(t), NRXState::Json(state)) => t.rx_service_version(state),
+            (Inner::Bincode(t), NRXState::Bincode) => t.rx_service_version(&()),
+            _ => None,
+        }
+    }
+
+    fn tx_response(
+        &mut self,
+        value: std::result::Result<impl Serialize, impl Serialize>,
+        state: NRXState,
+    ) -> Result<()> {
+        match (&mut self.inner, state) {
+            (Inner::Json(t), NRXState::Json(state)) => t.tx_response(value, state),
+            (Inner::Bincode(t), NRXState::Bincode) => t.tx_response(value, ()),
+            _ => Err(wrong_state()),
+        }
+    }
+
+    fn tx_error(&mut self, error: RPCError, state: NRXState) -> Result<()> {
+        match (&mut self.inner, state) {
+            (Inner::Json(t), NRXState::Json(state)) => t.tx_error(error, state),
+            (Inner::Bincode(t), NRXState::Bincode) => t.tx_error(error, ()),
+            _ => Err(wrong_state()),
+        }
+    }
+
+    fn discard_remaining(&mut self, state: &mut NRXState) -> Result<()> {
+        match (&mut self.inner, state) {
+            (Inner::Json(t), NRXState::Json(state)) => t.discard_remaining(state),
+            (Inner::Bincode(t), NRXState::Bincode) => t.discard_remaining(&mut ()),
+            _ => Err(wrong_state()),
+        }
+    }
+
+    fn reject_call(&mut self, state: NRXState, error: RPCError) -> Result<()> {
+        match (&mut self.inner, state) {
+            (Inner::Json(t), NRXState::Json(state)) => t.reject_call(state, error),
+            (Inner::Bincode(t), NRXState::Bincode) => t.reject_call((), error),
+            _ => Err(wrong_state()),
+        }
+    }
+
+    fn tx_no_response(&mut self, state: NRXState) -> Result<()> {
+        match (&mut self.inner, state) {
+            (Inner::Json(t), NRXState::Json(state)) => t.tx_no_response(state),
+            (Inner::Bincode(t), NRXState::Bincode) => t.tx_no_response(()),
+            _ => Err(wrong_state()),
+        }
+    }
+
+    fn set_notifications_only(&mut self) {
+        match &mut self.inner {
+            Inner::Json(t) => t.set_notifications_only(),
+            Inner::Bincode(t) => t.set_notifications_only(),
+        }
+    }
+
+    fn tx_stream_item(
+        &mut self,
+        value: std::result::Result<impl Serialize, impl Serialize>,
+        state: &mut NRXState,
+    ) -> Result<()> {
+        match (&mut self.inner, state) {
+            (Inner::Json(t), NRXState::Json(state)) => t.tx_stream_item(value, state),
+            (Inner::Bincode(t), NRXState::Bincode) => t.tx_stream_item(value, &mut ()),
+            _ => Err(wrong_state()),
+        }
+    }
+
+    fn tx_stream_end(&mut self, state: NRXState) -> Result<()> {
+        match (&mut self.inner, state) {
+            (Inner::Json(t), NRXState::Json(state)) => t.tx_stream_end(state),
+            (Inner::Bincode(t), NRXState::Bincode) => t.tx_stream_end(()),
+            _ => Err(wrong_state()),
+        }
+    }
+
+    fn rx_stream_cancelled(&mut self, state: &mut NRXState) -> Result<bool> {
+        match (&mut self.inner, state) {
+            (Inner::Json(t), NRXState::Json(state)) => t.rx_stream_cancelled(state),
+            (Inner::Bincode(t), NRXState::Bincode) => t.rx_stream_cancelled(&mut ()),
+            _ => Err(wrong_state()),
+        }
+    }
+}
+
+// Channel which yields `first`, if any, before reading from
+// `channel`, to give back the byte the server read while
+// negotiating.
+struct Replay<C> {
+    first: Option<u8>,
+    channel: C,
+}
+
+impl<C: Read> Read for Replay<C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.first {
+            Some(byte) if !buf.is_empty() => {
+                buf[0] = byte;
+                self.first = None;
+                Ok(1)
+            }
+            _ => self.channel.read(buf),
+        }
+    }
+}
+
+impl<C: Write> Write for Replay<C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.channel.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.channel.flush()
+    }
+}
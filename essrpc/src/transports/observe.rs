@@ -0,0 +1,291 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ClientTransport, MethodId, RPCError, Result, Upload};
+
+/// Receives metrics for the calls made through an
+/// [ObservedTransport](struct.ObservedTransport.html), e.g. to feed
+/// them to Prometheus or OpenTelemetry. Every method does nothing by
+/// default. Methods are identified by name, including any namespace.
+pub trait Observer: Send + Sync {
+    /// A call to `method` is about to be sent.
+    fn on_request(&self, _method: &str) {}
+
+    /// The request for a call to `method`, or a notification, has
+    /// been sent. `bytes` is its size, if the transport keeps track
+    /// of it; see
+    /// [ClientTransport::tx_request_bytes](../trait.ClientTransport.html#method.tx_request_bytes).
+    fn on_sent(&self, _method: &str, _bytes: Option<usize>) {}
+
+    /// The response to a call to `method` arrived `elapsed` after the
+    /// call began. `bytes` is the size of the response, if the
+    /// transport keeps track of it; see
+    /// [ClientTransport::rx_response_bytes](../trait.ClientTransport.html#method.rx_response_bytes).
+    /// A response carrying an error returned by the method itself is
+    /// still a response. For a streaming method, called once the
+    /// stream ends, with the size of its last response.
+    fn on_response(&self, _method: &str, _bytes: Option<usize>, _elapsed: Duration) {}
+
+    /// A call to `method` failed with `error` before any response
+    /// arrived, e.g. because the connection was lost.
+    fn on_error(&self, _method: &str, _error: &RPCError) {}
+}
+
+/// Client transport reporting each call made through the transport
+/// `T` to an [Observer](trait.Observer.html), and otherwise passing
+/// it through unchanged. Notifications are reported with
+/// `on_request` and `on_sent` alone, as no response arrives.
+///
+/// ```ignore
+/// let observer: Arc<dyn Observer> = Arc::new(Metrics::default());
+/// let client = FooRPCClient::new(ObservedTransport::new(JSONTransport::new(stream), observer));
+/// ```
+pub struct ObservedTransport<T> {
+    transport: T,
+    observer: Arc<dyn Observer>,
+}
+
+/// State of a call through an
+/// [ObservedTransport](struct.ObservedTransport.html): that of the
+/// inner transport, with the method called and when the call began.
+pub struct ObservedState<S> {
+    inner: S,
+    method: &'static str,
+    start: Instant,
+}
+
+impl<T> ObservedTransport<T> {
+    pub fn new(transport: T, observer: Arc<dyn Observer>) -> Self {
+        ObservedTransport {
+            transport,
+            observer,
+        }
+    }
+
+    /// Get the underlying transport
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Consume the wrapper, returning the underlying transport.
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+
+    fn check<R>(&self, method: &str, result: Result<R>) -> Result<R> {
+        if let Err(e) = &result {
+            self.observer.on_error(method, e);
+        }
+        result
+    }
+}
+
+impl<T: ClientTransport> ClientTransport for ObservedTransport<T> {
+    type TXState = ObservedState<T::TXState>;
+    type FinalState = ObservedState<T::FinalState>;
+
+    fn tx_begin_call(&mut self, method: MethodId) -> Result<Self::TXState> {
+        let name = method.name;
+        let start = Instant::now();
+        self.observer.on_request(name);
+        let result = self.transport.tx_begin_call(method);
+        let inner = self.check(name, result)?;
+        Ok(ObservedState {
+            inner,
+            method: name,
+            start,
+        })
+    }
+
+    fn tx_add_param(
+        &mut self,
+        name: &'static str,
+        value: impl Serialize,
+        state: &mut Self::TXState,
+    ) -> Result<()> {
+        let result = self.transport.tx_add_param(name, value, &mut state.inner);
+        self.check(state.method, result)
+    }
+
+    fn tx_add_upload(
+        &mut self,
+        name: &'static str,
+        upload: Upload<'_>,
+        state: &mut Self::TXState,
+    ) -> Result<()> {
+        let result = self.transport.tx_add_upload(name, upload, &mut state.inner);
+        self.check(state.method, result)
+    }
+
+    fn tx_set_service_version(&mut self, version: u32, state: &mut Self::TXState) -> Result<()> {
+        let result = self
+            .transport
+            .tx_set_service_version(version, &mut state.inner);
+        self.check(state.method, result)
+    }
+
+    fn tx_finalize(&mut self, state: Self::TXState) -> Result<Self::FinalState> {
+        let result = self.transport.tx_finalize(state.inner);
+        let inner = self.check(state.method, result)?;
+        self.observer
+            .on_sent(state.method, self.transport.tx_request_bytes());
+        Ok(ObservedState {
+            inner,
+            method: state.method,
+            start: state.start,
+        })
+    }
+
+    fn rx_response<R>(&mut self, state: Self::FinalState) -> Result<R>
+    where
+        for<'de> R: Deserialize<'de>,
+    {
+        let result = self.transport.rx_response(state.inner);
+        if result.is_ok() {
+            self.observer.on_response(
+                state.method,
+                self.transport.rx_response_bytes(),
+                state.start.elapsed(),
+            );
+        }
+        self.check(state.method, result)
+    }
+
+    fn tx_notify(&mut self, state: Self::TXState) -> Result<()> {
+        let result = self.transport.tx_notify(state.inner);
+        self.check(state.method, result)?;
+        self.observer
+            .on_sent(state.method, self.transport.tx_request_bytes());
+        Ok(())
+    }
+
+    fn rx_stream_item<R>(&mut self, state: &mut Self::FinalState) -> Result<Option<R>>
+    where
+        for<'de> R: Deserialize<'de>,
+    {
+        let result = self.transport.rx_stream_item(&mut state.inner);
+        if let Ok(None) = result {
+            self.observer.on_response(
+                state.method,
+                self.transport.rx_response_bytes(),
+                state.start.elapsed(),
+            );
+        }
+        self.check(state.method, result)
+    }
+
+    fn tx_cancel_stream(&mut self, state: Self::FinalState) -> Result<()> {
+        self.transport.tx_cancel_stream(state.inner)
+    }
+
+    fn rx_response_bytes(&self) -> Option<usize> {
+        self.transport.rx_response_bytes()
+    }
+
+    fn tx_request_bytes(&self) -> Option<usize> {
+        self.transport.tx_request_bytes()
+    }
+
+    fn tx_set_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        self.transport.tx_set_timeout(timeout)
+    }
+}
+
+#[cfg(feature = "async_client")]
+mod async_client {
+    use super::*;
+    use crate::{AsyncClientTransport, BoxFuture};
+    use futures::FutureExt;
+
+    impl<T: AsyncClientTransport> AsyncClientTransport for ObservedTransport<T> {
+        type TXState = ObservedState<T::TXState>;
+        type FinalState = ObservedState<T::FinalState>;
+
+        fn tx_begin_call(&mut self, method: MethodId) -> Result<Self::TXState> {
+            let name = method.name;
+            let start = Instant::now();
+            self.observer.on_request(name);
+            let result = self.transport.tx_begin_call(method);
+            let inner = self.check(name, result)?;
+            Ok(ObservedState {
+                inner,
+                method: name,
+                start,
+            })
+        }
+
+        fn tx_add_param(
+            &mut self,
+            name: &'static str,
+            value: impl Serialize,
+            state: &mut Self::TXState,
+        ) -> Result<()> {
+            let result = self.transport.tx_add_param(name, value, &mut state.inner);
+            self.check(state.method, result)
+        }
+
+        fn tx_set_service_version(
+            &mut self,
+            version: u32,
+            state: &mut Self::TXState,
+        ) -> Result<()> {
+            let result = self
+                .transport
+                .tx_set_service_version(version, &mut state.inner);
+            self.check(state.method, result)
+        }
+
+        fn tx_finalize(&mut self, state: Self::TXState) -> Result<Self::FinalState> {
+            let result = self.transport.tx_finalize(state.inner);
+            let inner = self.check(state.method, result)?;
+            self.observer.on_sent(state.method, None);
+            Ok(ObservedState {
+                inner,
+                method: state.method,
+                start: state.start,
+            })
+        }
+
+        // The sizes of requests and responses are not known to
+        // asynchronous transports, so are reported as None.
+        fn rx_response<R>(&mut self, state: Self::FinalState) -> BoxFuture<R, RPCError>
+        where
+            for<'de> R: Deserialize<'de>,
+            R: 'static,
+        {
+            let observer = self.observer.clone();
+            let ObservedState {
+                inner,
+                method,
+                start,
+            } = state;
+            self.transport
+                .rx_response(inner)
+                .map(move |result| {
+                    match &result {
+                        Ok(_) => observer.on_response(method, None, start.elapsed()),
+                        Err(e) => observer.on_error(method, e),
+                    }
+                    result
+                })
+                .boxed_local()
+        }
+
+        fn tx_notify(&mut self, state: Self::TXState) -> BoxFuture<(), RPCError> {
+            let observer = self.observer.clone();
+            let method = state.method;
+            self.transport
+                .tx_notify(state.inner)
+                .map(move |result| {
+                    match &result {
+                        Ok(_) => observer.on_sent(method, None),
+                        Err(e) => observer.on_error(method, e),
+                    }
+                    result
+                })
+                .boxed_local()
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use std::io;
+use std::mem;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures::future::{self, Either, FutureExt};
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, Cursor};
+use futures::stream::{FuturesOrdered, StreamExt};
+
+use super::json_framer::ValueFramer;
+use super::JSONAsyncServerTransport;
+use crate::{AsyncRPCServer, RPCError, RPCErrorKind, Result};
+
+/// Asynchronous JSON-RPC server which reads a client's next requests
+/// while the calls before them are still being served, so that a
+/// client sending several calls without waiting for each response
+/// (pipelining) is not held up by a slow one, and answers them in
+/// the order they were sent. Up to a set depth of calls are served
+/// at once, all on the task running
+/// [serve_connection](#method.serve_connection); once that many are
+/// in progress, no more is read from the connection until the oldest
+/// completes.
+///
+/// For each call, `make_server` is given a
+/// [JSONAsyncServerTransport](struct.JSONAsyncServerTransport.html)
+/// carrying just that request, and the server it returns serves it.
+/// State shared by all calls is shared by capturing an `Rc` or `Arc`
+/// in `make_server`, as with
+/// [ThreadedServer](struct.ThreadedServer.html). Unlike
+/// [MultiplexedClient](struct.MultiplexedClient.html) responses, those
+/// from this server come in request order, so plain clients keep
+/// working. A notification is served in its turn with nothing sent
+/// back, and a request which cannot be parsed is answered with a
+/// parse error and ends the connection, there being no telling where
+/// the next one starts.
+///
+/// ```ignore
+/// let state = Rc::new(State::new());
+/// PipelinedServer::new(move |transport| FooAsyncRPCServer::new(FooImpl::new(state.clone()), transport))
+///     .with_depth(16)
+///     .serve_connection(TokioChannel::new(stream))
+///     .await
+/// ```
+pub struct PipelinedServer<F> {
+    make_server: F,
+    depth: usize,
+}
+
+impl<S, F> PipelinedServer<F>
+where
+    S: AsyncRPCServer,
+    F: Fn(JSONAsyncServerTransport<PipelinedCall>) -> S,
+{
+    /// Create a server serving up to eight calls at once.
+    pub fn new(make_server: F) -> Self {
+        PipelinedServer {
+            make_server,
+            depth: 8,
+        }
+    }
+
+    /// Serve up to `depth` calls (at least one) at once.
+    pub fn with_depth(mut self, depth: usize) -> Self {
+        self.depth = depth.max(1);
+        self
+    }
+
+    /// Serve the calls read from `channel` until the client
+    /// disconnects, then finish those in progress and return
+    /// `Ok`. Errors serving a call are logged with the `log` crate and
+    /// answered as the call's transport answers them, leaving the
+    /// other calls unaffected. Failing to read from or write to the
+    /// channel ends serving with the error.
+    pub async fn serve_connection<C>(&self, channel: C) -> Result<()>
+    where
+        C: AsyncRead + AsyncWrite + Unpin,
+    {
+        let (mut reader, mut writer) = channel.split();
+        let mut in_flight = FuturesOrdered::new();
+        let mut buf = Vec::new();
+        let mut framer = ValueFramer::default();
+        let mut reading = true;
+        let mut chunk = [0u8; 4096];
+        loop {
+            while in_flight.len() < self.depth {
+                match framer.next_value(&buf) {
+                    Ok(Some((_, len))) => {
+                        in_flight.push_back(self.serve_call(buf.drain(..len).collect()))
+                    }
+                    Ok(None) => break,
+                    Err(_) => {
+                        // Let the call's transport answer with the
+                        // parse error
+                        in_flight.push_back(self.serve_call(mem::take(&mut buf)));
+                        reading = false;
+                        break;
+                    }
+                }
+            }
+            if !reading && in_flight.is_empty() {
+                return Ok(());
+            }
+
+            let read = if reading && in_flight.len() < self.depth {
+                reader.read(&mut chunk).left_future()
+            } else {
+                future::pending().right_future()
+            };
+            let served = if in_flight.is_empty() {
+                future::pending().right_future()
+            } else {
+                in_flight.next().left_future()
+            };
+            match future::select(read, served).await {
+                Either::Left((read, _)) => match read {
+                    Ok(0) => reading = false,
+                    Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                    Err(e) => {
+                        return Err(RPCError::with_cause(
+                            RPCErrorKind::TransportError,
+                            "json read failed",
+                            e,
+                        ))
+                    }
+                },
+                Either::Right((response, _)) => {
+                    let response: Vec<u8> = response.unwrap_or_default();
+                    if !response.is_empty() {
+                        let write_error = |e| {
+                            RPCError::with_cause(
+                                RPCErrorKind::TransportError,
+                                "json write failed",
+                                e,
+                            )
+                        };
+                        writer.write_all(&response).await.map_err(write_error)?;
+                        writer.flush().await.map_err(write_error)?;
+                    }
+                }
+            }
+        }
+    }
+
+    // Serve a single request, giving what the server sent back
+    async fn serve_call(&self, request: Vec<u8>) -> Vec<u8> {
+        let response = Arc::new(Mutex::new(Vec::new()));
+        let channel = PipelinedCall {
+            request: Cursor::new(request),
+            response: response.clone(),
+        };
+        // serve_loop, since the request may be a ping; it ends on
+        // reaching the end of the request.
+        let mut server = (self.make_server)(JSONAsyncServerTransport::new(channel));
+        if let Err(e) = server.serve_loop().await {
+            log::warn!("error serving call: {}", e);
+        }
+        drop(server);
+        let mut response = response.lock().unwrap_or_else(|e| e.into_inner());
+        mem::take(&mut *response)
+    }
+}
+
+/// Channel over which a [PipelinedServer](struct.PipelinedServer.html)
+/// serves a single call. Reading gives the request, and what is
+/// written is sent back over the client's connection in its turn.
+pub struct PipelinedCall {
+    request: Cursor<Vec<u8>>,
+    response: Arc<Mutex<Vec<u8>>>,
+}
+
+impl AsyncRead for PipelinedCall {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.request).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for PipelinedCall {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut response = self.response.lock().unwrap_or_else(|e| e.into_inner());
+        response.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,216 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ClientTransport, MethodId, RPCErrorKind, Result, Upload};
+
+/// Pool of client transports, so that connections may be reused
+/// across calls rather than established anew for each one. `F` is a
+/// factory which produces a fresh transport (typically opening a new
+/// connection) whenever the pool has none idle. The pool may be
+/// shared between threads, and works with any
+/// [ClientTransport](../trait.ClientTransport.html).
+///
+/// ```ignore
+/// let pool = TransportPool::new(|| {
+///     let stream = TcpStream::connect(addr).map_err(...)?;
+///     Ok(JSONTransport::new(stream))
+/// });
+/// let client = FooRPCClient::new(pool.get()?);
+/// client.bar("the answer".to_string(), 42)
+/// ```
+pub struct TransportPool<T, F>
+where
+    F: Fn() -> Result<T>,
+{
+    factory: F,
+    idle: Mutex<Vec<T>>,
+}
+
+impl<T, F> TransportPool<T, F>
+where
+    F: Fn() -> Result<T>,
+{
+    pub fn new(factory: F) -> Self {
+        TransportPool {
+            factory,
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Take an idle transport from the pool, or create one with the
+    /// factory if none is idle. The transport returns to the pool
+    /// when the guard is dropped.
+    pub fn get(&self) -> Result<PooledTransport<'_, T, F>> {
+        let transport = match self.lock_idle().pop() {
+            Some(transport) => transport,
+            None => (self.factory)()?,
+        };
+        Ok(PooledTransport {
+            pool: self,
+            transport: Some(transport),
+            poisoned: false,
+        })
+    }
+
+    /// Number of transports currently idle in the pool.
+    pub fn idle_count(&self) -> usize {
+        self.lock_idle().len()
+    }
+
+    fn lock_idle(&self) -> std::sync::MutexGuard<'_, Vec<T>> {
+        // The idle list is never left inconsistent, so a panic while
+        // it was locked does no harm.
+        self.idle.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A transport taken from a [TransportPool](struct.TransportPool.html),
+/// returned to the pool when dropped. Implements `ClientTransport`
+/// when the pooled transport does, so it may be given directly to a
+/// generated client. If a call fails in a way which may leave the
+/// connection unusable, such as `TransportEOF` or a failed write, the
+/// transport is poisoned and dropped rather than returned to the
+/// pool. Only errors reported by the server (`UnknownMethod` and
+/// `Application`) leave it in the pool.
+pub struct PooledTransport<'a, T, F>
+where
+    F: Fn() -> Result<T>,
+{
+    pool: &'a TransportPool<T, F>,
+    transport: Option<T>,
+    poisoned: bool,
+}
+
+impl<'a, T, F> PooledTransport<'a, T, F>
+where
+    F: Fn() -> Result<T>,
+{
+    /// Prevent the transport from returning to the pool, e.g. because
+    /// the caller knows the connection is no longer usable.
+    pub fn poison(&mut self) {
+        self.poisoned = true;
+    }
+
+    /// True if the transport will not return to the pool.
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned
+    }
+
+    // Poison the transport unless the result shows the connection is
+    // still in a known state. A failure partway through writing or
+    // reading a message may leave stray bytes on the connection.
+    fn check<R>(&mut self, result: Result<R>) -> Result<R> {
+        if let Err(e) = &result {
+            match e.kind {
+                RPCErrorKind::UnknownMethod | RPCErrorKind::Application(_) => (),
+                _ => self.poisoned = true,
+            }
+        }
+        result
+    }
+}
+
+impl<'a, T, F> Deref for PooledTransport<'a, T, F>
+where
+    F: Fn() -> Result<T>,
+{
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.transport.as_ref().unwrap()
+    }
+}
+
+impl<'a, T, F> DerefMut for PooledTransport<'a, T, F>
+where
+    F: Fn() -> Result<T>,
+{
+    fn deref_mut(&mut self) -> &mut T {
+        self.transport.as_mut().unwrap()
+    }
+}
+
+impl<'a, T, F> Drop for PooledTransport<'a, T, F>
+where
+    F: Fn() -> Result<T>,
+{
+    fn drop(&mut self) {
+        if let Some(transport) = self.transport.take() {
+            if !self.poisoned {
+                self.pool.lock_idle().push(transport);
+            }
+        }
+    }
+}
+
+impl<'a, T, F> ClientTransport for PooledTransport<'a, T, F>
+where
+    T: ClientTransport,
+    F: Fn() -> Result<T>,
+{
+    type TXState = T::TXState;
+    type FinalState = T::FinalState;
+
+    fn tx_begin_call(&mut self, method: MethodId) -> Result<T::TXState> {
+        let result = self.deref_mut().tx_begin_call(method);
+        self.check(result)
+    }
+
+    fn tx_add_param(
+        &mut self,
+        name: &'static str,
+        value: impl Serialize,
+        state: &mut T::TXState,
+    ) -> Result<()> {
+        let result = self.deref_mut().tx_add_param(name, value, state);
+        self.check(result)
+    }
+
+    fn tx_add_upload(
+        &mut self,
+        name: &'static str,
+        upload: Upload<'_>,
+        state: &mut T::TXState,
+    ) -> Result<()> {
+        let result = self.deref_mut().tx_add_upload(name, upload, state);
+        self.check(result)
+    }
+
+    fn tx_set_service_version(&mut self, version: u32, state: &mut T::TXState) -> Result<()> {
+        let result = self.deref_mut().tx_set_service_version(version, state);
+        self.check(result)
+    }
+
+    fn tx_finalize(&mut self, state: T::TXState) -> Result<T::FinalState> {
+        let result = self.deref_mut().tx_finalize(state);
+        self.check(result)
+    }
+
+    fn rx_response<R>(&mut self, state: T::FinalState) -> Result<R>
+    where
+        for<'de> R: Deserialize<'de>,
+    {
+        let result = self.deref_mut().rx_response(state);
+        self.check(result)
+    }
+
+    fn tx_notify(&mut self, state: T::TXState) -> Result<()> {
+        let result = self.deref_mut().tx_notify(state);
+        self.check(result)
+    }
+
+    fn rx_response_bytes(&self) -> Option<usize> {
+        self.deref().rx_response_bytes()
+    }
+
+    fn tx_request_bytes(&self) -> Option<usize> {
+        self.deref().tx_request_bytes()
+    }
+
+    fn tx_set_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        self.deref_mut().tx_set_timeout(timeout)
+    }
+}
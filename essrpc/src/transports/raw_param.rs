@@ -0,0 +1,225 @@
+// Recognising parameters which are serde_json RawValues, so that the
+// JSON transports can send their text as it is rather than parsing it
+// into a Value and serializing it again.
+
+use std::fmt;
+
+use serde::ser::{self, Impossible, Serialize, Serializer};
+use serde_json::value::RawValue;
+
+// Name under which serde_json serializes a RawValue, as a struct with
+// a single field of this name holding the text. serde_json's own
+// serializers look for it in the same way.
+const RAW_VALUE_TOKEN: &str = "$serde_json::private::RawValue";
+
+// The text of `value` if it is a RawValue, such as a `Box<RawValue>`
+// or `&RawValue`, or None for anything else.
+pub(super) fn raw_param(value: &impl Serialize) -> Option<Box<RawValue>> {
+    value.serialize(Probe).ok()
+}
+
+// Serializer accepting only a RawValue. Anything else fails at its
+// first call, so costs next to nothing to rule out.
+struct Probe;
+
+#[derive(Debug)]
+struct NotRaw;
+
+impl fmt::Display for NotRaw {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("not a raw value")
+    }
+}
+
+impl std::error::Error for NotRaw {}
+
+impl ser::Error for NotRaw {
+    fn custom<T: fmt::Display>(_msg: T) -> Self {
+        NotRaw
+    }
+}
+
+type Rejected = Impossible<Box<RawValue>, NotRaw>;
+
+impl Serializer for Probe {
+    type Ok = Box<RawValue>;
+    type Error = NotRaw;
+    type SerializeSeq = Rejected;
+    type SerializeTuple = Rejected;
+    type SerializeTupleStruct = Rejected;
+    type SerializeTupleVariant = Rejected;
+    type SerializeMap = Rejected;
+    type SerializeStruct = RawText;
+    type SerializeStructVariant = Rejected;
+
+    fn serialize_struct(self, name: &'static str, _len: usize) -> Result<RawText, NotRaw> {
+        if name == RAW_VALUE_TOKEN {
+            Ok(RawText(None))
+        } else {
+            Err(NotRaw)
+        }
+    }
+
+    fn serialize_bool(self, _v: bool) -> Result<Self::Ok, NotRaw> {
+        Err(NotRaw)
+    }
+
+    fn serialize_i8(self, _v: i8) -> Result<Self::Ok, NotRaw> {
+        Err(NotRaw)
+    }
+
+    fn serialize_i16(self, _v: i16) -> Result<Self::Ok, NotRaw> {
+        Err(NotRaw)
+    }
+
+    fn serialize_i32(self, _v: i32) -> Result<Self::Ok, NotRaw> {
+        Err(NotRaw)
+    }
+
+    fn serialize_i64(self, _v: i64) -> Result<Self::Ok, NotRaw> {
+        Err(NotRaw)
+    }
+
+    fn serialize_u8(self, _v: u8) -> Result<Self::Ok, NotRaw> {
+        Err(NotRaw)
+    }
+
+    fn serialize_u16(self, _v: u16) -> Result<Self::Ok, NotRaw> {
+        Err(NotRaw)
+    }
+
+    fn serialize_u32(self, _v: u32) -> Result<Self::Ok, NotRaw> {
+        Err(NotRaw)
+    }
+
+    fn serialize_u64(self, _v: u64) -> Result<Self::Ok, NotRaw> {
+        Err(NotRaw)
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<Self::Ok, NotRaw> {
+        Err(NotRaw)
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<Self::Ok, NotRaw> {
+        Err(NotRaw)
+    }
+
+    fn serialize_char(self, _v: char) -> Result<Self::Ok, NotRaw> {
+        Err(NotRaw)
+    }
+
+    fn serialize_str(self, _v: &str) -> Result<Self::Ok, NotRaw> {
+        Err(NotRaw)
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<Self::Ok, NotRaw> {
+        Err(NotRaw)
+    }
+
+    fn serialize_none(self) -> Result<Self::Ok, NotRaw> {
+        Err(NotRaw)
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, _value: &T) -> Result<Self::Ok, NotRaw> {
+        Err(NotRaw)
+    }
+
+    fn serialize_unit(self) -> Result<Self::Ok, NotRaw> {
+        Err(NotRaw)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, NotRaw> {
+        Err(NotRaw)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+    ) -> Result<Self::Ok, NotRaw> {
+        Err(NotRaw)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _value: &T,
+    ) -> Result<Self::Ok, NotRaw> {
+        Err(NotRaw)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Self::Ok, NotRaw> {
+        Err(NotRaw)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Rejected, NotRaw> {
+        Err(NotRaw)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Rejected, NotRaw> {
+        Err(NotRaw)
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Rejected, NotRaw> {
+        Err(NotRaw)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Rejected, NotRaw> {
+        Err(NotRaw)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Rejected, NotRaw> {
+        Err(NotRaw)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Rejected, NotRaw> {
+        Err(NotRaw)
+    }
+}
+
+// The single field of a RawValue: its text, as a string
+struct RawText(Option<Box<RawValue>>);
+
+impl ser::SerializeStruct for RawText {
+    type Ok = Box<RawValue>;
+    type Error = NotRaw;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), NotRaw> {
+        if key != RAW_VALUE_TOKEN {
+            return Err(NotRaw);
+        }
+        let text = match serde_json::to_value(value) {
+            Ok(serde_json::Value::String(text)) => text,
+            _ => return Err(NotRaw),
+        };
+        self.0 = Some(RawValue::from_string(text).map_err(|_| NotRaw)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Box<RawValue>, NotRaw> {
+        self.0.ok_or(NotRaw)
+    }
+}
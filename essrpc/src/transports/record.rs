@@ -0,0 +1,320 @@
+use std::io;
+use std::io::{Read, Write};
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::ReadTimeout;
+use crate::{
+    ClientTransport, MethodId, PartialMethodId, RPCError, Result, ServerTransport, Upload,
+};
+
+// Bytes which have crossed the channel since the last message was
+// recorded, in each direction.
+#[derive(Default)]
+struct Traffic {
+    read: Vec<u8>,
+    written: Vec<u8>,
+}
+
+/// Channel underlying the transport inside a
+/// [RecordingTransport](struct.RecordingTransport.html), keeping a
+/// copy of every byte read from or written to the channel `C`.
+pub struct RecordingChannel<C> {
+    channel: C,
+    traffic: Arc<Mutex<Traffic>>,
+}
+
+impl<C> RecordingChannel<C> {
+    /// Get the underlying channel
+    pub fn get_ref(&self) -> &C {
+        &self.channel
+    }
+
+    /// Consume the channel, returning the underlying channel.
+    pub fn into_inner(self) -> C {
+        self.channel
+    }
+}
+
+impl<C: Read> Read for RecordingChannel<C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.channel.read(buf)?;
+        self.traffic
+            .lock()
+            .unwrap()
+            .read
+            .extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<C: Write> Write for RecordingChannel<C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.channel.write(buf)?;
+        self.traffic
+            .lock()
+            .unwrap()
+            .written
+            .extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.channel.flush()
+    }
+}
+
+impl<C: ReadTimeout> ReadTimeout for RecordingChannel<C> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.channel.set_read_timeout(timeout)
+    }
+}
+
+/// Transport recording the bytes of every message sent or received
+/// by the transport `T`, for tests to check exactly what goes on the
+/// wire. `T` is built over a
+/// [RecordingChannel](struct.RecordingChannel.html) wrapping the
+/// real channel, and may be a client or a server transport.
+///
+/// Each message is one entry of [recorded](#method.recorded), in the
+/// order they crossed the channel: for a client, the request followed
+/// by the response (or each item of a streaming response); for a
+/// server, the request followed by whatever was sent in reply. A
+/// notification has no reply. Bytes which `T` reads ahead of the
+/// message it is receiving, e.g. a pipelined request, are recorded as
+/// part of that message.
+///
+/// ```ignore
+/// let transport = RecordingTransport::new(stream, JSONTransport::new);
+/// let recorded = transport.recorded();
+/// let foo = FooRPCClient::new(transport);
+/// foo.bar("the answer".to_string(), 42)?;
+/// let request: serde_json::Value = serde_json::from_slice(&recorded.lock().unwrap()[0])?;
+/// ```
+pub struct RecordingTransport<T> {
+    transport: T,
+    traffic: Arc<Mutex<Traffic>>,
+    recorded: Arc<Mutex<Vec<Vec<u8>>>>,
+}
+
+impl<T> RecordingTransport<T> {
+    /// Record the traffic of the transport `make` builds over `channel`.
+    pub fn new<C>(channel: C, make: impl FnOnce(RecordingChannel<C>) -> T) -> Self {
+        let traffic = Arc::new(Mutex::new(Traffic::default()));
+        let transport = make(RecordingChannel {
+            channel,
+            traffic: traffic.clone(),
+        });
+        RecordingTransport {
+            transport,
+            traffic,
+            recorded: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// The messages recorded so far, shared with the transport so
+    /// that it may still be inspected once the transport has been
+    /// moved into a client or server.
+    pub fn recorded(&self) -> Arc<Mutex<Vec<Vec<u8>>>> {
+        self.recorded.clone()
+    }
+
+    /// Get the underlying transport
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Consume the wrapper, returning the underlying transport.
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+
+    fn record_read(&self) {
+        let read = mem::take(&mut self.traffic.lock().unwrap().read);
+        self.record(read);
+    }
+
+    fn record_written(&self) {
+        let written = mem::take(&mut self.traffic.lock().unwrap().written);
+        self.record(written);
+    }
+
+    fn record(&self, message: Vec<u8>) {
+        if !message.is_empty() {
+            self.recorded.lock().unwrap().push(message);
+        }
+    }
+
+    // Record the request a server has read, then its reply once sent.
+    fn reply<R>(&mut self, send: impl FnOnce(&mut T) -> Result<R>) -> Result<R> {
+        self.record_read();
+        let result = send(&mut self.transport);
+        self.record_written();
+        result
+    }
+}
+
+impl<T: ClientTransport> ClientTransport for RecordingTransport<T> {
+    type TXState = T::TXState;
+    type FinalState = T::FinalState;
+
+    fn tx_begin_call(&mut self, method: MethodId) -> Result<Self::TXState> {
+        self.transport.tx_begin_call(method)
+    }
+
+    fn tx_add_param(
+        &mut self,
+        name: &'static str,
+        value: impl Serialize,
+        state: &mut Self::TXState,
+    ) -> Result<()> {
+        self.transport.tx_add_param(name, value, state)
+    }
+
+    fn tx_add_upload(
+        &mut self,
+        name: &'static str,
+        upload: Upload<'_>,
+        state: &mut Self::TXState,
+    ) -> Result<()> {
+        self.transport.tx_add_upload(name, upload, state)
+    }
+
+    fn tx_set_service_version(&mut self, version: u32, state: &mut Self::TXState) -> Result<()> {
+        self.transport.tx_set_service_version(version, state)
+    }
+
+    fn tx_finalize(&mut self, state: Self::TXState) -> Result<Self::FinalState> {
+        let result = self.transport.tx_finalize(state);
+        self.record_written();
+        result
+    }
+
+    fn rx_response<R>(&mut self, state: Self::FinalState) -> Result<R>
+    where
+        for<'de> R: Deserialize<'de>,
+    {
+        let result = self.transport.rx_response(state);
+        self.record_read();
+        result
+    }
+
+    fn tx_notify(&mut self, state: Self::TXState) -> Result<()> {
+        let result = self.transport.tx_notify(state);
+        self.record_written();
+        result
+    }
+
+    fn rx_stream_item<R>(&mut self, state: &mut Self::FinalState) -> Result<Option<R>>
+    where
+        for<'de> R: Deserialize<'de>,
+    {
+        let result = self.transport.rx_stream_item(state);
+        self.record_read();
+        result
+    }
+
+    fn tx_cancel_stream(&mut self, state: Self::FinalState) -> Result<()> {
+        let result = self.transport.tx_cancel_stream(state);
+        self.record_written();
+        self.record_read();
+        result
+    }
+
+    fn rx_response_bytes(&self) -> Option<usize> {
+        self.transport.rx_response_bytes()
+    }
+
+    fn tx_request_bytes(&self) -> Option<usize> {
+        self.transport.tx_request_bytes()
+    }
+
+    fn tx_set_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        self.transport.tx_set_timeout(timeout)
+    }
+}
+
+impl<T: ServerTransport> ServerTransport for RecordingTransport<T> {
+    type RXState = T::RXState;
+
+    fn rx_begin_call(&mut self) -> Result<(PartialMethodId, Self::RXState)> {
+        self.transport.rx_begin_call()
+    }
+
+    fn rx_read_param<P>(&mut self, name: &'static str, state: &mut Self::RXState) -> Result<P>
+    where
+        for<'de> P: Deserialize<'de>,
+    {
+        self.transport.rx_read_param(name, state)
+    }
+
+    fn rx_read_param_opt<P>(
+        &mut self,
+        name: &'static str,
+        state: &mut Self::RXState,
+    ) -> Result<Option<P>>
+    where
+        for<'de> P: Deserialize<'de>,
+    {
+        self.transport.rx_read_param_opt(name, state)
+    }
+
+    fn rx_read_upload(
+        &mut self,
+        name: &'static str,
+        state: &mut Self::RXState,
+    ) -> Result<Upload<'_>> {
+        self.transport.rx_read_upload(name, state)
+    }
+
+    fn rx_service_version(&self, state: &Self::RXState) -> Option<u32> {
+        self.transport.rx_service_version(state)
+    }
+
+    fn discard_remaining(&mut self, state: &mut Self::RXState) -> Result<()> {
+        self.transport.discard_remaining(state)
+    }
+
+    fn tx_response(
+        &mut self,
+        value: std::result::Result<impl Serialize, impl Serialize>,
+        state: Self::RXState,
+    ) -> Result<()> {
+        self.reply(|transport| transport.tx_response(value, state))
+    }
+
+    fn tx_error(&mut self, error: RPCError, state: Self::RXState) -> Result<()> {
+        self.reply(|transport| transport.tx_error(error, state))
+    }
+
+    fn reject_call(&mut self, state: Self::RXState, error: RPCError) -> Result<()> {
+        self.reply(|transport| transport.reject_call(state, error))
+    }
+
+    fn tx_no_response(&mut self, state: Self::RXState) -> Result<()> {
+        self.reply(|transport| transport.tx_no_response(state))
+    }
+
+    fn set_notifications_only(&mut self) {
+        self.transport.set_notifications_only()
+    }
+
+    fn tx_stream_item(
+        &mut self,
+        value: std::result::Result<impl Serialize, impl Serialize>,
+        state: &mut Self::RXState,
+    ) -> Result<()> {
+        self.reply(|transport| transport.tx_stream_item(value, state))
+    }
+
+    fn tx_stream_end(&mut self, state: Self::RXState) -> Result<()> {
+        self.reply(|transport| transport.tx_stream_end(state))
+    }
+
+    fn rx_stream_cancelled(&mut self, state: &mut Self::RXState) -> Result<bool> {
+        self.transport.rx_stream_cancelled(state)
+    }
+}
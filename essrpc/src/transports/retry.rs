@@ -0,0 +1,255 @@
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ClientTransport, MethodId, RPCClient, RPCError, RPCErrorKind, Result, Upload};
+
+/// Client wrapper which retries failed calls with exponential
+/// backoff, reconnecting before each retry. `F` is a factory which
+/// produces a fresh transport (typically opening a new connection),
+/// and `C` is the generated client, over a
+/// [RetryTransport](struct.RetryTransport.html) wrapping the
+/// factory's transports. The connection is kept across calls until
+/// one fails.
+///
+/// A call is retried only when it fails with `TransportError`,
+/// `TransportEOF` or `Timeout`, which suggest the connection rather
+/// than the call was at fault. Other errors, including those
+/// returned by the method itself, are returned at once. As a call
+/// which timed out or lost its connection may still have run on the
+/// server, only use this for idempotent calls.
+///
+/// ```ignore
+/// let client = RetryingClient::<FooRPCClient<_>, _, _>::new(|| {
+///     let stream = TcpStream::connect(addr).map_err(...)?;
+///     Ok(JSONTransport::new(stream))
+/// })
+/// .with_max_retries(5)
+/// .with_base_delay(Duration::from_millis(50));
+/// client.call(|foo| foo.bar("the answer".to_string(), 42))
+/// ```
+pub struct RetryingClient<C, T, F>
+where
+    C: RPCClient<TR = RetryTransport<T>>,
+    T: ClientTransport,
+    F: Fn() -> Result<T>,
+{
+    factory: F,
+    client: RefCell<Option<C>>,
+    last_error: Rc<Cell<Option<RPCErrorKind>>>,
+    max_retries: u32,
+    base_delay: Duration,
+    jitter: f64,
+    random: RandomState,
+}
+
+impl<C, T, F> RetryingClient<C, T, F>
+where
+    C: RPCClient<TR = RetryTransport<T>>,
+    T: ClientTransport,
+    F: Fn() -> Result<T>,
+{
+    /// Create a client retrying up to 3 times, first after 100ms,
+    /// without jitter. No connection is made until the first call.
+    pub fn new(factory: F) -> Self {
+        RetryingClient {
+            factory,
+            client: RefCell::new(None),
+            last_error: Rc::new(Cell::new(None)),
+            max_retries: 3,
+            base_delay: Duration::from_millis(100),
+            jitter: 0.0,
+            random: RandomState::new(),
+        }
+    }
+
+    /// Retry a failed call at most `max_retries` times, so that it is
+    /// attempted at most `max_retries + 1` times in all.
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Wait `delay` before the first retry, doubling the wait before
+    /// each one after.
+    pub fn with_base_delay(mut self, delay: Duration) -> Self {
+        self.base_delay = delay;
+        self
+    }
+
+    /// Shorten each wait by a random fraction of up to `jitter`,
+    /// between 0 and 1, so that clients which failed together do not
+    /// retry together.
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Make one or more calls with the client, retrying all of `f`
+    /// if it fails with a transport error. Errors from connecting
+    /// are retried in the same way.
+    pub fn call<R, E>(
+        &self,
+        f: impl Fn(&C) -> std::result::Result<R, E>,
+    ) -> std::result::Result<R, E>
+    where
+        E: From<RPCError>,
+    {
+        let mut attempt = 0;
+        loop {
+            let client = match self.take_client() {
+                Ok(client) => client,
+                Err(e) if retryable(e.kind) && attempt < self.max_retries => {
+                    self.backoff(attempt);
+                    attempt += 1;
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            self.last_error.set(None);
+            let result = f(&client);
+            let kind = self.last_error.take();
+            // Only keep the connection when it is in a known state, as
+            // TransportPool does.
+            if let None | Some(RPCErrorKind::UnknownMethod) | Some(RPCErrorKind::Application(_)) =
+                kind
+            {
+                *self.client.borrow_mut() = Some(client);
+            }
+            match kind {
+                Some(kind) if result.is_err() && retryable(kind) && attempt < self.max_retries => {
+                    self.backoff(attempt);
+                    attempt += 1;
+                }
+                _ => return result,
+            }
+        }
+    }
+
+    fn take_client(&self) -> Result<C> {
+        if let Some(client) = self.client.borrow_mut().take() {
+            return Ok(client);
+        }
+        let transport = (self.factory)()?;
+        Ok(C::new(RetryTransport {
+            transport,
+            last_error: self.last_error.clone(),
+        }))
+    }
+
+    fn backoff(&self, attempt: u32) {
+        let delay = self.base_delay * 2u32.saturating_pow(attempt);
+        let mut hasher = self.random.build_hasher();
+        hasher.write_u32(attempt);
+        let random = hasher.finish() as f64 / u64::MAX as f64;
+        thread::sleep(delay.mul_f64(1.0 - self.jitter * random));
+    }
+}
+
+fn retryable(kind: RPCErrorKind) -> bool {
+    matches!(
+        kind,
+        RPCErrorKind::TransportError | RPCErrorKind::TransportEOF | RPCErrorKind::Timeout
+    )
+}
+
+/// Transport used by the clients of a
+/// [RetryingClient](struct.RetryingClient.html). Passes calls through
+/// to the transport `T`, noting the kind of any error so that the
+/// `RetryingClient` can tell whether to retry.
+pub struct RetryTransport<T: ClientTransport> {
+    transport: T,
+    last_error: Rc<Cell<Option<RPCErrorKind>>>,
+}
+
+impl<T: ClientTransport> RetryTransport<T> {
+    fn check<R>(&mut self, result: Result<R>) -> Result<R> {
+        if let Err(e) = &result {
+            self.last_error.set(Some(e.kind));
+        }
+        result
+    }
+}
+
+impl<T: ClientTransport> ClientTransport for RetryTransport<T> {
+    type TXState = T::TXState;
+    type FinalState = T::FinalState;
+
+    fn tx_begin_call(&mut self, method: MethodId) -> Result<T::TXState> {
+        let result = self.transport.tx_begin_call(method);
+        self.check(result)
+    }
+
+    fn tx_add_param(
+        &mut self,
+        name: &'static str,
+        value: impl Serialize,
+        state: &mut T::TXState,
+    ) -> Result<()> {
+        let result = self.transport.tx_add_param(name, value, state);
+        self.check(result)
+    }
+
+    fn tx_add_upload(
+        &mut self,
+        name: &'static str,
+        upload: Upload<'_>,
+        state: &mut T::TXState,
+    ) -> Result<()> {
+        let result = self.transport.tx_add_upload(name, upload, state);
+        self.check(result)
+    }
+
+    fn tx_set_service_version(&mut self, version: u32, state: &mut T::TXState) -> Result<()> {
+        let result = self.transport.tx_set_service_version(version, state);
+        self.check(result)
+    }
+
+    fn tx_finalize(&mut self, state: T::TXState) -> Result<T::FinalState> {
+        let result = self.transport.tx_finalize(state);
+        self.check(result)
+    }
+
+    fn rx_response<R>(&mut self, state: T::FinalState) -> Result<R>
+    where
+        for<'de> R: Deserialize<'de>,
+    {
+        let result = self.transport.rx_response(state);
+        self.check(result)
+    }
+
+    fn tx_notify(&mut self, state: T::TXState) -> Result<()> {
+        let result = self.transport.tx_notify(state);
+        self.check(result)
+    }
+
+    fn rx_response_bytes(&self) -> Option<usize> {
+        self.transport.rx_response_bytes()
+    }
+
+    fn tx_request_bytes(&self) -> Option<usize> {
+        self.transport.tx_request_bytes()
+    }
+
+    fn tx_set_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        self.transport.tx_set_timeout(timeout)
+    }
+
+    fn rx_stream_item<R>(&mut self, state: &mut T::FinalState) -> Result<Option<R>>
+    where
+        for<'de> R: Deserialize<'de>,
+    {
+        let result = self.transport.rx_stream_item(state);
+        self.check(result)
+    }
+
+    fn tx_cancel_stream(&mut self, state: T::FinalState) -> Result<()> {
+        let result = self.transport.tx_cancel_stream(state);
+        self.check(result)
+    }
+}
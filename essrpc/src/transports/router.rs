@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+
+use crate::{RPCError, RPCErrorKind, RPCServer, Result, ServerTransport};
+
+type Handler<TR> = dyn FnMut(&mut TR, <TR as ServerTransport>::RXState) -> Result<()>;
+
+/// Server which dispatches calls by method name to handlers
+/// registered at runtime, for use where the trait being served is
+/// not known at compile time, such as in a gateway or proxy. Works
+/// with any [ServerTransport](../trait.ServerTransport.html).
+///
+/// A handler is given the transport and the state returned by
+/// `rx_begin_call`. It reads the parameters it needs and must finish
+/// the call with `tx_response` (or `tx_no_response`), or refuse it
+/// unread with `reject_call`, e.g. after checking its metadata. Calls to
+/// methods without a handler, including calls identified by number,
+/// are answered with `tx_error` and `RPCErrorKind::UnknownMethod`.
+///
+/// ```ignore
+/// let mut router = Router::new(JSONTransport::new(stream)).route("bar", |tr, mut state| {
+///     let a: String = tr.rx_read_param("a", &mut state)?;
+///     let b: i32 = tr.rx_read_param("b", &mut state)?;
+///     tr.tx_response(Ok::<_, SomeError>(format!("{} is {}", a, b)), state)
+/// });
+/// router.serve_loop()
+/// ```
+pub struct Router<TR: ServerTransport> {
+    tr: TR,
+    routes: HashMap<String, Box<Handler<TR>>>,
+}
+
+impl<TR: ServerTransport> Router<TR> {
+    pub fn new(transport: TR) -> Self {
+        Router {
+            tr: transport,
+            routes: HashMap::new(),
+        }
+    }
+
+    /// Handle calls to the method `name` with `handler`, replacing
+    /// any handler previously registered for it.
+    pub fn route(
+        mut self,
+        name: impl Into<String>,
+        handler: impl FnMut(&mut TR, TR::RXState) -> Result<()> + 'static,
+    ) -> Self {
+        self.routes.insert(name.into(), Box::new(handler));
+        self
+    }
+
+    /// Get the underlying transport
+    pub fn transport(&self) -> &TR {
+        &self.tr
+    }
+
+    /// Get a mutable reference to the underlying transport
+    pub fn transport_mut(&mut self) -> &mut TR {
+        &mut self.tr
+    }
+}
+
+impl<TR: ServerTransport> RPCServer for Router<TR> {
+    fn serve_single_call(&mut self) -> Result<()> {
+        let (method, rxstate) = self.tr.rx_begin_call()?;
+        // Routes are by name alone
+        let name = method.name(&[]);
+        let routes = &mut self.routes;
+        match name.and_then(|name| routes.get_mut(name)) {
+            Some(handler) => handler(&mut self.tr, rxstate).map_err(|e| match name {
+                Some(name) => e.with_method(name),
+                None => e,
+            }),
+            None => {
+                let e = RPCError::new(
+                    RPCErrorKind::UnknownMethod,
+                    format!("Unknown rpc method {}", method),
+                );
+                self.tr.tx_error(e, rxstate)
+            }
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use std::convert::Infallible;
+use std::io;
+use std::io::{Cursor, Read, Write};
+use std::mem;
+use std::net::TcpListener;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread;
+
+use super::json_framer::ValueFramer;
+use super::{JSONTransport, ACCEPT_RETRY_DELAY};
+use crate::{RPCErrorKind, RPCServer, Result};
+
+// Sends a response back over the connection its request came from
+type Respond = Arc<dyn Fn(&[u8]) -> io::Result<()> + Send + Sync>;
+
+// A request read from a connection, waiting for a worker
+struct Job {
+    request: Vec<u8>,
+    respond: Respond,
+}
+
+/// Blocking JSON-RPC server which reads calls from each connection
+/// and serves them on a fixed pool of worker threads, so that a slow
+/// call holds up neither the other calls on its connection nor other
+/// clients, while no more than a set number of threads is ever busy.
+///
+/// For each call, `make_server` is given a
+/// [JSONTransport](struct.JSONTransport.html) carrying just that
+/// request, and the server it returns serves it. State shared by all
+/// calls, such as a database handle, is shared by capturing an `Arc`
+/// in `make_server` and handing a clone to each server's
+/// implementation.
+///
+/// At most `max_in_flight` calls are queued or being served at once.
+/// Once that many are, connections are not read from until a call
+/// completes, leaving further requests to back up in the socket and
+/// so slowing clients down rather than queueing without bound.
+///
+/// Responses are sent as each call completes, so a client making
+/// several calls at once may get them out of order, matched by id as
+/// with a
+/// [MultiplexedClient](struct.MultiplexedClient.html); nor are
+/// notifications from one client necessarily handled in the order
+/// they were sent. Only unframed JSON is understood, and a request
+/// which cannot be parsed is answered with a parse error and ends the
+/// connection, there being no telling where the next one starts.
+///
+/// ```ignore
+/// let db = Arc::new(Database::open(path)?);
+/// ThreadedServer::new(move |transport| {
+///     FooRPCServer::new(FooImpl::new(db.clone()), transport)
+/// })
+/// .with_threads(8)
+/// .with_max_in_flight(64)
+/// .serve_listener(TcpListener::bind("0.0.0.0:4000")?)
+/// ```
+pub struct ThreadedServer<F> {
+    make_server: Arc<F>,
+    threads: usize,
+    max_in_flight: usize,
+}
+
+impl<S, F> ThreadedServer<F>
+where
+    S: RPCServer,
+    F: Fn(JSONTransport<CallChannel>) -> S + Send + Sync + 'static,
+{
+    /// Create a server with a worker thread per CPU and up to four
+    /// calls in flight per worker.
+    pub fn new(make_server: F) -> Self {
+        let threads = thread::available_parallelism().map_or(4, |n| n.get());
+        ThreadedServer {
+            make_server: Arc::new(make_server),
+            threads,
+            max_in_flight: threads * 4,
+        }
+    }
+
+    /// Serve calls on `threads` worker threads (at least one).
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Allow at most `max_in_flight` calls (at least one) to be
+    /// queued or served at once, across all connections.
+    pub fn with_max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight = max_in_flight.max(1);
+        self
+    }
+
+    /// Accept clients from `listener` indefinitely, as with
+    /// [serve_incoming](#method.serve_incoming). Never returns.
+    pub fn serve_listener(self, listener: TcpListener) -> Result<Infallible> {
+        self.serve_incoming(listener.incoming())?;
+        unreachable!("TcpListener::incoming never ends")
+    }
+
+    /// Serve each connection from `incoming`, reading its calls on a
+    /// thread of its own and serving them on the worker pool.
+    /// Failures to accept a connection are logged with the `log`
+    /// crate and retried after a short pause. Errors serving a call
+    /// other than the client disconnecting are logged too. Returns
+    /// once `incoming` is exhausted, leaving the connections already
+    /// accepted to be served.
+    pub fn serve_incoming<C>(self, incoming: impl IntoIterator<Item = io::Result<C>>) -> Result<()>
+    where
+        C: Send + Sync + 'static,
+        for<'a> &'a C: Read + Write,
+    {
+        let slots = Arc::new(Slots::new(self.max_in_flight));
+        let (jobs, queue) = mpsc::channel();
+        let queue = Arc::new(Mutex::new(queue));
+        for _ in 0..self.threads {
+            let queue = queue.clone();
+            let make_server = self.make_server.clone();
+            let slots = slots.clone();
+            thread::spawn(move || work(&queue, &*make_server, &slots));
+        }
+        for connection in incoming {
+            let connection = match connection {
+                Ok(connection) => connection,
+                Err(e) => {
+                    log::warn!("accept failed: {}", e);
+                    thread::sleep(ACCEPT_RETRY_DELAY);
+                    continue;
+                }
+            };
+            let jobs = jobs.clone();
+            let slots = slots.clone();
+            thread::spawn(move || read_calls(connection, &jobs, &slots));
+        }
+        Ok(())
+    }
+}
+
+/// Channel over which a [ThreadedServer](struct.ThreadedServer.html)
+/// serves a single call. Reading gives the request, and what is
+/// written is sent back over the client's connection when flushed.
+pub struct CallChannel {
+    request: Cursor<Vec<u8>>,
+    response: Vec<u8>,
+    respond: Respond,
+}
+
+impl Read for CallChannel {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.request.read(buf)
+    }
+}
+
+impl Write for CallChannel {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.response.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.response.is_empty() {
+            (self.respond)(&self.response)?;
+            self.response.clear();
+        }
+        Ok(())
+    }
+}
+
+// Number of calls which may yet be taken on, which readers wait on
+// when there are none
+struct Slots {
+    free: Mutex<usize>,
+    freed: Condvar,
+}
+
+impl Slots {
+    fn new(n: usize) -> Self {
+        Slots {
+            free: Mutex::new(n),
+            freed: Condvar::new(),
+        }
+    }
+
+    fn acquire(&self) {
+        let mut free = self.free.lock().unwrap();
+        while *free == 0 {
+            free = self.freed.wait(free).unwrap();
+        }
+        *free -= 1;
+    }
+
+    fn release(&self) {
+        *self.free.lock().unwrap() += 1;
+        self.freed.notify_one();
+    }
+}
+
+// Serve jobs from the queue until every reader has gone
+fn work<S: RPCServer>(
+    queue: &Mutex<mpsc::Receiver<Job>>,
+    make_server: &impl Fn(JSONTransport<CallChannel>) -> S,
+    slots: &Slots,
+) {
+    loop {
+        let job = match queue.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+        let channel = CallChannel {
+            request: Cursor::new(job.request),
+            response: Vec::new(),
+            respond: job.respond,
+        };
+        // serve_loop, since the request may be a batch or a ping; it
+        // ends on reaching the end of the request.
+        let served = panic::catch_unwind(AssertUnwindSafe(|| {
+            make_server(JSONTransport::new(channel)).serve_loop()
+        }));
+        match served {
+            Ok(Err(e)) if e.kind != RPCErrorKind::TransportEOF => {
+                log::warn!("error serving call: {}", e)
+            }
+            Err(_) => log::warn!("panic serving call"),
+            _ => (),
+        }
+        slots.release();
+    }
+}
+
+// Read requests from a connection and queue them for the workers,
+// until the client disconnects
+fn read_calls<C>(connection: C, jobs: &mpsc::Sender<Job>, slots: &Slots)
+where
+    C: Send + Sync + 'static,
+    for<'a> &'a C: Read + Write,
+{
+    let connection = Arc::new(connection);
+    let writer = Mutex::new(connection.clone());
+    let respond: Respond = Arc::new(move |response| {
+        let writer = writer.lock().unwrap();
+        let mut w = &**writer;
+        w.write_all(response)?;
+        w.flush()
+    });
+    let dispatch = |request| {
+        slots.acquire();
+        let job = Job {
+            request,
+            respond: respond.clone(),
+        };
+        if jobs.send(job).is_err() {
+            slots.release();
+        }
+    };
+
+    let mut buf = Vec::new();
+    let mut framer = ValueFramer::default();
+    let mut chunk = [0; 4096];
+    loop {
+        loop {
+            match framer.next_value(&buf) {
+                Ok(Some((_, len))) => dispatch(buf.drain(..len).collect()),
+                Ok(None) => break,
+                Err(_) => {
+                    // Let a worker answer with the parse error
+                    dispatch(mem::take(&mut buf));
+                    return;
+                }
+            }
+        }
+        let n = match (&*connection).read(&mut chunk) {
+            Ok(0) => return,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                log::warn!("error reading from client: {}", e);
+                return;
+            }
+        };
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
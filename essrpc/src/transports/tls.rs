@@ -0,0 +1,109 @@
+use std::convert::TryFrom;
+use std::io;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rustls::pki_types::ServerName;
+use rustls::{
+    ClientConfig, ClientConnection, ConnectionCommon, ServerConfig, ServerConnection, StreamOwned,
+};
+
+use super::{connect_tcp, JSONTransport, ReadTimeout};
+use crate::{RPCError, RPCErrorKind, Result};
+
+/// TLS client stream used by
+/// [JSONTransport::connect_tls](struct.JSONTransport.html#method.connect_tls).
+pub type TlsClientStream = StreamOwned<ClientConnection, TcpStream>;
+/// TLS server stream used by
+/// [JSONTlsAcceptor](struct.JSONTlsAcceptor.html).
+pub type TlsServerStream = StreamOwned<ServerConnection, TcpStream>;
+
+// rustls reports its own failures as io::Errors wrapping a
+// rustls::Error, which are told apart from failures of the socket.
+fn convert_tls_error(e: io::Error) -> RPCError {
+    let is_tls = e.get_ref().is_some_and(|inner| inner.is::<rustls::Error>());
+    if is_tls {
+        RPCError::with_cause(RPCErrorKind::Tls, "tls handshake failed", e)
+    } else {
+        RPCError::with_cause(RPCErrorKind::ConnectionFailed, "tls handshake io failed", e)
+    }
+}
+
+// Complete the handshake before handing out a transport, so that its
+// failure is reported as such rather than by the first call.
+fn handshake<D>(conn: &mut ConnectionCommon<D>, sock: &mut TcpStream) -> Result<()> {
+    while conn.is_handshaking() {
+        conn.complete_io(sock).map_err(convert_tls_error)?;
+    }
+    Ok(())
+}
+
+impl JSONTransport<TlsClientStream> {
+    /// Connect to `addr` over TLS, verifying the server's certificate
+    /// for `server_name` as `config` directs. The handshake is
+    /// completed before returning. A rejected handshake, e.g. for an
+    /// untrusted certificate, is reported with `RPCErrorKind::Tls`,
+    /// while failure to reach the server or loss of the connection
+    /// during the handshake is reported with
+    /// `RPCErrorKind::ConnectionFailed`. Enable the "tls" feature to
+    /// use this.
+    pub fn connect_tls(
+        addr: impl ToSocketAddrs,
+        server_name: &str,
+        config: Arc<ClientConfig>,
+    ) -> Result<Self> {
+        let name = ServerName::try_from(server_name.to_string())
+            .map_err(|e| RPCError::with_cause(RPCErrorKind::Tls, "invalid tls server name", e))?;
+        let mut conn = ClientConnection::new(config, name)
+            .map_err(|e| RPCError::with_cause(RPCErrorKind::Tls, "tls setup failed", e))?;
+        let mut sock = connect_tcp(addr)?;
+        handshake(&mut conn, &mut sock)?;
+        Ok(Self::new(StreamOwned::new(conn, sock)))
+    }
+}
+
+/// Server side of TLS, turning each accepted `TcpStream` into a
+/// [JSONTransport](struct.JSONTransport.html) once the handshake is
+/// complete. Enable the "tls" feature to use this.
+///
+/// ```ignore
+/// let acceptor = JSONTlsAcceptor::new(Arc::new(server_config));
+/// for stream in TcpListener::bind(addr)?.incoming() {
+///     let transport = acceptor.accept(stream?)?;
+///     thread::spawn(move || FooRPCServer::new(FooImpl::new(), transport).serve_loop());
+/// }
+/// ```
+pub struct JSONTlsAcceptor {
+    config: Arc<ServerConfig>,
+}
+
+impl JSONTlsAcceptor {
+    pub fn new(config: Arc<ServerConfig>) -> Self {
+        JSONTlsAcceptor { config }
+    }
+
+    /// Perform the TLS handshake on a newly accepted connection. Its
+    /// failure is reported with `RPCErrorKind::Tls`.
+    pub fn accept(&self, mut sock: TcpStream) -> Result<JSONTransport<TlsServerStream>> {
+        let mut conn = ServerConnection::new(self.config.clone())
+            .map_err(|e| RPCError::with_cause(RPCErrorKind::Tls, "tls setup failed", e))?;
+        handshake(&mut conn, &mut sock)?;
+        Ok(JSONTransport::new(StreamOwned::new(conn, sock)))
+    }
+
+    /// Get the server configuration
+    pub fn config(&self) -> &Arc<ServerConfig> {
+        &self.config
+    }
+}
+
+impl<C, T> ReadTimeout for StreamOwned<C, T>
+where
+    T: Read + Write + ReadTimeout,
+{
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_read_timeout(timeout)
+    }
+}
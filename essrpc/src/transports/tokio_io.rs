@@ -0,0 +1,166 @@
+use std::convert::Infallible;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::{Future, FutureExt};
+use tokio::io::ReadBuf;
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use super::{JSONAsyncServerTransport, ACCEPT_RETRY_DELAY};
+use crate::{AsyncRPCServer, RPCError, RPCErrorKind, Result};
+
+/// Adapter giving a tokio channel, such as `tokio::net::TcpStream`,
+/// the `futures` `AsyncRead+AsyncWrite` traits expected by
+/// [JSONAsyncServerTransport](struct.JSONAsyncServerTransport.html).
+/// Enable the "async_tcp" feature to use this.
+pub struct TokioChannel<T> {
+    inner: T,
+}
+
+impl<T> TokioChannel<T> {
+    pub fn new(inner: T) -> Self {
+        TokioChannel { inner }
+    }
+
+    /// Get the underlying tokio channel
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Consume the adapter, returning the underlying tokio channel.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: tokio::io::AsyncRead + Unpin> AsyncRead for TokioChannel<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut buf = ReadBuf::new(buf);
+        match Pin::new(&mut self.inner).poll_read(cx, &mut buf) {
+            Poll::Ready(Ok(())) => Poll::Ready(Ok(buf.filled().len())),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<T: tokio::io::AsyncWrite + Unpin> AsyncWrite for TokioChannel<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+type TcpTransport = JSONAsyncServerTransport<TokioChannel<TcpStream>>;
+
+impl TcpTransport {
+    /// Bind a TCP listener to `addr` and serve each client which
+    /// connects on its own task, as with
+    /// [serve_listener](#method.serve_listener). Only returns if
+    /// binding fails. Enable the "async_tcp" feature to use this.
+    pub async fn serve_tcp<S, F>(addr: impl ToSocketAddrs, make_server: F) -> Result<Infallible>
+    where
+        S: AsyncRPCServer + 'static,
+        F: FnMut(Self) -> S,
+    {
+        let listener = TcpListener::bind(addr).await.map_err(|e| {
+            RPCError::with_cause(RPCErrorKind::TransportError, "tcp bind failed", e)
+        })?;
+        Self::serve_listener(listener, make_server).await
+    }
+
+    /// Accept clients from `listener` indefinitely, creating a server
+    /// for each with `make_server` and running its `serve_loop` on a
+    /// new task. As async servers are not `Send`, the tasks are
+    /// spawned with `tokio::task::spawn_local`, so this must run
+    /// within a `tokio::task::LocalSet`. A client disconnecting ends
+    /// its task quietly; other errors serving a client, and failures
+    /// to accept one, are logged with the `log` crate, the latter
+    /// being retried after a short pause. Never returns.
+    pub async fn serve_listener<S, F>(
+        listener: TcpListener,
+        mut make_server: F,
+    ) -> Result<Infallible>
+    where
+        S: AsyncRPCServer + 'static,
+        F: FnMut(Self) -> S,
+    {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    log::warn!("tcp accept failed: {}", e);
+                    tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                    continue;
+                }
+            };
+            let mut server = make_server(Self::new(TokioChannel::new(stream)));
+            tokio::task::spawn_local(async move {
+                if let Err(e) = server.serve_loop().await {
+                    log::warn!("error serving tcp client: {}", e);
+                }
+            });
+        }
+    }
+
+    /// Like [serve_listener](#method.serve_listener), but once
+    /// `shutdown` completes, stop accepting clients, let each client's
+    /// server finish the call it is serving, as with
+    /// [AsyncRPCServer::serve_with_shutdown](../trait.AsyncRPCServer.html#method.serve_with_shutdown),
+    /// and return `Ok` when all of them have.
+    pub async fn serve_listener_with_shutdown<S, F>(
+        listener: TcpListener,
+        mut make_server: F,
+        shutdown: impl Future<Output = ()> + 'static,
+    ) -> Result<()>
+    where
+        S: AsyncRPCServer + 'static,
+        F: FnMut(Self) -> S,
+    {
+        let shutdown = shutdown.shared();
+        let mut tasks = Vec::new();
+        loop {
+            let mut stop = shutdown.clone();
+            let stop = Pin::new(&mut stop);
+            let stream = match crate::until_shutdown(listener.accept(), stop).await {
+                Some(Ok((stream, _))) => stream,
+                Some(Err(e)) => {
+                    log::warn!("tcp accept failed: {}", e);
+                    tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                    continue;
+                }
+                None => break,
+            };
+            let mut server = make_server(Self::new(TokioChannel::new(stream)));
+            let stop = shutdown.clone();
+            tasks.retain(|task: &tokio::task::JoinHandle<()>| !task.is_finished());
+            tasks.push(tokio::task::spawn_local(async move {
+                if let Err(e) = server.serve_with_shutdown(stop).await {
+                    log::warn!("error serving tcp client: {}", e);
+                }
+            }));
+        }
+        drop(listener);
+        for task in tasks {
+            let _ = task.await;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+use std::io;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+
+use super::JSONTransport;
+use crate::{RPCError, RPCErrorKind, Result};
+
+fn socket_error(msg: &str, e: io::Error) -> RPCError {
+    RPCError::with_cause(RPCErrorKind::TransportError, msg, e)
+}
+
+impl JSONTransport<UnixStream> {
+    /// Connect to the Unix domain socket at `path`, as bound by a
+    /// server with [bind_unix](#method.bind_unix). Failure to connect
+    /// is reported with `RPCErrorKind::ConnectionFailed`. Enable the
+    /// "unix" feature to use this.
+    pub fn connect_unix(path: impl AsRef<Path>) -> Result<Self> {
+        let stream = UnixStream::connect(path).map_err(|e| {
+            RPCError::with_cause(
+                RPCErrorKind::ConnectionFailed,
+                "unix socket connect failed",
+                e,
+            )
+        })?;
+        Ok(Self::new(stream))
+    }
+
+    /// Bind a Unix domain socket at `path` for a server. Each client
+    /// connecting to it is handed out as a `JSONTransport` by the
+    /// returned [JSONUnixListener](struct.JSONUnixListener.html). The
+    /// socket file is not removed when the listener is dropped, and
+    /// binding fails if it already exists. Enable the "unix" feature
+    /// to use this.
+    pub fn bind_unix(path: impl AsRef<Path>) -> Result<JSONUnixListener> {
+        let listener =
+            UnixListener::bind(path).map_err(|e| socket_error("unix socket bind failed", e))?;
+        Ok(JSONUnixListener { listener })
+    }
+}
+
+/// Unix domain socket listener producing a
+/// [JSONTransport](struct.JSONTransport.html) for each client which
+/// connects. Created by
+/// [JSONTransport::bind_unix](struct.JSONTransport.html#method.bind_unix).
+///
+/// ```ignore
+/// let listener = JSONTransport::bind_unix("/tmp/foo.sock")?;
+/// for transport in listener.incoming() {
+///     let transport = transport?;
+///     thread::spawn(move || FooRPCServer::new(FooImpl::new(), transport).serve_loop());
+/// }
+/// ```
+pub struct JSONUnixListener {
+    listener: UnixListener,
+}
+
+impl JSONUnixListener {
+    /// Wait for a client to connect.
+    pub fn accept(&self) -> Result<JSONTransport<UnixStream>> {
+        let (stream, _) = self
+            .listener
+            .accept()
+            .map_err(|e| socket_error("unix socket accept failed", e))?;
+        Ok(JSONTransport::new(stream))
+    }
+
+    /// Iterate over clients as they connect. Never returns `None`.
+    pub fn incoming(&self) -> impl Iterator<Item = Result<JSONTransport<UnixStream>>> + '_ {
+        std::iter::repeat_with(move || self.accept())
+    }
+
+    /// Get the underlying listener
+    pub fn listener(&self) -> &UnixListener {
+        &self.listener
+    }
+}
@@ -0,0 +1,345 @@
+use std::io;
+use std::io::{Read, Write};
+
+use serde::{Deserialize, Serialize};
+use serde_json::value::Value;
+use tungstenite::{Message, WebSocket};
+
+use super::json_codec::{
+    add_param, begin_call, call_from_value, check_response_id, convert_error, decode_error,
+    notification_for_state, read_param, read_param_opt, response_for_error, response_for_result,
+    result_from_response, set_service_version, stream_end_response, stream_item_from_response,
+    value_for_state, CallOptions, JRXState, JTXState, NonFiniteFloats,
+};
+use crate::{
+    ClientTransport, MethodId, PartialMethodId, RPCError, RPCErrorKind, Result, ServerTransport,
+};
+
+/// Transport implementation sending JSON-RPC over a WebSocket. Each
+/// request and response is a single WebSocket text message, so the
+/// WebSocket protocol itself marks where messages begin and end. The
+/// messages are the same as those of
+/// [JSONTransport](struct.JSONTransport.html), so browser clients and
+/// gateways may speak to the server directly. Binary messages are
+/// accepted and parsed as JSON too. A close frame from the peer ends
+/// the connection with `RPCErrorKind::TransportEOF`. Batch requests
+/// are not supported. Enable the "websocket_transport" feature to use
+/// this.
+///
+/// The WebSocket handshake is left to the caller, e.g.
+/// `tungstenite::accept` on the server and `tungstenite::client` on
+/// the client.
+pub struct WebSocketTransport<S: Read + Write> {
+    channel: WebSocket<S>,
+    options: CallOptions,
+}
+
+impl<S: Read + Write> WebSocketTransport<S> {
+    pub fn new(channel: WebSocket<S>) -> Self {
+        WebSocketTransport {
+            channel,
+            options: CallOptions::default(),
+        }
+    }
+
+    /// Get the underlying WebSocket
+    pub fn channel(&self) -> &WebSocket<S> {
+        &self.channel
+    }
+
+    /// Get a mutable reference to the underlying WebSocket.
+    pub fn channel_mut(&mut self) -> &mut WebSocket<S> {
+        &mut self.channel
+    }
+
+    /// Consume the transport, returning the underlying WebSocket.
+    pub fn into_channel(self) -> WebSocket<S> {
+        self.channel
+    }
+
+    fn read_value(&mut self) -> Result<Value> {
+        loop {
+            match self.channel.read().map_err(convert_ws_error)? {
+                Message::Text(text) => {
+                    return serde_json::from_str(&text)
+                        .map_err(|e| decode_error(e, text.as_bytes()));
+                }
+                Message::Binary(data) => {
+                    return serde_json::from_slice(&data).map_err(|e| decode_error(e, &data));
+                }
+                Message::Close(_) => return Err(closed()),
+                // Pings are answered by tungstenite itself
+                Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => (),
+            }
+        }
+    }
+
+    fn write_value(&mut self, value: &impl Serialize) -> Result<()> {
+        let text = serde_json::to_string(value).map_err(convert_error)?;
+        self.channel
+            .send(Message::Text(text))
+            .map_err(convert_ws_error)
+    }
+}
+
+fn closed() -> RPCError {
+    RPCError::new(RPCErrorKind::TransportEOF, "websocket closed")
+}
+
+fn convert_ws_error(e: tungstenite::Error) -> RPCError {
+    match e {
+        tungstenite::Error::ConnectionClosed
+        | tungstenite::Error::AlreadyClosed
+        | tungstenite::Error::Protocol(
+            tungstenite::error::ProtocolError::ResetWithoutClosingHandshake,
+        ) => closed(),
+        tungstenite::Error::Io(ref ioe) if ioe.kind() == io::ErrorKind::UnexpectedEof => closed(),
+        tungstenite::Error::Io(ref ioe)
+            if ioe.kind() == io::ErrorKind::WouldBlock || ioe.kind() == io::ErrorKind::TimedOut =>
+        {
+            RPCError::with_cause(RPCErrorKind::Timeout, "timed out reading websocket", e)
+        }
+        _ => RPCError::with_cause(RPCErrorKind::TransportError, "websocket failure", e),
+    }
+}
+
+impl<S: Read + Write> ClientTransport for WebSocketTransport<S> {
+    type TXState = JTXState;
+    /// The id of the request, checked against the response.
+    type FinalState = Value;
+
+    fn tx_begin_call(&mut self, method: MethodId) -> Result<JTXState> {
+        Ok(begin_call(method, &self.options))
+    }
+
+    fn tx_add_param(
+        &mut self,
+        name: &'static str,
+        value: impl Serialize,
+        state: &mut JTXState,
+    ) -> Result<()> {
+        add_param(name, value, state)
+    }
+
+    fn tx_set_service_version(&mut self, version: u32, state: &mut JTXState) -> Result<()> {
+        set_service_version(version, state);
+        Ok(())
+    }
+
+    fn tx_finalize(&mut self, state: JTXState) -> Result<Value> {
+        let request = value_for_state(&state, &self.options);
+        self.write_value(&request)?;
+        Ok(request.id().clone())
+    }
+
+    fn tx_notify(&mut self, state: JTXState) -> Result<()> {
+        self.write_value(&notification_for_state(&state, &self.options))
+    }
+
+    fn rx_response<T>(&mut self, id: Value) -> Result<T>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        let response = self.read_value()?;
+        check_response_id(&response, &id)?;
+        result_from_response(response, NonFiniteFloats::default())
+    }
+
+    fn rx_stream_item<T>(&mut self, id: &mut Value) -> Result<Option<T>>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        let response = self.read_value()?;
+        check_response_id(&response, id)?;
+        stream_item_from_response(response, NonFiniteFloats::default())
+    }
+}
+
+impl<S: Read + Write> ServerTransport for WebSocketTransport<S> {
+    type RXState = JRXState;
+
+    fn rx_begin_call(&mut self) -> Result<(PartialMethodId, JRXState)> {
+        let value = self.read_value()?;
+        call_from_value(value)
+    }
+
+    fn rx_read_param<T>(&mut self, name: &'static str, state: &mut JRXState) -> Result<T>
+    where
+        for<'de> T: serde::Deserialize<'de>,
+    {
+        read_param(name, state)
+    }
+
+    fn rx_read_param_opt<T>(
+        &mut self,
+        name: &'static str,
+        state: &mut JRXState,
+    ) -> Result<Option<T>>
+    where
+        for<'de> T: serde::Deserialize<'de>,
+    {
+        read_param_opt(name, state)
+    }
+
+    fn tx_response(
+        &mut self,
+        value: std::result::Result<impl Serialize, impl Serialize>,
+        state: JRXState,
+    ) -> Result<()> {
+        if state.is_notification() {
+            return Ok(());
+        }
+        let response = response_for_result(value, state.id, state.floats)?;
+        self.write_value(&response)
+    }
+
+    fn rx_service_version(&self, state: &JRXState) -> Option<u32> {
+        state.service_version()
+    }
+
+    fn tx_error(&mut self, error: RPCError, state: JRXState) -> Result<()> {
+        if state.is_notification() {
+            return Ok(());
+        }
+        self.write_value(&response_for_error(&error, state.id))
+    }
+
+    fn tx_stream_item(
+        &mut self,
+        value: std::result::Result<impl Serialize, impl Serialize>,
+        state: &mut JRXState,
+    ) -> Result<()> {
+        if state.is_notification() {
+            return Ok(());
+        }
+        let response = response_for_result(value, state.id.clone(), state.floats)?;
+        self.write_value(&response)
+    }
+
+    fn tx_stream_end(&mut self, state: JRXState) -> Result<()> {
+        if state.is_notification() {
+            return Ok(());
+        }
+        self.write_value(&stream_end_response(state.id))
+    }
+}
+
+#[cfg(feature = "async_websocket_transport")]
+mod async_server {
+    use super::*;
+    use crate::AsyncServerTransport;
+    use futures::{SinkExt, StreamExt};
+    use tokio::io::{AsyncRead, AsyncWrite};
+    use tokio_tungstenite::WebSocketStream;
+
+    /// Like WebSocketTransport except for use as
+    /// AsyncServerTransport, backed by `tokio-tungstenite`. Enable the
+    /// "async_websocket_transport" feature to use this.
+    pub struct WebSocketAsyncServerTransport<S> {
+        channel: WebSocketStream<S>,
+    }
+
+    impl<S: AsyncRead + AsyncWrite + Unpin> WebSocketAsyncServerTransport<S> {
+        pub fn new(channel: WebSocketStream<S>) -> Self {
+            WebSocketAsyncServerTransport { channel }
+        }
+
+        /// Get the underlying WebSocket stream
+        pub fn channel(&self) -> &WebSocketStream<S> {
+            &self.channel
+        }
+
+        /// Get a mutable reference to the underlying WebSocket stream.
+        pub fn channel_mut(&mut self) -> &mut WebSocketStream<S> {
+            &mut self.channel
+        }
+
+        /// Consume the transport, returning the underlying WebSocket stream.
+        pub fn into_channel(self) -> WebSocketStream<S> {
+            self.channel
+        }
+
+        async fn read_value(&mut self) -> Result<Value> {
+            loop {
+                let message = match self.channel.next().await {
+                    Some(message) => message.map_err(convert_ws_error)?,
+                    None => return Err(closed()),
+                };
+                match message {
+                    Message::Text(text) => {
+                        return serde_json::from_str(&text)
+                            .map_err(|e| decode_error(e, text.as_bytes()));
+                    }
+                    Message::Binary(data) => {
+                        return serde_json::from_slice(&data).map_err(|e| decode_error(e, &data));
+                    }
+                    Message::Close(_) => return Err(closed()),
+                    Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => (),
+                }
+            }
+        }
+
+        async fn write_value(&mut self, value: &Value) -> Result<()> {
+            let text = serde_json::to_string(value).map_err(convert_error)?;
+            self.channel
+                .send(Message::Text(text))
+                .await
+                .map_err(convert_ws_error)
+        }
+    }
+
+    #[async_trait::async_trait(?Send)]
+    impl<S: AsyncRead + AsyncWrite + Unpin> AsyncServerTransport for WebSocketAsyncServerTransport<S> {
+        type RXState = JRXState;
+
+        async fn rx_begin_call(&mut self) -> Result<(PartialMethodId, JRXState)> {
+            let value = self.read_value().await?;
+            call_from_value(value)
+        }
+
+        async fn rx_read_param<T>(&mut self, name: &'static str, state: &mut JRXState) -> Result<T>
+        where
+            for<'de> T: serde::Deserialize<'de>,
+        {
+            read_param(name, state)
+        }
+
+        async fn rx_read_param_opt<T>(
+            &mut self,
+            name: &'static str,
+            state: &mut JRXState,
+        ) -> Result<Option<T>>
+        where
+            for<'de> T: serde::Deserialize<'de>,
+        {
+            read_param_opt(name, state)
+        }
+
+        async fn tx_response<T: Serialize, E: Serialize>(
+            &mut self,
+            value: std::result::Result<T, E>,
+            state: JRXState,
+        ) -> Result<()> {
+            if state.is_notification() {
+                return Ok(());
+            }
+            let response = response_for_result(value, state.id, state.floats)?;
+            self.write_value(&response).await
+        }
+
+        fn rx_service_version(&self, state: &JRXState) -> Option<u32> {
+            state.service_version()
+        }
+
+        async fn tx_error(&mut self, error: RPCError, state: JRXState) -> Result<()> {
+            if state.is_notification() {
+                return Ok(());
+            }
+            self.write_value(&response_for_error(&error, state.id))
+                .await
+        }
+    }
+}
+
+#[cfg(feature = "async_websocket_transport")]
+pub use self::async_server::WebSocketAsyncServerTransport;
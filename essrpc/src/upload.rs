@@ -0,0 +1,46 @@
+//! Parameters streamed to the server rather than serialized whole.
+use std::io;
+use std::io::Read;
+
+/// Parameter whose bytes are streamed from the client to the server,
+/// for upload-style methods taking more data than is worth holding
+/// in memory at once. The parameter must be marked
+/// `#[essrpc(upload)]` and come last.
+///
+/// The client wraps any reader, such as a `File`, and the transport
+/// sends what it reads in chunks, after the other parameters. The
+/// server's method receives an `Upload` reading those chunks from
+/// the channel as it goes, so it should not hold on to it; whatever
+/// the method leaves unread is discarded once it returns. Needs a
+/// transport implementing
+/// [tx_add_upload](trait.ClientTransport.html#method.tx_add_upload)
+/// and
+/// [rx_read_upload](trait.ServerTransport.html#method.rx_read_upload),
+/// such as
+/// [BincodeTransport](transports/struct.BincodeTransport.html).
+///
+/// ```ignore
+/// #[essrpc]
+/// pub trait Store {
+///     fn put(&self, key: String, #[essrpc(upload)] data: Upload<'_>) -> Result<u64, SomeError>;
+/// }
+///
+/// client.put("backup".to_string(), Upload::new(File::open(path)?))?;
+/// ```
+pub struct Upload<'a> {
+    reader: Box<dyn Read + 'a>,
+}
+
+impl<'a> Upload<'a> {
+    pub fn new(reader: impl Read + 'a) -> Self {
+        Upload {
+            reader: Box::new(reader),
+        }
+    }
+}
+
+impl Read for Upload<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
@@ -1,14 +1,22 @@
 use essrpc::essrpc;
 use essrpc::transports::{
-    BincodeAsyncClientTransport, BincodeTransport, JSONAsyncClientTransport, JSONTransport,
-    ReadWrite,
+    AsyncSplitTransport, BincodeAsyncClientTransport, BincodeTransport, JSONAsyncClientTransport,
+    JSONAsyncServerTransport, JSONTransport, PipelinedServer, ReadWrite,
 };
-use essrpc::{AsyncRPCClient, RPCError, RPCServer};
-use futures::{executor::block_on};
+use essrpc::{AsyncRPCClient, AsyncRPCServer, RPCError, RPCServer};
+use futures::channel::oneshot;
+use futures::executor::block_on;
+use futures::future::pending;
+use futures::io::{AllowStdIo, AsyncReadExt};
 use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
 use std::fmt;
+use std::io;
+use std::io::Write;
 use std::ops::Deref;
+use std::rc::Rc;
 use std::result::Result;
+use std::time::Duration;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct TestError {
@@ -30,10 +38,90 @@ impl From<essrpc::RPCError> for TestError {
     }
 }
 
-#[essrpc(async, sync)]
+#[essrpc(async, sync, async_server)]
 pub trait Foo {
     fn bar(&self, a: String, b: i32) -> Result<String, TestError>;
     fn expect_error(&self) -> Result<String, TestError>;
+    #[essrpc(returns(text, len))]
+    fn describe(&self, a: String) -> Result<(String, usize), TestError>;
+    fn clear(&self) -> Result<(), TestError>;
+}
+
+#[essrpc(async)]
+pub trait Telemetry {
+    #[essrpc(notification)]
+    fn record(&self, sample: i32) -> Result<(), TestError>;
+}
+
+#[essrpc]
+pub trait Store {
+    async fn get(&self, key: String) -> Result<String, TestError>;
+    #[essrpc(notification)]
+    async fn touch(&self, key: String) -> Result<(), TestError>;
+}
+
+#[essrpc]
+pub trait Relay {
+    async fn hold(&self, n: i32) -> Result<i32, TestError>;
+    async fn release(&self) -> Result<(), TestError>;
+}
+
+#[essrpc(async)]
+pub trait Waiter {
+    #[essrpc(timeout_ms = 10)]
+    fn wait(&self) -> Result<(), TestError>;
+}
+
+#[essrpc]
+pub trait Lock {
+    #[essrpc(timeout_ms = 10)]
+    async fn acquire(&self) -> Result<(), TestError>;
+}
+
+struct StoreImpl;
+
+impl Store for StoreImpl {
+    async fn get(&self, key: String) -> Result<String, TestError> {
+        futures::future::ready(()).await;
+        Ok(format!("value of {}", key))
+    }
+    async fn touch(&self, _key: String) -> Result<(), TestError> {
+        Ok(())
+    }
+}
+
+// Holds each call to hold until a call to release, which a server
+// serving one call at a time would never read
+#[derive(Clone)]
+struct RelayImpl {
+    held: Rc<RefCell<Option<oneshot::Receiver<()>>>>,
+    release: Rc<RefCell<Option<oneshot::Sender<()>>>>,
+}
+
+impl RelayImpl {
+    fn new() -> Self {
+        let (release, held) = oneshot::channel();
+        RelayImpl {
+            held: Rc::new(RefCell::new(Some(held))),
+            release: Rc::new(RefCell::new(Some(release))),
+        }
+    }
+}
+
+impl Relay for RelayImpl {
+    async fn hold(&self, n: i32) -> Result<i32, TestError> {
+        let held = self.held.borrow_mut().take();
+        if let Some(held) = held {
+            let _ = held.await;
+        }
+        Ok(n)
+    }
+    async fn release(&self) -> Result<(), TestError> {
+        if let Some(release) = self.release.borrow_mut().take() {
+            let _ = release.send(());
+        }
+        Ok(())
+    }
 }
 
 struct FooImpl;
@@ -53,6 +141,13 @@ impl Foo for FooImpl {
             msg: "iamerror".to_string(),
         })
     }
+    fn describe(&self, a: String) -> Result<(String, usize), TestError> {
+        let len = a.len();
+        Ok((a, len))
+    }
+    fn clear(&self) -> Result<(), TestError> {
+        Ok(())
+    }
 }
 
 #[test]
@@ -73,11 +168,714 @@ fn basic_bincode() {
     }
 }
 
+#[test]
+fn basic_json_async_server() {
+    let foo = FooAsyncRPCClient::new(JSONAsyncClientTransport::new(json_async_server_transact));
+    match block_on(foo.bar("the answer".to_string(), 42)) {
+        Ok(result) => assert_eq!("the answer is 42", result),
+        Err(e) => panic!("error: {:?}", e),
+    }
+}
+
+#[test]
+fn unit_return() {
+    block_on(json_foo().clear()).unwrap();
+    block_on(bincode_foo().clear()).unwrap();
+    let foo = FooAsyncRPCClient::new(JSONAsyncClientTransport::new(json_async_server_transact));
+    block_on(foo.clear()).unwrap();
+    // The async server answers with a null result too
+    let response = block_on(json_async_server_transact(
+        br#"{"jsonrpc":"2.0","method":"clear","id":1}"#.to_vec(),
+    ))
+    .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&response).unwrap();
+    assert!(response["result"].is_null());
+    assert!(response.get("error").is_none());
+}
+
+#[test]
+fn json_async_server_authorizer() {
+    let foo = FooAsyncRPCClient::new(JSONAsyncClientTransport::new(|data: Vec<u8>| async move {
+        let mut response = Vec::new();
+        let channel = AllowStdIo::new(ReadWrite::new(data.deref(), &mut response));
+        let mut serve =
+            FooAsyncRPCServer::new(FooImpl::new(), JSONAsyncServerTransport::new(channel))
+                .with_authorizer(|method, _state| match method.to_string().as_str() {
+                    "bar" => Err(RPCError::new(
+                        essrpc::RPCErrorKind::Application(-32001),
+                        "not authorized",
+                    )),
+                    _ => Ok(()),
+                });
+        serve.serve_single_call().await?;
+        Ok(response)
+    }));
+    let e = block_on(foo.bar("the answer".to_string(), 42)).unwrap_err();
+    assert!(e.msg.contains("not authorized"), "{}", e.msg);
+    block_on(foo.clear()).unwrap();
+}
+
+#[test]
+fn json_async_split_transport() {
+    let request = br#"{"jsonrpc":"2.0","method":"bar","params":{"a":"the answer","b":42},"id":1}"#;
+    let mut response = Vec::new();
+    {
+        let channel = AllowStdIo::new(ReadWrite::new(&request[..], &mut response));
+        let (reader, writer) = channel.split();
+        let transport = AsyncSplitTransport::from_halves(reader, writer);
+        let mut serve = FooAsyncRPCServer::new(FooImpl::new(), transport);
+        block_on(serve.serve_single_call()).unwrap();
+    }
+    let response: serde_json::Value = serde_json::from_slice(&response).unwrap();
+    assert_eq!(response["result"], "the answer is 42");
+}
+
+#[test]
+fn json_async_named_returns() {
+    let foo = json_foo();
+    assert_eq!(
+        block_on(foo.describe("abc".to_string())).unwrap(),
+        ("abc".to_string(), 3)
+    );
+    let foo = FooAsyncRPCClient::new(JSONAsyncClientTransport::new(json_async_server_transact));
+    assert_eq!(
+        block_on(foo.describe("abcd".to_string())).unwrap(),
+        ("abcd".to_string(), 4)
+    );
+    let foo = bincode_foo();
+    assert_eq!(
+        block_on(foo.describe("ab".to_string())).unwrap(),
+        ("ab".to_string(), 2)
+    );
+}
+
+#[test]
+fn json_async_server_eof() {
+    let data: &[u8] = b"";
+    let mut response = Vec::new();
+    let transport =
+        JSONAsyncServerTransport::new(AllowStdIo::new(ReadWrite::new(data, &mut response)));
+    let mut serve = FooAsyncRPCServer::new(FooImpl::new(), transport);
+    match block_on(serve.serve()) {
+        Ok(_) => panic!("Expected EOF error"),
+        Err(e) => assert_eq!(e.kind, essrpc::RPCErrorKind::TransportEOF),
+    }
+}
+
+#[test]
+fn json_async_server_loop_ends_at_eof() {
+    let data: &[u8] = b"";
+    let mut response = Vec::new();
+    let transport =
+        JSONAsyncServerTransport::new(AllowStdIo::new(ReadWrite::new(data, &mut response)));
+    let mut serve = FooAsyncRPCServer::new(FooImpl::new(), transport);
+    assert!(block_on(serve.serve_loop()).is_ok());
+}
+
+#[test]
+fn json_async_server_malformed() {
+    let serve_malformed = |data: &[u8]| {
+        let mut response = Vec::new();
+        let transport =
+            JSONAsyncServerTransport::new(AllowStdIo::new(ReadWrite::new(data, &mut response)));
+        let mut serve = FooAsyncRPCServer::new(FooImpl::new(), transport);
+        let first = block_on(serve.serve_single_call()).unwrap_err();
+        let second = block_on(serve.serve_single_call()).unwrap_err();
+        (first, second)
+    };
+    let (err, _) = serve_malformed(br#"{"jsonrpc":"2.0","id":1,"method":"ba"#);
+    assert_eq!(err.kind, essrpc::RPCErrorKind::TransportEOF);
+    // The malformed message is dropped rather than failing every read
+    let (err, next) = serve_malformed(br#"{"a" 1}"#);
+    assert_eq!(err.kind, essrpc::RPCErrorKind::ParseError);
+    assert_eq!(next.kind, essrpc::RPCErrorKind::TransportEOF);
+    let (err, _) = serve_malformed("[".repeat(100_000).as_bytes());
+    assert_eq!(err.kind, essrpc::RPCErrorKind::ParseError);
+}
+
+#[test]
+fn json_async_server_max_message_bytes() {
+    let serve_limited = |data: &[u8], max| {
+        let mut response = Vec::new();
+        let transport =
+            JSONAsyncServerTransport::new(AllowStdIo::new(ReadWrite::new(data, &mut response)))
+                .with_max_message_bytes(max);
+        let mut serve = FooAsyncRPCServer::new(FooImpl::new(), transport);
+        block_on(serve.serve_single_call())
+    };
+    let request = br#"{"jsonrpc":"2.0","method":"bar","params":{"a":"the answer","b":42},"id":1}"#;
+    serve_limited(request, request.len()).unwrap();
+    let err = serve_limited(request, request.len() - 1).unwrap_err();
+    assert_eq!(err.kind, essrpc::RPCErrorKind::MessageTooLarge);
+    // Refused as soon as too much has arrived, before it is complete
+    let request = format!(
+        r#"{{"jsonrpc":"2.0","method":"bar","params":{{"a":"{}"#,
+        "x".repeat(8192)
+    );
+    let err = serve_limited(request.as_bytes(), 4096).unwrap_err();
+    assert_eq!(err.kind, essrpc::RPCErrorKind::MessageTooLarge);
+}
+
+#[test]
+fn json_async_server_invalid_request() {
+    let request = br#"{"jsonrpc":"2.0","params":{},"id":4}"#;
+    let mut response = Vec::new();
+    let channel = AllowStdIo::new(ReadWrite::new(&request[..], &mut response));
+    let mut serve = FooAsyncRPCServer::new(FooImpl::new(), JSONAsyncServerTransport::new(channel));
+    let err = block_on(serve.serve_single_call()).unwrap_err();
+    assert_eq!(err.kind, essrpc::RPCErrorKind::InvalidRequest);
+    let response: serde_json::Value = serde_json::from_slice(&response).unwrap();
+    assert_eq!(response["error"]["code"], -32600);
+    assert_eq!(response["id"], 4);
+}
+
+#[test]
+fn json_async_server_invalid_params() {
+    let request = br#"{"jsonrpc":"2.0","method":"bar","params":{"a":"x","b":"42"},"id":4}"#;
+    let mut response = Vec::new();
+    let channel = AllowStdIo::new(ReadWrite::new(&request[..], &mut response));
+    let mut serve = FooAsyncRPCServer::new(FooImpl::new(), JSONAsyncServerTransport::new(channel));
+    let err = block_on(serve.serve_single_call()).unwrap_err();
+    assert_eq!(err.kind, essrpc::RPCErrorKind::InvalidParams);
+    let response: serde_json::Value = serde_json::from_slice(&response).unwrap();
+    assert_eq!(response["error"]["code"], -32602);
+    assert_eq!(response["error"]["data"]["param"], "b");
+    assert_eq!(response["id"], 4);
+}
+
+// Channel which accepts at most a few bytes per write, as a
+// non-blocking or small-buffer channel may.
+struct Trickle<W: Write>(W);
+
+impl<W: Write> Write for Trickle<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = std::cmp::min(buf.len(), 3);
+        self.0.write(&buf[..n])
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+#[test]
+fn json_async_server_partial_writes() {
+    let request = br#"{"jsonrpc":"2.0","method":"bar","params":{"a":"the answer","b":42},"id":1}"#;
+    let mut response = Vec::new();
+    let channel = AllowStdIo::new(ReadWrite::new(&request[..], Trickle(&mut response)));
+    let mut serve = FooAsyncRPCServer::new(FooImpl::new(), JSONAsyncServerTransport::new(channel));
+    block_on(serve.serve_single_call()).unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&response).unwrap();
+    assert_eq!(response["result"], "the answer is 42");
+}
+
+#[test]
+fn json_async_server_version() {
+    let requests = concat!(
+        r#"{"jsonrpc":"1.0","method":"bar","params":{"a":"x","b":1},"id":1}"#,
+        r#"{"jsonrpc":"2.0","method":"bar","params":{"a":"y","b":2},"id":2}"#
+    );
+    let mut response = Vec::new();
+    let channel = AllowStdIo::new(ReadWrite::new(requests.as_bytes(), &mut response));
+    let mut serve = FooAsyncRPCServer::new(FooImpl::new(), JSONAsyncServerTransport::new(channel));
+    block_on(serve.serve_single_call()).unwrap();
+    let responses: Vec<serde_json::Value> = serde_json::Deserializer::from_slice(&response)
+        .into_iter()
+        .map(|r| r.unwrap())
+        .collect();
+    assert_eq!(responses.len(), 2);
+    assert_eq!(responses[0]["error"]["code"], -32600);
+    assert_eq!(responses[1]["result"], "y is 2");
+}
+
+#[test]
+fn json_async_server_ping() {
+    let requests = concat!(
+        r#"{"jsonrpc":"2.0","method":"rpc.ping","id":"p"}"#,
+        r#"{"jsonrpc":"2.0","method":"rpc.ping"}"#,
+        r#"{"jsonrpc":"2.0","method":"bar","params":{"a":"y","b":2},"id":2}"#
+    );
+    let mut response = Vec::new();
+    let channel = AllowStdIo::new(ReadWrite::new(requests.as_bytes(), &mut response));
+    let mut serve = FooAsyncRPCServer::new(FooImpl::new(), JSONAsyncServerTransport::new(channel));
+    block_on(serve.serve_single_call()).unwrap();
+    let responses: Vec<serde_json::Value> = serde_json::Deserializer::from_slice(&response)
+        .into_iter()
+        .map(|r| r.unwrap())
+        .collect();
+    assert_eq!(responses.len(), 2);
+    assert_eq!(
+        responses[0],
+        serde_json::json!({"jsonrpc": "2.0", "result": "pong", "id": "p"})
+    );
+    assert_eq!(responses[1]["result"], "y is 2");
+}
+
+#[cfg(feature = "async_websocket_transport")]
+#[tokio::test]
+async fn websocket_async_server() {
+    use essrpc::transports::WebSocketAsyncServerTransport;
+    use futures::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::protocol::Role;
+    use tokio_tungstenite::tungstenite::Message;
+    use tokio_tungstenite::WebSocketStream;
+
+    let (s1, s2) = tokio::io::duplex(4096);
+    let server = async {
+        let socket = WebSocketStream::from_raw_socket(s2, Role::Server, None).await;
+        let transport = WebSocketAsyncServerTransport::new(socket);
+        FooAsyncRPCServer::new(FooImpl::new(), transport)
+            .serve()
+            .await
+    };
+    let client = async {
+        let mut socket = WebSocketStream::from_raw_socket(s1, Role::Client, None).await;
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "bar",
+            "params": {"a": "the answer", "b": 42},
+            "id": 1
+        });
+        socket
+            .send(Message::Text(request.to_string()))
+            .await
+            .unwrap();
+        let response = match socket.next().await {
+            Some(Ok(Message::Text(text))) => text,
+            other => panic!("unexpected message {:?}", other),
+        };
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["result"], "the answer is 42");
+        assert_eq!(response["id"], 1);
+        socket.close(None).await.unwrap();
+    };
+    let (served, _) = futures::join!(server, client);
+    match served {
+        Ok(_) => panic!("Expected EOF error"),
+        Err(e) => assert_eq!(e.kind, essrpc::RPCErrorKind::TransportEOF),
+    }
+}
+
+#[cfg(feature = "async_tcp")]
+#[tokio::test]
+async fn json_async_serve_tcp() {
+    use essrpc::RPCClient;
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let local = tokio::task::LocalSet::new();
+    local.spawn_local(JSONAsyncServerTransport::serve_listener(
+        listener,
+        |transport| FooAsyncRPCServer::new(FooImpl::new(), transport),
+    ));
+    let clients = local.run_until(tokio::task::spawn_blocking(move || {
+        let first = FooRPCClient::new(JSONTransport::connect_tcp(addr).unwrap());
+        let second = FooRPCClient::new(JSONTransport::connect_tcp(addr).unwrap());
+        let result = second.bar("the answer".to_string(), 42).unwrap();
+        drop(second);
+        (result, first.bar("the question".to_string(), 6).unwrap())
+    }));
+    let (second, first) = clients.await.unwrap();
+    assert_eq!(second, "the answer is 42");
+    assert_eq!(first, "the question is 6");
+}
+
+#[cfg(feature = "async_tcp")]
+#[tokio::test]
+async fn json_multiplexed_client() {
+    use essrpc::transports::{MultiplexedClient, TokioChannel};
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+    let (client_io, mut server_io) = tokio::io::duplex(4096);
+    let (r, w) = tokio::io::split(client_io);
+    let (client, reader) = MultiplexedClient::new(
+        futures::io::BufReader::new(TokioChannel::new(r)),
+        TokioChannel::new(w),
+    );
+    // Answers two requests once both have arrived, the second first
+    let server = async move {
+        let mut buf = Vec::new();
+        let mut requests: Vec<serde_json::Value> = Vec::new();
+        while requests.len() < 2 {
+            let mut chunk = [0; 1024];
+            let n = server_io.read(&mut chunk).await.unwrap();
+            buf.extend_from_slice(&chunk[..n]);
+            requests = serde_json::Deserializer::from_slice(&buf)
+                .into_iter()
+                .take_while(|r| r.is_ok())
+                .map(|r| r.unwrap())
+                .collect();
+        }
+        for request in requests.iter().rev() {
+            let a = request["params"]["a"].as_str().unwrap();
+            let b = request["params"]["b"].as_i64().unwrap();
+            let response = serde_json::json!({
+                "jsonrpc": "2.0",
+                "result": format!("{} is {}", a, b),
+                "id": request["id"],
+            });
+            server_io
+                .write_all(response.to_string().as_bytes())
+                .await
+                .unwrap();
+        }
+    };
+    let foo = FooAsyncRPCClient::new(client.transport());
+    let calls = async {
+        let (first, second) = futures::join!(
+            foo.bar("a".to_string(), 1),
+            client.call::<Result<String, TestError>>("bar", serde_json::json!({"a": "b", "b": 2}))
+        );
+        assert_eq!(client.in_flight(), 0);
+        (first.unwrap(), second.unwrap().unwrap())
+    };
+    let (read, _, (first, second)) = futures::join!(reader, server, calls);
+    assert_eq!(first, "a is 1");
+    assert_eq!(second, "b is 2");
+    // The server hung up, ending the reader and failing later calls
+    read.unwrap();
+    let e = client
+        .call::<Result<String, TestError>>("bar", serde_json::json!({"a": "c", "b": 3}))
+        .await
+        .unwrap_err();
+    assert_eq!(e.kind, essrpc::RPCErrorKind::TransportEOF);
+}
+
+#[cfg(feature = "async_tcp")]
+#[tokio::test]
+async fn json_async_client_buf_reader_dropped_call() {
+    use essrpc::transports::TokioChannel;
+    use futures::FutureExt;
+
+    // Too small a pipe for a whole request, which the dropped call
+    // leaves part written, but room enough for the responses
+    let (requests, server_requests) = tokio::io::duplex(16);
+    let (responses, server_responses) = tokio::io::duplex(4096);
+    let mut server = FooAsyncRPCServer::new(
+        FooImpl::new(),
+        JSONAsyncServerTransport::new(TokioChannel::new(tokio::io::join(
+            server_requests,
+            server_responses,
+        ))),
+    );
+    let foo = FooAsyncRPCClient::new(JSONAsyncClientTransport::from_buf_reader(
+        futures::io::BufReader::new(TokioChannel::new(responses)),
+        TokioChannel::new(requests),
+    ));
+    assert!(foo.bar("dropped".to_string(), 1).now_or_never().is_none());
+    let serving = async {
+        server.serve_single_call().await.unwrap();
+        server.serve_single_call().await.unwrap();
+    };
+    let (_, result) = futures::join!(serving, foo.bar("the answer".to_string(), 42));
+    assert_eq!(result.unwrap(), "the answer is 42");
+}
+
+#[cfg(feature = "async_tcp")]
+#[tokio::test]
+async fn json_async_client_buf_reader_timeout() {
+    use essrpc::transports::TokioChannel;
+
+    let (client_io, server_io) = tokio::io::duplex(4096);
+    let mut server = FooAsyncRPCServer::new(
+        FooImpl::new(),
+        JSONAsyncServerTransport::new(TokioChannel::new(server_io)),
+    );
+    let (r, w) = tokio::io::split(client_io);
+    let foo = FooAsyncRPCClient::new(
+        JSONAsyncClientTransport::from_buf_reader(
+            futures::io::BufReader::new(TokioChannel::new(r)),
+            TokioChannel::new(w),
+        )
+        .with_timeout(Duration::from_millis(20)),
+    );
+    // Nothing is served yet, so the first call times out, and its
+    // response arrives only once the second call is waiting
+    let e = foo.bar("late".to_string(), 1).await.unwrap_err();
+    assert!(e.msg.contains("did not complete"), "{}", e);
+    let serving = async {
+        server.serve_single_call().await.unwrap();
+        server.serve_single_call().await.unwrap();
+    };
+    let (_, result) = futures::join!(serving, foo.bar("the answer".to_string(), 42));
+    assert_eq!(result.unwrap(), "the answer is 42");
+}
+
+#[cfg(feature = "async_tcp")]
+#[tokio::test]
+async fn json_async_serve_with_shutdown() {
+    use essrpc::transports::TokioChannel;
+
+    let (client_io, server_io) = tokio::io::duplex(4096);
+    let (stop, stopped) = futures::channel::oneshot::channel::<()>();
+    let mut server = FooAsyncRPCServer::new(
+        FooImpl::new(),
+        JSONAsyncServerTransport::new(TokioChannel::new(server_io)),
+    );
+    let serving = server.serve_with_shutdown(async {
+        let _ = stopped.await;
+    });
+    let (r, w) = tokio::io::split(client_io);
+    let foo = FooAsyncRPCClient::new(JSONAsyncClientTransport::from_buf_reader(
+        futures::io::BufReader::new(TokioChannel::new(r)),
+        TokioChannel::new(w),
+    ));
+    let client = async {
+        let result = foo.bar("the answer".to_string(), 42).await;
+        // The client stays connected, so only the shutdown ends the
+        // server
+        stop.send(()).unwrap();
+        result
+    };
+    let (served, result) = futures::join!(serving, client);
+    served.unwrap();
+    assert_eq!(result.unwrap(), "the answer is 42");
+}
+
+#[cfg(feature = "async_tcp")]
+#[tokio::test]
+async fn json_async_serve_listener_with_shutdown() {
+    use essrpc::RPCClient;
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (stop, stopped) = futures::channel::oneshot::channel::<()>();
+    let (done, connected) = std::sync::mpsc::channel();
+    let local = tokio::task::LocalSet::new();
+    let serving = local.spawn_local(JSONAsyncServerTransport::serve_listener_with_shutdown(
+        listener,
+        |transport| FooAsyncRPCServer::new(FooImpl::new(), transport),
+        async {
+            let _ = stopped.await;
+        },
+    ));
+    let client = local.run_until(tokio::task::spawn_blocking(move || {
+        let foo = FooRPCClient::new(JSONTransport::connect_tcp(addr).unwrap());
+        let result = foo.bar("the answer".to_string(), 42).unwrap();
+        stop.send(()).unwrap();
+        // Hold the connection open until the server has stopped
+        connected.recv().unwrap();
+        result
+    }));
+    let served = local.run_until(serving).await.unwrap();
+    served.unwrap();
+    done.send(()).unwrap();
+    assert_eq!(client.await.unwrap(), "the answer is 42");
+}
+
+#[test]
+fn json_async_notification() {
+    let client =
+        TelemetryAsyncRPCClient::new(JSONAsyncClientTransport::new(|data: Vec<u8>| async move {
+            let request: serde_json::Value = serde_json::from_slice(&data).unwrap();
+            assert_eq!(request["method"], "record");
+            assert!(request.get("id").is_none());
+            // The server sends nothing back
+            Ok(Vec::new())
+        }));
+    block_on(client.record(5)).unwrap();
+}
+
+#[test]
+fn json_async_client_buf_reader() {
+    let (s1, s2) = std::os::unix::net::UnixStream::pair().unwrap();
+    std::thread::spawn(move || FooRPCServer::new(FooImpl::new(), JSONTransport::new(s2)).serve());
+    // A buffer much smaller than a response, which is read in pieces
+    let reader = futures::io::BufReader::with_capacity(8, AllowStdIo::new(s1.try_clone().unwrap()));
+    let transport = JSONAsyncClientTransport::from_buf_reader(reader, AllowStdIo::new(s1));
+    let client = FooAsyncRPCClient::new(transport);
+    assert_eq!(
+        block_on(client.bar("the answer".to_string(), 42)).unwrap(),
+        "the answer is 42"
+    );
+    let (first, second) = block_on(futures::future::join(
+        client.bar("a".to_string(), 1),
+        client.describe("hello".to_string()),
+    ));
+    assert_eq!(first.unwrap(), "a is 1");
+    assert_eq!(second.unwrap(), ("hello".to_string(), 5));
+}
+
+#[test]
+fn json_async_large_messages() {
+    let (s1, s2) = std::os::unix::net::UnixStream::pair().unwrap();
+    let server = std::thread::spawn(move || {
+        let transport = JSONAsyncServerTransport::new(AllowStdIo::new(s2));
+        let mut serve = FooAsyncRPCServer::new(FooImpl::new(), transport);
+        block_on(serve.serve_single_call())
+    });
+    let reader =
+        futures::io::BufReader::with_capacity(64, AllowStdIo::new(s1.try_clone().unwrap()));
+    let client = FooAsyncRPCClient::new(JSONAsyncClientTransport::from_buf_reader(
+        reader,
+        AllowStdIo::new(s1),
+    ));
+    // Larger than any single read or write on either side
+    let a = "x".repeat(5 * 1024);
+    let (text, len) = block_on(client.describe(a.clone())).unwrap();
+    assert_eq!(text, a);
+    assert_eq!(len, a.len());
+    server.join().unwrap().unwrap();
+
+    // A server which hangs up without answering leaves the client at
+    // EOF once the whole request is written
+    let (s1, s2) = std::os::unix::net::UnixStream::pair().unwrap();
+    let server = std::thread::spawn(move || {
+        let mut requests =
+            serde_json::Deserializer::from_reader(s2).into_iter::<serde_json::Value>();
+        requests.next().unwrap().map(|_| ())
+    });
+    let reader = futures::io::BufReader::new(AllowStdIo::new(s1.try_clone().unwrap()));
+    let client = FooAsyncRPCClient::new(JSONAsyncClientTransport::from_buf_reader(
+        reader,
+        AllowStdIo::new(s1),
+    ));
+    let e = block_on(client.describe(a)).unwrap_err();
+    server.join().unwrap().unwrap();
+    assert!(e.msg.contains("transport EOF"), "{}", e.msg);
+}
+
+#[test]
+fn json_pipelined_server() {
+    let requests = concat!(
+        r#"{"jsonrpc":"2.0","method":"hold","params":{"n":7},"id":1}"#,
+        r#"{"jsonrpc":"2.0","method":"rpc.ping","id":"p"}"#,
+        r#"{"jsonrpc":"2.0","method":"hold","params":{"n":8}}"#,
+        r#"{"jsonrpc":"2.0","method":"release","params":{},"id":2}"#,
+        r#"{"jsonrpc":"2.0","method":"hold","params":{"n":"x"},"id":3}"#
+    );
+    let mut response = Vec::new();
+    let channel = AllowStdIo::new(ReadWrite::new(requests.as_bytes(), &mut response));
+    let relay = RelayImpl::new();
+    let server =
+        PipelinedServer::new(|transport| RelayAsyncRPCServer::new(relay.clone(), transport));
+    // The first call is served only once the release behind it is
+    // read, and answered first all the same
+    block_on(server.serve_connection(channel)).unwrap();
+    let responses: Vec<serde_json::Value> = serde_json::Deserializer::from_slice(&response)
+        .into_iter()
+        .map(|r| r.unwrap())
+        .collect();
+    assert_eq!(responses.len(), 4);
+    assert_eq!(responses[0]["result"], 7);
+    assert_eq!(responses[0]["id"], 1);
+    assert_eq!(responses[1]["id"], "p");
+    assert_eq!(responses[2]["id"], 2);
+    assert_eq!(responses[3]["error"]["code"], -32602);
+
+    // A request which cannot be parsed ends the connection
+    let requests = concat!(
+        r#"{"jsonrpc":"2.0","method":"release","params":{},"id":1}"#,
+        r#"{"jsonrpc""#,
+        r#"{"jsonrpc":"2.0","method":"release","params":{},"id":2}"#
+    );
+    let mut response = Vec::new();
+    let channel = AllowStdIo::new(ReadWrite::new(requests.as_bytes(), &mut response));
+    block_on(
+        PipelinedServer::new(|transport| RelayAsyncRPCServer::new(RelayImpl::new(), transport))
+            .with_depth(1)
+            .serve_connection(channel),
+    )
+    .unwrap();
+    let responses: Vec<serde_json::Value> = serde_json::Deserializer::from_slice(&response)
+        .into_iter()
+        .map(|r| r.unwrap())
+        .collect();
+    assert_eq!(responses.len(), 2);
+    assert_eq!(responses[0]["id"], 1);
+    assert_eq!(responses[1]["error"]["code"], -32700);
+}
+
+#[test]
+fn json_async_fn_methods() {
+    let store = StoreRPCClient::new(JSONAsyncClientTransport::new(store_transact));
+    match block_on(store.get("k".to_string())) {
+        Ok(result) => assert_eq!("value of k", result),
+        Err(e) => panic!("error: {:?}", e),
+    }
+    block_on(store.touch("k".to_string())).unwrap();
+}
+
+#[test]
+fn json_async_client_timeout() {
+    let foo = FooAsyncRPCClient::new(
+        JSONAsyncClientTransport::new(slow_json_transact).with_timeout(Duration::from_millis(10)),
+    );
+    match block_on(foo.bar("slow".to_string(), 42)) {
+        Ok(_) => panic!("Expected timeout"),
+        Err(e) => assert!(e.msg.contains("did not complete"), "{}", e),
+    }
+    // A call which timed out does not affect later calls
+    assert_eq!(
+        "the answer is 42",
+        block_on(foo.bar("the answer".to_string(), 42)).unwrap()
+    );
+}
+
+#[test]
+fn timeout_combinator() {
+    let foo = FooAsyncRPCClient::new(JSONAsyncClientTransport::new(slow_json_transact));
+    let slow = essrpc::timeout(foo.bar("slow".to_string(), 42), Duration::from_millis(10));
+    match block_on(slow) {
+        Ok(_) => panic!("Expected timeout"),
+        Err(e) => assert!(e.msg.contains("did not complete"), "{}", e),
+    }
+    let fast = essrpc::timeout(
+        foo.bar("the answer".to_string(), 42),
+        Duration::from_secs(10),
+    );
+    assert_eq!("the answer is 42", block_on(fast).unwrap());
+
+    let timeout: Result<(), RPCError> =
+        block_on(essrpc::timeout(pending(), Duration::from_millis(10)));
+    assert_eq!(timeout.unwrap_err().kind, essrpc::RPCErrorKind::Timeout);
+}
+
+#[test]
+fn json_async_method_timeout() {
+    let waiter = WaiterAsyncRPCClient::new(JSONAsyncClientTransport::new(never_transact));
+    match block_on(waiter.wait()) {
+        Ok(_) => panic!("Expected timeout"),
+        Err(e) => assert!(e.msg.contains("did not complete"), "{}", e),
+    }
+    let lock = LockRPCClient::new(JSONAsyncClientTransport::new(never_transact));
+    match block_on(lock.acquire()) {
+        Ok(_) => panic!("Expected timeout"),
+        Err(e) => assert!(e.msg.contains("did not complete"), "{}", e),
+    }
+}
+
+async fn never_transact(_data: Vec<u8>) -> Result<Vec<u8>, RPCError> {
+    pending().await
+}
+
+// Like json_transact, but never completes calls whose first
+// parameter is "slow".
+async fn slow_json_transact(data: Vec<u8>) -> Result<Vec<u8>, RPCError> {
+    let request: serde_json::Value = serde_json::from_slice(&data).unwrap();
+    if request["params"]["a"] == "slow" {
+        pending::<()>().await;
+    }
+    json_transact(data).await
+}
+
+async fn store_transact(data: Vec<u8>) -> Result<Vec<u8>, RPCError> {
+    let mut response = Vec::new();
+    let channel = AllowStdIo::new(ReadWrite::new(data.deref(), &mut response));
+    let mut serve = StoreAsyncRPCServer::new(StoreImpl, JSONAsyncServerTransport::new(channel));
+    serve.serve_single_call().await?;
+    Ok(response)
+}
+
+async fn json_async_server_transact(data: Vec<u8>) -> Result<Vec<u8>, RPCError> {
+    let mut response = Vec::new();
+    let channel = AllowStdIo::new(ReadWrite::new(data.deref(), &mut response));
+    let mut serve = FooAsyncRPCServer::new(FooImpl::new(), JSONAsyncServerTransport::new(channel));
+    serve.serve_single_call().await?;
+    Ok(response)
+}
+
 async fn json_transact(data: Vec<u8>) -> Result<Vec<u8>, RPCError> {
-		let mut response = Vec::new();
+    let mut response = Vec::new();
     let transport = JSONTransport::new(ReadWrite::new(data.deref(), &mut response));
-    let mut serve = FooRPCServer::new(FooImpl::new(), transport);
-    serve.serve_single_call()?;
+    FooRPCServer::new(FooImpl::new(), transport).serve_single_call()?;
     Ok(response)
 }
 
@@ -86,7 +884,7 @@ fn json_foo() -> impl FooAsync {
 }
 
 async fn bincode_transact(data: Vec<u8>) -> Result<Vec<u8>, RPCError> {
-		let mut response = Vec::new();
+    let mut response = Vec::new();
     let transport = BincodeTransport::new(ReadWrite::new(data.deref(), &mut response));
     let mut serve = FooRPCServer::new(FooImpl::new(), transport);
     serve.serve_single_call()?;
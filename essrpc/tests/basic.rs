@@ -2,15 +2,45 @@ extern crate essrpc;
 extern crate serde;
 
 use std::fmt;
+use std::io;
+use std::io::{Read, Write};
 use std::os::unix::net::UnixStream;
 use std::result::Result;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::thread;
+use std::time::Duration;
 
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
 use essrpc::essrpc;
-use essrpc::transports::{BincodeTransport, JSONTransport};
-use essrpc::{RPCClient, RPCErrorKind, RPCServer};
+use essrpc::transports::json_codec::JSONCodec;
+#[cfg(feature = "cbor_transport")]
+use essrpc::transports::CBORTransport;
+#[cfg(feature = "compression")]
+use essrpc::transports::CompressedChannel;
+#[cfg(feature = "http_server")]
+use essrpc::transports::HTTPService;
+#[cfg(feature = "http_transport")]
+use essrpc::transports::HTTPTransport;
+#[cfg(feature = "tls")]
+use essrpc::transports::JSONTlsAcceptor;
+#[cfg(feature = "msgpack_transport")]
+use essrpc::transports::MessagePackTransport;
+#[cfg(feature = "websocket_transport")]
+use essrpc::transports::WebSocketTransport;
+use essrpc::transports::{
+    BincodeTransport, ByteOrder, DuplexChannel, DynamicClient, EmptyParams, JRXState,
+    JSONTransport, JSONTransportConfig, NegotiatingTransport, NonFiniteFloats, ObservedTransport,
+    Observer, ReadWrite, RecordingTransport, RetryingClient, Router, SplitTransport,
+    ThreadedServer, TransportPool, WireFormat,
+};
+#[cfg(feature = "fd_passing")]
+use essrpc::transports::{Fd, FdChannel};
+use essrpc::{
+    ByteBuf, ClientTransport, MethodId, PartialMethodId, RPCClient, RPCErrorKind, RPCServer,
+    ServerTransport, Upload,
+};
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct TestError {
@@ -38,6 +68,394 @@ pub trait Foo {
     fn expect_error(&self) -> Result<String, TestError>;
 }
 
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub enum AccountError {
+    Insufficient { balance: u64 },
+    Rpc(String),
+}
+
+impl From<essrpc::RPCError> for AccountError {
+    fn from(error: essrpc::RPCError) -> Self {
+        AccountError::Rpc(error.to_string())
+    }
+}
+
+#[essrpc]
+pub trait Account {
+    fn withdraw(&self, amount: u64) -> Result<u64, AccountError>;
+}
+
+struct AccountImpl;
+
+impl Account for AccountImpl {
+    fn withdraw(&self, amount: u64) -> Result<u64, AccountError> {
+        match 100u64.checked_sub(amount) {
+            Some(balance) => Ok(balance),
+            None => Err(AccountError::Insufficient { balance: 100 }),
+        }
+    }
+}
+
+#[cfg(feature = "fd_passing")]
+#[essrpc]
+pub trait Supervisor {
+    fn write_to(&self, fd: Fd, text: String) -> Result<(), TestError>;
+    fn open_socket(&self, greeting: String) -> Result<Fd, TestError>;
+}
+
+#[cfg(feature = "fd_passing")]
+struct SupervisorImpl;
+
+#[cfg(feature = "fd_passing")]
+impl Supervisor for SupervisorImpl {
+    fn write_to(&self, fd: Fd, text: String) -> Result<(), TestError> {
+        let mut socket = UnixStream::from(fd.into_inner());
+        socket.write_all(text.as_bytes()).unwrap();
+        Ok(())
+    }
+    fn open_socket(&self, greeting: String) -> Result<Fd, TestError> {
+        let (mut ours, theirs) = UnixStream::pair().unwrap();
+        ours.write_all(greeting.as_bytes()).unwrap();
+        Ok(Fd::new(theirs.into()))
+    }
+}
+
+#[essrpc]
+pub trait Telemetry {
+    #[essrpc(notification)]
+    fn record(&self, sample: i32) -> Result<(), TestError>;
+    fn total(&self) -> Result<i32, TestError>;
+    fn reset(&self) -> Result<(), TestError>;
+}
+
+#[derive(Default)]
+struct TelemetryImpl {
+    total: std::cell::Cell<i32>,
+}
+
+impl Telemetry for TelemetryImpl {
+    fn record(&self, sample: i32) -> Result<(), TestError> {
+        self.total.set(self.total.get() + sample);
+        Ok(())
+    }
+    fn total(&self) -> Result<i32, TestError> {
+        Ok(self.total.get())
+    }
+    fn reset(&self) -> Result<(), TestError> {
+        self.total.set(0);
+        Ok(())
+    }
+}
+
+#[essrpc]
+pub trait Greeter {
+    fn greet(
+        &self,
+        #[essrpc(rename = "firstName")] first_name: String,
+    ) -> Result<String, TestError>;
+}
+
+struct GreeterImpl;
+
+impl Greeter for GreeterImpl {
+    fn greet(&self, first_name: String) -> Result<String, TestError> {
+        Ok(format!("hello {}", first_name))
+    }
+}
+
+#[essrpc]
+pub trait Counter {
+    #[essrpc(stream)]
+    fn count(&self, to: i32) -> impl Iterator<Item = Result<i32, TestError>>;
+    fn name(&self) -> Result<String, TestError>;
+}
+
+struct CounterImpl;
+
+impl Counter for CounterImpl {
+    fn count(&self, to: i32) -> impl Iterator<Item = Result<i32, TestError>> {
+        (1..=to).map(|i| {
+            if i == 3 {
+                Err(TestError {
+                    msg: "three".to_string(),
+                })
+            } else {
+                Ok(i)
+            }
+        })
+    }
+    fn name(&self) -> Result<String, TestError> {
+        Ok("counter".to_string())
+    }
+}
+
+#[essrpc]
+pub trait Echo {
+    #[essrpc(types(T = "i32, String, Vec<u8>"))]
+    fn echo<T: Serialize + DeserializeOwned>(&self, value: T) -> Result<T, TestError>;
+    #[essrpc(types(K = "String", V = "i32, bool"))]
+    fn pair<K, V>(&self, key: K, value: V) -> Result<String, TestError>
+    where
+        K: Serialize + DeserializeOwned + fmt::Debug,
+        V: Serialize + DeserializeOwned + fmt::Debug;
+}
+
+struct EchoImpl;
+
+impl Echo for EchoImpl {
+    fn echo<T: Serialize + DeserializeOwned>(&self, value: T) -> Result<T, TestError> {
+        Ok(value)
+    }
+    fn pair<K, V>(&self, key: K, value: V) -> Result<String, TestError>
+    where
+        K: Serialize + DeserializeOwned + fmt::Debug,
+        V: Serialize + DeserializeOwned + fmt::Debug,
+    {
+        Ok(format!("{:?}={:?}", key, value))
+    }
+}
+
+#[essrpc]
+pub trait Integers {
+    fn wide(&self, a: u64, b: i64) -> Result<(u64, i64), TestError>;
+    fn huge(&self, a: i128, b: u128) -> Result<(i128, u128), TestError>;
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Paging {
+    offset: u32,
+    limit: u32,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Query {
+    text: String,
+    #[serde(flatten)]
+    paging: Paging,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    language: Option<String>,
+}
+
+#[essrpc]
+pub trait Search {
+    fn search(&self, query: Query, limit: u32) -> Result<Query, TestError>;
+}
+
+struct SearchImpl;
+
+impl Search for SearchImpl {
+    fn search(&self, mut query: Query, limit: u32) -> Result<Query, TestError> {
+        query.paging.limit = query.paging.limit.min(limit);
+        Ok(query)
+    }
+}
+
+#[essrpc]
+pub trait Store {
+    fn put(
+        &self,
+        key: String,
+        #[essrpc(upload)] data: Upload<'_>,
+    ) -> Result<(String, u64), TestError>;
+    fn put_prefix(&self, n: u64, #[essrpc(upload)] data: Upload<'_>) -> Result<Vec<u8>, TestError>;
+}
+
+struct StoreImpl;
+
+impl Store for StoreImpl {
+    fn put(&self, key: String, mut data: Upload<'_>) -> Result<(String, u64), TestError> {
+        let len =
+            io::copy(&mut data, &mut io::sink()).map_err(|e| TestError { msg: e.to_string() })?;
+        Ok((key, len))
+    }
+
+    // Leaves the rest of the upload unread
+    fn put_prefix(&self, n: u64, data: Upload<'_>) -> Result<Vec<u8>, TestError> {
+        let mut prefix = Vec::new();
+        data.take(n)
+            .read_to_end(&mut prefix)
+            .map_err(|e| TestError { msg: e.to_string() })?;
+        Ok(prefix)
+    }
+}
+
+#[essrpc]
+pub trait Stats {
+    fn summary(&self, n: i32) -> Result<(i32, String, bool), TestError>;
+    #[essrpc(returns(count, label, done))]
+    fn named_summary(&self, n: i32) -> Result<(i32, String, bool), TestError>;
+    #[essrpc(returns(count))]
+    fn named_count(&self, n: i32) -> Result<(i32,), TestError>;
+}
+
+struct StatsImpl;
+
+impl Stats for StatsImpl {
+    fn summary(&self, n: i32) -> Result<(i32, String, bool), TestError> {
+        Ok((n, format!("{} items", n), n > 2))
+    }
+    fn named_summary(&self, n: i32) -> Result<(i32, String, bool), TestError> {
+        if n < 0 {
+            return Err(TestError {
+                msg: "negative".to_string(),
+            });
+        }
+        self.summary(n)
+    }
+    fn named_count(&self, n: i32) -> Result<(i32,), TestError> {
+        Ok((n,))
+    }
+}
+
+#[essrpc]
+pub trait Scaler {
+    fn scale(&self, x: f64, factor: f64) -> Result<f64, TestError>;
+}
+
+struct ScalerImpl;
+
+impl Scaler for ScalerImpl {
+    fn scale(&self, x: f64, factor: f64) -> Result<f64, TestError> {
+        Ok(x * factor)
+    }
+}
+
+#[essrpc]
+pub trait Blobs {
+    fn checksum(&self, #[essrpc(bytes)] data: Vec<u8>) -> Result<u64, TestError>;
+    fn reverse(
+        &self,
+        #[essrpc(bytes)] data: &[u8],
+        #[essrpc(rename = "suffix", bytes)] tail: Vec<u8>,
+    ) -> Result<ByteBuf, TestError>;
+}
+
+struct BlobsImpl;
+
+impl Blobs for BlobsImpl {
+    fn checksum(&self, data: Vec<u8>) -> Result<u64, TestError> {
+        Ok(data.iter().map(|b| *b as u64).sum())
+    }
+    fn reverse(&self, data: &[u8], tail: Vec<u8>) -> Result<ByteBuf, TestError> {
+        let mut reversed: Vec<u8> = data.iter().rev().cloned().collect();
+        reversed.extend(tail);
+        Ok(ByteBuf::new(reversed))
+    }
+}
+
+// A trait and a later version of it, adding parameters which clients
+// of the first version do not send
+#[essrpc]
+pub trait Inventory {
+    fn add(&self, item: String) -> Result<String, TestError>;
+}
+
+#[essrpc]
+pub trait InventoryV2 {
+    fn add(
+        &self,
+        item: String,
+        #[essrpc(default)] count: u32,
+        #[essrpc(default, rename = "comment")] note: Option<String>,
+    ) -> Result<String, TestError>;
+}
+
+struct InventoryImpl;
+
+impl InventoryV2 for InventoryImpl {
+    fn add(&self, item: String, count: u32, note: Option<String>) -> Result<String, TestError> {
+        Ok(format!(
+            "{} x{} ({})",
+            item,
+            count,
+            note.unwrap_or_default()
+        ))
+    }
+}
+
+#[essrpc(namespace = "Accounts")]
+pub trait Accounts {
+    fn get(&self, user: String) -> Result<i64, TestError>;
+}
+
+#[essrpc(namespace = "Billing")]
+pub trait Billing {
+    fn get(&self, invoice: u32) -> Result<String, TestError>;
+}
+
+// Successive versions of a trait, and an unversioned client of it
+#[essrpc(version = 2)]
+pub trait LedgerV2 {
+    fn balance(&self, user: String) -> Result<i64, TestError>;
+}
+
+#[essrpc(version = 3)]
+pub trait LedgerV3 {
+    fn balance(&self, user: String) -> Result<i64, TestError>;
+}
+
+#[essrpc]
+pub trait Ledger {
+    fn balance(&self, user: String) -> Result<i64, TestError>;
+}
+
+struct LedgerImpl;
+
+impl LedgerV3 for LedgerImpl {
+    fn balance(&self, user: String) -> Result<i64, TestError> {
+        Ok(user.len() as i64)
+    }
+}
+
+#[essrpc]
+pub trait Sleeper {
+    #[essrpc(timeout_ms = 50)]
+    fn nap(&self, ms: u64) -> Result<u64, TestError>;
+    #[essrpc(timeout_ms = 5000)]
+    fn sleep(&self, ms: u64) -> Result<u64, TestError>;
+    fn doze(&self, ms: u64) -> Result<u64, TestError>;
+}
+
+struct SleeperImpl;
+
+impl SleeperImpl {
+    fn sleep_for(ms: u64) -> Result<u64, TestError> {
+        thread::sleep(Duration::from_millis(ms));
+        Ok(ms)
+    }
+}
+
+impl Sleeper for SleeperImpl {
+    fn nap(&self, ms: u64) -> Result<u64, TestError> {
+        Self::sleep_for(ms)
+    }
+    fn sleep(&self, ms: u64) -> Result<u64, TestError> {
+        Self::sleep_for(ms)
+    }
+    fn doze(&self, ms: u64) -> Result<u64, TestError> {
+        Self::sleep_for(ms)
+    }
+}
+
+struct AccountsImpl;
+
+impl Accounts for AccountsImpl {
+    fn get(&self, user: String) -> Result<i64, TestError> {
+        Ok(user.len() as i64 * 100)
+    }
+}
+
+struct IntegersImpl;
+
+impl Integers for IntegersImpl {
+    fn wide(&self, a: u64, b: i64) -> Result<(u64, i64), TestError> {
+        Ok((a, b))
+    }
+    fn huge(&self, a: i128, b: u128) -> Result<(i128, u128), TestError> {
+        Ok((a, b))
+    }
+}
+
 struct FooImpl;
 
 impl FooImpl {
@@ -69,103 +487,4213 @@ fn basic_json() {
     client42(&foo);
 }
 
+#[cfg(feature = "msgpack_transport")]
 #[test]
-fn propagates_error() {
-    let foo = json_foo();
-    match foo.expect_error() {
-        Ok(_) => panic!("Should have generated an error"),
-        Err(e) => assert_eq!(&e.msg, "iamerror"),
-    }
+fn basic_msgpack() {
+    let foo = msgpack_foo();
+    client42(&foo);
 }
 
+#[cfg(feature = "cbor_transport")]
 #[test]
-fn serve_multiple() {
+fn basic_cbor() {
+    let foo = cbor_foo();
+    client42(&foo);
+}
+
+#[cfg(feature = "websocket_transport")]
+#[test]
+fn basic_websocket() {
+    let foo = websocket_foo();
+    client42(&foo);
+}
+
+#[cfg(feature = "http_transport")]
+#[test]
+fn basic_http() {
     let (s1, s2) = UnixStream::pair().unwrap();
     thread::spawn(move || {
-        let mut serve = FooRPCServer::new(FooImpl::new(), BincodeTransport::new(s2));
+        let mut serve = FooRPCServer::new(FooImpl::new(), HTTPTransport::server(s2));
         serve.serve()
     });
-    let foo = FooRPCClient::new(BincodeTransport::new(s1));
+    let foo = FooRPCClient::new(HTTPTransport::new(s1, "http://localhost/rpc").unwrap());
+    // Calls share the connection
     client42(&foo);
-    match foo.bar("the answer".to_string(), 43) {
-        Ok(result) => assert_eq!("the answer is 43", result),
-        Err(e) => panic!("error: {:?}", e),
+    client42(&foo);
+    match foo.expect_error() {
+        Ok(_) => panic!("call should have failed"),
+        Err(e) => assert_eq!(e.msg, "iamerror"),
     }
 }
 
+#[cfg(feature = "compression")]
 #[test]
-fn serve_single_call_ok_bincode() {
-    // Verify that serve_single_call returns an Ok result
+fn basic_compressed() {
     let (s1, s2) = UnixStream::pair().unwrap();
     thread::spawn(move || {
-        let foo = FooRPCClient::new(BincodeTransport::new(s1));
-        client42(&foo);
+        let mut serve = FooRPCServer::new(
+            FooImpl::new(),
+            BincodeTransport::new(CompressedChannel::new(s2)),
+        );
+        serve.serve()
     });
-    let mut serve = FooRPCServer::new(FooImpl::new(), BincodeTransport::new(s2));
-    serve.serve_single_call().unwrap()
+    let foo = FooRPCClient::new(BincodeTransport::new(CompressedChannel::new(s1)));
+    // Several calls share the deflate stream
+    client42(&foo);
+    client42(&foo);
 }
 
+#[cfg(feature = "compression")]
 #[test]
-fn serve_single_call_ok_json() {
-    // Verify that serve_single_call returns an Ok result
+fn compressed_mirror_serves_plain_client() {
     let (s1, s2) = UnixStream::pair().unwrap();
     thread::spawn(move || {
-        let foo = FooRPCClient::new(JSONTransport::new(s1));
-        client42(&foo);
+        let mut serve = FooRPCServer::new(
+            FooImpl::new(),
+            JSONTransport::new(CompressedChannel::mirror(s2)),
+        );
+        serve.serve_single_call()
     });
-    let mut serve = FooRPCServer::new(FooImpl::new(), JSONTransport::new(s2));
-    serve.serve_single_call().unwrap()
+    let foo = FooRPCClient::new(JSONTransport::new(s1));
+    client42(&foo);
 }
 
+#[cfg(feature = "compression")]
 #[test]
-fn serve_multiple_eof_on_disconnect_bincode() {
+fn compressed_mirror_serves_compressed_client() {
     let (s1, s2) = UnixStream::pair().unwrap();
     thread::spawn(move || {
-        let foo = FooRPCClient::new(BincodeTransport::new(s1));
-        client42(&foo);
+        let mut serve = FooRPCServer::new(
+            FooImpl::new(),
+            JSONTransport::new(CompressedChannel::mirror(s2)),
+        );
+        serve.serve_single_call()
     });
-    let mut serve = FooRPCServer::new(FooImpl::new(), BincodeTransport::new(s2));
-    match serve.serve() {
-        Ok(_) => panic!("Expected EOF error"),
-        Err(e) => assert_eq!(e.kind, RPCErrorKind::TransportEOF),
-    }
+    let foo = FooRPCClient::new(JSONTransport::new(CompressedChannel::new(s1)));
+    client42(&foo);
 }
 
 #[test]
-fn serve_multiple_eof_on_disconnect_json() {
+fn basic_json_numeric() {
     let (s1, s2) = UnixStream::pair().unwrap();
     thread::spawn(move || {
-        let foo = FooRPCClient::new(JSONTransport::new(s1));
-        client42(&foo);
+        let mut serve = FooRPCServer::new(FooImpl::new(), JSONTransport::new_numeric(s2));
+        serve.serve_single_call()
     });
-    let mut serve = FooRPCServer::new(FooImpl::new(), JSONTransport::new(s2));
-    match serve.serve() {
-        Ok(_) => panic!("Expected EOF error"),
-        Err(e) => assert_eq!(e.kind, RPCErrorKind::TransportEOF),
-    }
+    let foo = FooRPCClient::new(JSONTransport::new_numeric(s1));
+    client42(&foo);
 }
 
-fn client42<T: Foo>(client: &T) {
-    match client.bar("the answer".to_string(), 42) {
-        Ok(result) => assert_eq!("the answer is 42", result),
-        Err(e) => panic!("error: {:?}", e),
+#[test]
+fn json_method_encoding() {
+    // Name mode sends the method name, numeric mode sends the method number
+    let request = json_request(false);
+    assert_eq!(request["method"], serde_json::json!("bar"));
+    let request = json_request(true);
+    assert_eq!(request["method"], serde_json::json!(0));
+}
+
+#[test]
+fn json_pretty() {
+    let config = JSONTransportConfig { pretty: true };
+    let mut request = Vec::new();
+    {
+        let channel = ReadWrite::new(&[][..], &mut request);
+        let client = FooRPCClient::new(JSONTransport::with_config(channel, config.clone()));
+        assert!(client.bar("the answer".to_string(), 42).is_err());
     }
+    assert!(String::from_utf8(request)
+        .unwrap()
+        .contains("\n  \"method\": \"bar\""));
+
+    let (s1, s2) = UnixStream::pair().unwrap();
+    let server_config = config.clone();
+    thread::spawn(move || {
+        let transport = JSONTransport::with_config(s2, server_config);
+        let mut serve = FooRPCServer::new(FooImpl::new(), transport);
+        serve.serve()
+    });
+    let foo = FooRPCClient::new(JSONTransport::with_config(s1, config));
+    client42(&foo);
+    client42(&foo);
 }
 
-fn json_foo() -> impl Foo {
+#[test]
+fn json_handshake() {
     let (s1, s2) = UnixStream::pair().unwrap();
     thread::spawn(move || {
-        let mut serve = FooRPCServer::new(FooImpl::new(), JSONTransport::new(s2));
-        serve.serve_single_call()
+        let transport = JSONTransport::new(s2).with_handshake()?;
+        FooRPCServer::new(FooImpl::new(), transport).serve()
     });
-    FooRPCClient::new(JSONTransport::new(s1))
+    let foo = FooRPCClient::new(JSONTransport::new(s1).with_handshake().unwrap());
+    client42(&foo);
+
+    // A server knowing nothing of the handshake answers the greeting
+    // as an invalid request
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || FooRPCServer::new(FooImpl::new(), JSONTransport::new(s2)).serve());
+    let e = JSONTransport::new(s1).with_handshake().err().unwrap();
+    assert_eq!(e.kind, RPCErrorKind::HandshakeFailed);
+
+    let mut sent = Vec::new();
+    let channel = ReadWrite::new(&br#"{"essrpc": 2}"#[..], &mut sent);
+    let e = JSONTransport::new(channel).with_handshake().err().unwrap();
+    assert_eq!(e.kind, RPCErrorKind::HandshakeFailed);
+    assert!(e.to_string().contains("version 2"), "{}", e);
+    let sent: serde_json::Value = serde_json::from_slice(&sent).unwrap();
+    assert_eq!(sent, serde_json::json!({"essrpc": 1}));
+
+    // Nor is a peer which hangs up without a word taken for essrpc
+    let channel = ReadWrite::new(&[][..], Vec::new());
+    let e = JSONTransport::new(channel).with_handshake().err().unwrap();
+    assert_eq!(e.kind, RPCErrorKind::HandshakeFailed);
 }
 
-fn bincode_foo() -> impl Foo {
+#[test]
+fn json_renamed_param() {
+    let mut request = Vec::new();
+    {
+        let channel = ReadWrite::new(&[][..], &mut request);
+        let client = GreeterRPCClient::new(JSONTransport::new(channel));
+        // No server is present, so reading the response fails
+        assert!(client.greet("alice".to_string()).is_err());
+    }
+    let request: serde_json::Value = serde_json::from_slice(&request).unwrap();
+    assert_eq!(request["params"], serde_json::json!({"firstName": "alice"}));
+
     let (s1, s2) = UnixStream::pair().unwrap();
     thread::spawn(move || {
-        let mut serve = FooRPCServer::new(FooImpl::new(), BincodeTransport::new(s2));
+        let mut serve = GreeterRPCServer::new(GreeterImpl, JSONTransport::new(s2));
         serve.serve_single_call()
     });
-    FooRPCClient::new(BincodeTransport::new(s1))
+    let client = GreeterRPCClient::new(JSONTransport::new(s1));
+    assert_eq!(client.greet("alice".to_string()).unwrap(), "hello alice");
+}
+
+#[test]
+fn json_stream() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || CounterRPCServer::new(CounterImpl, JSONTransport::new(s2)).serve_loop());
+    let client = CounterRPCClient::new(JSONTransport::new(s1));
+    let items: Vec<_> = client.count(4).collect();
+    assert_eq!(items.len(), 4);
+    assert_eq!(items[0].as_ref().unwrap(), &1);
+    assert_eq!(items[1].as_ref().unwrap(), &2);
+    assert_eq!(items[2].as_ref().unwrap_err().msg, "three");
+    assert_eq!(items[3].as_ref().unwrap(), &4);
+    assert_eq!(client.count(0).count(), 0);
+    // The client is usable again once the stream has ended
+    assert_eq!(client.name().unwrap(), "counter");
+}
+
+// Counts the items it produces
+#[derive(Clone, Default)]
+struct ProducingCounter {
+    produced: std::sync::Arc<AtomicUsize>,
+}
+
+impl Counter for ProducingCounter {
+    fn count(&self, to: i32) -> impl Iterator<Item = Result<i32, TestError>> {
+        let produced = self.produced.clone();
+        (1..=to).map(move |i| {
+            produced.fetch_add(1, Ordering::SeqCst);
+            Ok(i)
+        })
+    }
+    fn name(&self) -> Result<String, TestError> {
+        Ok("producer".to_string())
+    }
+}
+
+#[test]
+fn json_stream_cancel() {
+    let counter = ProducingCounter::default();
+    let (c, s) = DuplexChannel::pair();
+    let server = JSONTransport::new(s).with_cancellation();
+    let serve = counter.clone();
+    thread::spawn(move || CounterRPCServer::new(serve, server).serve_loop());
+    let transport = RecordingTransport::new(c, JSONTransport::new);
+    let recorded = transport.recorded();
+    let client = CounterRPCClient::new(transport);
+    let first: Vec<_> = client
+        .count(1_000_000)
+        .take(3)
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(first, [1, 2, 3]);
+    {
+        let recorded = recorded.lock().unwrap();
+        let request: serde_json::Value = serde_json::from_slice(&recorded[0]).unwrap();
+        let cancel: serde_json::Value = serde_json::from_slice(&recorded[4]).unwrap();
+        assert_eq!(
+            cancel,
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "method": "$cancel",
+                "params": {"id": request["id"]}
+            })
+        );
+    }
+    // Items sent before the server saw the cancellation are skipped
+    assert_eq!(client.name().unwrap(), "producer");
+    let produced = counter.produced.load(Ordering::SeqCst);
+    assert!(produced < 1_000_000, "{} produced", produced);
+}
+
+#[test]
+fn json_stream_dropped_without_cancellation() {
+    let counter = ProducingCounter::default();
+    let (s1, s2) = UnixStream::pair().unwrap();
+    let serve = counter.clone();
+    thread::spawn(move || CounterRPCServer::new(serve, JSONTransport::new(s2)).serve_loop());
+    let client = CounterRPCClient::new(JSONTransport::new(s1));
+    assert_eq!(client.count(100).next().unwrap().unwrap(), 1);
+    // The server sends every item, which the client reads past
+    assert_eq!(client.name().unwrap(), "producer");
+    assert_eq!(counter.produced.load(Ordering::SeqCst), 100);
+}
+
+#[test]
+fn json_stream_end_on_wire() {
+    let request = br#"{"jsonrpc":"2.0","method":"count","params":{"to":2},"id":7}"#;
+    let mut response = Vec::new();
+    {
+        let channel = ReadWrite::new(&request[..], &mut response);
+        let mut serve = CounterRPCServer::new(CounterImpl, JSONTransport::new(channel));
+        serve.serve_single_call().unwrap();
+    }
+    let responses: Vec<serde_json::Value> = serde_json::Deserializer::from_slice(&response)
+        .into_iter()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(responses.len(), 3);
+    assert_eq!(responses[0]["result"], 1);
+    assert_eq!(responses[1]["result"], 2);
+    assert_eq!(responses[2]["stream_end"], true);
+    assert_eq!(responses[2]["id"], 7);
+}
+
+#[test]
+fn stream_unsupported() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        CounterRPCServer::new(CounterImpl, BincodeTransport::new(s2)).serve_loop()
+    });
+    let client = CounterRPCClient::new(BincodeTransport::new(s1));
+    let mut items = client.count(2);
+    assert!(items.next().unwrap().is_err());
+    assert!(items.next().is_none());
+}
+
+fn check_generic_methods<TR: ClientTransport>(client: &EchoRPCClient<TR>) {
+    assert_eq!(client.echo(42).unwrap(), 42);
+    assert_eq!(client.echo("hi".to_string()).unwrap(), "hi");
+    assert_eq!(client.echo(vec![1u8, 2]).unwrap(), vec![1, 2]);
+    assert_eq!(client.pair("k".to_string(), true).unwrap(), "\"k\"=true");
+    // The server was not compiled for u64
+    let err = client.echo(7u64).unwrap_err();
+    assert!(err.msg.contains("Unsupported type u64"), "{}", err.msg);
+}
+
+#[test]
+fn json_generic_method() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || EchoRPCServer::new(EchoImpl, JSONTransport::new(s2)).serve_loop());
+    check_generic_methods(&EchoRPCClient::new(JSONTransport::new(s1)));
+}
+
+#[test]
+fn bincode_generic_method() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || EchoRPCServer::new(EchoImpl, BincodeTransport::new(s2)).serve_loop());
+    let client = EchoRPCClient::new(BincodeTransport::new(s1));
+    assert_eq!(client.echo(42).unwrap(), 42);
+    assert_eq!(client.pair("k".to_string(), 3).unwrap(), "\"k\"=3");
+}
+
+fn check_stats<C: Stats>(client: &C) {
+    assert_eq!(client.summary(3).unwrap(), (3, "3 items".to_string(), true));
+    assert_eq!(
+        client.named_summary(1).unwrap(),
+        (1, "1 items".to_string(), false)
+    );
+    assert_eq!(client.named_summary(-1).unwrap_err().msg, "negative");
+    assert_eq!(client.named_count(7).unwrap(), (7,));
+}
+
+#[test]
+fn tuple_returns() {
+    let (client, server) = JSONTransport::pair();
+    thread::spawn(move || StatsRPCServer::new(StatsImpl, server).serve_loop());
+    check_stats(&StatsRPCClient::new(client));
+
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || StatsRPCServer::new(StatsImpl, BincodeTransport::new(s2)).serve_loop());
+    check_stats(&StatsRPCClient::new(BincodeTransport::new(s1)));
+
+    #[cfg(feature = "cbor_transport")]
+    {
+        let (s1, s2) = UnixStream::pair().unwrap();
+        thread::spawn(move || StatsRPCServer::new(StatsImpl, CBORTransport::new(s2)).serve_loop());
+        check_stats(&StatsRPCClient::new(CBORTransport::new(s1)));
+    }
+    #[cfg(feature = "msgpack_transport")]
+    {
+        let (s1, s2) = UnixStream::pair().unwrap();
+        thread::spawn(move || {
+            StatsRPCServer::new(StatsImpl, MessagePackTransport::new(s2)).serve_loop()
+        });
+        check_stats(&StatsRPCClient::new(MessagePackTransport::new(s1)));
+    }
+}
+
+fn check_blobs(blobs: &impl Blobs) {
+    let data: Vec<u8> = (0..=255).collect();
+    assert_eq!(blobs.checksum(data.clone()).unwrap(), 255 * 128);
+    assert_eq!(blobs.checksum(Vec::new()).unwrap(), 0);
+    let reversed = blobs.reverse(&data[..3], vec![9]).unwrap();
+    assert_eq!(reversed.into_vec(), vec![2, 1, 0, 9]);
+}
+
+#[test]
+fn bytes_params() {
+    let (client, server) = JSONTransport::pair();
+    thread::spawn(move || BlobsRPCServer::new(BlobsImpl, server).serve_loop());
+    check_blobs(&BlobsRPCClient::new(client));
+
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || BlobsRPCServer::new(BlobsImpl, BincodeTransport::new(s2)).serve_loop());
+    check_blobs(&BlobsRPCClient::new(BincodeTransport::new(s1)));
+
+    #[cfg(feature = "cbor_transport")]
+    {
+        let (s1, s2) = UnixStream::pair().unwrap();
+        thread::spawn(move || BlobsRPCServer::new(BlobsImpl, CBORTransport::new(s2)).serve_loop());
+        check_blobs(&BlobsRPCClient::new(CBORTransport::new(s1)));
+    }
+    #[cfg(feature = "msgpack_transport")]
+    {
+        let (s1, s2) = UnixStream::pair().unwrap();
+        thread::spawn(move || {
+            BlobsRPCServer::new(BlobsImpl, MessagePackTransport::new(s2)).serve_loop()
+        });
+        check_blobs(&BlobsRPCClient::new(MessagePackTransport::new(s1)));
+    }
+}
+
+#[test]
+fn json_bytes_on_wire() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || BlobsRPCClient::new(JSONTransport::new(s1)).reverse(&[1, 2, 3], vec![]));
+    let mut server = JSONTransport::new(s2);
+    let (_, state) = server.rx_begin_call().unwrap();
+    assert_eq!(
+        state.params().unwrap(),
+        &serde_json::json!({"data": "AQID", "suffix": ""})
+    );
+
+    // Arrays of numbers, as sent without the attribute, are accepted
+    let request =
+        br#"{"jsonrpc":"2.0","id":1,"method":"reverse","params":{"data":[1,2],"suffix":"Aw=="}}"#;
+    let mut response = Vec::new();
+    let transport = JSONTransport::new(ReadWrite::new(&request[..], &mut response));
+    BlobsRPCServer::new(BlobsImpl, transport)
+        .serve_single_call()
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&response).unwrap();
+    assert_eq!(response["result"], serde_json::json!("AgED"));
+
+    let request = br#"{"jsonrpc":"2.0","id":1,"method":"checksum","params":{"data":"!!"}}"#;
+    let transport = JSONTransport::new(ReadWrite::new(&request[..], io::sink()));
+    let err = BlobsRPCServer::new(BlobsImpl, transport)
+        .serve_single_call()
+        .unwrap_err();
+    assert_eq!(err.kind, RPCErrorKind::InvalidParams);
+}
+
+#[test]
+fn default_params() {
+    let (client, server) = JSONTransport::pair();
+    thread::spawn(move || InventoryV2RPCServer::new(InventoryImpl, server).serve_loop());
+    let old = InventoryRPCClient::new(client);
+    assert_eq!(old.add("nut".to_string()).unwrap(), "nut x0 ()");
+
+    let (client, server) = JSONTransport::pair();
+    thread::spawn(move || InventoryV2RPCServer::new(InventoryImpl, server).serve_loop());
+    let new = InventoryV2RPCClient::new(client);
+    assert_eq!(
+        new.add("bolt".to_string(), 3, Some("m6".to_string()))
+            .unwrap(),
+        "bolt x3 (m6)"
+    );
+
+    #[cfg(feature = "cbor_transport")]
+    {
+        let (s1, s2) = UnixStream::pair().unwrap();
+        thread::spawn(move || {
+            InventoryV2RPCServer::new(InventoryImpl, CBORTransport::new(s2)).serve_loop()
+        });
+        let old = InventoryRPCClient::new(CBORTransport::new(s1));
+        assert_eq!(old.add("nut".to_string()).unwrap(), "nut x0 ()");
+    }
+    #[cfg(feature = "msgpack_transport")]
+    {
+        let (s1, s2) = UnixStream::pair().unwrap();
+        thread::spawn(move || {
+            InventoryV2RPCServer::new(InventoryImpl, MessagePackTransport::new(s2)).serve_loop()
+        });
+        let old = InventoryRPCClient::new(MessagePackTransport::new(s1));
+        assert_eq!(old.add("nut".to_string()).unwrap(), "nut x0 ()");
+    }
+}
+
+#[test]
+fn json_default_params_positional() {
+    let requests = concat!(
+        r#"{"jsonrpc":"2.0","id":1,"method":"add","params":["nut"]}"#,
+        r#"{"jsonrpc":"2.0","id":2,"method":"add","params":["bolt",2,"m6"]}"#,
+        r#"{"jsonrpc":"2.0","id":3,"method":"add","params":{"item":"pin","comment":null}}"#,
+        r#"{"jsonrpc":"2.0","id":4,"method":"add"}"#,
+    );
+    let mut response = Vec::new();
+    {
+        let channel = ReadWrite::new(requests.as_bytes(), &mut response);
+        let mut serve = InventoryV2RPCServer::new(InventoryImpl, JSONTransport::new(channel));
+        for _ in 0..3 {
+            serve.serve_single_call().unwrap();
+        }
+        // Parameters without a default are still required
+        let err = serve.serve_single_call().unwrap_err();
+        assert_eq!(err.kind, RPCErrorKind::InvalidParams);
+    }
+    let responses: Vec<serde_json::Value> = serde_json::Deserializer::from_slice(&response)
+        .into_iter()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(responses[0]["result"], "nut x0 ()");
+    assert_eq!(responses[1]["result"], "bolt x2 (m6)");
+    assert_eq!(responses[2]["result"], "pin x0 ()");
+}
+
+#[test]
+fn json_named_returns_on_wire() {
+    let requests = concat!(
+        r#"{"jsonrpc":"2.0","id":1,"method":"summary","params":{"n":3}}"#,
+        r#"{"jsonrpc":"2.0","id":2,"method":"named_summary","params":{"n":3}}"#,
+    );
+    let mut response = Vec::new();
+    {
+        let channel = ReadWrite::new(requests.as_bytes(), &mut response);
+        let mut serve = StatsRPCServer::new(StatsImpl, JSONTransport::new(channel));
+        serve.serve_single_call().unwrap();
+        serve.serve_single_call().unwrap();
+    }
+    let responses: Vec<serde_json::Value> = serde_json::Deserializer::from_slice(&response)
+        .into_iter()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(
+        responses[0]["result"],
+        serde_json::json!([3, "3 items", true])
+    );
+    assert_eq!(
+        responses[1]["result"],
+        serde_json::json!({"count": 3, "label": "3 items", "done": true})
+    );
+
+    // The client reads the fields in any order, ignoring others
+    let (client, mut server) = JSONTransport::pair();
+    thread::spawn(move || {
+        let (_, state) = server.rx_begin_call().unwrap();
+        let result = serde_json::json!({"label": "x", "extra": 0, "done": false, "count": 2});
+        server.tx_response(Ok::<_, TestError>(result), state)
+    });
+    let client = StatsRPCClient::new(client);
+    assert_eq!(
+        client.named_summary(2).unwrap(),
+        (2, "x".to_string(), false)
+    );
+}
+
+fn json_integers() -> IntegersRPCClient<JSONTransport<DuplexChannel>> {
+    let (client, server) = JSONTransport::pair();
+    thread::spawn(move || IntegersRPCServer::new(IntegersImpl, server).serve_loop());
+    IntegersRPCClient::new(client)
+}
+
+#[test]
+fn json_flattened_param() {
+    let (c, s) = DuplexChannel::pair();
+    thread::spawn(move || SearchRPCServer::new(SearchImpl, JSONTransport::new(s)).serve_loop());
+    let client = RecordingTransport::new(c, JSONTransport::new);
+    let recorded = client.recorded();
+    let search = SearchRPCClient::new(client);
+
+    let mut query = Query {
+        text: "essrpc".to_string(),
+        paging: Paging {
+            offset: 10,
+            limit: 50,
+        },
+        language: None,
+    };
+    let result = search.search(query.clone(), 20).unwrap();
+    query.paging.limit = 20;
+    assert_eq!(result, query);
+    // The flattened fields are lifted into the struct's own object,
+    // which is itself nested under the parameter's name, and the
+    // skipped field is absent.
+    let request: serde_json::Value = serde_json::from_slice(&recorded.lock().unwrap()[0]).unwrap();
+    assert_eq!(
+        request["params"],
+        serde_json::json!({
+            "query": {"text": "essrpc", "offset": 10, "limit": 50},
+            "limit": 20
+        })
+    );
+
+    query.language = Some("rust".to_string());
+    assert_eq!(search.search(query.clone(), 20).unwrap(), query);
+}
+
+#[test]
+fn bincode_flattened_param_refused() {
+    let (c, s) = DuplexChannel::pair();
+    thread::spawn(move || SearchRPCServer::new(SearchImpl, BincodeTransport::new(s)).serve_loop());
+    let search = SearchRPCClient::new(BincodeTransport::new(c));
+    let query = Query {
+        text: "essrpc".to_string(),
+        paging: Paging {
+            offset: 10,
+            limit: 50,
+        },
+        language: None,
+    };
+    // Refused by the client rather than sent in a form the server
+    // would misread
+    let e = search.search(query, 20).unwrap_err();
+    assert!(e.msg.contains("bincode serialization"), "{}", e.msg);
+}
+
+#[test]
+fn bincode_upload() {
+    let (c, s) = DuplexChannel::pair();
+    thread::spawn(move || StoreRPCServer::new(StoreImpl, BincodeTransport::new(s)).serve_loop());
+    let store = StoreRPCClient::new(BincodeTransport::new(c));
+    // Several chunks' worth
+    let data: Vec<u8> = (0..300_000).map(|i| (i % 251) as u8).collect();
+    assert_eq!(
+        store
+            .put("big".to_string(), Upload::new(&data[..]))
+            .unwrap(),
+        ("big".to_string(), data.len() as u64)
+    );
+    assert_eq!(
+        store
+            .put("empty".to_string(), Upload::new(io::empty()))
+            .unwrap(),
+        ("empty".to_string(), 0)
+    );
+    // What the method leaves unread is skipped, ready for the next call
+    assert_eq!(
+        store.put_prefix(10, Upload::new(&data[..])).unwrap(),
+        &data[..10]
+    );
+    assert_eq!(
+        store
+            .put("again".to_string(), Upload::new(&data[..1000]))
+            .unwrap(),
+        ("again".to_string(), 1000)
+    );
+}
+
+#[test]
+fn json_upload_unsupported() {
+    let (c, s) = JSONTransport::pair();
+    thread::spawn(move || StoreRPCServer::new(StoreImpl, s).serve_loop());
+    let store = StoreRPCClient::new(c);
+    let e = store
+        .put("key".to_string(), Upload::new(&b"data"[..]))
+        .unwrap_err();
+    assert!(e.msg.contains("does not support upload"), "{}", e.msg);
+}
+
+#[test]
+fn json_64_bit_integers() {
+    let client = json_integers();
+    assert_eq!(
+        client.wide(u64::MAX, i64::MIN).unwrap(),
+        (u64::MAX, i64::MIN)
+    );
+    assert_eq!(
+        client.wide(u64::MAX - 1, i64::MAX).unwrap(),
+        (u64::MAX - 1, i64::MAX)
+    );
+    // Within the 64-bit range, 128-bit integers need no special support
+    assert_eq!(
+        client.huge(i64::MIN as i128, u64::MAX as u128).unwrap(),
+        (i64::MIN as i128, u64::MAX as u128)
+    );
+}
+
+#[cfg(feature = "json_arbitrary_precision")]
+#[test]
+fn json_128_bit_integers() {
+    let client = json_integers();
+    assert_eq!(
+        client.huge(i128::MIN, u128::MAX).unwrap(),
+        (i128::MIN, u128::MAX)
+    );
+    assert_eq!(
+        client.huge(i128::MAX, u64::MAX as u128 + 1).unwrap(),
+        (i128::MAX, u64::MAX as u128 + 1)
+    );
+}
+
+#[cfg(not(feature = "json_arbitrary_precision"))]
+#[test]
+fn json_128_bit_integers_refused() {
+    let client = json_integers();
+    assert!(client.huge(i128::MIN, 0).is_err());
+    // Nothing was sent, so the client is still usable
+    assert_eq!(client.huge(1, 2).unwrap(), (1, 2));
+}
+
+#[test]
+fn json_positional() {
+    let mut request = Vec::new();
+    {
+        let channel = ReadWrite::new(&[][..], &mut request);
+        let client = FooRPCClient::new(JSONTransport::positional(channel));
+        assert!(client.bar("the answer".to_string(), 42).is_err());
+    }
+    let request: serde_json::Value = serde_json::from_slice(&request).unwrap();
+    assert_eq!(request["params"], serde_json::json!(["the answer", 42]));
+
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let mut serve = FooRPCServer::new(FooImpl::new(), JSONTransport::new(s2));
+        serve.serve_single_call()
+    });
+    let foo = FooRPCClient::new(JSONTransport::positional(s1));
+    client42(&foo);
+}
+
+fn json_empty_params_request(empty_params: EmptyParams) -> serde_json::Value {
+    let mut request = Vec::new();
+    {
+        let channel = ReadWrite::new(&[][..], &mut request);
+        let client = FooRPCClient::new(JSONTransport::new(channel).with_empty_params(empty_params));
+        assert!(client.expect_error().is_err());
+    }
+    serde_json::from_slice(&request).unwrap()
+}
+
+#[test]
+fn json_empty_params() {
+    let request = json_empty_params_request(EmptyParams::Object);
+    assert_eq!(request["params"], serde_json::json!({}));
+    let request = json_empty_params_request(EmptyParams::Array);
+    assert_eq!(request["params"], serde_json::json!([]));
+    let request = json_empty_params_request(EmptyParams::Omit);
+    assert!(request.get("params").is_none());
+
+    // The server accepts a call without params
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let mut serve = FooRPCServer::new(FooImpl::new(), JSONTransport::new(s2));
+        serve.serve_single_call()
+    });
+    let client = FooRPCClient::new(JSONTransport::new(s1).with_empty_params(EmptyParams::Omit));
+    match client.expect_error() {
+        Ok(_) => panic!("Should have gotten an error"),
+        Err(e) => assert_eq!(e.msg, "iamerror"),
+    }
+}
+
+#[test]
+fn json_encode_call() {
+    let transport = JSONTransport::new(ReadWrite::new(io::empty(), io::sink()));
+    let bar = MethodId {
+        name: "bar",
+        num: 0,
+    };
+    let params = [("a", serde_json::json!("the answer")), ("b", 42.into())];
+    let bytes = transport.encode_call(bar, &params).unwrap();
+
+    // The same request a client sends, but for its random id
+    let mut encoded: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    let mut sent = json_request(false);
+    encoded.as_object_mut().unwrap().remove("id");
+    sent.as_object_mut().unwrap().remove("id");
+    assert_eq!(encoded, sent);
+
+    let (method, state) = transport.decode_call(&bytes).unwrap();
+    assert!(matches!(method, PartialMethodId::Name(name) if name == "bar"));
+    assert_eq!(state.params().unwrap()["b"], 42);
+
+    // A server answers the encoded request
+    let mut response = Vec::new();
+    {
+        let channel = ReadWrite::new(&bytes[..], &mut response);
+        let mut serve = FooRPCServer::new(FooImpl::new(), JSONTransport::new(channel));
+        serve.serve_single_call().unwrap();
+    }
+    let response: serde_json::Value = serde_json::from_slice(&response).unwrap();
+    assert_eq!(response["result"], "the answer is 42");
+}
+
+#[test]
+fn json_encode_call_framed() {
+    let transport = JSONTransport::framed(ReadWrite::new(io::empty(), io::sink()));
+    let bar = MethodId {
+        name: "bar",
+        num: 0,
+    };
+    let bytes = transport
+        .encode_call(bar, &[("a", "x".into()), ("b", 5.into())])
+        .unwrap();
+    assert_eq!(
+        u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize,
+        bytes.len() - 4
+    );
+    let (_, state) = transport.decode_call(&bytes).unwrap();
+    assert_eq!(state.params().unwrap()["a"], "x");
+    assert!(transport.decode_call(b"{").is_err());
+}
+
+#[test]
+fn json_duplicate_param() {
+    // Adding a named param twice would lose the first, so debug
+    // builds refuse it
+    let codec = JSONCodec::new();
+    let mut state = codec.begin_call(MethodId {
+        name: "bar",
+        num: 0,
+    });
+    codec.add_param("a", "x", &mut state).unwrap();
+    let second = codec.add_param("a", "y", &mut state);
+    if cfg!(debug_assertions) {
+        assert_eq!(second.unwrap_err().kind, RPCErrorKind::SerializationError);
+    }
+
+    // Positional params have no names to clash
+    let codec = JSONCodec::positional();
+    let mut state = codec.begin_call(MethodId {
+        name: "bar",
+        num: 0,
+    });
+    codec.add_param("a", "x", &mut state).unwrap();
+    codec.add_param("a", "y", &mut state).unwrap();
+}
+
+#[test]
+fn json_codec_by_hand() {
+    let client = JSONCodec::new();
+    let server = JSONCodec::new();
+    let bar = MethodId {
+        name: "bar",
+        num: 0,
+    };
+    let mut state = client.begin_call(bar);
+    client.add_param("a", "the answer", &mut state).unwrap();
+    client.add_param("b", 42, &mut state).unwrap();
+    let (request, id) = client.encode_call(&state).unwrap();
+    let notification = client.encode_notification(&state).unwrap();
+
+    // Messages sharing a stream are told apart by message_len
+    let mut stream = request.clone();
+    stream.extend_from_slice(&notification);
+    assert_eq!(server.message_len(&stream).unwrap(), Some(request.len()));
+    assert_eq!(server.message_len(&notification[..10]).unwrap(), None);
+
+    let (method, mut call) = server.decode_call(&request).unwrap();
+    assert!(matches!(method, PartialMethodId::Name(name) if name == "bar"));
+    let a: String = server.read_param("a", &mut call).unwrap();
+    let b: i32 = server.read_param("b", &mut call).unwrap();
+    let result: Result<String, TestError> = Ok(format!("{} is {}", a, b));
+    let response = server.encode_response(result, call.id()).unwrap();
+    let result: Result<String, TestError> = client.decode_response(&response, &id).unwrap();
+    assert_eq!(result.unwrap(), "the answer is 42");
+
+    let (_, call) = server.decode_call(&notification).unwrap();
+    assert!(call.is_notification());
+
+    let err = match server.decode_call(b"}") {
+        Ok(_) => panic!("decoded garbage"),
+        Err(e) => e,
+    };
+    assert_eq!(err.kind, RPCErrorKind::ParseError);
+    let response = server.encode_error(&err, &serde_json::Value::Null).unwrap();
+    let err = client
+        .decode_response::<Result<String, TestError>>(&response, &id)
+        .unwrap_err();
+    assert_eq!(err.kind, RPCErrorKind::ParseError);
+}
+
+#[test]
+fn json_ping() {
+    let (mut client, server) = JSONTransport::pair();
+    thread::spawn(move || FooRPCServer::new(FooImpl::new(), server).serve_loop());
+    client.ping().unwrap();
+    client.ping().unwrap();
+    // The server goes on serving calls after pings
+    let foo = FooRPCClient::new(client);
+    client42(&foo);
+
+    // A server expecting another name refuses the default ping
+    let (mut client, server) = JSONTransport::pair();
+    let server = server.with_ping_method("health");
+    thread::spawn(move || FooRPCServer::new(FooImpl::new(), server).serve_loop());
+    assert_eq!(client.ping().unwrap_err().kind, RPCErrorKind::UnknownMethod);
+    let mut client = client.with_ping_method("health");
+    client.ping().unwrap();
+}
+
+#[test]
+fn json_batch() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let mut serve = FooRPCServer::new(FooImpl::new(), JSONTransport::new(s2));
+        serve.serve()
+    });
+    let mut transport = JSONTransport::new(s1);
+    let mut batch = transport.begin_batch();
+    let mut calls = Vec::new();
+    for b in 42..45 {
+        let mut state = batch
+            .tx_begin_call(MethodId {
+                name: "bar",
+                num: 0,
+            })
+            .unwrap();
+        batch.tx_add_param("a", "the answer", &mut state).unwrap();
+        batch.tx_add_param("b", b, &mut state).unwrap();
+        calls.push((b, batch.tx_finalize(state).unwrap()));
+    }
+    assert_eq!(batch.len(), 3);
+    // Responses may be read in any order
+    for (b, call) in calls.into_iter().rev() {
+        let result: Result<String, TestError> = batch.rx_response(call).unwrap();
+        assert_eq!(format!("the answer is {}", b), result.unwrap());
+    }
+}
+
+// The messages a client sends and receives for a call to bar and
+// one to expect_error, over JSON-RPC or raw JSON
+fn recorded_foo_calls(raw: bool) -> Vec<serde_json::Value> {
+    let (c, s) = DuplexChannel::pair();
+    let server = if raw {
+        JSONTransport::raw(s)
+    } else {
+        JSONTransport::new(s)
+    };
+    thread::spawn(move || FooRPCServer::new(FooImpl::new(), server).serve_loop());
+    let transport = RecordingTransport::new(c, |c| {
+        if raw {
+            JSONTransport::raw(c)
+        } else {
+            JSONTransport::new(c)
+        }
+    });
+    let recorded = transport.recorded();
+    let client = FooRPCClient::new(transport);
+    assert_eq!(
+        client.bar("the answer".to_string(), 42).unwrap(),
+        "the answer is 42"
+    );
+    assert_eq!(client.expect_error().unwrap_err().msg, "iamerror");
+    let recorded = recorded.lock().unwrap();
+    recorded
+        .iter()
+        .map(|message| serde_json::from_slice(message).unwrap())
+        .collect()
+}
+
+#[test]
+fn json_rpc_envelope() {
+    let messages = recorded_foo_calls(false);
+    assert_eq!(messages.len(), 4);
+    assert_eq!(messages[0]["jsonrpc"], "2.0");
+    assert_eq!(messages[0]["method"], "bar");
+    assert!(!messages[0]["id"].is_null());
+    assert_eq!(
+        messages[1],
+        serde_json::json!({
+            "jsonrpc": "2.0",
+            "result": "the answer is 42",
+            "id": messages[0]["id"]
+        })
+    );
+}
+
+#[test]
+fn json_raw_envelope() {
+    let messages = recorded_foo_calls(true);
+    assert_eq!(
+        messages,
+        [
+            serde_json::json!({"method": "bar", "params": {"a": "the answer", "b": 42}}),
+            serde_json::json!({"result": "the answer is 42"}),
+            serde_json::json!({"method": "expect_error", "params": {}}),
+            serde_json::json!({
+                "error": {
+                    "code": -32000,
+                    "message": "application error",
+                    "data": {"msg": "iamerror"}
+                }
+            }),
+        ]
+    );
+
+    // A hand-written request to a raw server, which is answered
+    // although it has no id
+    let request = br#"{"method": "bar", "params": {"a": "raw", "b": 1}}"#;
+    let mut response = Vec::new();
+    FooRPCServer::new(
+        FooImpl::new(),
+        JSONTransport::raw(ReadWrite::new(&request[..], &mut response)),
+    )
+    .serve_single_call()
+    .unwrap();
+    assert_eq!(
+        serde_json::from_slice::<serde_json::Value>(&response).unwrap(),
+        serde_json::json!({"result": "raw is 1"})
+    );
+
+    // Batches need ids
+    let (c, _s) = DuplexChannel::pair();
+    let mut transport = JSONTransport::raw(c);
+    let mut batch = transport.begin_batch();
+    let state = batch
+        .tx_begin_call(MethodId {
+            name: "expect_error",
+            num: 1,
+        })
+        .unwrap();
+    let call = batch.tx_finalize(state).unwrap();
+    let e = batch
+        .rx_response::<Result<String, TestError>>(call)
+        .unwrap_err();
+    assert_eq!(e.kind, RPCErrorKind::IllegalState);
+}
+
+#[test]
+fn json_error_object() {
+    let request = br#"{"jsonrpc":"2.0","method":"expect_error","params":{},"id":7}"#;
+    let mut response = Vec::new();
+    let transport = JSONTransport::new(ReadWrite::new(&request[..], &mut response));
+    FooRPCServer::new(FooImpl::new(), transport)
+        .serve_single_call()
+        .unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&response).unwrap();
+    assert_eq!(response["id"], serde_json::json!(7));
+    assert!(response.get("result").is_none());
+    assert_eq!(response["error"]["code"], serde_json::json!(-32000));
+    assert_eq!(
+        response["error"]["data"]["msg"],
+        serde_json::json!("iamerror")
+    );
+}
+
+// Serve a single call from the requests, which may be preceded by
+// requests of the wrong JSON-RPC version, returning all the responses.
+fn json_serve_versions(requests: &[u8], strict: bool) -> Vec<serde_json::Value> {
+    let mut response = Vec::new();
+    let transport =
+        JSONTransport::new(ReadWrite::new(requests, &mut response)).with_strict_version(strict);
+    FooRPCServer::new(FooImpl::new(), transport)
+        .serve_single_call()
+        .unwrap();
+    serde_json::Deserializer::from_slice(&response)
+        .into_iter()
+        .map(|r| r.unwrap())
+        .collect()
+}
+
+#[test]
+fn json_version_missing() {
+    let requests = concat!(
+        r#"{"method":"bar","params":{"a":"x","b":1},"id":1}"#,
+        r#"{"jsonrpc":"2.0","method":"bar","params":{"a":"y","b":2},"id":2}"#
+    );
+    let responses = json_serve_versions(requests.as_bytes(), true);
+    assert_eq!(responses.len(), 2);
+    assert_eq!(responses[0]["id"], serde_json::json!(1));
+    assert_eq!(responses[0]["error"]["code"], serde_json::json!(-32600));
+    assert_eq!(responses[1]["result"], serde_json::json!("y is 2"));
+}
+
+#[test]
+fn json_version_wrong() {
+    let requests = concat!(
+        r#"{"jsonrpc":"1.0","method":"bar","params":{"a":"x","b":1},"id":1}"#,
+        r#"{"jsonrpc":2.0,"method":"bar","params":{"a":"x","b":1},"id":2}"#,
+        r#"{"jsonrpc":"2.0","method":"bar","params":{"a":"y","b":2},"id":3}"#
+    );
+    let responses = json_serve_versions(requests.as_bytes(), true);
+    assert_eq!(responses.len(), 3);
+    for (response, id) in responses[..2].iter().zip(1..) {
+        assert_eq!(response["id"], serde_json::json!(id));
+        assert_eq!(response["error"]["code"], serde_json::json!(-32600));
+    }
+    assert_eq!(responses[2]["result"], serde_json::json!("y is 2"));
+
+    // The client sees the error code
+    let response = serde_json::to_vec(&responses[0]).unwrap();
+    let mut transport = JSONTransport::new(ReadWrite::new(&response[..], Vec::new()));
+    match transport.rx_response::<Result<String, TestError>>(serde_json::json!(1)) {
+        Err(e) => assert_eq!(e.kind, RPCErrorKind::InvalidRequest),
+        Ok(_) => panic!("Should have gotten an error"),
+    }
+}
+
+#[test]
+fn json_version_lenient() {
+    let request = br#"{"jsonrpc":"1.0","method":"bar","params":{"a":"x","b":1},"id":1}"#;
+    let responses = json_serve_versions(request, false);
+    assert_eq!(responses.len(), 1);
+    assert_eq!(responses[0]["result"], serde_json::json!("x is 1"));
+
+    let (mut s1, s2) = UnixStream::pair().unwrap();
+    let mut server = JSONTransport::new(s2).with_strict_version(false);
+    s1.write_all(br#"{"method":"bar","params":{},"id":1}"#)
+        .unwrap();
+    let (_, state) = server.rx_begin_call().unwrap();
+    assert_eq!(state.version(), None);
+}
+
+#[test]
+fn json_foreign_error_object() {
+    // An error from a server which does not carry an essrpc error in `data`
+    let response = br#"{"jsonrpc":"2.0","error":{"code":-32099,"message":"boom"},"id":1}"#;
+    let mut transport = JSONTransport::new(ReadWrite::new(&response[..], Vec::new()));
+    match transport.rx_response::<Result<String, TestError>>(serde_json::json!(1)) {
+        Ok(_) => panic!("Should have generated an error"),
+        Err(e) => {
+            assert_eq!(e.kind, RPCErrorKind::Application(-32099));
+            assert_eq!(format!("{}", e), "application error -32099: boom");
+        }
+    }
+}
+
+#[test]
+fn json_unknown_method() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let mut serve = FooRPCServer::new(FooImpl::new(), JSONTransport::new(s2));
+        serve.serve()
+    });
+    let mut transport = JSONTransport::new(s1);
+    let state = transport
+        .tx_begin_call(MethodId {
+            name: "baz",
+            num: 7,
+        })
+        .unwrap();
+    let id = transport.tx_finalize(state).unwrap();
+    match transport.rx_response::<Result<String, TestError>>(id) {
+        Err(e) => assert_eq!(e.kind, RPCErrorKind::UnknownMethod),
+        Ok(_) => panic!("Should have gotten an error"),
+    }
+
+    // The server keeps serving after the unknown method
+    let foo = FooRPCClient::new(transport);
+    client42(&foo);
+}
+
+#[test]
+fn json_notification_has_no_id() {
+    let mut request = Vec::new();
+    {
+        // No response is read, so the empty input is never touched
+        let channel = ReadWrite::new(&[][..], &mut request);
+        let client = TelemetryRPCClient::new(JSONTransport::new(channel));
+        client.record(5).unwrap();
+    }
+    let request: serde_json::Value = serde_json::from_slice(&request).unwrap();
+    assert_eq!(request["method"], "record");
+    assert_eq!(request["params"]["sample"], 5);
+    assert!(request.get("id").is_none());
+}
+
+#[test]
+fn notifications_json() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let mut serve = TelemetryRPCServer::new(TelemetryImpl::default(), JSONTransport::new(s2));
+        serve.serve()
+    });
+    let client = TelemetryRPCClient::new(JSONTransport::new(s1));
+    client.record(1).unwrap();
+    client.record(2).unwrap();
+    assert_eq!(client.total().unwrap(), 3);
+}
+
+#[test]
+fn unit_return_json() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let mut serve = TelemetryRPCServer::new(TelemetryImpl::default(), JSONTransport::new(s2));
+        serve.serve()
+    });
+    let transport = RecordingTransport::new(s1, JSONTransport::new);
+    let recorded = transport.recorded();
+    let client = TelemetryRPCClient::new(transport);
+    client.record(5).unwrap();
+    client.reset().unwrap();
+    assert_eq!(client.total().unwrap(), 0);
+    // Unlike a notification, the call is answered, with a null result
+    let response: serde_json::Value = serde_json::from_slice(&recorded.lock().unwrap()[2]).unwrap();
+    assert!(response["result"].is_null());
+    assert!(response.get("error").is_none());
+}
+
+#[test]
+fn json_request_without_id_gets_no_response() {
+    // bar is an ordinary method, but a request without an id is a
+    // notification however the method is declared. The response to
+    // the second request must therefore be the only output.
+    let requests = concat!(
+        r#"{"jsonrpc":"2.0","method":"bar","params":{"a":"x","b":1}}"#,
+        r#"{"jsonrpc":"2.0","method":"nonexistent"}"#,
+        r#"{"jsonrpc":"2.0","method":"bar","params":{"a":"y","b":2},"id":3}"#
+    );
+    let mut response = Vec::new();
+    {
+        let channel = ReadWrite::new(requests.as_bytes(), &mut response);
+        let mut serve = FooRPCServer::new(FooImpl::new(), JSONTransport::new(channel));
+        for _ in 0..3 {
+            serve.serve_single_call().unwrap();
+        }
+    }
+    let response: serde_json::Value = serde_json::from_slice(&response).unwrap();
+    assert_eq!(response["result"], "y is 2");
+    assert_eq!(response["id"], 3);
+}
+
+#[test]
+fn json_corrupt_request() {
+    let codec = JSONCodec::new();
+    let decode = |request: &[u8]| match codec.decode_call(request) {
+        Ok(_) => panic!("request should have been refused"),
+        Err(e) => e,
+    };
+
+    // Invalid UTF-8 is quoted, replaced, in the error
+    let e = decode(b"{\"jsonrpc\":\"2.0\",\"method\":\"b\xffr\"}");
+    assert_eq!(e.kind, RPCErrorKind::ParseError);
+    assert!(e.to_string().contains("method"), "{}", e);
+    assert!(e.to_string().contains('\u{fffd}'), "{}", e);
+
+    // Long garbage is truncated
+    let garbage = vec![b'#'; 10_000];
+    let e = decode(&garbage);
+    assert_eq!(e.kind, RPCErrorKind::ParseError);
+    assert!(
+        e.to_string()
+            .contains(&format!("\"{}\"...", "#".repeat(64))),
+        "{}",
+        e
+    );
+    assert!(e.to_string().len() < 200, "{}", e);
+
+    let e = decode(br#"{"jsonrpc":"2.0","method":{"name":"bar"},"id":1}"#);
+    assert_eq!(e.kind, RPCErrorKind::InvalidRequest);
+    assert!(e.to_string().contains("name"), "{}", e);
+
+    let lenient = codec.with_strict_version(false);
+    let e = match lenient.decode_call(b"[1,2,3]") {
+        Ok(_) => panic!("request should have been refused"),
+        Err(e) => e,
+    };
+    assert_eq!(e.kind, RPCErrorKind::InvalidRequest);
+    assert!(e.to_string().contains("[1,2,3]"), "{}", e);
+}
+
+#[test]
+fn json_request_extra_members() {
+    let request = br#"{"meta":{"trace":"abc"},"id":1,"params":{"b":5,"a":"x"},"jsonrpc":"2.0","method":"bar","extra":[1,2]}"#;
+    let mut response = Vec::new();
+    {
+        let channel = ReadWrite::new(&request[..], &mut response);
+        let mut serve = FooRPCServer::new(FooImpl::new(), JSONTransport::new(channel));
+        serve.serve_single_call().unwrap();
+    }
+    let response: serde_json::Value = serde_json::from_slice(&response).unwrap();
+    assert_eq!(response["result"], "x is 5");
+    assert_eq!(response["id"], 1);
+}
+
+#[test]
+fn typed_remote_error() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || AccountRPCServer::new(AccountImpl, JSONTransport::new(s2)).serve());
+    let account = AccountRPCClient::new(JSONTransport::new(s1));
+    assert_eq!(account.withdraw(30), Ok(70));
+    assert_eq!(
+        account.withdraw(130),
+        Err(AccountError::Insufficient { balance: 100 })
+    );
+
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || AccountRPCServer::new(AccountImpl, BincodeTransport::new(s2)).serve());
+    let account = AccountRPCClient::new(BincodeTransport::new(s1));
+    assert_eq!(
+        account.withdraw(130),
+        Err(AccountError::Insufficient { balance: 100 })
+    );
+
+    // JSON carries the error in the data of a JSON-RPC error object
+    let request = br#"{"jsonrpc":"2.0","method":"withdraw","params":{"amount":130},"id":1}"#;
+    let mut response = Vec::new();
+    {
+        let channel = ReadWrite::new(&request[..], &mut response);
+        let mut serve = AccountRPCServer::new(AccountImpl, JSONTransport::new(channel));
+        serve.serve_single_call().unwrap();
+    }
+    let response: serde_json::Value = serde_json::from_slice(&response).unwrap();
+    assert_eq!(response["error"]["code"], -32000);
+    assert_eq!(
+        response["error"]["data"],
+        serde_json::json!({"Insufficient": {"balance": 100}})
+    );
+}
+
+#[cfg(feature = "fd_passing")]
+#[test]
+fn fd_passing() {
+    use std::io::Read;
+
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let transport = JSONTransport::new(FdChannel::new(s2));
+        SupervisorRPCServer::new(SupervisorImpl, transport).serve()
+    });
+    let supervisor = SupervisorRPCClient::new(JSONTransport::new(FdChannel::new(s1)));
+
+    // A descriptor sent as a parameter
+    let (ours, theirs) = UnixStream::pair().unwrap();
+    supervisor
+        .write_to(Fd::new(theirs.into()), "to the server".to_string())
+        .unwrap();
+    let mut text = String::new();
+    ours.take(13).read_to_string(&mut text).unwrap();
+    assert_eq!(text, "to the server");
+
+    // And one returned
+    for greeting in &["hello", "again"] {
+        let fd = supervisor.open_socket(greeting.to_string()).unwrap();
+        let mut text = String::new();
+        UnixStream::from(fd.into_inner())
+            .take(5)
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(&text, greeting);
+    }
+}
+
+#[test]
+fn json_sequential_ids() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let mut serve = FooRPCServer::new(FooImpl::new(), JSONTransport::new(s2));
+        serve.serve()
+    });
+    let foo = FooRPCClient::new(JSONTransport::new(s1).use_sequential_ids());
+    client42(&foo);
+    client42(&foo);
+
+    let transport =
+        JSONTransport::new(ReadWrite::new(io::empty(), io::sink())).use_sequential_ids();
+    let id = |transport: &JSONTransport<_>| {
+        let request = transport
+            .encode_call(
+                MethodId {
+                    name: "expect_error",
+                    num: 1,
+                },
+                &[],
+            )
+            .unwrap();
+        serde_json::from_slice::<serde_json::Value>(&request).unwrap()["id"].clone()
+    };
+    assert_eq!(id(&transport), 1);
+    assert_eq!(id(&transport), 2);
+}
+
+#[test]
+fn json_default_ids() {
+    let transport = JSONTransport::new(ReadWrite::new(io::empty(), io::sink()));
+    let request = transport
+        .encode_call(
+            MethodId {
+                name: "expect_error",
+                num: 1,
+            },
+            &[],
+        )
+        .unwrap();
+    let id = serde_json::from_slice::<serde_json::Value>(&request).unwrap()["id"].clone();
+    if cfg!(feature = "uuid_ids") {
+        assert_eq!(id.as_str().unwrap().len(), 36, "{}", id);
+    } else {
+        assert_eq!(id, 1);
+    }
+}
+
+#[test]
+fn json_id_generator() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    let mut request = Vec::new();
+    thread::spawn(move || {
+        let mut serve = FooRPCServer::new(FooImpl::new(), JSONTransport::new(s2));
+        serve.serve()
+    });
+    let mut transport = JSONTransport::new(s1);
+    let mut n = 0;
+    transport.set_id_generator(Box::new(move || {
+        n += 1;
+        serde_json::json!(format!("req-{}", n))
+    }));
+    let foo = FooRPCClient::new(transport);
+    client42(&foo);
+
+    {
+        let mut transport = JSONTransport::new(ReadWrite::new(&[][..], &mut request));
+        transport.set_id_generator(Box::new(|| serde_json::json!({"seq": 9})));
+        let foo = FooRPCClient::new(transport);
+        assert!(foo.bar("x".to_string(), 1).is_err());
+    }
+    let request: serde_json::Value = serde_json::from_slice(&request).unwrap();
+    assert_eq!(request["id"], serde_json::json!({"seq": 9}));
+}
+
+#[test]
+fn json_params_in_any_order() {
+    // The server reads parameters in declaration order, but finds
+    // JSON's named parameters wherever they are in the object,
+    // including the type tag of a generic method. The requests are
+    // written out because json! would sort their members.
+    let string = std::any::type_name::<String>();
+    let data = format!(
+        concat!(
+            r#"{{"jsonrpc":"2.0","id":1,"method":"pair","params":{{"value":true,"key":"k","<K>":"{0}","<V>":"bool"}}}}"#,
+            r#"{{"jsonrpc":"2.0","id":2,"method":"pair","params":{{"<V>":"i32","value":7,"<K>":"{0}","key":"j"}}}}"#,
+        ),
+        string
+    );
+    let mut response = Vec::new();
+    {
+        let channel = ReadWrite::new(data.as_bytes(), &mut response);
+        let mut serve = EchoRPCServer::new(EchoImpl, JSONTransport::new(channel));
+        serve.serve_single_call().unwrap();
+        serve.serve_single_call().unwrap();
+    }
+    let responses: Vec<serde_json::Value> = serde_json::Deserializer::from_slice(&response)
+        .into_iter()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(responses[0]["result"], "\"k\"=true");
+    assert_eq!(responses[1]["result"], "\"j\"=7");
+}
+
+#[test]
+fn json_lazy_params() {
+    // Named parameters in any order, positional ones, an invalid
+    // one, a ping and a batch, which is served as usual
+    let data = concat!(
+        r#"{"jsonrpc":"2.0","id":1,"method":"bar","params":{"b":42,"a":"the answer"}}"#,
+        r#"{"jsonrpc":"2.0","id":2,"method":"bar","params":["the answer",7]}"#,
+        r#"{"params":{"a":"the answer","b":"x"},"jsonrpc":"2.0","id":3,"method":"bar"}"#,
+        r#"{"jsonrpc":"2.0","id":4,"method":"rpc.ping"}"#,
+        r#"[{"jsonrpc":"2.0","id":5,"method":"bar","params":{"a":"one","b":1}}]"#,
+    );
+    let (seen, params) = std::sync::mpsc::channel();
+    let mut response = Vec::new();
+    {
+        let transport = JSONTransport::new(ReadWrite::new(data.as_bytes(), &mut response))
+            .with_lazy_params(true)
+            .on_call(move |state| seen.send(state.params().cloned()).unwrap());
+        let mut serve = FooRPCServer::new(FooImpl::new(), transport);
+        serve.serve_single_call().unwrap();
+        serve.serve_single_call().unwrap();
+        let err = serve.serve_single_call().unwrap_err();
+        assert_eq!(err.kind, RPCErrorKind::InvalidParams);
+        assert_eq!(err.param(), Some("b"));
+        serve.serve_single_call().unwrap();
+    }
+    let responses: Vec<serde_json::Value> = serde_json::Deserializer::from_slice(&response)
+        .into_iter()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(responses[0]["result"], "the answer is 42");
+    assert_eq!(responses[1]["result"], "the answer is 7");
+    assert_eq!(responses[2]["id"], 3);
+    assert_eq!(responses[2]["error"]["data"]["param"], "b");
+    assert_eq!(responses[3]["result"], "pong");
+    assert_eq!(responses[4][0]["result"], "one is 1");
+    assert_eq!(
+        params.try_iter().collect::<Vec<_>>(),
+        vec![
+            Some(serde_json::json!({"a": "the answer", "b": 42})),
+            Some(serde_json::json!(["the answer", 7])),
+            Some(serde_json::json!({"a": "the answer", "b": "x"})),
+            Some(serde_json::json!({"a": "one", "b": 1})),
+        ]
+    );
+
+    // The depth limit counts the params as part of the request
+    let codec = JSONCodec::new().with_lazy_params(true).with_max_depth(3);
+    let request = br#"{"jsonrpc":"2.0","id":1,"method":"bar","params":{"a":"x","b":[1]}}"#;
+    let (_, mut state) = codec.decode_call(request).unwrap();
+    let err = codec.read_param::<i32>("b", &mut state).unwrap_err();
+    assert_eq!(err.kind, RPCErrorKind::InvalidParams);
+    let request = br#"{"jsonrpc":"2.0","id":1,"method":"bar","params":{"a":"x","b":[[1]]}}"#;
+    let err = codec.decode_call(request).err().unwrap();
+    assert_eq!(err.kind, RPCErrorKind::SerializationError);
+}
+
+#[test]
+fn json_raw_value_params() {
+    use serde_json::value::RawValue;
+
+    // Opaque params are sent as their text stands, not reparsed
+    let raw = r#"{"b": 1.000000000000000001, "a":[ 1 ]}"#;
+    let bar = MethodId {
+        name: "bar",
+        num: 0,
+    };
+    let mut request = Vec::new();
+    {
+        let channel = ReadWrite::new(&[][..], &mut request);
+        let mut transport = JSONTransport::new(channel);
+        let mut state = transport.tx_begin_call(bar).unwrap();
+        transport
+            .tx_add_param(
+                "a",
+                RawValue::from_string(raw.to_string()).unwrap(),
+                &mut state,
+            )
+            .unwrap();
+        transport.tx_add_param("b", 42, &mut state).unwrap();
+        transport.tx_finalize(state).unwrap();
+    }
+    let text = String::from_utf8(request.clone()).unwrap();
+    assert!(text.contains(&format!(r#""a":{}"#, raw)), "{}", text);
+
+    let positional = JSONCodec::positional();
+    let mut state = positional.begin_call(MethodId {
+        name: "bar",
+        num: 0,
+    });
+    let value: &RawValue = serde_json::from_str(raw).unwrap();
+    positional.add_param("a", value, &mut state).unwrap();
+    positional.add_param("b", 42, &mut state).unwrap();
+    let (bytes, _) = positional.encode_call(&state).unwrap();
+    let text = String::from_utf8(bytes).unwrap();
+    assert!(
+        text.contains(&format!(r#""params":[{},42]"#, raw)),
+        "{}",
+        text
+    );
+
+    // Lazily read params keep their text on the way in too
+    let server = JSONCodec::new().with_lazy_params(true);
+    let (_, mut call) = server.decode_call(&request).unwrap();
+    let a: Box<RawValue> = server.read_param("a", &mut call).unwrap();
+    assert_eq!(a.get(), raw);
+    assert_eq!(server.read_param::<i32>("b", &mut call).unwrap(), 42);
+    let (_, mut call) = JSONCodec::new().decode_call(&request).unwrap();
+    let a: Box<RawValue> = JSONCodec::new().read_param("a", &mut call).unwrap();
+    let a: serde_json::Value = serde_json::from_str(a.get()).unwrap();
+    assert_eq!(a["a"], serde_json::json!([1]));
+}
+
+#[test]
+fn json_non_finite_floats() {
+    fn scaler(floats: NonFiniteFloats) -> ScalerRPCClient<JSONTransport<UnixStream>> {
+        let (s1, s2) = UnixStream::pair().unwrap();
+        thread::spawn(move || {
+            let transport = JSONTransport::new(s2).with_non_finite_floats(floats);
+            ScalerRPCServer::new(ScalerImpl, transport).serve()
+        });
+        ScalerRPCClient::new(JSONTransport::new(s1).with_non_finite_floats(floats))
+    }
+
+    let strings = scaler(NonFiniteFloats::Strings);
+    assert!(strings.scale(f64::NAN, 2.0).unwrap().is_nan());
+    assert_eq!(strings.scale(f64::INFINITY, 2.0).unwrap(), f64::INFINITY);
+    assert_eq!(
+        strings.scale(2.0, f64::NEG_INFINITY).unwrap(),
+        f64::NEG_INFINITY
+    );
+    assert_eq!(strings.scale(1.5, 2.0).unwrap(), 3.0);
+
+    // null, as serde_json sends them, is read back as NaN
+    let nulls = scaler(NonFiniteFloats::Null);
+    assert!(nulls.scale(f64::NAN, 2.0).unwrap().is_nan());
+    assert!(nulls.scale(f64::INFINITY, 2.0).unwrap().is_nan());
+
+    let codec = JSONCodec::new().with_non_finite_floats(NonFiniteFloats::Strings);
+    let mut state = codec.begin_call(MethodId {
+        name: "scale",
+        num: 0,
+    });
+    codec
+        .add_param("x", vec![1.5, f64::NAN], &mut state)
+        .unwrap();
+    codec
+        .add_param("factor", f64::NEG_INFINITY, &mut state)
+        .unwrap();
+    let (request, _) = codec.encode_call(&state).unwrap();
+    let text = String::from_utf8(request.clone()).unwrap();
+    assert!(text.contains(r#""x":[1.5,"NaN"]"#), "{}", text);
+    assert!(text.contains(r#""factor":"-Infinity""#), "{}", text);
+    // Read the same way whether params are parsed up front or lazily
+    for codec in [codec.clone(), codec.with_lazy_params(true)] {
+        let (_, mut call) = codec.decode_call(&request).unwrap();
+        let x: Vec<f64> = codec.read_param("x", &mut call).unwrap();
+        assert_eq!(x[0], 1.5);
+        assert!(x[1].is_nan());
+        let factor: f64 = codec.read_param("factor", &mut call).unwrap();
+        assert_eq!(factor, f64::NEG_INFINITY);
+    }
+    // Strings which name no float are still refused
+    let codec = JSONCodec::new().with_non_finite_floats(NonFiniteFloats::Strings);
+    let (_, mut call) = codec
+        .decode_call(br#"{"jsonrpc":"2.0","method":"scale","params":{"x":"nan"},"id":1}"#)
+        .unwrap();
+    let e = codec.read_param::<f64>("x", &mut call).unwrap_err();
+    assert_eq!(e.kind, RPCErrorKind::InvalidParams);
+
+    let refusing = JSONCodec::new().with_non_finite_floats(NonFiniteFloats::Error);
+    let mut state = refusing.begin_call(MethodId {
+        name: "scale",
+        num: 0,
+    });
+    let e = refusing
+        .add_param("x", f64::INFINITY, &mut state)
+        .unwrap_err();
+    assert_eq!(e.kind, RPCErrorKind::SerializationError);
+    refusing.add_param("x", 1.5, &mut state).unwrap();
+    let (_, mut call) = refusing
+        .decode_call(br#"{"jsonrpc":"2.0","method":"scale","params":{"x":null},"id":1}"#)
+        .unwrap();
+    let e = refusing.read_param::<f64>("x", &mut call).unwrap_err();
+    assert_eq!(e.kind, RPCErrorKind::InvalidParams);
+}
+
+#[test]
+fn notifications_bincode() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let mut serve =
+            TelemetryRPCServer::new(TelemetryImpl::default(), BincodeTransport::new(s2));
+        serve.serve()
+    });
+    let client = TelemetryRPCClient::new(BincodeTransport::new(s1));
+    client.record(1).unwrap();
+    client.record(2).unwrap();
+    assert_eq!(client.total().unwrap(), 3);
+    client.reset().unwrap();
+    assert_eq!(client.total().unwrap(), 0);
+}
+
+#[test]
+fn json_batch_with_notification() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let mut serve = TelemetryRPCServer::new(TelemetryImpl::default(), JSONTransport::new(s2));
+        serve.serve()
+    });
+    let mut transport = JSONTransport::new(s1);
+    let mut batch = transport.begin_batch();
+    let mut state = batch
+        .tx_begin_call(MethodId {
+            name: "record",
+            num: 0,
+        })
+        .unwrap();
+    batch.tx_add_param("sample", 7, &mut state).unwrap();
+    batch.tx_notify(state).unwrap();
+    let state = batch
+        .tx_begin_call(MethodId {
+            name: "total",
+            num: 1,
+        })
+        .unwrap();
+    let call = batch.tx_finalize(state).unwrap();
+    let total: Result<i32, TestError> = batch.rx_response(call).unwrap();
+    assert_eq!(total.unwrap(), 7);
+}
+
+#[test]
+fn json_echoes_request_id() {
+    let request = serde_json::to_vec(&json_request(false)).unwrap();
+    let mut response = Vec::new();
+    let transport = JSONTransport::new(ReadWrite::new(&request[..], &mut response));
+    FooRPCServer::new(FooImpl::new(), transport)
+        .serve_single_call()
+        .unwrap();
+    let request: serde_json::Value = serde_json::from_slice(&request).unwrap();
+    let response: serde_json::Value = serde_json::from_slice(&response).unwrap();
+    assert!(!request["id"].is_null());
+    assert_eq!(request["id"], response["id"]);
+    assert_eq!(response["jsonrpc"], serde_json::json!("2.0"));
+    assert_eq!(response["result"], serde_json::json!("the answer is 42"));
+}
+
+#[test]
+fn json_rejects_mismatched_id() {
+    let response = br#"{"jsonrpc":"2.0","result":"the answer is 42","id":2}"#;
+    let mut transport = JSONTransport::new(ReadWrite::new(&response[..], Vec::new()));
+    let result = transport.rx_response::<Result<String, TestError>>(serde_json::json!(1));
+    assert_eq!(result.unwrap_err().kind, RPCErrorKind::SerializationError);
+}
+
+#[test]
+fn json_broken_pipe_is_eof() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    drop(s2);
+    let mut transport = JSONTransport::new(s1);
+    let state = transport
+        .tx_begin_call(MethodId {
+            name: "bar",
+            num: 0,
+        })
+        .unwrap();
+    let e = transport.tx_finalize(state).unwrap_err();
+    assert_eq!(e.kind, RPCErrorKind::TransportEOF);
+    assert!(std::error::Error::source(&e).is_some());
+}
+
+#[test]
+fn json_malformed_is_parse_error() {
+    let response = br#"{"jsonrpc":"2.0","result":]"#;
+    let mut transport = JSONTransport::new(ReadWrite::new(&response[..], Vec::new()));
+    let result = transport.rx_response::<Result<String, TestError>>(serde_json::json!(1));
+    assert_eq!(result.unwrap_err().kind, RPCErrorKind::ParseError);
+}
+
+#[test]
+fn server_error_names_method() {
+    for request in &[
+        r#"{"jsonrpc":"2.0","method":"bar","params":{"a":"x"},"id":1}"#,
+        r#"{"jsonrpc":"2.0","method":0,"params":{"a":"x"},"id":1}"#,
+    ] {
+        let transport = JSONTransport::new(ReadWrite::new(request.as_bytes(), io::sink()));
+        let err = FooRPCServer::new(FooImpl::new(), transport)
+            .serve_single_call()
+            .unwrap_err();
+        assert_eq!(err.method(), Some("bar"));
+        assert!(format!("{}", err).starts_with("in method bar: "), "{}", err);
+    }
+
+    // Errors reading the next call are not attributed to any method
+    let transport = JSONTransport::new(ReadWrite::new(&b""[..], io::sink()));
+    let err = FooRPCServer::new(FooImpl::new(), transport)
+        .serve_single_call()
+        .unwrap_err();
+    assert_eq!(err.method(), None);
+}
+
+#[test]
+fn json_framed_multiple() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let mut serve = FooRPCServer::new(FooImpl::new(), JSONTransport::framed(s2));
+        serve.serve()
+    });
+    let foo = FooRPCClient::new(JSONTransport::framed(s1));
+    for b in 42..45 {
+        match foo.bar("the answer".to_string(), b) {
+            Ok(result) => assert_eq!(format!("the answer is {}", b), result),
+            Err(e) => panic!("error: {:?}", e),
+        }
+    }
+}
+
+#[test]
+fn json_framed_wire_format() {
+    // Trailing whitespace inside a frame does not desync the stream
+    let body = br#"{"jsonrpc":"2.0","method":"bar","params":{"a":"x","b":1},"id":1}  "#;
+    let mut request = (body.len() as u32).to_be_bytes().to_vec();
+    request.extend_from_slice(body);
+    let mut response = Vec::new();
+    let transport = JSONTransport::framed(ReadWrite::new(&request[..], &mut response));
+    FooRPCServer::new(FooImpl::new(), transport)
+        .serve_single_call()
+        .unwrap();
+    let len = u32::from_be_bytes([response[0], response[1], response[2], response[3]]);
+    assert_eq!(len as usize, response.len() - 4);
+    let response: serde_json::Value = serde_json::from_slice(&response[4..]).unwrap();
+    assert_eq!(response["result"], serde_json::json!("x is 1"));
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn json_framed_compressed() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let transport = JSONTransport::framed_compressed(s2, 64);
+        FooRPCServer::new(FooImpl::new(), transport).serve()
+    });
+    let foo = FooRPCClient::new(JSONTransport::framed_compressed(s1, 64));
+    client42(&foo);
+    let long = "x".repeat(10_000);
+    assert_eq!(foo.bar(long.clone(), 1).unwrap(), format!("{} is 1", long));
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn json_framed_compressed_wire_format() {
+    let bar = |a: &str| {
+        let transport =
+            JSONTransport::framed_compressed(ReadWrite::new(io::empty(), io::sink()), 256);
+        let params = [("a", serde_json::json!(a)), ("b", serde_json::json!(1))];
+        transport
+            .encode_call(
+                MethodId {
+                    name: "bar",
+                    num: 0,
+                },
+                &params,
+            )
+            .unwrap()
+    };
+
+    // A short request is sent as it is, after its flags and length
+    let request = bar("x");
+    assert_eq!(request[0], 0);
+    let len = u32::from_be_bytes([request[1], request[2], request[3], request[4]]);
+    assert_eq!(len as usize, request.len() - 5);
+    assert!(serde_json::from_slice::<serde_json::Value>(&request[5..]).is_ok());
+
+    // A long one is compressed, as is its response
+    let long = "x".repeat(10_000);
+    let request = bar(&long);
+    assert_eq!(request[0], 1);
+    assert!(request.len() < 1_000, "{}", request.len());
+    let mut response = Vec::new();
+    let transport =
+        JSONTransport::framed_compressed(ReadWrite::new(&request[..], &mut response), 256);
+    FooRPCServer::new(FooImpl::new(), transport)
+        .serve_single_call()
+        .unwrap();
+    assert_eq!(response[0], 1);
+    let mut transport =
+        JSONTransport::framed_compressed(ReadWrite::new(&response[..], io::sink()), 256);
+    let id = transport.decode_call(&request).unwrap().1.id().clone();
+    let result = transport.rx_response::<Result<String, TestError>>(id);
+    assert_eq!(result.unwrap().unwrap(), format!("{} is 1", long));
+
+    // The limit on message size applies once decompressed
+    let transport = JSONTransport::framed_compressed(ReadWrite::new(&request[..], io::sink()), 256)
+        .with_max_message_bytes(1024);
+    let err = FooRPCServer::new(FooImpl::new(), transport)
+        .serve_single_call()
+        .unwrap_err();
+    assert_eq!(err.kind, RPCErrorKind::MessageTooLarge);
+}
+
+#[test]
+fn json_max_message_bytes() {
+    let (mut s1, s2) = UnixStream::pair().unwrap();
+    let transport = JSONTransport::new(s2).with_max_message_bytes(1024);
+    let mut serve = FooRPCServer::new(FooImpl::new(), transport);
+    // A message which never ends fails once the limit is reached,
+    // rather than waiting for the rest of it
+    s1.write_all(br#"{"jsonrpc":"2.0","method":"bar","params":{"a":""#)
+        .unwrap();
+    s1.write_all(&[b'x'; 4096]).unwrap();
+    let err = serve.serve_single_call().unwrap_err();
+    assert_eq!(err.kind, RPCErrorKind::MessageTooLarge);
+
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let transport = JSONTransport::new(s2).with_max_message_bytes(1024);
+        FooRPCServer::new(FooImpl::new(), transport).serve_loop()
+    });
+    let foo = FooRPCClient::new(JSONTransport::new(s1));
+    client42(&foo);
+}
+
+// Serve a single call made of `request`, returning the error.
+fn json_serve_malformed(request: &[u8], max_depth: Option<usize>) -> essrpc::RPCError {
+    let mut transport = JSONTransport::new(ReadWrite::new(request, io::sink()));
+    if let Some(depth) = max_depth {
+        transport = transport.with_max_depth(depth);
+    }
+    FooRPCServer::new(FooImpl::new(), transport)
+        .serve_single_call()
+        .unwrap_err()
+}
+
+#[test]
+fn json_deeply_nested() {
+    // Refused rather than overflowing the stack
+    let request = "[".repeat(100_000);
+    let err = json_serve_malformed(request.as_bytes(), None);
+    assert_eq!(err.kind, RPCErrorKind::ParseError);
+    let request = format!(
+        r#"{{"jsonrpc":"2.0","id":1,"method":"bar","params":{{"a":{}"x"{},"b":1}}}}"#,
+        "[".repeat(100_000),
+        "]".repeat(100_000)
+    );
+    let err = json_serve_malformed(request.as_bytes(), None);
+    assert_eq!(err.kind, RPCErrorKind::ParseError);
+}
+
+#[test]
+fn json_max_depth() {
+    let request =
+        br#"{"jsonrpc":"2.0","id":1,"method":"bar","params":{"a":"x","b":1},"extra":[[]]}"#;
+    let err = json_serve_malformed(request, Some(2));
+    assert_eq!(err.kind, RPCErrorKind::SerializationError);
+    assert!(err.to_string().contains("nested"), "{}", err);
+
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let transport = JSONTransport::new(s2).with_max_depth(2);
+        FooRPCServer::new(FooImpl::new(), transport).serve_loop()
+    });
+    client42(&FooRPCClient::new(JSONTransport::new(s1)));
+}
+
+#[test]
+fn json_truncated_request() {
+    let request =
+        br#"{"jsonrpc":"2.0","id":1,"method":"bar","params":{"a":"x","b":[1.5e3,true,null]}}"#;
+    for end in 0..request.len() {
+        let err = json_serve_malformed(&request[..end], None);
+        assert!(err.is_eof(), "{}: {}", end, err);
+    }
+    for garbage in [&b"}"[..], br#"{"id":01}"#, br#"{"a" 1}"#, b"\xff\xfe"] {
+        let err = json_serve_malformed(garbage, None);
+        assert_eq!(err.kind, RPCErrorKind::ParseError, "{:?}", garbage);
+    }
+}
+
+// Serve a single call made of `request`, returning the error and
+// the server's response.
+fn json_serve_invalid(request: &[u8]) -> (essrpc::RPCError, serde_json::Value) {
+    let mut response = Vec::new();
+    let transport = JSONTransport::new(ReadWrite::new(request, &mut response));
+    let err = FooRPCServer::new(FooImpl::new(), transport)
+        .serve_single_call()
+        .unwrap_err();
+    (err, serde_json::from_slice(&response).unwrap())
+}
+
+#[test]
+fn json_parse_error_response() {
+    let (err, response) = json_serve_invalid(br#"{"jsonrpc":"2.0","method":"bar","params":]"#);
+    assert_eq!(err.kind, RPCErrorKind::ParseError);
+    assert_eq!(response["error"]["code"], serde_json::json!(-32700));
+    assert_eq!(response["id"], serde_json::Value::Null);
+}
+
+#[test]
+fn json_invalid_request_response() {
+    for (request, id) in [
+        (
+            &br#"{"jsonrpc":"2.0","params":{},"id":4}"#[..],
+            serde_json::json!(4),
+        ),
+        (
+            br#"{"jsonrpc":"2.0","method":true,"id":5}"#,
+            serde_json::json!(5),
+        ),
+        (b"[]", serde_json::Value::Null),
+    ] {
+        let (err, response) = json_serve_invalid(request);
+        assert_eq!(err.kind, RPCErrorKind::InvalidRequest, "{:?}", request);
+        assert_eq!(response["error"]["code"], serde_json::json!(-32600));
+        assert_eq!(response["id"], id);
+    }
+
+    // Clients see the kinds of either error
+    let response = br#"{"jsonrpc":"2.0","error":{"code":-32700,"message":"bad"},"id":null}"#;
+    let mut transport = JSONTransport::new(ReadWrite::new(&response[..], Vec::new()));
+    let err = transport
+        .rx_response::<Result<String, TestError>>(serde_json::json!(1))
+        .unwrap_err();
+    assert_eq!(err.kind, RPCErrorKind::ParseError);
+}
+
+#[test]
+fn json_invalid_params_response() {
+    for request in [
+        &br#"{"jsonrpc":"2.0","method":"bar","params":{"a":"x","b":"42"},"id":1}"#[..],
+        br#"{"jsonrpc":"2.0","method":"bar","params":["x","42"],"id":1}"#,
+    ] {
+        let (err, response) = json_serve_invalid(request);
+        assert_eq!(err.kind, RPCErrorKind::InvalidParams, "{:?}", request);
+        assert_eq!(err.param(), Some("b"));
+        assert_eq!(response["error"]["code"], serde_json::json!(-32602));
+        let data = &response["error"]["data"];
+        assert_eq!(data["param"], "b");
+        assert_eq!(data["reason"], "invalid");
+        assert!(
+            data["detail"].as_str().unwrap().contains("invalid type"),
+            "{}",
+            data
+        );
+    }
+
+    let request = br#"{"jsonrpc":"2.0","method":"bar","params":{"a":"x"},"id":1}"#;
+    let (err, response) = json_serve_invalid(request);
+    assert_eq!(err.kind, RPCErrorKind::InvalidParams);
+    assert_eq!(response["error"]["code"], serde_json::json!(-32602));
+    assert_eq!(
+        response["error"]["data"],
+        serde_json::json!({"param": "b", "reason": "missing"})
+    );
+}
+
+// Server transport reading each call as lines: the method number,
+// then each param as json, then an empty line ending the request.
+// Params are read from the channel as they go, so one which fails
+// leaves the rest of its request to be skipped.
+struct LineTransport<R: io::BufRead> {
+    lines: R,
+    responses: std::rc::Rc<std::cell::RefCell<Vec<String>>>,
+}
+
+impl<R: io::BufRead> LineTransport<R> {
+    fn read_line(&mut self) -> Result<String, essrpc::RPCError> {
+        let mut line = String::new();
+        match self.lines.read_line(&mut line) {
+            Ok(0) => Err(essrpc::RPCError::new(RPCErrorKind::TransportEOF, "eof")),
+            Ok(_) => Ok(line.trim_end().to_string()),
+            Err(e) => Err(essrpc::RPCError::with_cause(
+                RPCErrorKind::TransportError,
+                "read failed",
+                e,
+            )),
+        }
+    }
+}
+
+impl<R: io::BufRead> ServerTransport for LineTransport<R> {
+    type RXState = ();
+
+    fn rx_begin_call(&mut self) -> Result<(PartialMethodId, ()), essrpc::RPCError> {
+        let num = self.read_line()?.parse().map_err(|_| {
+            essrpc::RPCError::new(RPCErrorKind::InvalidRequest, "bad method number")
+        })?;
+        Ok((PartialMethodId::Num(num), ()))
+    }
+
+    fn rx_read_param<T>(
+        &mut self,
+        name: &'static str,
+        _state: &mut (),
+    ) -> Result<T, essrpc::RPCError>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        serde_json::from_str(&self.read_line()?)
+            .map_err(|e| essrpc::RPCError::with_cause(RPCErrorKind::InvalidParams, name, e))
+    }
+
+    fn tx_response(
+        &mut self,
+        value: Result<impl Serialize, impl Serialize>,
+        _state: (),
+    ) -> Result<(), essrpc::RPCError> {
+        self.read_line()?;
+        let response = serde_json::to_string(&value).unwrap();
+        self.responses.borrow_mut().push(response);
+        Ok(())
+    }
+
+    fn discard_remaining(&mut self, _state: &mut ()) -> Result<(), essrpc::RPCError> {
+        while !self.read_line()?.is_empty() {}
+        Ok(())
+    }
+}
+
+#[test]
+fn discard_remaining_after_bad_param() {
+    // The first call's a is not a string, leaving its b unread
+    let requests = "0\n1\n2\n\n0\n\"the answer\"\n42\n\n";
+    let transport = LineTransport {
+        lines: requests.as_bytes(),
+        responses: Default::default(),
+    };
+    let responses = transport.responses.clone();
+    let mut serve = FooRPCServer::new(FooImpl::new(), transport);
+    let err = serve.serve_single_call().unwrap_err();
+    assert_eq!(err.kind, RPCErrorKind::InvalidParams);
+    // The next call is read from its start
+    serve.serve_single_call().unwrap();
+    assert_eq!(*responses.borrow(), [r#"{"Ok":"the answer is 42"}"#]);
+    assert!(serve.serve_single_call().unwrap_err().is_eof());
+
+    // Bincode cannot tell where a request ends
+    let mut transport = BincodeTransport::new(ReadWrite::new(io::empty(), io::sink()));
+    let err = ServerTransport::discard_remaining(&mut transport, &mut ()).unwrap_err();
+    assert_eq!(err.kind, RPCErrorKind::TransportError);
+}
+
+#[test]
+fn json_retry_cloned_state() {
+    // The first connection is gone by the time the call is sent
+    let (s1, s2) = UnixStream::pair().unwrap();
+    drop(s2);
+    let mut transport = JSONTransport::new(s1);
+    let mut state = transport
+        .tx_begin_call(MethodId {
+            name: "bar",
+            num: 0,
+        })
+        .unwrap();
+    transport
+        .tx_add_param("a", "the answer", &mut state)
+        .unwrap();
+    transport.tx_add_param("b", 42, &mut state).unwrap();
+    let retry = state.clone();
+    assert!(transport.tx_finalize(state).is_err());
+
+    // The same call goes through on a fresh one
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || FooRPCServer::new(FooImpl::new(), JSONTransport::new(s2)).serve());
+    let mut transport = JSONTransport::new(s1);
+    let id = transport.tx_finalize(retry).unwrap();
+    let result = transport.rx_response::<Result<String, TestError>>(id);
+    assert_eq!(result.unwrap().unwrap(), "the answer is 42");
+}
+
+#[test]
+fn json_invalid_params_client() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let mut serve = FooRPCServer::new(FooImpl::new(), JSONTransport::new(s2));
+        serve.serve()
+    });
+    let mut transport = JSONTransport::new(s1);
+    let mut state = transport
+        .tx_begin_call(MethodId {
+            name: "bar",
+            num: 0,
+        })
+        .unwrap();
+    transport
+        .tx_add_param("a", "the answer", &mut state)
+        .unwrap();
+    transport.tx_add_param("b", "42", &mut state).unwrap();
+    let id = transport.tx_finalize(state).unwrap();
+    match transport.rx_response::<Result<String, TestError>>(id) {
+        Err(e) => {
+            assert_eq!(e.kind, RPCErrorKind::InvalidParams);
+            assert_eq!(e.param(), Some("b"));
+        }
+        Ok(_) => panic!("Should have gotten an error"),
+    }
+}
+
+fn bar_call<C: ClientTransport>(transport: &mut C, b: i32) -> C::FinalState {
+    let mut state = transport
+        .tx_begin_call(MethodId {
+            name: "bar",
+            num: 0,
+        })
+        .unwrap();
+    transport
+        .tx_add_param("a", "the answer", &mut state)
+        .unwrap();
+    transport.tx_add_param("b", b, &mut state).unwrap();
+    transport.tx_finalize(state).unwrap()
+}
+
+#[test]
+fn json_manual_flush() {
+    let mut transport = JSONTransport::new(ReadWrite::new(&[][..], Vec::new()));
+    transport.set_auto_flush(false);
+    bar_call(&mut transport, 1);
+    bar_call(&mut transport, 2);
+    assert!(transport.channel().writable().is_empty());
+    transport.flush().unwrap();
+    let written = transport.channel().writable();
+    let requests = serde_json::Deserializer::from_slice(written).into_iter::<serde_json::Value>();
+    assert_eq!(requests.count(), 2);
+
+    // Calls are flushed before waiting for their responses
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let mut transport = JSONTransport::new(s2);
+        transport.set_auto_flush(false);
+        FooRPCServer::new(FooImpl::new(), transport).serve_loop()
+    });
+    let mut transport = JSONTransport::new(s1);
+    transport.set_auto_flush(false);
+    let ids: Vec<_> = (0..3).map(|b| bar_call(&mut transport, b)).collect();
+    for (b, id) in ids.into_iter().enumerate() {
+        let result: Result<String, TestError> = transport.rx_response(id).unwrap();
+        assert_eq!(result.unwrap(), format!("the answer is {}", b));
+    }
+    client42(&FooRPCClient::new(transport));
+}
+
+#[test]
+fn json_flush_on_drop() {
+    // Calls held without auto flush are sent when the transport is
+    // dropped or its channel taken
+    let (s1, s2) = UnixStream::pair().unwrap();
+    let mut transport = JSONTransport::framed(s1);
+    transport.set_auto_flush(false);
+    bar_call(&mut transport, 1);
+    drop(transport);
+    let mut server = JSONTransport::framed(s2);
+    let (method, _) = server.rx_begin_call().unwrap();
+    assert_eq!(method, PartialMethodId::Name("bar".to_string()));
+    match server.rx_begin_call() {
+        Err(e) => assert_eq!(e.kind, RPCErrorKind::TransportEOF),
+        Ok(_) => panic!("Expected EOF error"),
+    }
+
+    let mut transport = JSONTransport::new(ReadWrite::new(&[][..], Vec::new()));
+    transport.set_auto_flush(false);
+    bar_call(&mut transport, 2);
+    assert!(!transport.into_channel().writable().is_empty());
+
+    // Only close says whether they could be sent
+    let (s1, s2) = UnixStream::pair().unwrap();
+    drop(s2);
+    let mut transport = JSONTransport::new(s1);
+    transport.set_auto_flush(false);
+    bar_call(&mut transport, 3);
+    assert_eq!(
+        transport.close().unwrap_err().kind,
+        RPCErrorKind::TransportEOF
+    );
+}
+
+#[test]
+fn json_framed_max_message_bytes() {
+    let mut request = 1_000_000u32.to_be_bytes().to_vec();
+    request.extend_from_slice(b"{}");
+    let transport = JSONTransport::framed(ReadWrite::new(&request[..], io::sink()))
+        .with_max_message_bytes(1024);
+    let err = FooRPCServer::new(FooImpl::new(), transport)
+        .serve_single_call()
+        .unwrap_err();
+    assert_eq!(err.kind, RPCErrorKind::MessageTooLarge);
+}
+
+#[test]
+fn serve_multiple_eof_on_disconnect_json_framed() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let foo = FooRPCClient::new(JSONTransport::framed(s1));
+        client42(&foo);
+    });
+    let mut serve = FooRPCServer::new(FooImpl::new(), JSONTransport::framed(s2));
+    match serve.serve() {
+        Ok(_) => panic!("Expected EOF error"),
+        Err(e) => assert_eq!(e.kind, RPCErrorKind::TransportEOF),
+    }
+}
+
+#[test]
+fn json_into_channel() {
+    let (s1, _s2) = UnixStream::pair().unwrap();
+    let mut transport = JSONTransport::new(s1);
+    let timeout = Some(Duration::from_secs(10));
+    transport.channel_mut().set_read_timeout(timeout).unwrap();
+    let channel = transport.into_channel();
+    assert_eq!(channel.read_timeout().unwrap(), timeout);
+}
+
+#[test]
+fn split_transport() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let transport = SplitTransport::from_halves(s2.try_clone().unwrap(), s2);
+        FooRPCServer::new(FooImpl::new(), transport).serve_loop()
+    });
+    let transport = SplitTransport::from_halves(s1.try_clone().unwrap(), s1)
+        .with_timeout(Duration::from_secs(10))
+        .unwrap();
+    // The timeout is set on the read half
+    let timeout = transport.channel().readable().read_timeout().unwrap();
+    assert_eq!(timeout, Some(Duration::from_secs(10)));
+    let foo = FooRPCClient::new(transport);
+    client42(&foo);
+}
+
+#[test]
+fn json_timeout() {
+    // The other end of the pair never responds
+    let (s1, _s2) = UnixStream::pair().unwrap();
+    let mut transport = JSONTransport::new(s1)
+        .with_timeout(Duration::from_millis(50))
+        .unwrap();
+    let result = transport.rx_response::<Result<String, TestError>>(serde_json::json!(1));
+    assert!(result.unwrap_err().is_timeout());
+}
+
+#[test]
+fn json_timeout_part_way_through_response() {
+    let (s1, mut s2) = UnixStream::pair().unwrap();
+    let mut transport = JSONTransport::new(s1)
+        .with_timeout(Duration::from_millis(50))
+        .unwrap();
+    // A timeout before any of the response arrives is harmless
+    let result = transport.rx_response::<Result<String, TestError>>(serde_json::json!(1));
+    assert!(result.unwrap_err().is_timeout());
+    s2.write_all(b"{\"jsonrpc\":\"2.0\",").unwrap();
+    let result = transport.rx_response::<Result<String, TestError>>(serde_json::json!(1));
+    assert!(result.unwrap_err().is_timeout());
+    // The rest of the response must not be taken for the next
+    s2.write_all(b"\"result\":{\"Ok\":\"late\"},\"id\":1}")
+        .unwrap();
+    let e = transport
+        .rx_response::<Result<String, TestError>>(serde_json::json!(1))
+        .unwrap_err();
+    assert_eq!(e.kind, essrpc::RPCErrorKind::TransportError);
+    assert!(e.to_string().contains("unusable"), "{}", e);
+}
+
+#[test]
+fn propagates_error() {
+    let foo = json_foo();
+    match foo.expect_error() {
+        Ok(_) => panic!("Should have generated an error"),
+        Err(e) => assert_eq!(&e.msg, "iamerror"),
+    }
+}
+
+#[test]
+fn serve_multiple() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let mut serve = FooRPCServer::new(FooImpl::new(), BincodeTransport::new(s2));
+        serve.serve()
+    });
+    let foo = FooRPCClient::new(BincodeTransport::new(s1));
+    client42(&foo);
+    match foo.bar("the answer".to_string(), 43) {
+        Ok(result) => assert_eq!("the answer is 43", result),
+        Err(e) => panic!("error: {:?}", e),
+    }
+}
+
+#[test]
+fn serve_single_call_ok_bincode() {
+    // Verify that serve_single_call returns an Ok result
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let foo = FooRPCClient::new(BincodeTransport::new(s1));
+        client42(&foo);
+    });
+    let mut serve = FooRPCServer::new(FooImpl::new(), BincodeTransport::new(s2));
+    serve.serve_single_call().unwrap()
+}
+
+#[test]
+fn serve_single_call_ok_json() {
+    // Verify that serve_single_call returns an Ok result
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let foo = FooRPCClient::new(JSONTransport::new(s1));
+        client42(&foo);
+    });
+    let mut serve = FooRPCServer::new(FooImpl::new(), JSONTransport::new(s2));
+    serve.serve_single_call().unwrap()
+}
+
+#[test]
+fn serve_multiple_eof_on_disconnect_bincode() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let foo = FooRPCClient::new(BincodeTransport::new(s1));
+        client42(&foo);
+    });
+    let mut serve = FooRPCServer::new(FooImpl::new(), BincodeTransport::new(s2));
+    match serve.serve() {
+        Ok(_) => panic!("Expected EOF error"),
+        Err(e) => assert_eq!(e.kind, RPCErrorKind::TransportEOF),
+    }
+}
+
+#[cfg(feature = "msgpack_transport")]
+#[test]
+fn propagates_error_msgpack() {
+    let foo = msgpack_foo();
+    match foo.expect_error() {
+        Ok(_) => panic!("Should have generated an error"),
+        Err(e) => assert_eq!(&e.msg, "iamerror"),
+    }
+}
+
+#[cfg(feature = "msgpack_transport")]
+#[test]
+fn serve_multiple_eof_on_disconnect_msgpack() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let foo = FooRPCClient::new(MessagePackTransport::new(s1));
+        client42(&foo);
+    });
+    let mut serve = FooRPCServer::new(FooImpl::new(), MessagePackTransport::new(s2));
+    match serve.serve() {
+        Ok(_) => panic!("Expected EOF error"),
+        Err(e) => assert_eq!(e.kind, RPCErrorKind::TransportEOF),
+    }
+}
+
+#[cfg(feature = "cbor_transport")]
+#[test]
+fn propagates_error_cbor() {
+    let foo = cbor_foo();
+    match foo.expect_error() {
+        Ok(_) => panic!("Should have generated an error"),
+        Err(e) => assert_eq!(&e.msg, "iamerror"),
+    }
+}
+
+#[cfg(feature = "cbor_transport")]
+#[test]
+fn serve_multiple_eof_on_disconnect_cbor() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let foo = FooRPCClient::new(CBORTransport::new(s1));
+        client42(&foo);
+    });
+    let mut serve = FooRPCServer::new(FooImpl::new(), CBORTransport::new(s2));
+    match serve.serve() {
+        Ok(_) => panic!("Expected EOF error"),
+        Err(e) => assert_eq!(e.kind, RPCErrorKind::TransportEOF),
+    }
+}
+
+#[cfg(feature = "websocket_transport")]
+#[test]
+fn propagates_error_websocket() {
+    let foo = websocket_foo();
+    match foo.expect_error() {
+        Ok(_) => panic!("Should have generated an error"),
+        Err(e) => assert_eq!(&e.msg, "iamerror"),
+    }
+}
+
+#[cfg(feature = "websocket_transport")]
+#[test]
+fn serve_multiple_eof_on_disconnect_websocket() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let (socket, _) = tungstenite::client("ws://localhost/", s1).unwrap();
+        let foo = FooRPCClient::new(WebSocketTransport::new(socket));
+        client42(&foo);
+    });
+    let socket = tungstenite::accept(s2).unwrap();
+    let mut serve = FooRPCServer::new(FooImpl::new(), WebSocketTransport::new(socket));
+    match serve.serve() {
+        Ok(_) => panic!("Expected EOF error"),
+        Err(e) => assert_eq!(e.kind, RPCErrorKind::TransportEOF),
+    }
+}
+
+#[cfg(feature = "websocket_transport")]
+#[test]
+fn websocket_close_frame_is_eof() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let (mut socket, _) = tungstenite::client("ws://localhost/", s1).unwrap();
+        socket.close(None).unwrap();
+        // Wait for the server to acknowledge the close
+        while socket.read().is_ok() {}
+    });
+    let socket = tungstenite::accept(s2).unwrap();
+    let mut serve = FooRPCServer::new(FooImpl::new(), WebSocketTransport::new(socket));
+    match serve.serve() {
+        Ok(_) => panic!("Expected EOF error"),
+        Err(e) => assert_eq!(e.kind, RPCErrorKind::TransportEOF),
+    }
+}
+
+#[cfg(feature = "unix")]
+#[test]
+fn json_unix_socket() {
+    let path = std::env::temp_dir().join(format!("essrpc-test-{}.sock", std::process::id()));
+    let listener = JSONTransport::bind_unix(&path).unwrap();
+    thread::spawn(move || {
+        let mut serve = FooRPCServer::new(FooImpl::new(), listener.accept().unwrap());
+        serve.serve_loop()
+    });
+    let foo = FooRPCClient::new(JSONTransport::connect_unix(&path).unwrap());
+    client42(&foo);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "unix")]
+#[test]
+fn json_unix_connect_failed() {
+    let path = std::env::temp_dir().join("essrpc-test-nonexistent.sock");
+    let err = JSONTransport::connect_unix(&path).err().unwrap();
+    assert_eq!(err.kind, RPCErrorKind::ConnectionFailed);
+}
+
+#[test]
+fn json_tcp() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        FooRPCServer::new(FooImpl::new(), JSONTransport::new(stream)).serve_loop()
+    });
+    let foo = FooRPCClient::new(JSONTransport::connect_tcp(addr).unwrap());
+    client42(&foo);
+}
+
+#[test]
+fn json_serve_tcp() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        JSONTransport::serve_listener(listener, |transport| {
+            FooRPCServer::new(FooImpl::new(), transport)
+        })
+    });
+    // A client disconnecting does not affect the others
+    let first = FooRPCClient::new(JSONTransport::connect_tcp(addr).unwrap());
+    client42(&FooRPCClient::new(
+        JSONTransport::connect_tcp(addr).unwrap(),
+    ));
+    client42(&first);
+    client42(&FooRPCClient::new(
+        JSONTransport::connect_tcp(addr).unwrap(),
+    ));
+}
+
+#[test]
+fn json_serve_tcp_bind_failed() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let err = JSONTransport::serve_tcp(addr, |transport| {
+        FooRPCServer::new(FooImpl::new(), transport)
+    })
+    .unwrap_err();
+    assert_eq!(err.kind, RPCErrorKind::TransportError);
+}
+
+// Sleeper counting how many of its calls run at once, shared by
+// every server a ThreadedServer creates
+#[derive(Clone, Default)]
+struct GaugedSleeper {
+    busy: std::sync::Arc<AtomicUsize>,
+    peak: std::sync::Arc<AtomicUsize>,
+}
+
+impl Sleeper for GaugedSleeper {
+    fn nap(&self, ms: u64) -> Result<u64, TestError> {
+        self.doze(ms)
+    }
+    fn sleep(&self, ms: u64) -> Result<u64, TestError> {
+        self.doze(ms)
+    }
+    fn doze(&self, ms: u64) -> Result<u64, TestError> {
+        let busy = self.busy.fetch_add(1, Ordering::SeqCst) + 1;
+        self.peak.fetch_max(busy, Ordering::SeqCst);
+        let result = SleeperImpl::sleep_for(ms);
+        self.busy.fetch_sub(1, Ordering::SeqCst);
+        result
+    }
+}
+
+// Serve `clients` connections with a ThreadedServer, returning the
+// client ends
+fn threaded_sleepers(
+    sleeper: GaugedSleeper,
+    clients: usize,
+    max_in_flight: usize,
+) -> Vec<UnixStream> {
+    let (ours, theirs): (Vec<_>, Vec<_>) =
+        (0..clients).map(|_| UnixStream::pair().unwrap()).unzip();
+    ThreadedServer::new(move |transport| SleeperRPCServer::new(sleeper.clone(), transport))
+        .with_threads(4)
+        .with_max_in_flight(max_in_flight)
+        .serve_incoming(theirs.into_iter().map(Ok))
+        .unwrap();
+    ours
+}
+
+#[test]
+fn threaded_server_concurrent_calls() {
+    let sleeper = GaugedSleeper::default();
+    let mut clients = threaded_sleepers(sleeper.clone(), 2, 4);
+
+    // Calls on one connection are served at once, and answered as
+    // each completes
+    let mut s = clients.pop().unwrap();
+    s.write_all(
+        br#"{"jsonrpc":"2.0","method":"doze","params":{"ms":300},"id":1}
+{"jsonrpc":"2.0","method":"doze","params":{"ms":0},"id":2}"#,
+    )
+    .unwrap();
+    let responses: Vec<serde_json::Value> = serde_json::Deserializer::from_reader(&s)
+        .into_iter()
+        .take(2)
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(responses[0]["id"], 2);
+    assert_eq!(responses[1]["id"], 1);
+    assert_eq!(responses[1]["result"], 300);
+
+    // As are calls from separate clients
+    let client = SleeperRPCClient::new(JSONTransport::new(clients.pop().unwrap()));
+    let caller = thread::spawn(move || client.doze(200).unwrap());
+    thread::sleep(Duration::from_millis(50));
+    let client = SleeperRPCClient::new(JSONTransport::new(s));
+    assert_eq!(client.doze(100).unwrap(), 100);
+    assert_eq!(sleeper.busy.load(Ordering::SeqCst), 1);
+    assert_eq!(caller.join().unwrap(), 200);
+    assert_eq!(sleeper.peak.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn threaded_server_max_in_flight() {
+    let sleeper = GaugedSleeper::default();
+    let callers: Vec<_> = threaded_sleepers(sleeper.clone(), 6, 2)
+        .into_iter()
+        .map(|s| {
+            let client = SleeperRPCClient::new(JSONTransport::new(s));
+            thread::spawn(move || client.doze(50).unwrap())
+        })
+        .collect();
+    for caller in callers {
+        assert_eq!(caller.join().unwrap(), 50);
+    }
+    // Though there are threads to spare, calls beyond the limit wait
+    assert_eq!(sleeper.peak.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn json_tcp_connect_failed() {
+    // Nothing listens on the port once the listener is dropped
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let err = JSONTransport::connect_tcp(addr).err().unwrap();
+    assert_eq!(err.kind, RPCErrorKind::ConnectionFailed);
+    assert!(std::error::Error::source(&err).is_some());
+
+    let err = JSONTransport::connect_tcp("nonexistent.invalid:1")
+        .err()
+        .unwrap();
+    assert_eq!(err.kind, RPCErrorKind::ConnectionFailed);
+}
+
+#[test]
+fn negotiated_bincode() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    let server = thread::spawn(move || {
+        let transport = NegotiatingTransport::server(s2).unwrap();
+        assert_eq!(transport.format(), WireFormat::Bincode);
+        FooRPCServer::new(FooImpl::new(), transport).serve_loop()
+    });
+    let transport = NegotiatingTransport::client(s1, WireFormat::Bincode).unwrap();
+    assert_eq!(transport.format(), WireFormat::Bincode);
+    let foo = FooRPCClient::new(transport);
+    client42(&foo);
+    drop(foo);
+    assert!(server.join().unwrap().is_ok());
+}
+
+#[test]
+fn negotiating_server_accepts_json_client() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let transport = NegotiatingTransport::server(s2).unwrap();
+        assert_eq!(transport.format(), WireFormat::JSON);
+        FooRPCServer::new(FooImpl::new(), transport).serve_loop()
+    });
+    client42(&FooRPCClient::new(JSONTransport::new(s1)));
+}
+
+#[test]
+fn negotiating_json_client_accepts_json_server() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || FooRPCServer::new(FooImpl::new(), JSONTransport::new(s2)).serve_loop());
+    let transport = NegotiatingTransport::client(s1, WireFormat::JSON).unwrap();
+    assert_eq!(transport.format(), WireFormat::JSON);
+    client42(&FooRPCClient::new(transport));
+}
+
+#[test]
+fn negotiating_server_falls_back_to_json() {
+    let (mut s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let transport = NegotiatingTransport::server(s2).unwrap();
+        FooRPCServer::new(FooImpl::new(), transport).serve_loop()
+    });
+    // Offer a format the server does not know
+    s1.write_all(&[0x7f]).unwrap();
+    let mut reply = [0u8];
+    io::Read::read_exact(&mut s1, &mut reply).unwrap();
+    assert_eq!(reply[0], 1);
+    client42(&FooRPCClient::new(JSONTransport::new(s1)));
+}
+
+fn foo_router(channel: UnixStream) -> Router<JSONTransport<UnixStream>> {
+    Router::new(JSONTransport::new(channel))
+        .route("bar", |tr, mut state| {
+            let a: String = tr.rx_read_param("a", &mut state)?;
+            let b: i32 = tr.rx_read_param("b", &mut state)?;
+            tr.tx_response(Ok::<_, TestError>(format!("{} is {}", a, b)), state)
+        })
+        .route("expect_error", |tr, state: JRXState| {
+            let msg = format!("params were {}", state.params().unwrap());
+            tr.tx_response(Err::<String, _>(TestError { msg }), state)
+        })
+}
+
+// Router refusing calls to bar which do not carry the right token in
+// their metadata.
+fn gated_router(channel: UnixStream) -> Router<JSONTransport<UnixStream>> {
+    Router::new(JSONTransport::new(channel)).route("bar", |tr, mut state| {
+        let token = state.meta().and_then(|meta| meta.get("token"));
+        if token != Some(&serde_json::json!("secret")) {
+            let error = essrpc::RPCError::new(RPCErrorKind::Application(-32001), "not authorized");
+            return tr.reject_call(state, error);
+        }
+        let a: String = tr.rx_read_param("a", &mut state)?;
+        let b: i32 = tr.rx_read_param("b", &mut state)?;
+        tr.tx_response(Ok::<_, TestError>(format!("{} is {}", a, b)), state)
+    })
+}
+
+#[test]
+fn router_reject_call() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || gated_router(s2).serve_loop());
+    let foo = FooRPCClient::new(JSONTransport::new(s1));
+    // The server keeps serving the connection after refusing a call.
+    for _ in 0..2 {
+        match foo.bar("the answer".to_string(), 42) {
+            Ok(_) => panic!("Should have been refused"),
+            Err(e) => assert!(e.msg.contains("not authorized"), "{}", e.msg),
+        }
+    }
+
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || gated_router(s2).serve_loop());
+    let mut transport = JSONTransport::new(s1);
+    let mut context = serde_json::Map::new();
+    context.insert("token".to_string(), serde_json::json!("secret"));
+    transport.set_context(context);
+    client42(&FooRPCClient::new(transport));
+}
+
+// Server refusing calls to bar which do not carry the right token
+// in their metadata, while letting anything else through.
+fn authorized_foo_server(channel: UnixStream) -> FooRPCServer<FooImpl, JSONTransport<UnixStream>> {
+    FooRPCServer::new(FooImpl::new(), JSONTransport::new(channel)).with_authorizer(
+        |method, state: &JRXState| {
+            let token = state.meta().and_then(|meta| meta.get("token"));
+            if method.to_string() == "bar" && token != Some(&serde_json::json!("secret")) {
+                return Err(essrpc::RPCError::new(
+                    RPCErrorKind::Application(-32001),
+                    "not authorized",
+                ));
+            }
+            Ok(())
+        },
+    )
+}
+
+#[test]
+fn server_authorizer_json() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || authorized_foo_server(s2).serve());
+    let foo = FooRPCClient::new(JSONTransport::new(s1));
+    match foo.bar("the answer".to_string(), 42) {
+        Ok(_) => panic!("Should have been refused"),
+        Err(e) => assert!(e.msg.contains("not authorized"), "{}", e.msg),
+    }
+    // Other calls still reach the implementation
+    assert_eq!(foo.expect_error().unwrap_err().msg, "iamerror");
+
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || authorized_foo_server(s2).serve());
+    let mut transport = JSONTransport::new(s1);
+    let mut context = serde_json::Map::new();
+    context.insert("token".to_string(), serde_json::json!("secret"));
+    transport.set_context(context);
+    client42(&FooRPCClient::new(transport));
+}
+
+#[test]
+fn server_authorizer_bincode() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    let server = thread::spawn(move || {
+        let mut serve = FooRPCServer::new(FooImpl::new(), BincodeTransport::new(s2))
+            .with_authorizer(|method, _state| {
+                assert_eq!(method, &PartialMethodId::Num(0));
+                Err(essrpc::RPCError::new(RPCErrorKind::Other, "refused"))
+            });
+        serve.serve_single_call()
+    });
+    let foo = FooRPCClient::new(BincodeTransport::new(s1));
+    // Bincode cannot answer with an error, so the server gives up
+    assert!(foo.bar("the answer".to_string(), 42).is_err());
+    let e = server.join().unwrap().unwrap_err();
+    assert_eq!(e.kind(), RPCErrorKind::Other);
+    assert_eq!(e.method(), Some("bar"));
+}
+
+#[test]
+fn json_service_version_on_wire() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        LedgerV3RPCClient::new(JSONTransport::new(s1)).balance("ann".to_string())
+    });
+    let mut server = JSONTransport::new(s2);
+    let (_, state) = server.rx_begin_call().unwrap();
+    assert_eq!(server.rx_service_version(&state), Some(3));
+    assert_eq!(
+        LedgerV3RPCClient::<JSONTransport<UnixStream>>::VERSION,
+        Some(3)
+    );
+    assert_eq!(LedgerRPCClient::<JSONTransport<UnixStream>>::VERSION, None);
+}
+
+#[test]
+fn service_version_mismatch() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || LedgerV3RPCServer::new(LedgerImpl, JSONTransport::new(s2)).serve());
+    let old = LedgerV2RPCClient::new(JSONTransport::new(s1));
+    // The client's error type keeps only the message
+    let err = old.balance("ann".to_string()).unwrap_err().to_string();
+    assert!(err.contains("version mismatch"), "{}", err);
+    assert!(err.contains("version 2"), "{}", err);
+    // The server carries on, refusing each call in turn
+    assert!(old.balance("bob".to_string()).is_err());
+
+    // Clients which send no version are served
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || LedgerV3RPCServer::new(LedgerImpl, JSONTransport::new(s2)).serve());
+    let unversioned = LedgerRPCClient::new(JSONTransport::new(s1));
+    assert_eq!(unversioned.balance("ann".to_string()).unwrap(), 3);
+}
+
+// Server of version 3 of the ledger which also serves version 2
+fn compatible_ledger() -> UnixStream {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        LedgerV3RPCServer::new(LedgerImpl, JSONTransport::new(s2))
+            .with_compatible_versions(2..=3)
+            .serve()
+    });
+    s1
+}
+
+#[test]
+fn service_compatible_versions() {
+    let old = LedgerV2RPCClient::new(JSONTransport::new(compatible_ledger()));
+    assert_eq!(old.balance("ann".to_string()).unwrap(), 3);
+    let new = LedgerV3RPCClient::new(JSONTransport::new(compatible_ledger()));
+    assert_eq!(new.balance("bob".to_string()).unwrap(), 3);
+
+    // A version from beyond the range is still refused
+    let mut transport = JSONTransport::new(compatible_ledger());
+    let mut state = transport
+        .tx_begin_call(MethodId {
+            name: "balance",
+            num: 0,
+        })
+        .unwrap();
+    transport.tx_set_service_version(4, &mut state).unwrap();
+    transport.tx_add_param("user", "ann", &mut state).unwrap();
+    let id = transport.tx_finalize(state).unwrap();
+    match transport.rx_response::<Result<i64, TestError>>(id) {
+        Err(e) => assert_eq!(e.kind, RPCErrorKind::VersionMismatch),
+        Ok(_) => panic!("Should have been refused"),
+    }
+}
+
+#[test]
+fn router_dispatches_by_name() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || foo_router(s2).serve_loop());
+    let foo = FooRPCClient::new(JSONTransport::new(s1));
+    client42(&foo);
+    match foo.expect_error() {
+        Ok(_) => panic!("Should have generated an error"),
+        Err(e) => assert_eq!(&e.msg, "params were {}"),
+    }
+}
+
+#[test]
+fn router_unknown_method() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || foo_router(s2).serve_loop());
+    let mut transport = JSONTransport::new(s1);
+    let state = transport
+        .tx_begin_call(MethodId {
+            name: "baz",
+            num: 7,
+        })
+        .unwrap();
+    let id = transport.tx_finalize(state).unwrap();
+    match transport.rx_response::<Result<String, TestError>>(id) {
+        Err(e) => assert_eq!(e.kind, RPCErrorKind::UnknownMethod),
+        Ok(_) => panic!("Should have gotten an error"),
+    }
+}
+
+#[test]
+fn dynamic_client() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let mut serve = FooRPCServer::new(FooImpl::new(), JSONTransport::new(s2));
+        serve.serve()
+    });
+    let client = DynamicClient::new(JSONTransport::new(s1));
+    let result = client
+        .call("bar", serde_json::json!({"a": "the answer", "b": 42}))
+        .unwrap();
+    assert_eq!(result, serde_json::json!("the answer is 42"));
+
+    let err = client
+        .call("expect_error", serde_json::Value::Null)
+        .unwrap_err();
+    assert_eq!(err.kind, RPCErrorKind::Application(-32000));
+    assert!(
+        err.to_string().ends_with(r#"{"msg":"iamerror"}"#),
+        "{}",
+        err
+    );
+    let err = client.call("nope", serde_json::json!({})).unwrap_err();
+    assert_eq!(err.kind, RPCErrorKind::UnknownMethod);
+    let err = client
+        .call("bar", serde_json::json!({"a": "x"}))
+        .unwrap_err();
+    assert_eq!(err.kind, RPCErrorKind::InvalidParams);
+    assert_eq!(err.param(), Some("b"));
+    let err = client.call("bar", serde_json::json!(42)).unwrap_err();
+    assert_eq!(err.kind, RPCErrorKind::InvalidParams);
+
+    // Positional params suit positional transports
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let mut serve = FooRPCServer::new(FooImpl::new(), JSONTransport::new(s2));
+        serve.serve()
+    });
+    let client = DynamicClient::new(JSONTransport::positional(s1));
+    let result = client
+        .call("bar", serde_json::json!(["the answer", 7]))
+        .unwrap();
+    assert_eq!(result, serde_json::json!("the answer is 7"));
+}
+
+#[test]
+fn namespaced_method_name() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let accounts = AccountsRPCClient::new(JSONTransport::new(s1));
+        accounts.get("ann".to_string())
+    });
+    let mut server = JSONTransport::new(s2);
+    let (method, _) = server.rx_begin_call().unwrap();
+    match method {
+        PartialMethodId::Name(name) => assert_eq!(name, "Accounts.get"),
+        PartialMethodId::Num(_) => panic!("Expected a method name"),
+    }
+}
+
+#[test]
+fn namespaced_methods() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        AccountsRPCServer::new(AccountsImpl, JSONTransport::new(s2)).serve_loop()
+    });
+    let mut transport = JSONTransport::new(s1);
+
+    // The bare name is not one of the server's methods
+    let state = transport
+        .tx_begin_call(MethodId {
+            name: "get",
+            num: 0,
+        })
+        .unwrap();
+    let id = transport.tx_finalize(state).unwrap();
+    match transport.rx_response::<Result<i64, TestError>>(id) {
+        Err(e) => assert_eq!(e.kind, RPCErrorKind::UnknownMethod),
+        Ok(_) => panic!("Should have gotten an error"),
+    }
+
+    let accounts = AccountsRPCClient::new(transport);
+    assert_eq!(accounts.get("ann".to_string()).unwrap(), 300);
+}
+
+// Serves both namespaced traits, whose methods share the name `get`
+fn namespace_router(channel: UnixStream) -> Router<JSONTransport<UnixStream>> {
+    Router::new(JSONTransport::new(channel))
+        .route("Accounts.get", |tr, mut state| {
+            let user: String = tr.rx_read_param("user", &mut state)?;
+            tr.tx_response(AccountsImpl.get(user), state)
+        })
+        .route("Billing.get", |tr, mut state| {
+            let invoice: u32 = tr.rx_read_param("invoice", &mut state)?;
+            tr.tx_response(Ok::<_, TestError>(format!("invoice {}", invoice)), state)
+        })
+}
+
+#[test]
+fn namespaces_share_router() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || namespace_router(s2).serve_loop());
+    let accounts = AccountsRPCClient::new(JSONTransport::new(s1));
+    assert_eq!(accounts.get("bob".to_string()).unwrap(), 300);
+
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || namespace_router(s2).serve_loop());
+    let billing = BillingRPCClient::new(JSONTransport::new(s1));
+    assert_eq!(billing.get(7).unwrap(), "invoice 7");
+}
+
+#[test]
+fn json_context_meta() {
+    let (client, server) = JSONTransport::pair();
+    let (tx, rx) = std::sync::mpsc::channel();
+    let server = server.on_call(move |state| {
+        tx.send(state.meta().cloned()).unwrap();
+    });
+    thread::spawn(move || FooRPCServer::new(FooImpl::new(), server).serve_loop());
+
+    let mut client = client;
+    let mut context = serde_json::Map::new();
+    context.insert("trace".to_string(), serde_json::json!("abc123"));
+    client.set_context(context.clone());
+    let foo = FooRPCClient::new(client);
+    client42(&foo);
+    assert_eq!(rx.recv().unwrap(), Some(context));
+
+    // Without a context, no meta member is sent
+    let mut request = Vec::new();
+    {
+        let channel = ReadWrite::new(&[][..], &mut request);
+        let foo = FooRPCClient::new(JSONTransport::new(channel));
+        assert!(foo.bar("the answer".to_string(), 42).is_err());
+    }
+    let request: serde_json::Value = serde_json::from_slice(&request).unwrap();
+    assert!(request.get("meta").is_none());
+}
+
+#[test]
+fn duplex_threads() {
+    let (client, server) = JSONTransport::pair();
+    thread::spawn(move || FooRPCServer::new(FooImpl::new(), server).serve_loop());
+    let foo = FooRPCClient::new(client);
+    client42(&foo);
+    client42(&foo);
+}
+
+#[test]
+fn duplex_single_thread_step() {
+    let (mut client, server) = JSONTransport::pair();
+    let mut server = FooRPCServer::new(FooImpl::new(), server);
+    let mut state = client
+        .tx_begin_call(MethodId {
+            name: "bar",
+            num: 0,
+        })
+        .unwrap();
+    client.tx_add_param("a", "the answer", &mut state).unwrap();
+    client.tx_add_param("b", 42, &mut state).unwrap();
+    let state = client.tx_finalize(state).unwrap();
+    assert_eq!(client.channel().pending(), 0);
+    server.serve_single_call().unwrap();
+    assert!(client.channel().pending() > 0);
+    let result: Result<String, TestError> = client.rx_response(state).unwrap();
+    assert_eq!(result.unwrap(), "the answer is 42");
+}
+
+#[test]
+fn duplex_eof_when_dropped() {
+    let (client, server) = JSONTransport::pair();
+    drop(client);
+    let mut serve = FooRPCServer::new(FooImpl::new(), server);
+    assert!(serve.serve_single_call().unwrap_err().is_eof());
+}
+
+#[test]
+fn duplex_timeout() {
+    let (c1, _c2) = DuplexChannel::pair();
+    let mut transport = JSONTransport::new(c1)
+        .with_timeout(Duration::from_millis(50))
+        .unwrap();
+    let result = transport.rx_response::<Result<String, TestError>>(serde_json::json!(1));
+    assert!(result.unwrap_err().is_timeout());
+}
+
+#[cfg(feature = "tls")]
+fn tls_client_config(trust_server: bool) -> std::sync::Arc<rustls::ClientConfig> {
+    use rustls::pki_types::pem::PemObject;
+    let mut roots = rustls::RootCertStore::empty();
+    if trust_server {
+        let cert = include_bytes!("../examples/tls/cert.pem");
+        roots
+            .add(rustls::pki_types::CertificateDer::from_pem_slice(cert).unwrap())
+            .unwrap();
+    }
+    let provider = std::sync::Arc::new(rustls::crypto::ring::default_provider());
+    let config = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    std::sync::Arc::new(config)
+}
+
+// Address of a server accepting a single TLS connection.
+#[cfg(feature = "tls")]
+fn tls_server() -> std::net::SocketAddr {
+    use rustls::pki_types::pem::PemObject;
+    let cert = include_bytes!("../examples/tls/cert.pem");
+    let key = include_bytes!("../examples/tls/key.pem");
+    let provider = std::sync::Arc::new(rustls::crypto::ring::default_provider());
+    let config = rustls::ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![rustls::pki_types::CertificateDer::from_pem_slice(cert).unwrap()],
+            rustls::pki_types::PrivateKeyDer::from_pem_slice(key).unwrap(),
+        )
+        .unwrap();
+    let acceptor = JSONTlsAcceptor::new(std::sync::Arc::new(config));
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let transport = acceptor.accept(stream)?;
+        FooRPCServer::new(FooImpl::new(), transport).serve_loop()
+    });
+    addr
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn json_tls() {
+    let addr = tls_server();
+    let transport = JSONTransport::connect_tls(addr, "localhost", tls_client_config(true)).unwrap();
+    let foo = FooRPCClient::new(transport);
+    client42(&foo);
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn json_tls_untrusted() {
+    let addr = tls_server();
+    match JSONTransport::connect_tls(addr, "localhost", tls_client_config(false)) {
+        Ok(_) => panic!("Handshake should have failed"),
+        Err(e) => assert_eq!(e.kind, RPCErrorKind::Tls),
+    }
+}
+
+#[test]
+fn transport_pool_reuses() {
+    let created = AtomicUsize::new(0);
+    let pool = TransportPool::new(|| {
+        created.fetch_add(1, Ordering::SeqCst);
+        let (s1, s2) = UnixStream::pair().unwrap();
+        thread::spawn(move || {
+            let mut serve = FooRPCServer::new(FooImpl::new(), JSONTransport::new(s2));
+            serve.serve()
+        });
+        Ok(JSONTransport::new(s1))
+    });
+    for _ in 0..3 {
+        let foo = FooRPCClient::new(pool.get().unwrap());
+        client42(&foo);
+    }
+    assert_eq!(created.load(Ordering::SeqCst), 1);
+    assert_eq!(pool.idle_count(), 1);
+}
+
+#[test]
+fn transport_pool_drops_poisoned() {
+    let pool = TransportPool::new(|| {
+        let (s1, s2) = UnixStream::pair().unwrap();
+        thread::spawn(move || {
+            let mut serve = FooRPCServer::new(FooImpl::new(), JSONTransport::new(s2));
+            serve.serve_single_call()
+        });
+        Ok(JSONTransport::new(s1))
+    });
+    {
+        let foo = FooRPCClient::new(pool.get().unwrap());
+        client42(&foo);
+    }
+    assert_eq!(pool.idle_count(), 1);
+    {
+        // The server has gone away, so the pooled connection hits EOF
+        let foo = FooRPCClient::new(pool.get().unwrap());
+        assert!(foo.bar("the answer".to_string(), 42).is_err());
+    }
+    assert_eq!(pool.idle_count(), 0);
+}
+
+#[test]
+fn retrying_client_reconnects() {
+    let created = AtomicUsize::new(0);
+    let client = RetryingClient::<FooRPCClient<_>, _, _>::new(|| {
+        let (client, server) = JSONTransport::pair();
+        // The first two connections are lost before the call
+        if created.fetch_add(1, Ordering::SeqCst) < 2 {
+            drop(server);
+        } else {
+            thread::spawn(move || FooRPCServer::new(FooImpl::new(), server).serve_loop());
+        }
+        Ok(client)
+    })
+    .with_base_delay(Duration::from_millis(1))
+    .with_jitter(0.5);
+    for _ in 0..3 {
+        let result = client.call(|foo| foo.bar("the answer".to_string(), 42));
+        assert_eq!(result.unwrap(), "the answer is 42");
+    }
+    // The working connection is kept once made
+    assert_eq!(created.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn retrying_client_gives_up() {
+    let created = AtomicUsize::new(0);
+    let client = RetryingClient::<FooRPCClient<_>, _, _>::new(|| {
+        created.fetch_add(1, Ordering::SeqCst);
+        Ok(JSONTransport::pair().0)
+    })
+    .with_max_retries(2)
+    .with_base_delay(Duration::from_millis(1));
+    assert!(client
+        .call(|foo| foo.bar("the answer".to_string(), 42))
+        .is_err());
+    assert_eq!(created.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn retrying_client_does_not_retry_errors() {
+    let created = AtomicUsize::new(0);
+    let client = RetryingClient::<FooRPCClient<_>, _, _>::new(|| {
+        created.fetch_add(1, Ordering::SeqCst);
+        let (client, server) = JSONTransport::pair();
+        thread::spawn(move || FooRPCServer::new(FooImpl::new(), server).serve_loop());
+        Ok(client)
+    });
+    // The method's own error
+    assert_eq!(
+        client.call(|foo| foo.expect_error()).unwrap_err().msg,
+        "iamerror"
+    );
+    assert_eq!(created.load(Ordering::SeqCst), 1);
+
+    // A response which cannot be deserialized
+    let created = AtomicUsize::new(0);
+    let client = RetryingClient::<FooRPCClient<_>, _, _>::new(|| {
+        created.fetch_add(1, Ordering::SeqCst);
+        Ok(JSONTransport::new(ReadWrite::new(&b"]"[..], io::sink())))
+    });
+    let err = client
+        .call(|foo| foo.bar("the answer".to_string(), 42))
+        .unwrap_err();
+    assert!(err.msg.contains("json"), "{}", err.msg);
+    assert_eq!(created.load(Ordering::SeqCst), 1);
+}
+
+#[derive(Default)]
+struct RecordingObserver {
+    events: std::sync::Mutex<Vec<String>>,
+}
+
+impl Observer for RecordingObserver {
+    fn on_request(&self, method: &str) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("request {}", method));
+    }
+
+    fn on_sent(&self, method: &str, bytes: Option<usize>) {
+        assert!(bytes.unwrap() > 0);
+        self.events.lock().unwrap().push(format!("sent {}", method));
+    }
+
+    fn on_response(&self, method: &str, bytes: Option<usize>, _elapsed: Duration) {
+        let bytes = bytes.unwrap();
+        assert!(bytes > 0);
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("response {}", method));
+    }
+
+    fn on_error(&self, method: &str, error: &essrpc::RPCError) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("error {} {}", method, error.kind));
+    }
+}
+
+#[test]
+fn observed_transport() {
+    let observer = std::sync::Arc::new(RecordingObserver::default());
+    let (client, server) = JSONTransport::pair();
+    let handle = thread::spawn(move || {
+        let mut serve = FooRPCServer::new(FooImpl::new(), server);
+        serve.serve_single_call().unwrap();
+    });
+    let foo = FooRPCClient::new(ObservedTransport::new(client, observer.clone()));
+    assert_eq!(
+        foo.bar("the answer".to_string(), 42).unwrap(),
+        "the answer is 42"
+    );
+    // The server is gone before the second call
+    handle.join().unwrap();
+    assert!(foo.bar("the answer".to_string(), 42).is_err());
+    assert_eq!(
+        *observer.events.lock().unwrap(),
+        [
+            "request bar",
+            "sent bar",
+            "response bar",
+            "request bar",
+            "error bar transport EOF"
+        ]
+    );
+}
+
+#[test]
+fn json_last_stats() {
+    let (c, s) = DuplexChannel::pair();
+    thread::spawn(move || CounterRPCServer::new(CounterImpl, JSONTransport::new(s)).serve_loop());
+    let mut transport = RecordingTransport::new(c, JSONTransport::new);
+    let recorded = transport.recorded();
+    assert_eq!(transport.transport().last_stats(), None);
+
+    let mut state = transport
+        .tx_begin_call(MethodId {
+            name: "count",
+            num: 0,
+        })
+        .unwrap();
+    transport.tx_add_param("to", 2, &mut state).unwrap();
+    let mut id = transport.tx_finalize(state).unwrap();
+    assert_eq!(transport.transport().last_stats(), None);
+    let mut items = 0;
+    while let Some(item) = transport
+        .rx_stream_item::<Result<i32, TestError>>(&mut id)
+        .unwrap()
+    {
+        item.unwrap();
+        items += 1;
+    }
+    assert_eq!(items, 2);
+
+    // The request, two items and the end of the stream
+    let recorded = recorded.lock().unwrap();
+    assert_eq!(recorded.len(), 4);
+    let stats = transport.transport().last_stats().unwrap();
+    assert_eq!(stats.request_bytes, recorded[0].len());
+    assert_eq!(
+        stats.response_bytes,
+        recorded[1..].iter().map(Vec::len).sum::<usize>()
+    );
+    assert_eq!(transport.tx_request_bytes(), Some(recorded[0].len()));
+}
+
+#[test]
+fn recording_transport_json() {
+    let (c, s) = DuplexChannel::pair();
+    let server = RecordingTransport::new(s, JSONTransport::new);
+    let server_recorded = server.recorded();
+    let handle = thread::spawn(move || {
+        let mut serve = FooRPCServer::new(FooImpl::new(), server);
+        serve.serve_single_call().unwrap();
+    });
+    let client = RecordingTransport::new(c, JSONTransport::new);
+    let recorded = client.recorded();
+    let foo = FooRPCClient::new(client);
+    assert_eq!(
+        foo.bar("the answer".to_string(), 42).unwrap(),
+        "the answer is 42"
+    );
+    handle.join().unwrap();
+
+    let recorded = recorded.lock().unwrap();
+    assert_eq!(recorded.len(), 2);
+    let request: serde_json::Value = serde_json::from_slice(&recorded[0]).unwrap();
+    assert_eq!(request["method"], "bar");
+    assert_eq!(
+        request["params"],
+        serde_json::json!({"a": "the answer", "b": 42})
+    );
+    let response: serde_json::Value = serde_json::from_slice(&recorded[1]).unwrap();
+    assert_eq!(response["result"], "the answer is 42");
+    assert_eq!(response["id"], request["id"]);
+    // Both ends saw the same bytes
+    assert_eq!(*server_recorded.lock().unwrap(), *recorded);
+}
+
+#[test]
+fn recording_transport_bincode() {
+    let (c, s) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let mut serve = FooRPCServer::new(FooImpl::new(), BincodeTransport::new(s));
+        serve.serve_single_call()
+    });
+    let client = RecordingTransport::new(c, BincodeTransport::new);
+    let recorded = client.recorded();
+    let foo = FooRPCClient::new(client);
+    client42(&foo);
+    let recorded = recorded.lock().unwrap();
+    assert_eq!(recorded.len(), 2);
+    assert!(recorded[0].ends_with(&42i32.to_be_bytes()));
+}
+
+#[test]
+fn bincode_byte_order() {
+    // Integers are big-endian unless chosen otherwise, the method
+    // number first
+    for (order, num) in [
+        (ByteOrder::BigEndian, [0, 0, 0, 1]),
+        (ByteOrder::LittleEndian, [1, 0, 0, 0]),
+    ] {
+        let mut request = Vec::new();
+        let mut transport =
+            BincodeTransport::new(ReadWrite::new(io::empty(), &mut request)).with_byte_order(order);
+        transport
+            .tx_begin_call(MethodId {
+                name: "baz",
+                num: 1,
+            })
+            .unwrap();
+        transport.tx_add_param("a", 0x0102i16, &mut ()).unwrap();
+        transport.tx_finalize(()).unwrap();
+        let param = match order {
+            ByteOrder::BigEndian => [1, 2],
+            ByteOrder::LittleEndian => [2, 1],
+        };
+        assert_eq!(request, [&num[..], &param[..]].concat());
+
+        // Whatever the order, both ends agreeing on it decode each
+        // other, uploads included
+        let (c, s) = DuplexChannel::pair();
+        thread::spawn(move || {
+            let transport = BincodeTransport::new(s).with_byte_order(order);
+            StoreRPCServer::new(StoreImpl, transport).serve_loop()
+        });
+        let store = StoreRPCClient::new(BincodeTransport::new(c).with_byte_order(order));
+        let data: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+        assert_eq!(
+            store
+                .put("key".to_string(), Upload::new(&data[..]))
+                .unwrap(),
+            ("key".to_string(), data.len() as u64)
+        );
+    }
+
+    // Disagreeing ends misread the method number
+    let (c, s) = DuplexChannel::pair();
+    thread::spawn(move || {
+        let transport = BincodeTransport::new(s).with_byte_order(ByteOrder::LittleEndian);
+        TelemetryRPCServer::new(TelemetryImpl::default(), transport).serve_single_call()
+    });
+    let telemetry = TelemetryRPCClient::new(BincodeTransport::new(c));
+    assert!(telemetry.total().is_err());
+}
+
+#[test]
+fn serve_multiple_eof_on_disconnect_json() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let foo = FooRPCClient::new(JSONTransport::new(s1));
+        client42(&foo);
+    });
+    let mut serve = FooRPCServer::new(FooImpl::new(), JSONTransport::new(s2));
+    match serve.serve() {
+        Ok(_) => panic!("Expected EOF error"),
+        Err(e) => assert_eq!(e.kind, RPCErrorKind::TransportEOF),
+    }
+}
+
+#[test]
+fn serve_loop_until_disconnect() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    let server = thread::spawn(move || {
+        let mut serve = FooRPCServer::new(FooImpl::new(), JSONTransport::new(s2));
+        serve.serve_loop()
+    });
+    {
+        let foo = FooRPCClient::new(JSONTransport::new(s1));
+        client42(&foo);
+        match foo.bar("the answer".to_string(), 43) {
+            Ok(result) => assert_eq!("the answer is 43", result),
+            Err(e) => panic!("error: {:?}", e),
+        }
+    }
+    assert!(server.join().unwrap().is_ok());
+}
+
+#[test]
+fn serve_loop_propagates_error() {
+    let request = br#"{"jsonrpc":"2.0","method":"bar","params":]"#;
+    let mut response = Vec::new();
+    let transport = JSONTransport::new(ReadWrite::new(&request[..], &mut response));
+    let mut serve = FooRPCServer::new(FooImpl::new(), transport);
+    let e = serve.serve_loop().unwrap_err();
+    assert_eq!(e.kind, RPCErrorKind::ParseError);
+}
+
+#[test]
+fn error_kind_helpers() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    drop(s1);
+    let mut serve = FooRPCServer::new(FooImpl::new(), JSONTransport::new(s2));
+    let e = serve.serve_single_call().unwrap_err();
+    assert!(e.is_eof());
+    assert!(!e.is_timeout());
+    assert_eq!(e.kind(), RPCErrorKind::TransportEOF);
+}
+
+#[test]
+fn error_kinds_keep_their_wire_positions() {
+    // The kinds of the last release, as bincode numbers them
+    let kinds = [
+        RPCErrorKind::SerializationError,
+        RPCErrorKind::UnknownMethod,
+        RPCErrorKind::TransportError,
+        RPCErrorKind::TransportEOF,
+        RPCErrorKind::IllegalState,
+        RPCErrorKind::Other,
+    ];
+    for (i, kind) in kinds.iter().enumerate() {
+        assert_eq!(bincode::serialize(kind).unwrap(), (i as u32).to_le_bytes());
+    }
+}
+
+#[derive(Debug)]
+struct OuterError(io::Error);
+
+impl fmt::Display for OuterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "outer")
+    }
+}
+
+impl std::error::Error for OuterError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+#[test]
+fn error_source_chain() {
+    let inner = io::Error::other("inner");
+    let e = essrpc::RPCError::with_cause(RPCErrorKind::TransportError, "failed", OuterError(inner))
+        .with_method("bar");
+    assert_eq!(e.to_string(), "in method bar: transport error: failed");
+    let mut chain = Vec::new();
+    let mut source = std::error::Error::source(&e);
+    while let Some(cause) = source {
+        chain.push(cause.to_string());
+        source = cause.source();
+    }
+    assert_eq!(chain, vec!["outer", "inner"]);
+
+    // Usable with `?` in functions returning boxed errors
+    let result = || -> Result<(), Box<dyn std::error::Error>> {
+        Err(essrpc::RPCError::new(RPCErrorKind::Timeout, "too slow"))?
+    };
+    assert_eq!(result().unwrap_err().to_string(), "timeout: too slow");
+}
+
+#[test]
+fn error_io_conversions() {
+    let e = essrpc::RPCError::from(io::Error::from(io::ErrorKind::UnexpectedEof));
+    assert!(e.is_eof());
+    let e = essrpc::RPCError::from(io::Error::from(io::ErrorKind::WouldBlock));
+    assert!(e.is_timeout());
+    let e: essrpc::RPCError = io::Error::other("broken").into();
+    assert_eq!(e.kind(), RPCErrorKind::TransportError);
+    assert_eq!(e.cause().unwrap().to_string(), "broken");
+
+    let e = io::Error::from(essrpc::RPCError::new(RPCErrorKind::Timeout, "too slow"));
+    assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+    assert_eq!(e.to_string(), "timeout: too slow");
+    let e = io::Error::from(essrpc::RPCError::new(RPCErrorKind::ParseError, "bad"));
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+    // Round trips through io::Error without loss
+    let e = essrpc::RPCError::new(RPCErrorKind::Application(7), "app").with_method("bar");
+    let e = essrpc::RPCError::from(io::Error::from(e));
+    assert_eq!(e.kind(), RPCErrorKind::Application(7));
+    assert_eq!(e.to_string(), "in method bar: application error 7: app");
+
+    // Usable with `?` in both directions
+    let to_rpc = || -> Result<(), essrpc::RPCError> { Err(io::Error::other("x"))? };
+    assert_eq!(to_rpc().unwrap_err().kind(), RPCErrorKind::TransportError);
+    let to_io =
+        || -> io::Result<()> { Err(essrpc::RPCError::new(RPCErrorKind::TransportEOF, "gone"))? };
+    assert_eq!(to_io().unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+}
+
+// Channel which accepts at most a few bytes per write, as a
+// non-blocking or small-buffer channel may.
+struct Trickle<W: Write>(W);
+
+impl<W: Write> Write for Trickle<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = std::cmp::min(buf.len(), 3);
+        self.0.write(&buf[..n])
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+#[test]
+fn partial_writes_deliver_whole_call() {
+    let mut request = Vec::new();
+    {
+        let channel = ReadWrite::new(&[][..], Trickle(&mut request));
+        let foo = FooRPCClient::new(JSONTransport::new(channel));
+        // No server is present, so reading the response fails
+        assert!(foo.bar("the answer".to_string(), 42).is_err());
+    }
+    let mut response = Vec::new();
+    {
+        let channel = ReadWrite::new(&request[..], Trickle(&mut response));
+        let mut serve = FooRPCServer::new(FooImpl::new(), JSONTransport::new(channel));
+        serve.serve_single_call().unwrap();
+    }
+    let response: serde_json::Value = serde_json::from_slice(&response).unwrap();
+    assert_eq!(response["result"], "the answer is 42");
+}
+
+#[test]
+fn partial_writes_deliver_whole_call_bincode() {
+    let mut request = Vec::new();
+    {
+        let channel = ReadWrite::new(&[][..], Trickle(&mut request));
+        let foo = FooRPCClient::new(BincodeTransport::new(channel));
+        assert!(foo.bar("the answer".to_string(), 42).is_err());
+    }
+    let mut response = Vec::new();
+    {
+        let channel = ReadWrite::new(&request[..], Trickle(&mut response));
+        let mut serve = FooRPCServer::new(FooImpl::new(), BincodeTransport::new(channel));
+        serve.serve_single_call().unwrap();
+    }
+    let foo = FooRPCClient::new(BincodeTransport::new(ReadWrite::new(
+        &response[..],
+        io::sink(),
+    )));
+    client42(&foo);
+}
+
+#[test]
+fn partial_method_id_name() {
+    const FOO_METHODS: &[&str] = &["bar", "expect_error"];
+    let (s1, s2) = UnixStream::pair().unwrap();
+    // A dispatcher written by hand, routing by name whichever way the
+    // client identifies the method
+    let server = thread::spawn(move || {
+        let mut transport = JSONTransport::new(s2);
+        let mut seen = Vec::new();
+        for _ in 0..2 {
+            let (method, mut state) = transport.rx_begin_call().unwrap();
+            seen.push(method.to_string());
+            let result = match method.name(FOO_METHODS) {
+                Some("bar") => {
+                    let a: String = transport.rx_read_param("a", &mut state).unwrap();
+                    let b: i32 = transport.rx_read_param("b", &mut state).unwrap();
+                    Ok(format!("{} is {}", a, b))
+                }
+                name => Err(TestError {
+                    msg: format!("no {:?}", name),
+                }),
+            };
+            transport.tx_response(result, state).unwrap();
+        }
+        seen
+    });
+    let foo = FooRPCClient::new(JSONTransport::new_numeric(s1));
+    client42(&foo);
+    assert_eq!(
+        foo.expect_error().unwrap_err().msg,
+        "no Some(\"expect_error\")"
+    );
+    assert_eq!(server.join().unwrap(), vec!["#0", "#1"]);
+
+    let by_name = PartialMethodId::Name("Accounts.get".to_string());
+    assert_eq!(by_name.name(&[]), Some("Accounts.get"));
+    assert_eq!(by_name.to_string(), "Accounts.get");
+    assert_eq!(PartialMethodId::Num(2).name(FOO_METHODS), None);
+}
+
+#[test]
+fn method_list() {
+    type Client = FooRPCClient<JSONTransport<UnixStream>>;
+    type Server = FooRPCServer<FooImpl, JSONTransport<UnixStream>>;
+    assert_eq!(Client::METHODS, &[(0, "bar"), (1, "expect_error")]);
+    assert_eq!(Server::METHODS, Client::METHODS);
+    assert_eq!(
+        AccountsRPCServer::<AccountsImpl, BincodeTransport<UnixStream>>::METHODS,
+        &[(0, "Accounts.get")]
+    );
+
+    // The names suit PartialMethodId::name
+    let names: Vec<&str> = Server::METHODS.iter().map(|(_, name)| *name).collect();
+    assert_eq!(PartialMethodId::Num(1).name(&names), Some("expect_error"));
+}
+
+fn client42<T: Foo>(client: &T) {
+    match client.bar("the answer".to_string(), 42) {
+        Ok(result) => assert_eq!("the answer is 42", result),
+        Err(e) => panic!("error: {:?}", e),
+    }
+}
+
+// Capture the request a JSON client writes for a call to `bar`.
+fn json_request(numeric: bool) -> serde_json::Value {
+    let mut request = Vec::new();
+    {
+        let channel = ReadWrite::new(&[][..], &mut request);
+        let transport = if numeric {
+            JSONTransport::new_numeric(channel)
+        } else {
+            JSONTransport::new(channel)
+        };
+        let client = FooRPCClient::new(transport);
+        // No server is present, so reading the response fails
+        assert!(client.bar("the answer".to_string(), 42).is_err());
+    }
+    serde_json::from_slice(&request).unwrap()
+}
+
+fn json_foo() -> impl Foo {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let mut serve = FooRPCServer::new(FooImpl::new(), JSONTransport::new(s2));
+        serve.serve_single_call()
+    });
+    FooRPCClient::new(JSONTransport::new(s1))
+}
+
+fn bincode_foo() -> impl Foo {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let mut serve = FooRPCServer::new(FooImpl::new(), BincodeTransport::new(s2));
+        serve.serve_single_call()
+    });
+    FooRPCClient::new(BincodeTransport::new(s1))
+}
+
+#[cfg(feature = "msgpack_transport")]
+fn msgpack_foo() -> impl Foo {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let mut serve = FooRPCServer::new(FooImpl::new(), MessagePackTransport::new(s2));
+        serve.serve_single_call()
+    });
+    FooRPCClient::new(MessagePackTransport::new(s1))
+}
+
+#[cfg(feature = "cbor_transport")]
+fn cbor_foo() -> impl Foo {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let mut serve = FooRPCServer::new(FooImpl::new(), CBORTransport::new(s2));
+        serve.serve_single_call()
+    });
+    FooRPCClient::new(CBORTransport::new(s1))
+}
+
+#[cfg(feature = "websocket_transport")]
+fn websocket_foo() -> impl Foo {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let socket = tungstenite::accept(s2).unwrap();
+        let mut serve = FooRPCServer::new(FooImpl::new(), WebSocketTransport::new(socket));
+        serve.serve_single_call()
+    });
+    let (socket, _) = tungstenite::client("ws://localhost/", s1).unwrap();
+    FooRPCClient::new(WebSocketTransport::new(socket))
+}
+
+fn json_sleeper(
+    configure: impl FnOnce(JSONTransport<UnixStream>) -> JSONTransport<UnixStream>,
+) -> SleeperRPCClient<JSONTransport<UnixStream>> {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let mut serve = SleeperRPCServer::new(SleeperImpl, JSONTransport::new(s2));
+        serve.serve()
+    });
+    SleeperRPCClient::new(configure(JSONTransport::new(s1)))
+}
+
+#[test]
+fn method_timeout() {
+    let sleeper = json_sleeper(|t| t.with_call_timeouts());
+    assert_eq!(sleeper.nap(0).unwrap(), 0);
+    // Without a timeout of its own, a call waits as long as it takes
+    assert_eq!(sleeper.doze(100).unwrap(), 100);
+    match sleeper.nap(200) {
+        Ok(_) => panic!("nap should have timed out"),
+        Err(e) => assert!(e.msg.contains("timeout: "), "{}", e.msg),
+    }
+}
+
+#[test]
+fn method_timeout_late_response_skipped() {
+    let sleeper = json_sleeper(|t| t.with_call_timeouts());
+    match sleeper.nap(100) {
+        Ok(_) => panic!("nap should have timed out"),
+        Err(e) => assert!(e.msg.contains("timeout: "), "{}", e.msg),
+    }
+    // The response to the nap arrives first, and is skipped
+    assert_eq!(sleeper.doze(1).unwrap(), 1);
+}
+
+#[test]
+fn method_timeout_raw() {
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let mut serve = SleeperRPCServer::new(SleeperImpl, JSONTransport::raw(s2));
+        serve.serve()
+    });
+    let sleeper = SleeperRPCClient::new(JSONTransport::raw(s1).with_call_timeouts());
+    match sleeper.nap(100) {
+        Ok(_) => panic!("nap should have timed out"),
+        Err(e) => assert!(e.msg.contains("timeout: "), "{}", e.msg),
+    }
+    // Nothing tells the late response apart from the next
+    match sleeper.doze(1) {
+        Ok(_) => panic!("the transport should be unusable"),
+        Err(e) => assert!(e.msg.contains("unusable"), "{}", e.msg),
+    }
+}
+
+#[test]
+fn method_timeout_overrides_transport_timeout() {
+    let sleeper = json_sleeper(|t| t.with_timeout(Duration::from_millis(50)).unwrap());
+    assert_eq!(sleeper.sleep(100).unwrap(), 100);
+    // The transport's own timeout is restored after the call
+    match sleeper.doze(200) {
+        Ok(_) => panic!("doze should have timed out"),
+        Err(e) => assert!(e.msg.contains("timeout: "), "{}", e.msg),
+    }
+}
+
+#[test]
+fn method_timeout_unsupported() {
+    let sleeper = json_sleeper(|t| t);
+    assert_eq!(sleeper.doze(0).unwrap(), 0);
+    match sleeper.nap(0) {
+        Ok(_) => panic!("nap should need with_call_timeouts"),
+        Err(e) => assert!(e.msg.contains("with_call_timeouts"), "{}", e.msg),
+    }
+}
+
+#[cfg(feature = "http_transport")]
+#[test]
+fn http_wire_format() {
+    use std::io::{BufRead, BufReader, Read};
+
+    let (mut s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let mut serve = FooRPCServer::new(FooImpl::new(), HTTPTransport::server(s2));
+        serve.serve()
+    });
+    let mut reader = BufReader::new(s1.try_clone().unwrap());
+    let mut read_response = || {
+        let mut head = Vec::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line == "\r\n" {
+                break;
+            }
+            head.push(line.trim_end().to_string());
+        }
+        let len = head
+            .iter()
+            .find_map(|h| h.strip_prefix("Content-Length: "))
+            .map_or(0, |len| len.parse().unwrap());
+        let mut body = vec![0; len];
+        reader.read_exact(&mut body).unwrap();
+        (head, body)
+    };
+
+    // Only POST is served
+    s1.write_all(b"GET /rpc HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let (head, _) = read_response();
+    assert_eq!(head[0], "HTTP/1.1 405 Method Not Allowed");
+    assert!(head.contains(&"Allow: POST".to_string()));
+
+    let body = br#"{"jsonrpc":"2.0","method":"bar","params":{"a":"x","b":1},"id":7}"#;
+    write!(
+        s1,
+        "POST /rpc HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    )
+    .unwrap();
+    s1.write_all(body).unwrap();
+    let (head, body) = read_response();
+    assert_eq!(head[0], "HTTP/1.1 200 OK");
+    assert!(head.contains(&"Content-Type: application/json".to_string()));
+    let response: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(response["result"], "x is 1");
+    assert_eq!(response["id"], 7);
+
+    // A request body may be chunked
+    let body = br#"{"jsonrpc":"2.0","method":"bar","params":{"a":"y","b":2},"id":8}"#;
+    write!(
+        s1,
+        "POST /rpc HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n",
+        10
+    )
+    .unwrap();
+    s1.write_all(&body[..10]).unwrap();
+    write!(s1, "\r\n{:x}\r\n", body.len() - 10).unwrap();
+    s1.write_all(&body[10..]).unwrap();
+    s1.write_all(b"\r\n0\r\n\r\n").unwrap();
+    let (_, body) = read_response();
+    let response: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(response["result"], "y is 2");
+}
+
+#[cfg(feature = "http_transport")]
+#[test]
+fn http_chunked_response() {
+    use std::io::{BufRead, BufReader, Read};
+
+    let (s1, s2) = UnixStream::pair().unwrap();
+    thread::spawn(move || {
+        let mut reader = BufReader::new(s2.try_clone().unwrap());
+        let mut len = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line == "\r\n" {
+                break;
+            }
+            if let Some(l) = line.trim_end().strip_prefix("Content-Length: ") {
+                len = l.parse().unwrap();
+            }
+        }
+        let mut body = vec![0; len];
+        reader.read_exact(&mut body).unwrap();
+        let request: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let response =
+            serde_json::json!({"jsonrpc": "2.0", "result": "chunked", "id": request["id"]})
+                .to_string();
+        let (first, rest) = response.split_at(5);
+        let mut s2 = s2;
+        write!(
+            s2,
+            "HTTP/1.1 100 Continue\r\n\r\n\
+             HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n\
+             {:x};ext=1\r\n{}\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+            first.len(),
+            first,
+            rest.len(),
+            rest
+        )
+        .unwrap();
+    });
+    let foo = FooRPCClient::new(HTTPTransport::new(s1, "http://localhost/rpc").unwrap());
+    assert_eq!(foo.bar("x".to_string(), 1).unwrap(), "chunked");
+}
+
+#[cfg(feature = "http_transport")]
+#[test]
+fn http_status_errors() {
+    let call = |status: &str| {
+        let response = format!("HTTP/1.1 {}\r\nContent-Length: 5\r\n\r\nproxy", status);
+        let channel = ReadWrite::new(io::Cursor::new(response.into_bytes()), io::sink());
+        let mut transport = HTTPTransport::new(channel, "http://localhost/rpc").unwrap();
+        let state = transport
+            .tx_begin_call(MethodId {
+                name: "bar",
+                num: 0,
+            })
+            .unwrap();
+        let id = transport.tx_finalize(state).unwrap();
+        transport.rx_response::<String>(id).unwrap_err()
+    };
+    assert_eq!(
+        call("503 Service Unavailable").kind,
+        RPCErrorKind::ConnectionFailed
+    );
+    assert_eq!(call("502 Bad Gateway").kind, RPCErrorKind::ConnectionFailed);
+    assert_eq!(call("504 Gateway Timeout").kind, RPCErrorKind::Timeout);
+    assert_eq!(
+        call("413 Payload Too Large").kind,
+        RPCErrorKind::MessageTooLarge
+    );
+    let e = call("404 Not Found");
+    assert_eq!(e.kind, RPCErrorKind::TransportError);
+    assert!(e.to_string().contains("404 Not Found"), "{}", e);
+}
+
+#[cfg(feature = "http_transport")]
+#[test]
+fn http_max_message_bytes() {
+    let (mut s1, s2) = UnixStream::pair().unwrap();
+    let server = thread::spawn(move || {
+        let transport = HTTPTransport::server(s2).with_max_message_bytes(16);
+        let mut serve = FooRPCServer::new(FooImpl::new(), transport);
+        serve.serve()
+    });
+    s1.write_all(b"POST / HTTP/1.1\r\nContent-Length: 100\r\n\r\n")
+        .unwrap();
+    let e = server.join().unwrap().unwrap_err();
+    assert_eq!(e.kind, RPCErrorKind::MessageTooLarge);
+    let mut response = String::new();
+    io::Read::read_to_string(&mut s1, &mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 413 "), "{}", response);
+}
+
+#[cfg(feature = "http_transport")]
+#[test]
+fn http_connect() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut serve = FooRPCServer::new(FooImpl::new(), HTTPTransport::server(stream));
+        serve.serve()
+    });
+    let url = format!("http://127.0.0.1:{}/rpc", port);
+    let foo = FooRPCClient::new(HTTPTransport::connect(&url).unwrap());
+    client42(&foo);
+    assert!(HTTPTransport::connect("ftp://127.0.0.1/").is_err());
+}
+
+#[cfg(feature = "http_server")]
+#[test]
+fn http_service() {
+    let service = HTTPService::new(|transport| FooRPCServer::new(FooImpl::new(), transport));
+    let response = service.respond(
+        &br#"{"jsonrpc":"2.0","id":1,"method":"bar","params":{"a":"the answer","b":42}}"#[..],
+    );
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "application/json");
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["result"], "the answer is 42");
+
+    // Each request is served afresh, and a batch as a whole
+    let response = service.respond(
+        &br#"[{"jsonrpc":"2.0","id":2,"method":"bar","params":{"a":"x","b":1}},
+              {"jsonrpc":"2.0","id":3,"method":"bar","params":{"a":"y","b":2}}]"#[..],
+    );
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body.as_array().unwrap().len(), 2);
+
+    // Neither a notification nor anything else but POST gets a body
+    let service =
+        HTTPService::new(|transport| TelemetryRPCServer::new(TelemetryImpl::default(), transport));
+    let response =
+        service.respond(&br#"{"jsonrpc":"2.0","method":"record","params":{"sample":1}}"#[..]);
+    assert_eq!(response.status(), 204);
+    assert!(response.body().is_empty());
+    let request = http::Request::get("/rpc").body(Vec::new()).unwrap();
+    let response = service.handle(request);
+    assert_eq!(response.status(), 405);
+    assert_eq!(response.headers()["allow"], "POST");
+
+    // A request which cannot be parsed is answered with a parse error
+    let request = http::Request::post("/rpc").body(&b"{"[..]).unwrap();
+    let response = service.handle(request);
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["error"]["code"], -32700);
+    let response = service.respond(&b"}"[..]);
+    let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+    assert_eq!(body["error"]["code"], -32700);
+}
+
+// Writer collecting what a tracing subscriber logs
+#[cfg(feature = "tracing")]
+#[derive(Clone, Default)]
+struct TraceLog(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+#[cfg(feature = "tracing")]
+impl Write for TraceLog {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Subscriber logging spans, as they close, to `log`
+#[cfg(feature = "tracing")]
+fn trace_dispatch(log: &TraceLog) -> tracing::Dispatch {
+    let log = log.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(move || log.clone())
+        .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
+        .finish();
+    tracing::Dispatch::new(subscriber)
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn tracing_spans() {
+    let log = TraceLog::default();
+    let client = trace_dispatch(&log);
+    let server = trace_dispatch(&log);
+    let serve = move |channel| {
+        let server = server.clone();
+        thread::spawn(move || {
+            tracing::dispatcher::with_default(&server, || {
+                FooRPCServer::new(FooImpl::new(), JSONTransport::new(channel)).serve()
+            })
+        })
+    };
+    // Each server finishes once its client hangs up, so its spans are
+    // logged by the time it is joined
+    let servers = tracing::dispatcher::with_default(&client, || {
+        let (s1, s2) = UnixStream::pair().unwrap();
+        let foo_server = serve(s2);
+        let mut transport = JSONTransport::new(s1);
+        transport.set_id_generator(Box::new(|| serde_json::json!("call-1")));
+        client42(&FooRPCClient::new(transport));
+
+        let (s1, s2) = UnixStream::pair().unwrap();
+        let accounts_server = serve(s2);
+        let accounts = AccountsRPCClient::new(JSONTransport::new(s1));
+        assert!(accounts.get("ann".to_string()).is_err());
+        vec![foo_server, accounts_server]
+    });
+    for server in servers {
+        let _ = server.join().unwrap();
+    }
+    let log = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+    assert!(
+        log.contains(r#"rpc.client{method="bar" id="call-1"}"#),
+        "{}",
+        log
+    );
+    assert!(
+        log.contains(r#"rpc.server{method=bar id="call-1"}"#),
+        "{}",
+        log
+    );
+    assert!(
+        log.contains(r#"rpc.client{method="Accounts.get" id="#),
+        "{}",
+        log
+    );
+    assert!(log.contains("rpc call failed"), "{}", log);
+}
+
+// Channel holding the requests a client wrote, from which the server
+// may only read. Any attempt to write is refused, and noted.
+struct ReadOnlyChannel {
+    requests: io::Cursor<Vec<u8>>,
+    wrote: std::rc::Rc<std::cell::Cell<bool>>,
+}
+
+impl Read for ReadOnlyChannel {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.requests.read(buf)
+    }
+}
+
+impl Write for ReadOnlyChannel {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        self.wrote.set(true);
+        Err(io::Error::new(io::ErrorKind::PermissionDenied, "read-only"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct SharedTelemetry(std::rc::Rc<std::cell::Cell<i32>>);
+
+impl Telemetry for SharedTelemetry {
+    fn record(&self, sample: i32) -> Result<(), TestError> {
+        self.0.set(self.0.get() + sample);
+        Ok(())
+    }
+    fn total(&self) -> Result<i32, TestError> {
+        Ok(self.0.get())
+    }
+    fn reset(&self) -> Result<(), TestError> {
+        self.0.set(0);
+        Ok(())
+    }
+}
+
+#[test]
+fn notifications_only_server() {
+    let mut requests = Vec::new();
+    {
+        let client =
+            TelemetryRPCClient::new(JSONTransport::new(ReadWrite::new(&[][..], &mut requests)));
+        client.record(3).unwrap();
+        // Nothing answers, but the call is sent
+        assert!(client.total().is_err());
+        client.record(4).unwrap();
+    }
+    assert!(JSONTransport::new(ReadWrite::new(&[][..], &mut requests))
+        .ping()
+        .is_err());
+    requests.extend_from_slice(br#"{"jsonrpc":"2.0","method":"nosuch","id":9}"#);
+    requests.extend_from_slice(br#"{"jsonrpc":"1.0","method":"reset","id":10}"#);
+    requests.extend_from_slice(br#"{"jsonrpc":"2.0","method":"record","params":{"sample":5}}"#);
+
+    let total = std::rc::Rc::new(std::cell::Cell::new(0));
+    let wrote = std::rc::Rc::new(std::cell::Cell::new(false));
+    let channel = ReadOnlyChannel {
+        requests: io::Cursor::new(requests),
+        wrote: wrote.clone(),
+    };
+    TelemetryRPCServer::new(SharedTelemetry(total.clone()), JSONTransport::new(channel))
+        .notifications_only()
+        .serve_loop()
+        .unwrap();
+    assert_eq!(total.get(), 12);
+    assert!(!wrote.get());
+
+    // Bincode has no notifications of its own, so the server alone
+    // keeps it from answering
+    let mut requests = Vec::new();
+    {
+        let client = TelemetryRPCClient::new(BincodeTransport::new(ReadWrite::new(
+            &[][..],
+            &mut requests,
+        )));
+        client.record(2).unwrap();
+        assert!(client.total().is_err());
+    }
+    let channel = ReadOnlyChannel {
+        requests: io::Cursor::new(requests),
+        wrote: wrote.clone(),
+    };
+    TelemetryRPCServer::new(
+        SharedTelemetry(total.clone()),
+        BincodeTransport::new(channel),
+    )
+    .notifications_only()
+    .serve_loop()
+    .unwrap();
+    assert_eq!(total.get(), 14);
+    assert!(!wrote.get());
 }
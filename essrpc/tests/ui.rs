@@ -0,0 +1,10 @@
+// Checks the diagnostics for misuse of #[essrpc]. The expected output
+// names ClientTransport::tx_add_param, which rustc qualifies once
+// AsyncClientTransport has a method of the same name, so the check is
+// left out with async_client.
+#[cfg(not(feature = "async_client"))]
+#[test]
+fn ui() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
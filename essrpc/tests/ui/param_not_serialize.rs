@@ -0,0 +1,17 @@
+use essrpc::essrpc;
+use essrpc::RPCError;
+use serde::Deserialize;
+
+// Can be received, but not sent
+#[derive(Deserialize)]
+pub struct Point {
+    pub x: i32,
+    pub y: i32,
+}
+
+#[essrpc]
+pub trait Plotter {
+    fn plot(&self, point: Point) -> Result<(), RPCError>;
+}
+
+fn main() {}
@@ -12,12 +12,13 @@ use core::convert::AsRef;
 use proc_macro::TokenStream;
 use proc_macro2::TokenStream as TokenStream2;
 use proc_macro2::{Ident, Span, TokenTree};
-use quote::{quote, ToTokens};
+use quote::{quote, quote_spanned, ToTokens};
+use std::collections::HashSet;
 use std::ops::Deref;
 use syn::parse_quote;
 use syn::{
-    punctuated::Punctuated, token::Comma, /*spanned::Spanned,*/ FnArg, ItemTrait, LitStr, Pat,
-    TraitItem, TraitItemMethod,
+    punctuated::Punctuated, spanned::Spanned, token::Comma, FnArg, ItemTrait, Lit, LitStr, Meta,
+    NestedMeta, Pat, PatType, TraitItem, TraitItemMethod,
 };
 
 /// The main macro which does the magic. When applied to a trait `Foo`
@@ -31,58 +32,269 @@ use syn::{
 /// `Result` and a `FooAsyncRPCClient` type implementing `FooAsync`
 /// and [AsyncRPCClient](../essrpc/trait.AsyncRPCClient.html).
 ///
+/// For an asynchronous server, the argument `async_server` generates
+/// a `FooAsyncRPCServer` type implementing
+/// [AsyncRPCServer](../essrpc/trait.AsyncRPCServer.html).
+///
+/// Each generated client and server has a constant `METHODS` listing
+/// the number and name of every method, in the order the trait
+/// declares them, for proxies and gateways routing calls by name
+/// (e.g. `FooRPCServer::<FooImpl, JSONTransport<TcpStream>>::METHODS`).
+///
+/// Servers may be given an
+/// [Authorizer](../essrpc/type.Authorizer.html) with
+/// `with_authorizer`, which sees each call's method and transport
+/// state, such as its metadata, before it is dispatched, and may
+/// refuse it. A server made `notifications_only` never writes to its
+/// transport, dropping the results of all calls as for notifications.
+///
+/// If the trait's methods are `async fn`, the generated
+/// `FooRPCClient` requires an
+/// [AsyncClientTransport](../essrpc/trait.AsyncClientTransport.html)
+/// and implements `Foo` directly, awaiting the transport, and only
+/// `FooAsyncRPCServer` is generated, awaiting the implementation. All
+/// methods of such a trait must be async and the `sync` and `async`
+/// arguments do not apply.
+///
+/// Individual methods may be marked `#[essrpc(notification)]` to
+/// make them one-way: the client does not wait for a response and
+/// the server does not send one. Such methods must return
+/// `Result<(), E>`.
+///
+/// Methods marked `#[essrpc(stream)]` must return `impl Iterator<Item
+/// = Result<T, E>>`. The server sends each item as the implementation
+/// produces it, and the client's method returns an iterator yielding
+/// them as they arrive. Dropping the iterator early asks the server
+/// to stop, which it checks for between items. Streaming is only
+/// supported by synchronous clients and servers.
+///
+/// The argument `namespace = "Name"` prefixes each method's name on
+/// the wire with `Name.`, so that `get` is called as `Name.get`. The
+/// server only answers to the prefixed names. This lets the methods
+/// of several traits be told apart by a single dispatcher, such as a
+/// [Router](../essrpc/transports/struct.Router.html), over a format
+/// which sends method names, such as JSON.
+///
+/// The argument `version = N` stamps each call from the client with
+/// version `N` of the trait, and the server refuses calls stamped
+/// with any other version with `RPCErrorKind::VersionMismatch` before
+/// dispatching them. `with_compatible_versions` on the server widens
+/// this to a range of versions. Calls without a version, such as
+/// those from a client of an unversioned trait, are served. Only
+/// transports which can carry the version, such as JSON, send it;
+/// others leave calls unstamped.
+///
+/// Parameters are named on the wire by their identifiers, unless
+/// marked `#[essrpc(rename = "wireName")]`.
+///
+/// A parameter marked `#[essrpc(default)]` may be left out by the
+/// client, as a client built against an older version of the trait
+/// would, in which case the server passes `Default::default()`, or
+/// `None` for an `Option`. Only transports which name parameters on
+/// the wire, such as JSON, CBOR and MessagePack, can tell that one is
+/// missing.
+///
+/// Parameters of byte types such as `Vec<u8>` or `&[u8]` may be
+/// marked `#[essrpc(bytes)]` to send them as
+/// [Bytes](../essrpc/struct.Bytes.html): a base64 string in JSON
+/// and raw bytes in binary formats, rather than a sequence of
+/// numbers. The server receives a
+/// [ByteBuf](../essrpc/struct.ByteBuf.html), which is converted to
+/// the parameter's type with `From`, or borrowed if it is a
+/// reference.
+///
+/// The last parameter may be an [Upload](../essrpc/struct.Upload.html)
+/// marked `#[essrpc(upload)]`, whose bytes the client streams from a
+/// reader and the server's method reads from the channel as it goes,
+/// rather than either holding them all in memory. Needs a transport
+/// supporting uploads, such as bincode, and is only supported by
+/// synchronous clients and servers, on methods which do not stream
+/// their response.
+///
+/// A method returning `Result<(A, B), E>` may be marked
+/// `#[essrpc(returns(a, b))]` to send its tuple as a struct with
+/// those field names, such as a JSON object, rather than as a
+/// sequence. The client still receives the tuple.
+///
+/// A method marked `#[essrpc(timeout_ms = 50)]` fails with
+/// `RPCErrorKind::Timeout` if its response does not arrive within
+/// that many milliseconds. Synchronous clients set the timeout on
+/// the transport for the call with
+/// [ClientTransport::tx_set_timeout](../essrpc/trait.ClientTransport.html#method.tx_set_timeout),
+/// so need a transport supporting it, and asynchronous clients apply
+/// [timeout](../essrpc/fn.timeout.html) to the response. Not
+/// supported on notifications or streaming methods.
+///
+/// Methods may be generic over type parameters, but the server must
+/// be compiled for each type it accepts, listed with
+/// `#[essrpc(types(T = "i32, String"))]`. The client sends the name
+/// of the type it calls the method with, from
+/// `std::any::type_name`, which the server matches against the listed
+/// types, so both ends should be built by the same compiler. Calls
+/// with other types fail with `RPCErrorKind::SerializationError`.
+/// Lifetime and const parameters, `impl Trait` parameters and the
+/// `async` client are not supported.
+///
 /// See the crate-level documentation for examples.
 #[proc_macro_attribute]
 pub fn essrpc(args: TokenStream, input: TokenStream) -> TokenStream {
     let args: TokenStream2 = args.into();
     let mut sync_client = false;
     let mut async_client = false;
-    for tok in args {
+    let mut async_server = false;
+    let mut namespace: Option<String> = None;
+    let mut version: Option<u32> = None;
+    let mut args = args.into_iter();
+    while let Some(tok) = args.next() {
         if let TokenTree::Ident(ident) = tok {
             match ident.to_string().as_ref() {
                 "sync" => sync_client = true,
                 "async" => async_client = true,
+                "async_server" => async_server = true,
+                "namespace" => namespace = Some(parse_namespace(&mut args)),
+                "version" => version = Some(parse_version(&mut args)),
                 _ => (),
             }
         }
     }
-
-    if !sync_client && !async_client {
-        sync_client = true
-    }
-
-    let mut result: TokenStream2 = input.clone().into();
+    let namespace = namespace.as_deref();
 
     // TODO better error handling
-    let ast_trait: ItemTrait = syn::parse(input).unwrap();
+    let mut ast_trait: ItemTrait = syn::parse(input).unwrap();
 
-    let trait_ident = ast_trait.ident;
+    let trait_ident = ast_trait.ident.clone();
 
     let mut methods: Vec<TraitItemMethod> = Vec::new();
 
-    // Look at each method
-    for item in ast_trait.items {
+    // Look at each method. Our own attributes are kept on the copies
+    // used for code generation but removed from the trait itself.
+    for item in ast_trait.items.iter_mut() {
         if let TraitItem::Method(m) = item {
+            verify_notification(m);
+            verify_stream(m);
+            verify_generics(m);
+            verify_return_names(m);
+            verify_timeout(m);
+            verify_upload(m);
+            verify_param_names(m);
             methods.push(m.clone());
+            m.attrs.retain(|attr| !attr.path.is_ident("essrpc"));
+            m.sig.inputs = strip_param_attrs(&m.sig.inputs);
         }
     }
 
+    let async_fns = methods.iter().any(is_async);
+    if async_fns || async_client || async_server {
+        verify_no_streams(&trait_ident, &methods);
+        verify_no_uploads(&trait_ident, &methods);
+    }
+    if async_client {
+        verify_no_generics(&trait_ident, &methods);
+    }
+    if async_fns {
+        verify_async_fns(&trait_ident, &methods, sync_client || async_client);
+        // The generated client and server are not Send, so there is
+        // no Send bound the trait could usefully promise.
+        ast_trait
+            .attrs
+            .push(parse_quote!(#[allow(async_fn_in_trait)]));
+    } else if !sync_client && !async_client {
+        sync_client = true
+    }
+
+    let mut result: TokenStream2 = ast_trait.into_token_stream();
+    result.extend(create_param_assertions(&methods));
+
+    if async_fns {
+        result.extend(create_client(
+            &trait_ident,
+            &methods,
+            namespace,
+            version,
+            true,
+        ));
+        result.extend(create_server(
+            &trait_ident,
+            &methods,
+            namespace,
+            version,
+            true,
+        ));
+        return result.into();
+    }
+
     if async_client {
         result.extend(create_async_client_trait(&trait_ident, &methods));
         result.extend(create_client(
             &async_client_trait_ident(&trait_ident),
             &methods,
+            namespace,
+            version,
             true,
         ));
     }
     if sync_client {
-        result.extend(create_client(&trait_ident, &methods, false));
+        result.extend(create_client(
+            &trait_ident,
+            &methods,
+            namespace,
+            version,
+            false,
+        ));
+    }
+    result.extend(create_server(
+        &trait_ident,
+        &methods,
+        namespace,
+        version,
+        false,
+    ));
+    if async_server {
+        result.extend(create_server(
+            &trait_ident,
+            &methods,
+            namespace,
+            version,
+            true,
+        ));
     }
-    result.extend(create_server(&trait_ident, &methods));
 
     result.into()
 }
 
+// Assertions that each parameter type can be serialized and
+// deserialized. Without them a type which cannot is reported deep
+// inside the generated client and server, rather than at the
+// parameter. Generic methods are left to the compiler, as their
+// parameter types may name type parameters.
+fn create_param_assertions(methods: &[TraitItemMethod]) -> TokenStream2 {
+    let mut assertions = TokenStream2::new();
+    for method in methods {
+        if !method.sig.generics.params.is_empty() {
+            continue;
+        }
+        for p in method.sig.inputs.iter() {
+            // Bytes are sent as essrpc::Bytes, whatever their type,
+            // and uploads are streamed rather than serialized
+            if let FnArg::Typed(arg) = p {
+                if is_bytes_param(arg) || is_upload_param(arg) {
+                    continue;
+                }
+                let ty = &arg.ty;
+                assertions.extend(quote_spanned!(ty.span()=> essrpc::assert_param::<#ty>();));
+            }
+        }
+    }
+    if assertions.is_empty() {
+        return assertions;
+    }
+    quote!(
+        const _: fn() = || {
+            #assertions
+        };
+    )
+}
+
 fn client_ident(trait_ident: &Ident) -> Ident {
     Ident::new(&format!("{}RPCClient", trait_ident), Span::call_site())
 }
@@ -117,6 +329,21 @@ fn server_ident(trait_ident: &Ident) -> Ident {
     Ident::new(&format!("{}RPCServer", trait_ident), Span::call_site())
 }
 
+fn async_server_ident(trait_ident: &Ident) -> Ident {
+    Ident::new(&format!("{}AsyncRPCServer", trait_ident), Span::call_site())
+}
+
+fn server_transport_ident(async_server: bool) -> Ident {
+    Ident::new(
+        if async_server {
+            "AsyncServerTransport"
+        } else {
+            "ServerTransport"
+        },
+        Span::call_site(),
+    )
+}
+
 fn make_pat_literal_str(pat: &Pat) -> LitStr {
     match pat {
         Pat::Ident(p) => make_ident_literal_str(&p.ident),
@@ -129,6 +356,548 @@ fn make_ident_literal_str(ident: &Ident) -> LitStr {
     LitStr::new(&as_str, Span::call_site())
 }
 
+// The string literal following `namespace =` in the trait's arguments.
+fn parse_namespace(args: &mut impl Iterator<Item = TokenTree>) -> String {
+    match (args.next(), args.next()) {
+        (Some(TokenTree::Punct(eq)), Some(TokenTree::Literal(lit))) if eq.as_char() == '=' => {
+            match syn::parse2::<LitStr>(TokenTree::Literal(lit).into()) {
+                Ok(lit) if !lit.value().is_empty() => lit.value(),
+                _ => panic!("essrpc namespace must be a non-empty string"),
+            }
+        }
+        _ => panic!("expected namespace = \"Name\" in essrpc arguments"),
+    }
+}
+
+// The integer literal following `version =` in the trait's arguments.
+fn parse_version(args: &mut impl Iterator<Item = TokenTree>) -> u32 {
+    match (args.next(), args.next()) {
+        (Some(TokenTree::Punct(eq)), Some(TokenTree::Literal(lit))) if eq.as_char() == '=' => {
+            match syn::parse2::<syn::LitInt>(TokenTree::Literal(lit).into()) {
+                Ok(lit) => lit
+                    .base10_parse()
+                    .unwrap_or_else(|_| panic!("essrpc version must be a u32")),
+                _ => panic!("essrpc version must be an integer"),
+            }
+        }
+        _ => panic!("expected version = N in essrpc arguments"),
+    }
+}
+
+// The VERSION constant of the generated client and server: the
+// trait's version, if it has one.
+fn version_const(version: Option<u32>) -> TokenStream2 {
+    let version = match version {
+        Some(version) => quote!(Some(#version)),
+        None => quote!(None),
+    };
+    quote!(pub const VERSION: Option<u32> = #version;)
+}
+
+// Name of a method on the wire: its identifier, prefixed by the
+// trait's namespace, if it has one.
+fn method_name_literal(ident: &Ident, namespace: Option<&str>) -> LitStr {
+    match namespace {
+        Some(namespace) => LitStr::new(&format!("{}.{}", namespace, ident), Span::call_site()),
+        None => make_ident_literal_str(ident),
+    }
+}
+
+// Name of a parameter on the wire: its identifier, unless renamed by
+// `#[essrpc(rename = "...")]`.
+fn param_name_literal(arg: &PatType) -> LitStr {
+    param_attrs(arg)
+        .into_iter()
+        .find_map(|nested| match nested {
+            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("rename") => match nv.lit {
+                Lit::Str(name) => Some(name),
+                _ => None,
+            },
+            _ => None,
+        })
+        .unwrap_or_else(|| make_pat_literal_str(&arg.pat))
+}
+
+// True if a parameter is marked `#[essrpc(bytes)]`, to be sent as
+// essrpc::Bytes.
+fn is_bytes_param(arg: &PatType) -> bool {
+    has_param_flag(arg, "bytes")
+}
+
+// True if a parameter is marked `#[essrpc(upload)]`, to be streamed
+// as an essrpc::Upload.
+fn is_upload_param(arg: &PatType) -> bool {
+    has_param_flag(arg, "upload")
+}
+
+// True if a parameter is marked `#[essrpc(default)]`, so that the
+// server uses its default value when the client does not send it.
+fn is_default_param(arg: &PatType) -> bool {
+    has_param_flag(arg, "default")
+}
+
+fn has_param_flag(arg: &PatType, flag: &str) -> bool {
+    param_attrs(arg)
+        .iter()
+        .any(|nested| matches!(nested, NestedMeta::Meta(Meta::Path(path)) if path.is_ident(flag)))
+}
+
+// The items of a parameter's `#[essrpc(...)]` attributes, which may
+// be `rename = "..."`, `bytes`, `default` and `upload`.
+fn param_attrs(arg: &PatType) -> Vec<NestedMeta> {
+    let malformed = || -> ! {
+        panic!(
+            "Malformed essrpc attribute on parameter {}",
+            arg.pat.clone().into_token_stream()
+        )
+    };
+    let mut items = Vec::new();
+    for attr in arg.attrs.iter().filter(|attr| attr.path.is_ident("essrpc")) {
+        let list = match attr.parse_meta() {
+            Ok(Meta::List(list)) => list,
+            _ => malformed(),
+        };
+        for nested in list.nested {
+            match &nested {
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("rename") => (),
+                NestedMeta::Meta(Meta::Path(path))
+                    if path.is_ident("bytes")
+                        || path.is_ident("default")
+                        || path.is_ident("upload") => {}
+                _ => malformed(),
+            }
+            items.push(nested);
+        }
+    }
+    items
+}
+
+// Parameters without our own attributes, which the compiler would
+// not accept in generated code.
+fn strip_param_attrs(inputs: &Punctuated<FnArg, Comma>) -> Punctuated<FnArg, Comma> {
+    let mut inputs = inputs.clone();
+    for p in inputs.iter_mut() {
+        if let FnArg::Typed(arg) = p {
+            arg.attrs.retain(|attr| !attr.path.is_ident("essrpc"));
+        }
+    }
+    inputs
+}
+
+// True if the method has the given flag in an `#[essrpc(...)]`
+// attribute.
+fn has_method_flag(method: &TraitItemMethod, flag: &str) -> bool {
+    method
+        .attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("essrpc"))
+        .any(|attr| match attr.parse_meta() {
+            Ok(Meta::List(list)) => list.nested.iter().any(|nested| match nested {
+                NestedMeta::Meta(Meta::Path(path)) => path.is_ident(flag),
+                _ => false,
+            }),
+            _ => panic!("Malformed essrpc attribute on {}", method.sig.ident),
+        })
+}
+
+fn is_async(method: &TraitItemMethod) -> bool {
+    method.sig.asyncness.is_some()
+}
+
+// Panics unless every RPC method of a trait with async methods is
+// async, as the generated client and server are wholly async.
+fn verify_async_fns(trait_ident: &Ident, methods: &[TraitItemMethod], client_args: bool) {
+    if client_args {
+        panic!(
+            "essrpc arguments sync and async do not apply to trait {} with async methods",
+            trait_ident
+        );
+    }
+    for method in methods {
+        if has_self_param(method) && !is_async(method) {
+            panic!(
+                "RPC trait {} has async methods, so {} must be async too",
+                trait_ident, method.sig.ident
+            );
+        }
+    }
+}
+
+fn is_notification(method: &TraitItemMethod) -> bool {
+    has_method_flag(method, "notification")
+}
+
+// Panics if a notification does not return Result<(), E>, as there is
+// no response to take any other value from.
+fn verify_notification(method: &TraitItemMethod) {
+    if !is_notification(method) {
+        return;
+    }
+    match get_result_types(&method.sig.output) {
+        Some((syn::Type::Tuple(ok_type), _)) if ok_type.elems.is_empty() => (),
+        _ => panic!(
+            "notification method {} must return Result<(), E>",
+            method.sig.ident
+        ),
+    }
+}
+
+fn is_stream(method: &TraitItemMethod) -> bool {
+    has_method_flag(method, "stream")
+}
+
+// Panics if a streaming method does not return impl Iterator<Item =
+// Result<T, E>>, or is also a notification.
+fn verify_stream(method: &TraitItemMethod) {
+    if !is_stream(method) {
+        return;
+    }
+    if is_notification(method) {
+        panic!(
+            "method {} cannot be both a notification and a stream",
+            method.sig.ident
+        );
+    }
+    get_stream_item_types(method);
+}
+
+// Panics if any method streams its response, which asynchronous
+// clients and servers do not support.
+fn verify_no_streams(trait_ident: &Ident, methods: &[TraitItemMethod]) {
+    if let Some(method) = methods.iter().find(|m| is_stream(m)) {
+        panic!(
+            "streaming method {} of trait {} is only supported by synchronous clients and servers",
+            method.sig.ident, trait_ident
+        );
+    }
+}
+
+// Panics if an upload parameter is not the method's last, as the
+// server reads it after the others, or is on a streaming method,
+// whose response would be sent while the upload is still borrowing
+// the transport.
+fn verify_upload(method: &TraitItemMethod) {
+    let mut params = method.sig.inputs.iter().filter_map(|p| match p {
+        FnArg::Typed(arg) => Some(arg),
+        _ => None,
+    });
+    let last = params.next_back();
+    if let Some(arg) = params.find(|arg| is_upload_param(arg)) {
+        panic!(
+            "upload parameter {} of RPC method {} must be the last parameter",
+            arg.pat.clone().into_token_stream(),
+            method.sig.ident
+        );
+    }
+    if last.is_some_and(is_upload_param) && is_stream(method) {
+        panic!(
+            "streaming method {} cannot take an upload parameter",
+            method.sig.ident
+        );
+    }
+}
+
+// Panics if two parameters have the same name on the wire, through
+// `#[essrpc(rename = "...")]`, as the second would replace the first
+// among named params.
+fn verify_param_names(method: &TraitItemMethod) {
+    let mut names = HashSet::new();
+    for p in method.sig.inputs.iter() {
+        if let FnArg::Typed(arg) = p {
+            let name = param_name_literal(arg).value();
+            if !names.insert(name.clone()) {
+                panic!(
+                    "parameter {} of RPC method {} has the same name, \"{}\", as another parameter",
+                    arg.pat.clone().into_token_stream(),
+                    method.sig.ident,
+                    name
+                );
+            }
+        }
+    }
+}
+
+// Panics if any method takes an upload parameter, which asynchronous
+// clients and servers do not support.
+fn verify_no_uploads(trait_ident: &Ident, methods: &[TraitItemMethod]) {
+    let has_upload = |method: &&TraitItemMethod| {
+        method
+            .sig
+            .inputs
+            .iter()
+            .any(|p| matches!(p, FnArg::Typed(arg) if is_upload_param(arg)))
+    };
+    if let Some(method) = methods.iter().find(has_upload) {
+        panic!(
+            "method {} of trait {} takes an upload parameter, which is only supported by synchronous clients and servers",
+            method.sig.ident, trait_ident
+        );
+    }
+}
+
+/// For a return type of the form impl Iterator<Item = Result<T, E>>,
+/// figure out what T and E are.
+fn get_stream_item_types(method: &TraitItemMethod) -> (syn::Type, syn::Type) {
+    let item_type = match get_return_type(method) {
+        syn::Type::ImplTrait(it) => it.bounds.iter().find_map(|bound| match bound {
+            syn::TypeParamBound::Trait(tb) => {
+                let seg = tb.path.segments.last()?;
+                if seg.ident != "Iterator" {
+                    return None;
+                }
+                match &seg.arguments {
+                    syn::PathArguments::AngleBracketed(args) => {
+                        args.args.iter().find_map(|arg| match arg {
+                            syn::GenericArgument::Binding(b) if b.ident == "Item" => {
+                                Some(b.ty.clone())
+                            }
+                            _ => None,
+                        })
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }),
+        _ => None,
+    };
+    item_type
+        .and_then(|ty| get_result_types(&syn::ReturnType::Type(Default::default(), Box::new(ty))))
+        .unwrap_or_else(|| {
+            panic!(
+                "streaming method {} must return impl Iterator<Item = Result<T, E>>",
+                method.sig.ident
+            )
+        })
+}
+
+// The field names given by `#[essrpc(returns(a, b))]`, if any.
+fn get_return_names(method: &TraitItemMethod) -> Option<Vec<Ident>> {
+    let mut names = None;
+    for attr in method
+        .attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("essrpc"))
+    {
+        let nested = match attr.parse_meta() {
+            Ok(Meta::List(list)) => list.nested,
+            _ => panic!("Malformed essrpc attribute on {}", method.sig.ident),
+        };
+        for returns in nested.iter().filter_map(|nested| match nested {
+            NestedMeta::Meta(Meta::List(returns)) if returns.path.is_ident("returns") => {
+                Some(returns)
+            }
+            _ => None,
+        }) {
+            let idents = returns.nested.iter().map(|nested| match nested {
+                NestedMeta::Meta(Meta::Path(path)) if path.get_ident().is_some() => {
+                    path.get_ident().unwrap().clone()
+                }
+                _ => panic!("Malformed essrpc returns on {}", method.sig.ident),
+            });
+            names = Some(idents.collect());
+        }
+    }
+    names
+}
+
+// The timeout given by `#[essrpc(timeout_ms = ...)]`, if any, as an
+// expression for the Duration.
+fn get_timeout(method: &TraitItemMethod) -> Option<TokenStream2> {
+    let mut timeout = None;
+    for attr in method
+        .attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("essrpc"))
+    {
+        let nested = match attr.parse_meta() {
+            Ok(Meta::List(list)) => list.nested,
+            _ => panic!("Malformed essrpc attribute on {}", method.sig.ident),
+        };
+        for nested in nested.iter() {
+            if let NestedMeta::Meta(Meta::NameValue(nv)) = nested {
+                if !nv.path.is_ident("timeout_ms") {
+                    continue;
+                }
+                let ms: u64 = match &nv.lit {
+                    Lit::Int(ms) => ms.base10_parse().ok(),
+                    _ => None,
+                }
+                .unwrap_or_else(|| {
+                    panic!(
+                        "essrpc timeout_ms on {} must be a number of milliseconds",
+                        method.sig.ident
+                    )
+                });
+                timeout = Some(quote!(std::time::Duration::from_millis(#ms)));
+            }
+        }
+    }
+    timeout
+}
+
+// Panics if a method without a response to wait for has a timeout.
+fn verify_timeout(method: &TraitItemMethod) {
+    if get_timeout(method).is_some() && (is_notification(method) || is_stream(method)) {
+        panic!(
+            "essrpc timeout_ms is not supported on notification or streaming method {}",
+            method.sig.ident
+        );
+    }
+}
+
+// Panics unless a method with named returns returns a tuple with one
+// name for each element, and has a response to send it in.
+fn verify_return_names(method: &TraitItemMethod) {
+    let names = match get_return_names(method) {
+        Some(names) => names,
+        None => return,
+    };
+    if is_notification(method) || is_stream(method) {
+        panic!(
+            "essrpc returns is not supported on notification or streaming method {}",
+            method.sig.ident
+        );
+    }
+    let elems = match get_result_types(&method.sig.output) {
+        Some((syn::Type::Tuple(ok_type), _)) => ok_type.elems.len(),
+        _ => 0,
+    };
+    if elems == 0 || elems > 8 || elems != names.len() {
+        panic!(
+            "method {} with essrpc returns must return Result<(...), E> with a tuple of \
+             one to eight elements, one for each name",
+            method.sig.ident
+        );
+    }
+}
+
+// For a method with named returns, the declaration of the type naming
+// its fields, and the type its result is sent as in place of the
+// return type.
+fn named_return_types(method: &TraitItemMethod) -> Option<(TokenStream2, syn::Type)> {
+    let names: Vec<LitStr> = get_return_names(method)?
+        .iter()
+        .map(make_ident_literal_str)
+        .collect();
+    let (ok_type, err_type) = get_result_types(&method.sig.output)?;
+    let decl = quote!(
+        struct EssrpcReturnNames;
+        impl essrpc::ReturnNames for EssrpcReturnNames {
+            const NAMES: &'static [&'static str] = &[#(#names),*];
+        }
+    );
+    let wire_type = parse_quote!(
+        std::result::Result<essrpc::NamedReturn<#ok_type, EssrpcReturnNames>, #err_type>
+    );
+    Some((decl, wire_type))
+}
+
+// Name on the wire of the type tag for a generic method's type
+// parameter. It cannot collide with a parameter name.
+fn type_tag_literal(param: &Ident) -> LitStr {
+    LitStr::new(&format!("<{}>", param), Span::call_site())
+}
+
+// The types each type parameter of a generic method may be
+// instantiated with on the server, from `#[essrpc(types(T =
+// "i32, String"))]`, in the order the parameters are declared.
+fn get_type_param_instances(method: &TraitItemMethod) -> Vec<(Ident, Vec<syn::Type>)> {
+    let mut listed: Vec<(Ident, Vec<syn::Type>)> = Vec::new();
+    for attr in method
+        .attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("essrpc"))
+    {
+        let nested = match attr.parse_meta() {
+            Ok(Meta::List(list)) => list.nested,
+            _ => panic!("Malformed essrpc attribute on {}", method.sig.ident),
+        };
+        for types in nested.iter().filter_map(|nested| match nested {
+            NestedMeta::Meta(Meta::List(types)) if types.path.is_ident("types") => Some(types),
+            _ => None,
+        }) {
+            for nested in types.nested.iter() {
+                let (param, lit) = match nested {
+                    NestedMeta::Meta(Meta::NameValue(nv)) => match (nv.path.get_ident(), &nv.lit) {
+                        (Some(param), Lit::Str(lit)) => (param.clone(), lit),
+                        _ => panic!("Malformed essrpc types on {}", method.sig.ident),
+                    },
+                    _ => panic!("Malformed essrpc types on {}", method.sig.ident),
+                };
+                let parsed = lit
+                    .parse_with(Punctuated::<syn::Type, Comma>::parse_terminated)
+                    .unwrap_or_else(|e| {
+                        panic!(
+                            "essrpc types for {} of {} are not a list of types: {}",
+                            param, method.sig.ident, e
+                        )
+                    });
+                listed.push((param, parsed.into_iter().collect()));
+            }
+        }
+    }
+
+    let mut instances = Vec::new();
+    for param in method.sig.generics.type_params() {
+        let types = listed
+            .iter()
+            .position(|(listed, _)| *listed == param.ident)
+            .map(|i| listed.remove(i).1);
+        match types {
+            Some(types) if !types.is_empty() => instances.push((param.ident.clone(), types)),
+            _ => panic!(
+                "generic RPC method {} must list the types the server accepts for {} \
+                 with #[essrpc(types({} = \"...\"))]",
+                method.sig.ident, param.ident, param.ident
+            ),
+        }
+    }
+    if let Some((param, _)) = listed.first() {
+        panic!(
+            "essrpc types given for {}, which is not a type parameter of {}",
+            param, method.sig.ident
+        );
+    }
+    instances
+}
+
+// Panics if a method is generic in a way the generated code cannot
+// support: over lifetimes or constants, or through impl Trait
+// parameters, which the server cannot name.
+fn verify_generics(method: &TraitItemMethod) {
+    let generics = &method.sig.generics;
+    if generics.lifetimes().next().is_some() || generics.const_params().next().is_some() {
+        panic!(
+            "generic RPC method {} may only have type parameters",
+            method.sig.ident
+        );
+    }
+    for p in method.sig.inputs.iter() {
+        if let FnArg::Typed(arg) = p {
+            if let syn::Type::ImplTrait(_) = *arg.ty {
+                panic!(
+                    "parameter {} of RPC method {} cannot be impl Trait, use a named type parameter",
+                    arg.pat.clone().into_token_stream(),
+                    method.sig.ident
+                );
+            }
+        }
+    }
+    get_type_param_instances(method);
+}
+
+// Panics if any method is generic, which the async client does not
+// support.
+fn verify_no_generics(trait_ident: &Ident, methods: &[TraitItemMethod]) {
+    if let Some(method) = methods.iter().find(|m| !m.sig.generics.params.is_empty()) {
+        panic!(
+            "generic method {} of trait {} is not supported by the async client",
+            method.sig.ident, trait_ident
+        );
+    }
+}
+
 // True if has self param, false if has default implementation. Panics
 // if no self and no default.
 fn verify_self_param_or_unneeded(method: &TraitItemMethod) -> bool {
@@ -152,33 +921,205 @@ fn has_self_param(method: &TraitItemMethod) -> bool {
 }
 
 // Client method implementation for the call to tx_begin_call through
-// tx_finalize. This portion is shared between sync and async.
-fn client_method_tx_send(method: &TraitItemMethod, id: u32) -> TokenStream2 {
+// tx_add_param for each parameter. This portion is shared between
+// sync and async and between calls and notifications.
+fn client_method_tx_begin(
+    method: &TraitItemMethod,
+    namespace: Option<&str>,
+    id: u32,
+) -> TokenStream2 {
+    let tx_params = client_method_tx_params(method, namespace, id);
+    quote!(
+        let mut tr = self.tr.borrow_mut();
+        #tx_params
+    )
+}
+
+// As client_method_tx_begin, but with the transport already borrowed
+// as `tr`.
+fn client_method_tx_params(
+    method: &TraitItemMethod,
+    namespace: Option<&str>,
+    id: u32,
+) -> TokenStream2 {
     let ident = &method.sig.ident;
     let param_tokens = &method.sig.inputs;
 
     let mut add_param_tokens = TokenStream2::new();
 
+    // Type tags of a generic method come first, as the server needs
+    // them to know the types of the other parameters.
+    for param in method.sig.generics.type_params() {
+        let ty = &param.ident;
+        let tag_literal = type_tag_literal(ty);
+        add_param_tokens.extend(quote!(
+            tr.tx_add_param(#tag_literal, std::any::type_name::<#ty>(), &mut state)?;));
+    }
+
     for p in param_tokens.iter() {
         if let FnArg::Typed(arg) = p {
             let name = &arg.pat;
-            let name_literal = make_pat_literal_str(name);
-            add_param_tokens.extend(quote!(tr.tx_add_param(#name_literal, #name, &mut state)?;));
+            let name_literal = param_name_literal(arg);
+            add_param_tokens.extend(if is_bytes_param(arg) {
+                quote!(tr.tx_add_param(#name_literal, essrpc::Bytes::new(&#name[..]), &mut state)?;)
+            } else if is_upload_param(arg) {
+                quote!(tr.tx_add_upload(#name_literal, #name, &mut state)?;)
+            } else {
+                quote!(tr.tx_add_param(#name_literal, #name, &mut state)?;)
+            });
         }
     }
 
-    let ident_literal = make_ident_literal_str(ident);
+    let ident_literal = method_name_literal(ident, namespace);
     quote!(
-        let mut tr = self.tr.borrow_mut();
         let mut state = tr.tx_begin_call(essrpc::MethodId{name: #ident_literal, num: #id})?;
+        if let Some(version) = Self::VERSION {
+            tr.tx_set_service_version(version, &mut state)?;
+        }
         #add_param_tokens
+    )
+}
+
+// Client method implementation for the call to tx_begin_call through
+// tx_finalize. This portion is shared between sync and async.
+fn client_method_tx_send(
+    method: &TraitItemMethod,
+    namespace: Option<&str>,
+    id: u32,
+) -> TokenStream2 {
+    let tx_begin = client_method_tx_begin(method, namespace, id);
+    quote!(
+        #tx_begin
         let state = tr.tx_finalize(state)?;
     )
 }
 
-fn impl_client_method(method: &TraitItemMethod, id: u32) -> TokenStream2 {
+// Synchronous client method implementation for sending the call and
+// reading its response into `ret`, which is of `wire_type`, within
+// the call's span. The transport's timeout is set around the call if
+// the method has one, and put back whether or not the call succeeds.
+fn client_method_tx_rx(
+    method: &TraitItemMethod,
+    namespace: Option<&str>,
+    id: u32,
+    wire_type: &syn::Type,
+) -> TokenStream2 {
+    let call = match get_timeout(method) {
+        Some(timeout) => {
+            let tx_begin = client_method_tx_begin(method, namespace, id);
+            quote!(
+                #tx_begin
+                tr.tx_set_timeout(Some(#timeout))?;
+                let ret = tr.tx_finalize(state).and_then(|state| tr.rx_response(state));
+                let reset = tr.tx_set_timeout(None);
+                ret.and_then(|ret| reset.map(|_| ret))
+            )
+        }
+        None => {
+            let tx_send = client_method_tx_send(method, namespace, id);
+            quote!(
+                #tx_send
+                tr.rx_response(state)
+            )
+        }
+    };
+    let span = client_span(method, namespace);
+    quote!(
+        #span
+        let ret: std::result::Result<#wire_type, essrpc::RPCError> = call_span.in_scope(|| { #call });
+        call_span.record_result(&ret);
+    )
+}
+
+// The span covering a call from the client, as `call_span`
+fn client_span(method: &TraitItemMethod, namespace: Option<&str>) -> TokenStream2 {
+    let ident_literal = method_name_literal(&method.sig.ident, namespace);
+    quote!(let call_span = essrpc::CallSpan::client(#ident_literal);)
+}
+
+fn impl_client_method(method: &TraitItemMethod, namespace: Option<&str>, id: u32) -> TokenStream2 {
     let ident = &method.sig.ident;
-    let param_tokens = &method.sig.inputs;
+    let generics = &method.sig.generics;
+    let where_clause = &generics.where_clause;
+    let param_tokens = strip_param_attrs(&method.sig.inputs);
+
+    if !verify_self_param_or_unneeded(method) {
+        return TokenStream2::new();
+    }
+
+    let rettype = get_return_type(method);
+
+    let span = client_span(method, namespace);
+
+    if is_notification(method) {
+        let tx_begin = client_method_tx_begin(method, namespace, id);
+        return quote!(
+        fn #ident #generics(#param_tokens) -> #rettype #where_clause {
+            #span
+            let ret = call_span.in_scope(|| -> std::result::Result<(), essrpc::RPCError> {
+                #tx_begin
+                tr.tx_notify(state)
+            });
+            call_span.record_result(&ret);
+            ret?;
+            Ok(())
+        });
+    }
+
+    if is_stream(method) {
+        let tx_params = client_method_tx_params(method, namespace, id);
+        let (ok_type, err_type) = get_stream_item_types(method);
+        return quote!(
+        fn #ident #generics(#param_tokens) -> #rettype #where_clause {
+            // The transport stays borrowed by the stream, so errors
+            // sending the call are yielded from it too. The span
+            // covers only sending the call.
+            let mut tr = self.tr.borrow_mut();
+            #span
+            let state = call_span.in_scope(|| -> std::result::Result<_, essrpc::RPCError> {
+                #tx_params
+                tr.tx_finalize(state)
+            });
+            call_span.record_result(&state);
+            essrpc::ClientStream::<_, #ok_type, #err_type>::new(tr, state)
+        });
+    }
+
+    if let Some((names_decl, wire_type)) = named_return_types(method) {
+        let tx_rx = client_method_tx_rx(method, namespace, id, &wire_type);
+        return quote!(
+        fn #ident #generics(#param_tokens) -> #rettype #where_clause {
+            #names_decl
+            #tx_rx
+            match ret {
+                Ok(v) => v.map(|named| named.0),
+                Err(e) => Err(e.into())
+            }
+        });
+    }
+
+    let tx_rx = client_method_tx_rx(method, namespace, id, rettype);
+    quote!(
+    fn #ident #generics(#param_tokens) -> #rettype #where_clause {
+        #tx_rx
+        match ret {
+            Ok(v) => v,
+            Err(e) => Err(e.into())
+        }
+    })
+}
+
+// Client method for an async fn trait method, which awaits the
+// AsyncClientTransport in place of returning a boxed future.
+fn impl_async_fn_client_method(
+    method: &TraitItemMethod,
+    namespace: Option<&str>,
+    id: u32,
+) -> TokenStream2 {
+    let ident = &method.sig.ident;
+    let generics = &method.sig.generics;
+    let where_clause = &generics.where_clause;
+    let param_tokens = strip_param_attrs(&method.sig.inputs);
 
     if !verify_self_param_or_unneeded(method) {
         return TokenStream2::new();
@@ -186,13 +1127,66 @@ fn impl_client_method(method: &TraitItemMethod, id: u32) -> TokenStream2 {
 
     let rettype = get_return_type(method);
 
-    let tx_send = client_method_tx_send(method, id);
+    let span = client_span(method, namespace);
+
+    // The transport is borrowed only while building the call, as the
+    // futures it returns do not borrow it.
+    if is_notification(method) {
+        let tx_begin = client_method_tx_begin(method, namespace, id);
+        return quote!(
+        async fn #ident #generics(#param_tokens) -> #rettype #where_clause {
+            #span
+            let ret = call_span.instrument(async {
+                let notified = {
+                    #tx_begin
+                    tr.tx_notify(state)
+                };
+                notified.await
+            }).await;
+            call_span.record_result(&ret);
+            ret?;
+            Ok(())
+        });
+    }
+
+    let tx_send = client_method_tx_send(method, namespace, id);
+    let apply_timeout = get_timeout(method)
+        .map(|timeout| quote!(let response = essrpc::timeout(response, #timeout);))
+        .unwrap_or_default();
+
+    if let Some((names_decl, wire_type)) = named_return_types(method) {
+        return quote!(
+        async fn #ident #generics(#param_tokens) -> #rettype #where_clause {
+            #names_decl
+            #span
+            let ret: std::result::Result<#wire_type, essrpc::RPCError> = call_span.instrument(async {
+                let response: essrpc::BoxFuture<#wire_type, essrpc::RPCError> = {
+                    #tx_send
+                    tr.rx_response(state)
+                };
+                #apply_timeout
+                response.await
+            }).await;
+            call_span.record_result(&ret);
+            match ret {
+                Ok(v) => v.map(|named| named.0),
+                Err(e) => Err(e.into())
+            }
+        });
+    }
 
     quote!(
-    fn #ident(#param_tokens) -> #rettype {
-        #tx_send
-        let ret: std::result::Result<#rettype, essrpc::RPCError> =
-            tr.rx_response(state);
+    async fn #ident #generics(#param_tokens) -> #rettype #where_clause {
+        #span
+        let ret: std::result::Result<#rettype, essrpc::RPCError> = call_span.instrument(async {
+            let response: essrpc::BoxFuture<#rettype, essrpc::RPCError> = {
+                #tx_send
+                tr.rx_response(state)
+            };
+            #apply_timeout
+            response.await
+        }).await;
+        call_span.record_result(&ret);
         match ret {
             Ok(v) => v,
             Err(e) => Err(e.into())
@@ -211,10 +1205,17 @@ fn get_return_type(method: &TraitItemMethod) -> &syn::Type {
 }
 
 fn param_tokens_after_this(method: &TraitItemMethod) -> Punctuated<FnArg, Comma> {
-    method.sig.inputs.clone().into_pairs().skip(1).collect()
+    strip_param_attrs(&method.sig.inputs)
+        .into_pairs()
+        .skip(1)
+        .collect()
 }
 
-fn impl_async_client_method(method: &TraitItemMethod, id: u32) -> TokenStream2 {
+fn impl_async_client_method(
+    method: &TraitItemMethod,
+    namespace: Option<&str>,
+    id: u32,
+) -> TokenStream2 {
     let ident = &method.sig.ident;
 
     // get the parameters without the &self as we want to add a lifetime to that
@@ -226,7 +1227,42 @@ fn impl_async_client_method(method: &TraitItemMethod, id: u32) -> TokenStream2 {
 
     let orig_rettype = get_return_type(method);
     let rettype = get_future_return_type(method);
-    let tx_send = client_method_tx_send(method, id);
+
+    let span = client_span(method, namespace);
+
+    if is_notification(method) {
+        let tx_begin = client_method_tx_begin(method, namespace, id);
+        return quote!(
+        fn #ident<'a>(&'a self, #param_tokens) -> #rettype {
+            use futures;
+            use futures::future::FutureExt;
+            use futures::future::TryFutureExt;
+            #span
+            let call = futures::future::lazy(move |_| {
+                #tx_begin
+                Ok(tr.tx_notify(state))
+            }).and_then(|notified| notified);
+            async move {
+                let ret: std::result::Result<(), essrpc::RPCError> = call_span.instrument(call).await;
+                call_span.record_result(&ret);
+                ret.map_err(|e| e.into())
+            }.boxed_local()
+        });
+    }
+
+    let tx_send = client_method_tx_send(method, namespace, id);
+
+    let rx_response = match get_timeout(method) {
+        Some(timeout) => quote!(
+            essrpc::timeout(self.tr.borrow_mut().rx_response(state), #timeout).boxed_local()
+        ),
+        None => quote!(self.tr.borrow_mut().rx_response(state)),
+    };
+
+    let (names_decl, wire_type, from_wire) = match named_return_types(method) {
+        Some((decl, wire_type)) => (decl, wire_type, quote!(ret.map(|named| named.0))),
+        None => (TokenStream2::new(), orig_rettype.clone(), quote!(ret)),
+    };
 
     quote!(
     fn #ident<'a>(&'a self, #param_tokens) -> #rettype {
@@ -234,14 +1270,22 @@ fn impl_async_client_method(method: &TraitItemMethod, id: u32) -> TokenStream2 {
         use futures;
         use futures::future::FutureExt;
         use futures::future::TryFutureExt;
-        futures::future::lazy(move |_| {
+        #names_decl
+        #span
+        let call = futures::future::lazy(move |_| {
             #tx_send
             Ok(state)
-        }).and_then(move |state| -> essrpc::BoxFuture<#orig_rettype, essrpc::RPCError> {
-            self.tr.borrow_mut().rx_response(state)
-        }).map_err(|e: essrpc::RPCError| e.into())
-            .and_then(|ret| futures::future::ready(ret))
-            .boxed_local()
+        }).and_then(move |state| -> essrpc::BoxFuture<#wire_type, essrpc::RPCError> {
+            #rx_response
+        });
+        async move {
+            let ret: std::result::Result<#wire_type, essrpc::RPCError> = call_span.instrument(call).await;
+            call_span.record_result(&ret);
+            match ret {
+                Ok(ret) => #from_wire,
+                Err(e) => Err(e.into()),
+            }
+        }.boxed_local()
     })
 }
 
@@ -307,6 +1351,8 @@ fn get_result_types(result_type: &syn::ReturnType) -> Option<(syn::Type, syn::Ty
 fn create_client(
     trait_ident: &Ident,
     methods: &[TraitItemMethod],
+    namespace: Option<&str>,
+    version: Option<u32>,
     async_client: bool,
 ) -> TokenStream2 {
     let client_ident = client_ident(trait_ident);
@@ -314,13 +1360,17 @@ fn create_client(
     let rpcclient_ident = rpcclient_ident(async_client);
 
     let mut method_impl_tokens = TokenStream2::new();
+    let method_list = method_list(methods, namespace);
+    let version_const = version_const(version);
 
     let mut mcnt = 0;
     for method in methods {
-        method_impl_tokens.extend(if async_client {
-            impl_async_client_method(method, mcnt)
+        method_impl_tokens.extend(if is_async(method) {
+            impl_async_fn_client_method(method, namespace, mcnt)
+        } else if async_client {
+            impl_async_client_method(method, namespace, mcnt)
         } else {
-            impl_client_method(method, mcnt)
+            impl_client_method(method, namespace, mcnt)
         });
         mcnt += 1;
     }
@@ -330,6 +1380,13 @@ fn create_client(
             tr: std::cell::RefCell<TR>,
         }
 
+        impl <TR> #client_ident<TR> where
+            TR: essrpc::#transport_ident {
+
+            #method_list
+            #version_const
+        }
+
         impl <TR> essrpc::#rpcclient_ident for #client_ident<TR> where
             TR: essrpc::#transport_ident {
 
@@ -348,36 +1405,263 @@ fn create_client(
     )
 }
 
-fn create_server(trait_ident: &Ident, methods: &[TraitItemMethod]) -> TokenStream2 {
-    let server_ident = server_ident(trait_ident);
+// The METHODS constant of the generated client and server, listing
+// each method's number and name as they are sent.
+fn method_list(methods: &[TraitItemMethod], namespace: Option<&str>) -> TokenStream2 {
+    let entries = methods.iter().enumerate().map(|(num, method)| {
+        let num = num as u32;
+        let name = method_name_literal(&method.sig.ident, namespace);
+        quote!((#num, #name))
+    });
+    quote!(
+        /// The methods of the trait, as the number and name (including
+        /// any namespace) by which calls identify them.
+        pub const METHODS: &'static [(u32, &'static str)] = &[#(#entries),*];
+    )
+}
+
+fn create_server(
+    trait_ident: &Ident,
+    methods: &[TraitItemMethod],
+    namespace: Option<&str>,
+    version: Option<u32>,
+    async_server: bool,
+) -> TokenStream2 {
+    let server_ident = if async_server {
+        async_server_ident(trait_ident)
+    } else {
+        server_ident(trait_ident)
+    };
+    let transport_ident = server_transport_ident(async_server);
+
+    let serve_single_call = quote!(
+        fn serve_single_call(&mut self) -> std::result::Result<(), essrpc::RPCError>
+    );
+    let (rpcserver_impl, serve_single_call, rx_begin_call, await_tokens) = if async_server {
+        (
+            quote!(#[essrpc::async_trait(?Send)] impl <TR, T> essrpc::AsyncRPCServer),
+            quote!(async #serve_single_call),
+            quote!(self.tr.rx_begin_call().await?),
+            quote!(.await),
+        )
+    } else {
+        (
+            quote!(impl <TR, T> essrpc::RPCServer),
+            serve_single_call,
+            quote!(self.tr.rx_begin_call()?),
+            TokenStream2::new(),
+        )
+    };
+    // A notifications-only server answers nothing, errors included
+    let tx_error = quote!(
+        if self.notifications_only {
+            let _ = e;
+            self.tr.tx_no_response(rxstate)#await_tokens
+        } else {
+            self.tr.tx_error(e, rxstate)#await_tokens
+        }
+    );
+    let reject_call = quote!(
+        if self.notifications_only {
+            let _ = e;
+            self.tr.tx_no_response(rxstate)#await_tokens
+        } else {
+            self.tr.reject_call(rxstate, e)#await_tokens
+        }
+    );
+    let rxstate_type = quote!(<TR as essrpc::#transport_ident>::RXState);
 
+    let method_list = method_list(methods, namespace);
     let mut server_method_matches = TokenStream2::new();
     let mut server_by_name_matches = TokenStream2::new();
+    let mut server_by_num_matches = TokenStream2::new();
 
     let mut mcnt = 0;
     for method in methods {
-        server_method_matches.extend(create_server_match(method, mcnt));
-        let ident_literal = make_ident_literal_str(&method.sig.ident);
+        server_method_matches.extend(create_server_match(method, mcnt, async_server, &tx_error));
+        let ident_literal = method_name_literal(&method.sig.ident, namespace);
         server_by_name_matches.extend(quote!(#ident_literal => #mcnt,));
+        server_by_num_matches.extend(quote!(#mcnt => Some(#ident_literal),));
         mcnt += 1;
     }
 
+    let dispatch = quote!(
+        match id {
+            #server_method_matches
+            _ => {
+                let e = essrpc::RPCError::new(
+                    essrpc::RPCErrorKind::UnknownMethod, format!("Unknown rpc method {:?}", method));
+                #tx_error
+            }
+        }
+    );
+    // The call is dispatched within a block of its own, so that errors
+    // from it can be attributed to the method.
+    let dispatch = if async_server {
+        quote!(async { #dispatch }.await)
+    } else {
+        quote!((|| { #dispatch })())
+    };
+
+    // A versioned server refuses calls stamped with a version it is not
+    // compatible with before the authorizer sees them. Calls with no
+    // version at all are let through.
+    let version_const = version_const(version);
+    let (versions_field, versions_init, versions_builder, authorized) = match version {
+        Some(version) => (
+            quote!(versions: std::ops::RangeInclusive<u32>,),
+            quote!(versions: #version..=#version,),
+            quote!(
+                /// Also serve clients built against any of `versions`
+                /// of the trait, rather than only its own version, such
+                /// as during a rolling upgrade. Calls from other
+                /// versions are refused with
+                /// `RPCErrorKind::VersionMismatch`.
+                pub fn with_compatible_versions(
+                    mut self,
+                    versions: std::ops::RangeInclusive<u32>,
+                ) -> Self {
+                    self.versions = versions;
+                    self
+                }
+            ),
+            quote!(
+                match (self.tr.rx_service_version(&rxstate), &self.authorizer) {
+                    (Some(version), _) if !self.versions.contains(&version) => {
+                        Err(essrpc::RPCError::new(
+                            essrpc::RPCErrorKind::VersionMismatch,
+                            format!(
+                                "version {} is not served (serving {}..={})",
+                                version,
+                                self.versions.start(),
+                                self.versions.end()
+                            ),
+                        ))
+                    }
+                    (_, Some(authorize)) => authorize(&method, &rxstate),
+                    (_, None) => Ok(()),
+                }
+            ),
+        ),
+        None => (
+            TokenStream2::new(),
+            TokenStream2::new(),
+            TokenStream2::new(),
+            quote!(match &self.authorizer {
+                Some(authorize) => authorize(&method, &rxstate),
+                None => Ok(()),
+            }),
+        ),
+    };
+
+    // Errors while serving a known method say which. A call the
+    // authorizer refuses is answered without reading its parameters.
+    let serve_call = quote!(
+        let id = match &method {
+            essrpc::PartialMethodId::Num(num) => *num,
+            essrpc::PartialMethodId::Name(name) => Self::method_num_from_name(&name),
+        };
+        let authorized = #authorized;
+        let result = match authorized {
+            Ok(()) => #dispatch,
+            Err(e) => #reject_call,
+        };
+        result.map_err(|e| match Self::method_name_from_num(id) {
+            Some(name) => e.with_method(name),
+            None => e,
+        })
+    );
+    // An async server waits for the next call alongside the shutdown
+    // signal, but once it has the call, serves it to the end.
+    let serve_methods = if async_server {
+        quote!(
+            #serve_single_call {
+                let mut never = std::future::pending::<()>();
+                let never = std::pin::Pin::new(&mut never);
+                self.serve_single_call_until(never).await.map(|_| ())
+            }
+
+            async fn serve_single_call_until(
+                &mut self,
+                shutdown: std::pin::Pin<&mut dyn std::future::Future<Output = ()>>,
+            ) -> std::result::Result<bool, essrpc::RPCError> {
+                let (method, mut rxstate) =
+                    match essrpc::until_shutdown(self.tr.rx_begin_call(), shutdown).await {
+                        Some(call) => call?,
+                        None => return Ok(false),
+                    };
+                let call_span = essrpc::CallSpan::server(&method);
+                let served: std::result::Result<(), essrpc::RPCError> =
+                    call_span.instrument(async { #serve_call }).await;
+                call_span.record_result(&served);
+                served.map(|()| true)
+            }
+        )
+    } else {
+        quote!(
+            #serve_single_call {
+                let (method, mut rxstate) = #rx_begin_call;
+                let call_span = essrpc::CallSpan::server(&method);
+                let served = call_span.in_scope(|| { #serve_call });
+                call_span.record_result(&served);
+                served
+            }
+        )
+    };
+
     quote!(
         pub struct #server_ident<T, TR> where
             T: #trait_ident,
-            TR: essrpc::ServerTransport {
+            TR: essrpc::#transport_ident {
 
             tr: TR,
-            imp: T
+            imp: T,
+            #versions_field
+            authorizer: Option<essrpc::Authorizer<#rxstate_type>>,
+            notifications_only: bool,
         }
 
         impl <T, TR> #server_ident<T, TR> where
             T: #trait_ident,
-            TR: essrpc::ServerTransport {
+            TR: essrpc::#transport_ident {
+
+            #method_list
+            #version_const
 
             pub fn new(imp: T, transport: TR) -> Self {
                 #server_ident{tr: transport,
-                              imp: imp}
+                              imp: imp,
+                              #versions_init
+                              authorizer: None,
+                              notifications_only: false}
+            }
+
+            #versions_builder
+
+            /// Check each call with `authorizer` before dispatching it,
+            /// refusing those for which it returns an error. See
+            /// [Authorizer](../essrpc/type.Authorizer.html).
+            pub fn with_authorizer(
+                mut self,
+                authorizer: impl Fn(&essrpc::PartialMethodId, &#rxstate_type)
+                    -> std::result::Result<(), essrpc::RPCError> + Send + 'static,
+            ) -> Self {
+                self.authorizer = Some(Box::new(authorizer));
+                self
+            }
+
+            /// Serve every call as a notification, never writing to
+            /// the transport's channel, e.g. for a one-way ingestion
+            /// endpoint reading from a pub/sub topic, whose clients
+            /// only send notifications. The results of methods, and
+            /// errors such as unknown methods, are dropped without
+            /// being serialized, and the transport is told, with
+            /// [set_notifications_only](../essrpc/trait.ServerTransport.html#method.set_notifications_only),
+            /// to answer nothing itself either.
+            pub fn notifications_only(mut self) -> Self {
+                self.tr.set_notifications_only();
+                self.notifications_only = true;
+                self
             }
 
             fn method_num_from_name(name: &str) -> u32 {
@@ -387,31 +1671,109 @@ fn create_server(trait_ident: &Ident, methods: &[TraitItemMethod]) -> TokenStrea
                 }
             }
 
+            fn method_name_from_num(num: u32) -> Option<&'static str> {
+                match num {
+                    #server_by_num_matches
+                    _ => None
+                }
+            }
+
         }
 
-        impl <TR, T> essrpc::RPCServer for #server_ident<T, TR> where
-            TR: essrpc::ServerTransport,
+        #rpcserver_impl for #server_ident<T, TR> where
+            TR: essrpc::#transport_ident,
             T: #trait_ident
         {
-            fn serve_single_call(&mut self) -> std::result::Result<(), essrpc::RPCError> {
-                let (method, mut rxstate) = self.tr.rx_begin_call()?;
-                let id = match &method {
-                    essrpc::PartialMethodId::Num(num) => *num,
-                    essrpc::PartialMethodId::Name(name) => Self::method_num_from_name(&name),
-                };
-                match id {
-                    #server_method_matches
-                    _ => {
-                        Err(essrpc::RPCError::new(
-                            essrpc::RPCErrorKind::UnknownMethod, format!("Unknown rpc method {:?}", method)))
-                    }
-                }
-            }
+            #serve_methods
         }
     )
 }
 
-fn create_server_match(method: &TraitItemMethod, id: u32) -> TokenStream2 {
+fn create_server_match(
+    method: &TraitItemMethod,
+    id: u32,
+    async_server: bool,
+    tx_error: &TokenStream2,
+) -> TokenStream2 {
+    let instances = get_type_param_instances(method);
+    let body =
+        server_dispatch_instances(method, async_server, tx_error, &instances, &mut Vec::new());
+    quote!(
+        #id => #body,
+    )
+}
+
+// Server code reading the type tags of a generic method and calling it
+// with the types they name, from those listed in
+// `#[essrpc(types(...))]`. For a method which is not generic, just
+// the call.
+fn server_dispatch_instances(
+    method: &TraitItemMethod,
+    async_server: bool,
+    tx_error: &TokenStream2,
+    instances: &[(Ident, Vec<syn::Type>)],
+    chosen: &mut Vec<syn::Type>,
+) -> TokenStream2 {
+    let ((param, types), rest) = match instances.split_first() {
+        Some(first) => first,
+        None => return server_call(method, async_server, chosen),
+    };
+    let tag_literal = type_tag_literal(param);
+    let await_tokens = if async_server {
+        quote!(.await)
+    } else {
+        TokenStream2::new()
+    };
+    let read_tag = read_or_discard(
+        quote!(self.tr.rx_read_param(#tag_literal, &mut rxstate)#await_tokens),
+        &await_tokens,
+    );
+    let read_tag = quote!(let tag: String = #read_tag;);
+    let mut branches = TokenStream2::new();
+    for ty in types {
+        chosen.push(ty.clone());
+        let call = server_dispatch_instances(method, async_server, tx_error, rest, chosen);
+        chosen.pop();
+        branches.extend(quote!(if tag == std::any::type_name::<#ty>() #call else));
+    }
+    let unsupported = LitStr::new(
+        &format!(
+            "Unsupported type {{}} for {} of rpc method {}",
+            param, method.sig.ident
+        ),
+        Span::call_site(),
+    );
+    quote!({
+        #read_tag
+        #branches {
+            let e = essrpc::RPCError::new(
+                essrpc::RPCErrorKind::SerializationError, format!(#unsupported, tag));
+            #tx_error
+        }
+    })
+}
+
+// Server code giving the parameter read by `read`, or if reading it
+// failed, skipping the rest of the request and returning the error.
+fn read_or_discard(read: TokenStream2, await_tokens: &TokenStream2) -> TokenStream2 {
+    quote!(match #read {
+        Ok(param) => param,
+        Err(e) => {
+            let _ = self.tr.discard_remaining(&mut rxstate)#await_tokens;
+            return Err(e);
+        }
+    })
+}
+
+// Server code reading the parameters of a method, calling it, and
+// responding. `type_args` instantiates a generic method. Parameters
+// are read in declaration order, matching the order the client adds
+// them in, which order-sensitive transports rely on.
+fn server_call(
+    method: &TraitItemMethod,
+    async_server: bool,
+    type_args: &[syn::Type],
+) -> TokenStream2 {
     let ident = &method.sig.ident;
     let param_tokens = &method.sig.inputs;
 
@@ -422,11 +1784,50 @@ fn create_server_match(method: &TraitItemMethod, id: u32) -> TokenStream2 {
     for p in param_tokens.iter() {
         if let FnArg::Typed(arg) = p {
             let name = &arg.pat;
-            let name_literal = make_pat_literal_str(name);
-            let ty = &arg.ty;
-            param_retrieve_tokens.extend(
-                quote!(let #name: #ty = self.tr.rx_read_param(#name_literal, &mut rxstate)?;),
-            );
+            let name_literal = param_name_literal(arg);
+            // The types of a generic method's parameters may name its
+            // type parameters, so are left to be inferred from the call.
+            let ty = if type_args.is_empty() {
+                arg.ty.clone().into_token_stream()
+            } else {
+                quote!(_)
+            };
+            let await_tokens = if async_server {
+                quote!(.await)
+            } else {
+                TokenStream2::new()
+            };
+            // A parameter the client may leave out gets its default
+            let read = if is_default_param(arg) {
+                let read = read_or_discard(
+                    quote!(self.tr.rx_read_param_opt(#name_literal, &mut rxstate)#await_tokens),
+                    &await_tokens,
+                );
+                quote!(#read.unwrap_or_default())
+            } else {
+                read_or_discard(
+                    quote!(self.tr.rx_read_param(#name_literal, &mut rxstate)#await_tokens),
+                    &await_tokens,
+                )
+            };
+            param_retrieve_tokens.extend(if is_bytes_param(arg) {
+                // Received as essrpc::ByteBuf, then converted to or
+                // borrowed as the declared type
+                let ty = &arg.ty;
+                let convert = match ty.as_ref() {
+                    syn::Type::Reference(_) => quote!(&#name),
+                    _ => quote!(#name.into()),
+                };
+                quote!(
+                    let #name: essrpc::ByteBuf = #read;
+                    let #name: #ty = #convert;
+                )
+            } else if is_upload_param(arg) {
+                // Borrows the transport until the method returns
+                quote!(let #name: #ty = self.tr.rx_read_upload(#name_literal, &mut rxstate)?;)
+            } else {
+                quote!(let #name: #ty = #read;)
+            });
             if first {
                 first = false;
             } else {
@@ -436,11 +1837,67 @@ fn create_server_match(method: &TraitItemMethod, id: u32) -> TokenStream2 {
         }
     }
 
-    quote!(
-        #id => {
-            #param_retrieve_tokens
-            let ret = self.imp.#ident(#param_call_tokens);
-            self.tr.tx_response(ret)
-        },
-    )
+    let tx_response = match (is_notification(method), async_server) {
+        _ if is_stream(method) => quote!(if self.notifications_only {
+            drop(ret);
+            self.tr.tx_no_response(rxstate)
+        } else {
+            // Stop producing as soon as the client cancels
+            for item in ret {
+                if self.tr.rx_stream_cancelled(&mut rxstate)? {
+                    break;
+                }
+                self.tr.tx_stream_item(item, &mut rxstate)?;
+            }
+            self.tr.tx_stream_end(rxstate)
+        }),
+        // Nobody is waiting for the result of a notification
+        (true, true) => quote!({
+            let _ = ret;
+            self.tr.tx_no_response(rxstate).await
+        }),
+        (true, false) => quote!({
+            let _ = ret;
+            self.tr.tx_no_response(rxstate)
+        }),
+        (false, true) => quote!(if self.notifications_only {
+            let _ = ret;
+            self.tr.tx_no_response(rxstate).await
+        } else {
+            self.tr.tx_response(ret, rxstate).await
+        }),
+        (false, false) => quote!(if self.notifications_only {
+            let _ = ret;
+            self.tr.tx_no_response(rxstate)
+        } else {
+            self.tr.tx_response(ret, rxstate)
+        }),
+    };
+
+    let turbofish = if type_args.is_empty() {
+        TokenStream2::new()
+    } else {
+        quote!(::<#(#type_args),*>)
+    };
+    let call = if is_async(method) {
+        quote!(self.imp.#ident #turbofish(#param_call_tokens).await)
+    } else {
+        quote!(self.imp.#ident #turbofish(#param_call_tokens))
+    };
+
+    // Send a tuple with named returns as a struct
+    let to_wire = match named_return_types(method) {
+        Some((names_decl, _)) => quote!(
+            #names_decl
+            let ret = ret.map(essrpc::NamedReturn::<_, EssrpcReturnNames>::new);
+        ),
+        None => TokenStream2::new(),
+    };
+
+    quote!({
+        #param_retrieve_tokens
+        let ret = #call;
+        #to_wire
+        #tx_response
+    })
 }